@@ -2,7 +2,7 @@ use ::gitlab::{AsyncGitlab, GitlabBuilder};
 use clap::Parser;
 use color_eyre::eyre::{Result, WrapErr};
 
-use buildbtw_poc::gitlab::fetch_all_source_repo_changes;
+use buildbtw_poc::{git::PackagingRemote, gitlab::fetch_all_source_repo_changes};
 
 use arch_pkg_repo_updater::args::{self, Args};
 use arch_pkg_repo_updater::state::State;
@@ -38,15 +38,18 @@ async fn main() -> Result<()> {
     let mut state = State::from_filesystem()?;
     let client = new_gitlab_client(&args.gitlab, &gitlab_token).await?;
 
-    let last_fetched = fetch_all_source_repo_changes(
+    let changes = fetch_all_source_repo_changes(
         &client,
         state.last_updated,
-        args.gitlab.gitlab_domain,
+        PackagingRemote::gitlab(
+            &args.gitlab.gitlab_domain,
+            &args.gitlab.gitlab_packages_group,
+        ),
         args.gitlab.gitlab_packages_group,
     )
     .await?;
 
-    state.last_updated = last_fetched;
+    state.last_updated = changes.last_fetched;
     state.write_to_filesystem()?;
 
     Ok(())
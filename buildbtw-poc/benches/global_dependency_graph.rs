@@ -1,6 +1,7 @@
 use buildbtw_poc::{
     BuildNamespace, BuildNamespaceStatus,
     build_set_graph::{build_global_dependency_graphs, gather_packages_metadata},
+    pacman_conf::default_base_repositories,
     source_repos::SourceRepos,
 };
 use criterion::{Criterion, criterion_group, criterion_main};
@@ -19,6 +20,11 @@ pub fn criterion_benchmark(c: &mut Criterion) {
             current_origin_changesets: Vec::new(),
             created_at: time::OffsetDateTime::now_utc(),
             status: BuildNamespaceStatus::Active,
+            base_repositories: default_base_repositories(),
+            parent_id: None,
+            bump_pkgrel: false,
+            rebase_origin_changesets: false,
+            build_dispatch: None,
         };
 
         let mut source_repos = SourceRepos::new().await.unwrap();
@@ -1,12 +1,16 @@
 #!/usr/bin/bash
 set -o nounset -o pipefail -o xtrace -o errexit
 
-REPO_URL=$1
+PACMAN_CONF=$1
+COMMIT_HASH=$2
 
 pacman --noconfirm -Syu devtools
 
-# Add buildbtw repo for this namespace
-sed -i "$ a [buildbtw-namespace]\nServer = $REPO_URL" /usr/share/devtools/pacman.conf.d/*
+# Use the pacman.conf generated by the server for this namespace,
+# regardless of which repository pkgctl picks a config for
+for conf in /usr/share/devtools/pacman.conf.d/*.conf; do
+    cp "$PACMAN_CONF" "$conf"
+done
 
 # Create user to run the build as non-root
 # but give them sudo access because it actually does need root
@@ -16,6 +20,8 @@ echo 'builder ALL=(ALL:ALL) NOPASSWD: ALL' >> /etc/sudoers
 # Setup up working directory for build with correct permissions
 cp -R /mnt/src_repo /build
 cd /build
+# The pipeline runs on a branch or tag, which may point to a newer commit
+git -c safe.directory=/build checkout --detach "$COMMIT_HASH"
 chown -R builder .
 
 # Import upstream GPG keys
@@ -28,8 +28,15 @@ prepare() {
 run() {
     # the host should be reachable at 10.0.2.2 since we're using
     # user mode networking
-    pacman_repo_url="http://10.0.2.2:${CUSTOM_ENV_SERVER_PORT}/repo/${CUSTOM_ENV_NAMESPACE_NAME}_${CUSTOM_ENV_ITERATION_ID}/os/${CUSTOM_ENV_ARCHITECTURE}"
+    repo_base_url="http://10.0.2.2:${CUSTOM_ENV_SERVER_PORT}/repo/"
     output_dir=$(sudo -u buildbtw mktemp -d)
+    config_dir=$(sudo -u buildbtw mktemp -d)
+
+    # The server generates the pacman.conf for this build, containing the
+    # namespace repository on top of the namespace's base repositories.
+    sudo -u buildbtw --set-home curl --fail -o "${config_dir}/pacman.conf" \
+        --get --data-urlencode "repo_base_url=${repo_base_url}" \
+        "http://127.0.0.1:${CUSTOM_ENV_SERVER_PORT}/iteration/${CUSTOM_ENV_ITERATION_ID}/architecture/${CUSTOM_ENV_ARCHITECTURE}/pacman.conf"
 
     sudo -u buildbtw --set-home \
     vmexec run archlinux --rm --pmem /var/lib/archbuild:30 \
@@ -37,19 +44,22 @@ run() {
         --volume "${CUSTOM_ENV_CI_PROJECT_DIR}":/mnt/src_repo:ro \
         --volume /srv/buildbtw/gitlab-executor:/mnt/bin:ro \
         --volume "${output_dir}":/mnt/output \
+        --volume "${config_dir}":/mnt/config:ro \
         -- \
-        /mnt/bin/build-inside-vm.sh "${pacman_repo_url}" || exit "${BUILD_FAILURE_EXIT_CODE:-1}"
+        /mnt/bin/build-inside-vm.sh /mnt/config/pacman.conf "${CUSTOM_ENV_COMMIT_HASH}" || exit "${BUILD_FAILURE_EXIT_CODE:-1}"
 
     tree "$output_dir"
-    package_file_names=(${CUSTOM_ENV_PACKAGE_FILE_NAMES})
-    for file in "${package_file_names[@]}"; do
-        # extract everything before first hyphen followed by digit
-        pkgname="${file%%-[0-9]*}"
+    # Upload all package files makepkg produced, regardless of their compression.
+    # The server figures out which split package each file belongs to.
+    for file in "${output_dir}"/*.pkg.tar*; do
+        file_name="$(basename "${file}")"
+        # Skip signatures and other files that aren't packages
+        [[ ${file_name} == *.sig ]] && continue
 
-        sudo -u buildbtw --set-home curl -v -X POST --data-binary @"${output_dir}/${file}" "http://127.0.0.1:${CUSTOM_ENV_SERVER_PORT}/iteration/${CUSTOM_ENV_ITERATION_ID}/pkgbase/${CUSTOM_ENV_PKGBASE}/pkgname/${pkgname}/architecture/${CUSTOM_ENV_ARCHITECTURE}/package"
+        sudo -u buildbtw --set-home curl -v --fail-with-body -X POST --data-binary @"${file}" "http://127.0.0.1:${CUSTOM_ENV_SERVER_PORT}/iteration/${CUSTOM_ENV_ITERATION_ID}/pkgbase/${CUSTOM_ENV_PKGBASE}/architecture/${CUSTOM_ENV_ARCHITECTURE}/package/${file_name}"
     done
 
-    rm -rf "${output_dir}"
+    rm -rf "${output_dir}" "${config_dir}"
 }
 
 # https://docs.gitlab.com/runner/executors/custom.html#cleanup
@@ -0,0 +1,12 @@
+-- Signing status of each package file uploaded to a namespace repository.
+create table package_signatures (
+    id text not null primary key,
+    build_set_iteration_id text not null
+        references build_set_iterations (id),
+    pkgbase text not null,
+    architecture text not null,
+    file_name text not null,
+    status text not null,
+    updated_at text not null,
+    unique (build_set_iteration_id, architecture, file_name)
+) strict;
@@ -0,0 +1,4 @@
+-- Repositories that builds in a namespace run against.
+-- Existing namespaces keep building on top of core and extra.
+alter table build_namespaces
+    add column base_repositories text not null default '["core","extra"]';
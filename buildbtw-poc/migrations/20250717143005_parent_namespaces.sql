@@ -0,0 +1,7 @@
+-- Namespaces can build on top of the results of another namespace.
+alter table build_namespaces
+    add column parent_namespace_id text references build_namespaces (id);
+
+-- The newest iteration of the parent namespace at the time an iteration was created.
+alter table build_set_iterations
+    add column parent_iteration_id text references build_set_iterations (id);
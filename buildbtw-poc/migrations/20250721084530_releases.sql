@@ -0,0 +1,11 @@
+create table releases (
+    id text not null primary key,
+    namespace_id text not null references build_namespaces (id),
+    build_set_iteration_id text not null references build_set_iterations (id),
+    target_repository text not null,
+    plan text not null,
+    status text not null,
+    error text,
+    created_at text not null,
+    updated_at text not null
+) strict;
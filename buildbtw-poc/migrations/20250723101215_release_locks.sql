@@ -0,0 +1,7 @@
+create table release_locks (
+    target_repository text not null,
+    pkgbase text not null,
+    release_id text not null references releases (id),
+    acquired_at text not null,
+    primary key (target_repository, pkgbase)
+) strict;
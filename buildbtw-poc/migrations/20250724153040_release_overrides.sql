@@ -0,0 +1,6 @@
+create table release_overrides (
+    namespace_id text not null references build_namespaces (id),
+    pkgbase text not null,
+    release_override text not null,
+    primary key (namespace_id, pkgbase)
+) strict;
@@ -0,0 +1,8 @@
+create table gitlab_commit_statuses (
+    build_set_iteration_id text not null references build_set_iterations (id),
+    pkgbase text not null,
+    architecture text not null,
+    status text not null,
+    updated_at text not null,
+    primary key (build_set_iteration_id, pkgbase, architecture)
+) strict;
@@ -0,0 +1,11 @@
+create table gitlab_merge_requests (
+    namespace_id text not null references build_namespaces (id),
+    pkgbase text not null,
+    branch_name text not null,
+    state text not null,
+    gitlab_iid integer,
+    web_url text,
+    note_id integer,
+    reported_summary text,
+    primary key (namespace_id, pkgbase)
+) strict;
@@ -0,0 +1,3 @@
+-- Build dependents from rebuild branches with an automatic pkgrel bump.
+alter table build_namespaces
+    add column bump_pkgrel integer not null default false;
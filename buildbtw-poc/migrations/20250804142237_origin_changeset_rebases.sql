@@ -0,0 +1,14 @@
+-- Rebase origin changeset branches onto main when it moves.
+alter table build_namespaces
+    add column rebase_origin_changesets integer not null default false;
+
+-- The most recent rebase of each origin changeset branch in a namespace.
+create table origin_changeset_rebases (
+    namespace_id text not null references build_namespaces (id),
+    pkgbase text not null,
+    branch_name text not null,
+    onto_commit_hash text not null,
+    outcome text not null,
+    updated_at text not null,
+    primary key (namespace_id, pkgbase)
+) strict;
@@ -0,0 +1,9 @@
+-- Retrying a pipeline on gitlab keeps its ID, so each retry is recorded
+-- as a new generation of the pipeline to keep track of all build attempts.
+alter table gitlab_pipelines
+    add column generation integer not null default 1;
+
+-- Last known status of each generation.
+-- Unknown for pipelines created before this was tracked.
+alter table gitlab_pipelines
+    add column status text;
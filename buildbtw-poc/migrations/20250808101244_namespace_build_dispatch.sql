@@ -0,0 +1,3 @@
+-- Where the builds of a namespace run. If null, the server's default is used.
+alter table build_namespaces
+    add column build_dispatch text;
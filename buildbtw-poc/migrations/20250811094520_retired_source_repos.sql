@@ -0,0 +1,7 @@
+-- Source repositories of archived or removed projects.
+-- They stay on disk, but are excluded from build graphs.
+create table retired_source_repos (
+    pkgbase text primary key not null,
+    reason text not null,
+    retired_at text not null
+) strict;
@@ -0,0 +1,9 @@
+-- The most recent check of each origin changeset's .SRCINFO in a namespace.
+create table srcinfo_checks (
+    namespace_id text not null references build_namespaces (id),
+    pkgbase text not null,
+    commit_hash text not null,
+    outcome text not null,
+    checked_at text not null,
+    primary key (namespace_id, pkgbase)
+) strict;
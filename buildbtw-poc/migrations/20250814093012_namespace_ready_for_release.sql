@@ -0,0 +1,4 @@
+-- When all tracked merge requests of a namespace were merged.
+-- Null while merge requests are still open, or if the namespace doesn't track any.
+alter table build_namespaces
+    add column ready_for_release_at text;
@@ -0,0 +1,5 @@
+-- When archiving the job logs of a pipeline generation was started.
+-- Both the webhook and status polling notice finished pipelines,
+-- this makes sure only one of them archives the logs.
+alter table gitlab_pipelines
+    add column logs_archived_at text;
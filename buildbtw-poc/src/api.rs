@@ -5,13 +5,43 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    BuildNamespace, GitRepoRef, build_set_graph::BuildSetGraph, source_info::ConcreteArchitecture,
+    BuildNamespace, GitRepoRef,
+    build_set_graph::BuildSetGraph,
+    conflicts::NamespaceOverlap,
+    gitlab::PipelineStatus,
+    gitlab::merge_requests::TrackedMergeRequest,
+    rebase::OriginChangesetRebase,
+    release::{Release, ReleaseLock},
+    source_info::ConcreteArchitecture,
+    srcinfo_check::{BlockingSrcinfoCheck, SrcinfoCheck},
 };
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ShowNamespaceJson {
     pub architecture_iteration: Option<ArchitectureIteration>,
     pub namespace: BuildNamespace,
+    /// Merge requests tracked for the namespace's origin changesets.
+    #[serde(default)]
+    pub merge_requests: Vec<TrackedMergeRequest>,
+    /// Whether all tracked merge requests were merged, so the namespace can be released.
+    #[serde(default)]
+    pub ready_for_release: bool,
+    /// Most recent rebases of the namespace's origin changeset branches onto `main`.
+    #[serde(default)]
+    pub origin_changeset_rebases: Vec<OriginChangesetRebase>,
+    /// Most recent checks of the origin changesets' `.SRCINFO` files against their PKGBUILDs.
+    #[serde(default)]
+    pub srcinfo_checks: Vec<SrcinfoCheck>,
+}
+
+/// Returned after creating a namespace.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateNamespaceJson {
+    #[serde(flatten)]
+    pub namespace: BuildNamespace,
+    /// Other active namespaces that touch the same pkgbases as the new namespace.
+    #[serde(default)]
+    pub overlapping_namespaces: Vec<NamespaceOverlap>,
 }
 
 /// We don't send the whole iteration as that would contain
@@ -24,3 +54,29 @@ pub struct ArchitectureIteration {
     pub origin_changesets: Vec<GitRepoRef>,
     pub build_graph: BuildSetGraph,
 }
+
+/// Returned after confirming a release.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfirmReleaseJson {
+    pub release: Release,
+    /// Locks held by other releases that this release is waiting for.
+    /// If empty, the release holds locks on all of its pkgbases.
+    pub blocking_locks: Vec<ReleaseLock>,
+}
+
+/// Returned with a conflict status if an iteration can't be created
+/// because `.SRCINFO` checks are pending or failed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockingSrcinfoChecksJson {
+    pub blocking: Vec<BlockingSrcinfoCheck>,
+}
+
+/// One attempt at building a node via a gitlab pipeline.
+/// Retrying a pipeline creates a new generation of it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PipelineAttempt {
+    pub generation: i64,
+    /// Unknown for pipelines created before statuses were tracked.
+    pub status: Option<PipelineStatus>,
+    pub gitlab_url: String,
+}
@@ -1,7 +1,12 @@
 use clap::{Parser, Subcommand};
 use color_eyre::eyre::{OptionExt, Result};
 
-use buildbtw_poc::GitRepoRef;
+use buildbtw_poc::{
+    BuildDispatch, GitRepoRef,
+    pacman_conf::BaseRepository,
+    release_selection::{ReleaseOverride, ReleaseStrategy},
+    source_info::ConcreteArchitecture,
+};
 use url::Url;
 
 fn parse_git_changeset(value: &str) -> Result<GitRepoRef> {
@@ -30,6 +35,24 @@ pub enum Command {
         /// List of package source commits to use as root for the build graph. Format: `pkbase/git_ref`, where git_ref can be a commit hash, branch name, or tag. E.g.: "linux/main"
         #[arg(value_parser(parse_git_changeset))]
         origin_changesets: Vec<GitRepoRef>,
+        /// Repository to build on top of, can be given multiple times. Either the name of an official repository like `core-testing`, or another namespace in the format `namespace:name`. Default: core, extra
+        #[arg(short, long = "base-repository")]
+        base_repositories: Vec<BaseRepository>,
+        /// Name of a namespace to build on top of. Packages are resolved using the parent's origin changesets, and builds use the parent's repository. A new iteration is created whenever the parent gets a new iteration
+        #[arg(short, long)]
+        parent: Option<String>,
+        /// Open (or attach to) a GitLab merge request for each origin changeset branch and post the namespace's build results to it. The namespace is ready for release once all merge requests are merged
+        #[arg(short, long, action, default_value = "false")]
+        merge_requests: bool,
+        /// Build dependents of the origin changesets from a rebuild branch with an automatic pkgrel bump, which is pushed to each dependent's source repository
+        #[arg(long, action, default_value = "false")]
+        bump_pkgrel: bool,
+        /// Rebase origin changeset branches onto main and push them whenever main moves, as long as this is possible without conflicts
+        #[arg(long, action, default_value = "false")]
+        rebase: bool,
+        /// Where to run the namespace's builds. If omitted, the server's default is used
+        #[arg(long, value_enum)]
+        build_dispatch: Option<BuildDispatch>,
     },
     /// Cancel a build namespace. No new iterations or builds will be created. Existing builds will not be interrupted
     Cancel {
@@ -52,11 +75,60 @@ pub enum Command {
         #[arg()]
         name: String,
     },
+    /// Retry the failed GitLab pipeline of a package in the newest iteration of a namespace, instead of creating a new iteration
+    RetryPipeline {
+        #[arg()]
+        name: String,
+        #[arg()]
+        pkgbase: String,
+        #[arg(short, long, default_value = "x86_64")]
+        architecture: ConcreteArchitecture,
+    },
+    /// Cancel the running GitLab pipeline of a package in the newest iteration of a namespace. The build is marked as failed
+    CancelPipeline {
+        #[arg()]
+        name: String,
+        #[arg()]
+        pkgbase: String,
+        #[arg(short, long, default_value = "x86_64")]
+        architecture: ConcreteArchitecture,
+    },
     /// Show status and builds for a namespace
     Show {
         #[arg()]
         name: String,
     },
+    /// Release the packages of the latest iteration of a namespace into a target repository. Shows the release plan and asks for confirmation before adding it to the release queue
+    Release {
+        #[arg()]
+        name: String,
+        /// Name of the repository to release into, e.g. "extra-testing"
+        #[arg(short, long)]
+        target_repository: String,
+        /// How to select the packages to release
+        #[arg(short, long, value_enum, default_value_t)]
+        strategy: ReleaseStrategy,
+        /// Confirm the release plan without asking. Default: false
+        #[arg(short, long, action, default_value = "false")]
+        yes: bool,
+    },
+    /// Cancel the pending release of a namespace and remove it from the release queue. Releases that are already moving packages can't be cancelled
+    CancelRelease {
+        #[arg()]
+        name: String,
+    },
+    /// Always include or exclude a pkgbase when releasing a namespace, regardless of the release strategy
+    ReleaseOverride {
+        #[arg()]
+        name: String,
+        #[arg()]
+        pkgbase: String,
+        /// Leave out to remove the override
+        #[arg(value_enum)]
+        release_override: Option<ReleaseOverride>,
+    },
+    /// List pkgbases locked by confirmed releases
+    ReleaseLocks,
 }
 
 #[derive(Debug, Clone, Parser)]
@@ -1,15 +1,26 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+};
 
 use clap::Parser;
-use color_eyre::eyre::{Context, Result};
+use color_eyre::eyre::{Context, Result, bail};
 use colored::Colorize;
 use itertools::Itertools;
 use reqwest::header::ACCEPT;
 use time::format_description;
 
 use buildbtw_poc::{
-    BuildNamespace, BuildNamespaceStatus, BuildSetIteration, GitRepoRef, PackageBuildStatus,
-    api::ShowNamespaceJson,
+    BuildNamespace, BuildNamespaceStatus, BuildSetIteration, PackageBuildStatus, Pkgbase,
+    api::{
+        BlockingSrcinfoChecksJson, ConfirmReleaseJson, CreateNamespaceJson, PipelineAttempt,
+        ShowNamespaceJson,
+    },
+    rebase::RebaseOutcome,
+    release::{CreateRelease, Release, ReleaseLock},
+    release_selection::{ReleaseOverride, ReleaseStrategy, SetReleaseOverride},
+    source_info::ConcreteArchitecture,
+    srcinfo_check::SrcinfoCheckOutcome,
 };
 use url::Url;
 
@@ -34,8 +45,24 @@ async fn main() -> Result<()> {
         Command::New {
             name,
             origin_changesets,
+            base_repositories,
+            parent,
+            merge_requests,
+            bump_pkgrel,
+            rebase,
+            build_dispatch,
         } => {
-            create_namespace(name, origin_changesets, &args.server_url).await?;
+            let create = buildbtw_poc::CreateBuildNamespace {
+                name,
+                origin_changesets,
+                base_repositories,
+                parent,
+                merge_requests,
+                bump_pkgrel,
+                rebase_origin_changesets: rebase,
+                build_dispatch,
+            };
+            create_namespace(create, &args.server_url).await?;
         }
         Command::Cancel { name } => {
             update_namespace(name, BuildNamespaceStatus::Cancelled, &args.server_url).await?;
@@ -47,9 +74,40 @@ async fn main() -> Result<()> {
         Command::Retry { name } => {
             create_build_iteration(name, &args.server_url).await?;
         }
+        Command::RetryPipeline {
+            name,
+            pkgbase,
+            architecture,
+        } => {
+            update_pipeline(name, pkgbase, architecture, "retry", &args.server_url).await?;
+        }
+        Command::CancelPipeline {
+            name,
+            pkgbase,
+            architecture,
+        } => {
+            update_pipeline(name, pkgbase, architecture, "cancel", &args.server_url).await?;
+        }
         Command::Show { name } => {
             show_namespace(name, &args.server_url).await?;
         }
+        Command::Release {
+            name,
+            target_repository,
+            strategy,
+            yes,
+        } => {
+            release_namespace(name, target_repository, strategy, yes, &args.server_url).await?;
+        }
+        Command::CancelRelease { name } => cancel_release(name, &args.server_url).await?,
+        Command::ReleaseOverride {
+            name,
+            pkgbase,
+            release_override,
+        } => {
+            set_release_override(name, pkgbase, release_override, &args.server_url).await?;
+        }
+        Command::ReleaseLocks => list_release_locks(&args.server_url).await?,
     }
     Ok(())
 }
@@ -75,16 +133,10 @@ async fn update_namespace(
 }
 
 async fn create_namespace(
-    name: Option<String>,
-    origin_changesets: Vec<GitRepoRef>,
+    create: buildbtw_poc::CreateBuildNamespace,
     server_url: &Url,
 ) -> Result<BuildNamespace> {
-    let create = buildbtw_poc::CreateBuildNamespace {
-        name,
-        origin_changesets,
-    };
-
-    let response: BuildNamespace = reqwest::Client::new()
+    let response: CreateNamespaceJson = reqwest::Client::new()
         .post(server_url.join("/namespace")?)
         .json(&create)
         .send()
@@ -97,24 +149,228 @@ async fn create_namespace(
 
     tracing::trace!("{response:#?}");
 
+    let namespace = response.namespace;
     println!(
         r#"Created build namespace "{name}": {namespace_url}"#,
-        name = response.name,
+        name = namespace.name,
         namespace_url = server_url
-            .join(format!("/namespace/{name}", name = response.name.as_str()).as_str())?
+            .join(format!("/namespace/{name}", name = namespace.name.as_str()).as_str())?
     );
-    Ok(response)
+
+    for overlap in response.overlapping_namespaces {
+        let warning = format!(
+            r#"Warning: namespace "{name}" touches the same pkgbases: {pkgbases}"#,
+            name = overlap.namespace_name,
+            pkgbases = overlap.pkgbases.iter().join(", ")
+        );
+        println!("{}", warning.yellow());
+    }
+
+    Ok(namespace)
+}
+
+async fn release_namespace(
+    name: String,
+    target_repository: String,
+    strategy: ReleaseStrategy,
+    skip_confirmation: bool,
+    server_url: &Url,
+) -> Result<()> {
+    let client = reqwest::Client::new();
+    let release: Release = client
+        .post(server_url.join(&format!("/namespace/{name}/release"))?)
+        .json(&CreateRelease {
+            target_repository,
+            strategy,
+        })
+        .send()
+        .await
+        .wrap_err("Failed to send to server")?
+        .map_reqwest_error()
+        .await?
+        .json()
+        .await?;
+
+    print!("{}", release.plan.summary());
+
+    if !skip_confirmation {
+        print!("Add this release to the release queue? [y/N] ");
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Release cancelled");
+            return Ok(());
+        }
+    }
+
+    let ConfirmReleaseJson {
+        release,
+        blocking_locks,
+    } = client
+        .post(server_url.join(&format!(
+            "/namespace/{name}/release/{release_id}/confirm",
+            release_id = release.id
+        ))?)
+        .send()
+        .await
+        .wrap_err("Failed to send to server")?
+        .map_reqwest_error()
+        .await?
+        .json()
+        .await?;
+
+    println!(
+        "Release {} is {:?}, follow its progress at {}",
+        release.id,
+        release.status,
+        server_url.join(&format!("/namespace/{name}"))?
+    );
+    if !blocking_locks.is_empty() {
+        let warning = format!(
+            "Waiting for locks held by other releases: {locks}\nIf they release first, this namespace has to be rebuilt on top of them.",
+            locks = blocking_locks
+                .iter()
+                .map(|lock| format!("{} ({})", lock.pkgbase, lock.namespace_name))
+                .join(", ")
+        );
+        println!("{}", warning.yellow());
+    }
+    Ok(())
+}
+
+async fn cancel_release(name: String, server_url: &Url) -> Result<()> {
+    let release: Release = reqwest::Client::new()
+        .post(server_url.join(&format!("/namespace/{name}/release/cancel"))?)
+        .send()
+        .await
+        .wrap_err("Failed to send to server")?
+        .map_reqwest_error()
+        .await?
+        .json()
+        .await?;
+
+    println!(
+        "Cancelled release {} of namespace {}",
+        release.id,
+        name.bold()
+    );
+    Ok(())
+}
+
+async fn set_release_override(
+    name: String,
+    pkgbase: String,
+    release_override: Option<ReleaseOverride>,
+    server_url: &Url,
+) -> Result<()> {
+    let overrides: BTreeMap<Pkgbase, ReleaseOverride> = reqwest::Client::new()
+        .put(server_url.join(&format!("/namespace/{name}/release-override/{pkgbase}"))?)
+        .json(&SetReleaseOverride { release_override })
+        .send()
+        .await
+        .wrap_err("Failed to send to server")?
+        .map_reqwest_error()
+        .await?
+        .json()
+        .await?;
+
+    println!("Release overrides of namespace {}:", name.bold());
+    for (pkgbase, release_override) in overrides {
+        println!("  {release_override:?} {pkgbase}");
+    }
+    Ok(())
+}
+
+/// Retry or cancel the gitlab pipeline of a package, depending on `action`.
+async fn update_pipeline(
+    name: String,
+    pkgbase: String,
+    architecture: ConcreteArchitecture,
+    action: &str,
+    server_url: &Url,
+) -> Result<()> {
+    let attempts: Vec<PipelineAttempt> = reqwest::Client::new()
+        .post(server_url.join(&format!(
+            "/namespace/{name}/pipeline/{architecture}/{pkgbase}/{action}"
+        ))?)
+        .send()
+        .await
+        .wrap_err("Failed to send to server")?
+        .map_reqwest_error()
+        .await?
+        .json()
+        .await?;
+
+    println!("Pipelines of {} ({architecture}):", pkgbase.bold());
+    for attempt in attempts {
+        let status = attempt
+            .status
+            .map(|status| format!("{status:?}"))
+            .unwrap_or_else(|| "Unknown".to_string());
+        println!("  #{} {status} {}", attempt.generation, attempt.gitlab_url);
+    }
+    Ok(())
+}
+
+async fn list_release_locks(server_url: &Url) -> Result<()> {
+    let locks: Vec<ReleaseLock> = reqwest::Client::new()
+        .get(server_url.join("/release-lock")?)
+        .send()
+        .await
+        .context("Failed to read from server")?
+        .map_reqwest_error()
+        .await?
+        .json()
+        .await?;
+
+    if locks.is_empty() {
+        println!("No pkgbases are locked");
+        return Ok(());
+    }
+
+    let date_format = format_description::parse("[year]-[month]-[day] [hour]:[minute]")?;
+    for lock in locks {
+        println!(
+            "🔒 {} {}/{} {}",
+            lock.acquired_at.format(&date_format)?.dimmed(),
+            lock.target_repository,
+            lock.pkgbase.to_string().bold(),
+            format!("(namespace {})", lock.namespace_name).dimmed(),
+        );
+    }
+
+    Ok(())
 }
 
 async fn create_build_iteration(name: String, server_url: &Url) -> Result<BuildSetIteration> {
-    let response: BuildSetIteration = reqwest::Client::new()
+    let response = reqwest::Client::new()
         .post(server_url.join(&format!("/namespace/{name}/iteration"))?)
         .json(&())
         .send()
         .await
-        .context("Failed to send to server")?
-        .json()
-        .await?;
+        .context("Failed to send to server")?;
+    if response.status() == reqwest::StatusCode::CONFLICT {
+        let body = response.text().await?;
+        let Ok(checks) = serde_json::from_str::<BlockingSrcinfoChecksJson>(&body) else {
+            bail!("Server refused to create an iteration: {body}");
+        };
+        for check in &checks.blocking {
+            let reason = match &check.outcome {
+                None => "not checked yet".to_string(),
+                Some(SrcinfoCheckOutcome::UpToDate) => "up to date".to_string(),
+                Some(SrcinfoCheckOutcome::Stale { .. }) => "stale".to_string(),
+                Some(SrcinfoCheckOutcome::Failed { error }) => format!("check failed: {error}"),
+            };
+            println!(
+                "{}/{}: {reason}",
+                check.pkgbase.to_string().bold(),
+                check.git_ref
+            );
+        }
+        bail!("The .SRCINFO of these origin changesets is stale or hasn't been checked yet");
+    }
+    let response: BuildSetIteration = response.error_for_status()?.json().await?;
 
     tracing::info!("Created iteration: {:#?}", response.id);
     Ok(response)
@@ -172,6 +428,64 @@ async fn show_namespace(name: String, server_url: &Url) -> Result<()> {
         .await?;
 
     println!(r#"Namespace "{name}" ({url})"#);
+    println!(
+        "Base repositories: {}",
+        response.namespace.base_repositories.iter().join(", ")
+    );
+
+    if !response.merge_requests.is_empty() {
+        println!("Merge requests:");
+        for merge_request in &response.merge_requests {
+            println!(
+                "    {:?} {}/{} {}",
+                merge_request.state,
+                merge_request.pkgbase,
+                merge_request.branch_name,
+                merge_request
+                    .web_url
+                    .as_ref()
+                    .map(Url::to_string)
+                    .unwrap_or_default()
+                    .dimmed()
+            );
+        }
+        if response.ready_for_release {
+            println!(
+                "{}",
+                "All merge requests are merged, ready for release".green()
+            );
+        }
+    }
+
+    for rebase in &response.origin_changeset_rebases {
+        let warning = match &rebase.outcome {
+            RebaseOutcome::Conflict { paths } => format!(
+                "Warning: can't rebase {}/{} onto main without conflicts in {}",
+                rebase.pkgbase,
+                rebase.branch_name,
+                paths.join(", ")
+            ),
+            RebaseOutcome::Failed { error } => format!(
+                "Warning: failed to rebase {}/{} onto main: {error}",
+                rebase.pkgbase, rebase.branch_name,
+            ),
+            RebaseOutcome::UpToDate | RebaseOutcome::Rebased { .. } => continue,
+        };
+        println!("{}", warning.yellow());
+    }
+
+    for check in response
+        .srcinfo_checks
+        .iter()
+        .filter(|check| check.is_stale())
+    {
+        let warning = format!(
+            "Warning: the committed .SRCINFO of {} ({}) doesn't match its PKGBUILD",
+            check.pkgbase,
+            &check.commit_hash.as_ref()[..8]
+        );
+        println!("{}", warning.yellow());
+    }
 
     let iteration = match response.architecture_iteration {
         Some(res) => res,
@@ -1,5 +1,11 @@
 use std::net::IpAddr;
 
+use buildbtw_poc::BuildDispatch;
+use buildbtw_poc::git::{GitCredentials, PackagingRemote, is_valid_url_template};
+use buildbtw_poc::signing::{GpgSigner, HttpSigner, SignerBackend};
+use buildbtw_poc::source_info::ConcreteArchitecture;
+use camino::Utf8PathBuf;
+use clap::ValueEnum;
 use clap::{Parser, Subcommand, command};
 use color_eyre::Result;
 use url::Url;
@@ -23,15 +29,46 @@ pub struct Args {
     #[arg(long, env, hide_env_values = true)]
     pub database_url: redact::Secret<String>,
 
+    /// Where to run builds of namespaces that don't choose for themselves.
+    /// Dispatching builds to gitlab requires the gitlab options to be specified.
+    #[arg(long, env, value_enum, default_value = "worker")]
+    pub build_dispatch: BuildDispatch,
+
+    /// Where to run builds for an architecture, overriding `build-dispatch`, e.g. "aarch64=worker".
+    /// Can be given multiple times. Namespaces that choose where to run builds
+    /// for themselves aren't affected.
+    #[arg(
+        long,
+        env,
+        value_delimiter = ',',
+        value_parser(parse_architecture_dispatch)
+    )]
+    pub build_dispatch_for_architecture: Vec<(ConcreteArchitecture, BuildDispatch)>,
+
+    /// URL of the buildbtw worker to send builds to.
+    #[arg(long, env, default_value = "http://0.0.0.0:8090")]
+    pub worker_url: Url,
+
+    /// What to do when the committed .SRCINFO of an origin changeset doesn't match its PKGBUILD.
+    /// Checking requires `makepkg` and `bwrap`.
+    #[arg(long, env, value_enum, default_value = "warn")]
+    pub stale_srcinfo: StaleSrcinfo,
+
     #[command(flatten)]
     pub gitlab: Option<Gitlab>,
+
+    #[command(flatten)]
+    pub sources: Sources,
+
+    #[command(flatten)]
+    pub signing: Signing,
 }
 
 #[derive(Debug, Clone, clap::Args)]
-#[group(requires_all = ["gitlab_token", "gitlab_domain", "gitlab_packages_group", "run_builds_on_gitlab"], multiple = true)]
+#[group(requires_all = ["gitlab_token", "gitlab_domain", "gitlab_packages_group"], multiple = true)]
 pub struct Gitlab {
     /// Used for fetching updates to package source repositories (requires `read_api` scope),
-    /// dispatching builds to gitlab (requires `api` scope, only if `run-builds-on-gitlab` is true).
+    /// dispatching builds to gitlab (requires `api` scope, only if `build-dispatch` is "gitlab").
     /// If set, requires all other gitlab-related options to be specified as well.
     /// If omitted, requires all other gitlab-related options to be omitted as well.
     #[arg(long, env, hide_env_values = true, required = false)]
@@ -48,17 +85,218 @@ pub struct Gitlab {
     #[arg(long, env, required = false)]
     pub gitlab_packages_group: String,
 
-    /// Dispatch builds to gitlab pipelines instead of a buildbtw worker instance.
-    /// Requires gitlab token to be specified.
-    // TODO: make this an enum BuildDispatch {Gitlab, Local} and move it
-    // out of the `Gitlab` struct
-    #[arg(long, env, required = false, default_value = "false")]
-    pub run_builds_on_gitlab: bool,
     /// Update package source CI settings to point to the specified CI configuration file.
     /// Specifying this will result in changes to the settings of all packages in the group defined by `gitlab_packages_group`.
     /// See https://gitlab.archlinux.org/help/ci/pipelines/settings.md#specify-a-custom-cicd-configuration-file
     #[arg(long, env, required = false)]
     pub gitlab_packages_ci_config: Option<String>,
+    /// Signing token of the GitLab webhook sending push, tag push and pipeline events
+    /// to `/gitlab/webhook`, e.g. "whsec_...".
+    /// If set, changes are picked up as soon as GitLab reports them,
+    /// and polling GitLab for changes only happens occasionally to catch missed events.
+    #[arg(long, env, hide_env_values = true, required = false)]
+    pub gitlab_webhook_signing_token: Option<redact::Secret<String>>,
+    /// Report the build status of each namespace as external commit statuses
+    /// on the commits of its origin changesets (requires `api` scope).
+    #[arg(long, env, required = false, default_value = "false")]
+    pub report_commit_statuses_to_gitlab: bool,
+    /// Talk to the gitlab API via plain HTTP instead of HTTPS.
+    /// Only meant for testing against a local stand-in such as `buildbtw_poc::gitlab::mock`.
+    #[arg(long, env, required = false, default_value = "false")]
+    pub gitlab_insecure: bool,
+}
+
+impl Gitlab {
+    /// Base URL of the gitlab instance, e.g. "https://gitlab.archlinux.org".
+    pub fn base_url(&self) -> Result<Url> {
+        let scheme = if self.gitlab_insecure {
+            "http"
+        } else {
+            "https"
+        };
+        Ok(Url::parse(&format!("{scheme}://{}", self.gitlab_domain))?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StaleSrcinfo {
+    /// Don't check .SRCINFO files
+    Off,
+    /// Show a warning on the namespace
+    Warn,
+    /// Don't create iterations or schedule builds until the .SRCINFO was checked and is up to date
+    Block,
+}
+
+/// Parses an architecture and the dispatcher to use for it, e.g. "aarch64=worker"
+fn parse_architecture_dispatch(src: &str) -> Result<(ConcreteArchitecture, BuildDispatch), String> {
+    let (architecture, dispatch) = src
+        .split_once('=')
+        .ok_or_else(|| "must be of the form ARCHITECTURE=DISPATCH".to_string())?;
+    let architecture = architecture
+        .parse()
+        .map_err(|_| format!("unknown architecture {architecture:?}"))?;
+    let dispatch = BuildDispatch::from_str(dispatch, true)?;
+    Ok((architecture, dispatch))
+}
+
+/// Checks whether a source repository URL template contains a placeholder for the package
+fn parse_url_template(src: &str) -> Result<String, String> {
+    if is_valid_url_template(src) {
+        Ok(src.to_string())
+    } else {
+        Err("must contain {pkgbase} or {project}".to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SourceProviderKind {
+    /// Query the gitlab API for projects that changed
+    Gitlab,
+    /// Compare the branches of each remote repository with the local clones, like `git ls-remote`
+    Git,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SourceRepoAuth {
+    /// Don't authenticate
+    None,
+    /// Use the keys of the running SSH agent
+    SshAgent,
+    /// Use the SSH private key given by `source-repo-ssh-key`
+    SshKey,
+    /// Use `source-repo-username` and `source-repo-password`, e.g. an access token
+    Password,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Sources {
+    /// URL of package source repositories, with `{pkgbase}` or `{project}`
+    /// (the gitlab project path of the pkgbase) as placeholder,
+    /// e.g. "https://example.org/packages/{pkgbase}.git" or "file:///srv/mirror/{project}.git".
+    /// Defaults to cloning via SSH from `gitlab-packages-group` on `gitlab-domain`.
+    #[arg(long, env, value_parser(parse_url_template))]
+    pub source_repo_url_template: Option<String>,
+
+    /// How to discover changes to package source repositories.
+    /// Defaults to `gitlab` if the gitlab options are specified, and `git` otherwise.
+    #[arg(long, env, value_enum)]
+    pub source_provider: Option<SourceProviderKind>,
+
+    /// File listing the pkgbases to track with the `git` source provider, one per line.
+    /// Source repositories that were cloned before are always tracked.
+    #[arg(long, env)]
+    pub source_repo_pkgbases_file: Option<Utf8PathBuf>,
+
+    /// How to authenticate when cloning, fetching and pushing package source repositories.
+    #[arg(long, env, value_enum, default_value = "ssh-agent")]
+    pub source_repo_auth: SourceRepoAuth,
+
+    /// Username for `password` authentication.
+    #[arg(long, env, default_value = "git")]
+    pub source_repo_username: String,
+
+    /// Password or access token for `password` authentication,
+    /// or passphrase of the key for `ssh-key` authentication.
+    #[arg(
+        long,
+        env,
+        hide_env_values = true,
+        required_if_eq("source_repo_auth", "password")
+    )]
+    pub source_repo_password: Option<redact::Secret<String>>,
+
+    /// SSH private key for `ssh-key` authentication.
+    #[arg(long, env, required_if_eq("source_repo_auth", "ssh-key"))]
+    pub source_repo_ssh_key: Option<Utf8PathBuf>,
+}
+
+impl Sources {
+    pub fn credentials(&self) -> GitCredentials {
+        match self.source_repo_auth {
+            SourceRepoAuth::None => GitCredentials::None,
+            SourceRepoAuth::SshAgent => GitCredentials::SshAgent,
+            SourceRepoAuth::SshKey => GitCredentials::SshKey {
+                private_key: self.source_repo_ssh_key.clone().unwrap_or_default(),
+                passphrase: self.source_repo_password.clone(),
+            },
+            SourceRepoAuth::Password => GitCredentials::UserPassword {
+                username: self.source_repo_username.clone(),
+                password: self
+                    .source_repo_password
+                    .clone()
+                    .unwrap_or_else(|| redact::Secret::new(String::new())),
+            },
+        }
+    }
+
+    /// Where to clone package source repositories from, if configured.
+    pub fn packaging_remote(&self, gitlab: Option<&Gitlab>) -> Option<PackagingRemote> {
+        let url_template = match (&self.source_repo_url_template, gitlab) {
+            (Some(url_template), _) => url_template.clone(),
+            (None, Some(gitlab)) => format!(
+                "git@{}:{}/{{project}}.git",
+                gitlab.gitlab_domain, gitlab.gitlab_packages_group
+            ),
+            (None, None) => return None,
+        };
+        Some(PackagingRemote::new(url_template, self.credentials()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SigningBackend {
+    /// Don't sign packages and repository databases
+    None,
+    /// Sign using the GnuPG keyring of the user running the server
+    Gpg,
+    /// Send files to a HTTP signing service
+    Http,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Signing {
+    /// How to sign uploaded packages and the repository databases of namespaces.
+    /// Packages are signed after they have been uploaded to the server,
+    /// and before they're added to the repository.
+    #[arg(long, env, value_enum, default_value = "none")]
+    pub signing_backend: SigningBackend,
+
+    /// Key ID or fingerprint to use with the `gpg` signing backend.
+    /// If omitted, the default key of the keyring is used.
+    #[arg(long, env)]
+    pub gpg_signing_key: Option<String>,
+
+    /// GnuPG home directory to use with the `gpg` signing backend.
+    /// If omitted, `~/.gnupg` is used.
+    #[arg(long, env)]
+    pub gpg_homedir: Option<Utf8PathBuf>,
+
+    /// URL of the signing service to use with the `http` signing backend.
+    /// Files are sent in the body of a POST request, and the service is expected to
+    /// respond with a binary detached signature.
+    #[arg(long, env, required_if_eq("signing_backend", "http"))]
+    pub signing_service_url: Option<Url>,
+
+    /// Sent as a bearer token to the signing service.
+    #[arg(long, env, hide_env_values = true)]
+    pub signing_service_token: Option<redact::Secret<String>>,
+}
+
+impl Signing {
+    /// Create the signer selected by the `signing_backend` option, if any.
+    pub fn signer(&self) -> Option<SignerBackend> {
+        match self.signing_backend {
+            SigningBackend::None => None,
+            SigningBackend::Gpg => Some(SignerBackend::Gpg(GpgSigner {
+                key: self.gpg_signing_key.clone(),
+                homedir: self.gpg_homedir.clone(),
+            })),
+            SigningBackend::Http => self.signing_service_url.clone().map(|url| {
+                SignerBackend::Http(HttpSigner::new(url, self.signing_service_token.clone()))
+            }),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Subcommand)]
@@ -0,0 +1,60 @@
+use color_eyre::eyre::{Context, Result};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use buildbtw_poc::{Pkgbase, gitlab::CommitBuildStatus, source_info::ConcreteArchitecture};
+
+/// The status we last reported to gitlab for the commit of `pkgbase`
+/// in the given iteration and architecture, if any.
+pub async fn read(
+    pool: &SqlitePool,
+    iteration_id: Uuid,
+    pkgbase: &Pkgbase,
+    architecture: ConcreteArchitecture,
+) -> Result<Option<CommitBuildStatus>> {
+    let iteration_id = iteration_id.hyphenated();
+    let status = sqlx::query_scalar!(
+        r#"
+        select status as "status: CommitBuildStatus"
+        from gitlab_commit_statuses
+        where build_set_iteration_id = $1 and pkgbase = $2 and architecture = $3
+        "#,
+        iteration_id,
+        pkgbase,
+        architecture
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(status)
+}
+
+pub async fn set(
+    pool: &SqlitePool,
+    iteration_id: Uuid,
+    pkgbase: &Pkgbase,
+    architecture: ConcreteArchitecture,
+    status: CommitBuildStatus,
+) -> Result<()> {
+    let iteration_id = iteration_id.hyphenated();
+    let updated_at = time::OffsetDateTime::now_utc();
+    sqlx::query!(
+        r#"
+        insert into gitlab_commit_statuses
+        (build_set_iteration_id, pkgbase, architecture, status, updated_at)
+        values ($1, $2, $3, $4, $5)
+        on conflict (build_set_iteration_id, pkgbase, architecture)
+        do update set status = $4, updated_at = $5
+        "#,
+        iteration_id,
+        pkgbase,
+        architecture,
+        status,
+        updated_at
+    )
+    .execute(pool)
+    .await
+    .wrap_err("Failed to store gitlab commit status")?;
+
+    Ok(())
+}
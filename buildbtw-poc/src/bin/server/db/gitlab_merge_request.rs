@@ -0,0 +1,138 @@
+use color_eyre::eyre::{Context, Result};
+use sqlx::SqlitePool;
+use url::Url;
+use uuid::Uuid;
+
+use buildbtw_poc::{
+    GitRepoRef, Pkgbase,
+    gitlab::merge_requests::{TrackedMergeRequest, TrackedMergeRequestState},
+};
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct DbGitlabMergeRequest {
+    pub namespace_id: Uuid,
+    pub pkgbase: Pkgbase,
+    pub branch_name: String,
+    pub state: TrackedMergeRequestState,
+    // See `DbGitlabPipeline` for why these are i64
+    pub gitlab_iid: Option<i64>,
+    pub web_url: Option<String>,
+    pub note_id: Option<i64>,
+    /// Summary comment we last posted to the merge request
+    pub reported_summary: Option<String>,
+}
+
+impl DbGitlabMergeRequest {
+    pub fn into_tracked(self) -> Result<TrackedMergeRequest> {
+        Ok(TrackedMergeRequest {
+            pkgbase: self.pkgbase,
+            branch_name: self.branch_name,
+            state: self.state,
+            web_url: self.web_url.as_deref().map(Url::parse).transpose()?,
+        })
+    }
+}
+
+/// Start tracking merge requests for the given origin changesets.
+/// Merge requests that are already tracked are left untouched.
+pub async fn create_requested(
+    pool: &SqlitePool,
+    namespace_id: Uuid,
+    origin_changesets: &[GitRepoRef],
+) -> Result<()> {
+    let namespace_id = namespace_id.hyphenated();
+    for (pkgbase, branch_name) in origin_changesets {
+        sqlx::query!(
+            r#"
+            insert into gitlab_merge_requests
+            (namespace_id, pkgbase, branch_name, state)
+            values ($1, $2, $3, $4)
+            on conflict (namespace_id, pkgbase) do nothing
+            "#,
+            namespace_id,
+            pkgbase,
+            branch_name,
+            TrackedMergeRequestState::Requested,
+        )
+        .execute(pool)
+        .await
+        .wrap_err("Failed to store gitlab merge request")?;
+    }
+
+    Ok(())
+}
+
+pub async fn list_for_namespace(
+    pool: &SqlitePool,
+    namespace_id: Uuid,
+) -> Result<Vec<DbGitlabMergeRequest>> {
+    let namespace_id = namespace_id.hyphenated();
+    sqlx::query_as!(
+        DbGitlabMergeRequest,
+        r#"
+        select
+            namespace_id as "namespace_id: uuid::fmt::Hyphenated",
+            pkgbase as "pkgbase: Pkgbase",
+            branch_name,
+            state as "state: TrackedMergeRequestState",
+            gitlab_iid,
+            web_url,
+            note_id,
+            reported_summary
+        from gitlab_merge_requests
+        where namespace_id = $1
+        order by pkgbase
+        "#,
+        namespace_id
+    )
+    .fetch_all(pool)
+    .await
+    .wrap_err("Failed to read gitlab merge requests from DB")
+}
+
+pub async fn update(pool: &SqlitePool, merge_request: &DbGitlabMergeRequest) -> Result<()> {
+    let namespace_id = merge_request.namespace_id.hyphenated();
+    sqlx::query!(
+        r#"
+        update gitlab_merge_requests
+        set state = $3, gitlab_iid = $4, web_url = $5, note_id = $6, reported_summary = $7
+        where namespace_id = $1 and pkgbase = $2
+        "#,
+        namespace_id,
+        merge_request.pkgbase,
+        merge_request.state,
+        merge_request.gitlab_iid,
+        merge_request.web_url,
+        merge_request.note_id,
+        merge_request.reported_summary,
+    )
+    .execute(pool)
+    .await
+    .wrap_err("Failed to update gitlab merge request")?;
+
+    Ok(())
+}
+
+/// All tracked merge requests with the given gitlab IID, across all projects.
+pub async fn list_by_iid(pool: &SqlitePool, gitlab_iid: i64) -> Result<Vec<DbGitlabMergeRequest>> {
+    sqlx::query_as!(
+        DbGitlabMergeRequest,
+        r#"
+        select
+            namespace_id as "namespace_id: uuid::fmt::Hyphenated",
+            pkgbase as "pkgbase: Pkgbase",
+            branch_name,
+            state as "state: TrackedMergeRequestState",
+            gitlab_iid,
+            web_url,
+            note_id,
+            reported_summary
+        from gitlab_merge_requests
+        where gitlab_iid = $1
+        "#,
+        gitlab_iid
+    )
+    .fetch_all(pool)
+    .await
+    .wrap_err("Failed to read gitlab merge requests from DB")
+}
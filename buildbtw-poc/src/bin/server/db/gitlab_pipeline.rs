@@ -4,7 +4,11 @@ use sqlx::SqlitePool;
 use url::Url;
 use uuid::Uuid;
 
-use buildbtw_poc::{Pkgbase, source_info::ConcreteArchitecture};
+use buildbtw_poc::{
+    Pkgbase,
+    gitlab::{PipelineStatus, pipeline_status::PipelineId},
+    source_info::ConcreteArchitecture,
+};
 
 #[derive(sqlx::FromRow, Serialize)]
 pub struct DbGitlabPipeline {
@@ -23,6 +27,22 @@ pub struct DbGitlabPipeline {
     pub project_gitlab_iid: i64,
     pub gitlab_iid: i64,
     pub gitlab_url: String,
+
+    /// Starts at 1 and is incremented each time the pipeline is retried.
+    pub generation: i64,
+    pub status: Option<PipelineStatus>,
+}
+
+impl DbGitlabPipeline {
+    pub fn pipeline_id(&self) -> Result<PipelineId> {
+        Ok(PipelineId {
+            project_id: self
+                .project_gitlab_iid
+                .try_into()
+                .wrap_err("Invalid project id")?,
+            pipeline_id: self.gitlab_iid.try_into().wrap_err("Invalid pipeline id")?,
+        })
+    }
 }
 
 pub struct CreateDbGitlabPipeline {
@@ -33,6 +53,9 @@ pub struct CreateDbGitlabPipeline {
     pub project_gitlab_iid: i64,
     pub gitlab_iid: i64,
     pub gitlab_url: Url,
+
+    pub generation: i64,
+    pub status: PipelineStatus,
 }
 
 pub async fn create(pool: &SqlitePool, pipeline: CreateDbGitlabPipeline) -> Result<()> {
@@ -42,8 +65,8 @@ pub async fn create(pool: &SqlitePool, pipeline: CreateDbGitlabPipeline) -> Resu
     sqlx::query!(
         r#"
         insert into gitlab_pipelines
-        (id, build_set_iteration_id, pkgbase, architecture, project_gitlab_iid, gitlab_iid, gitlab_url)
-        values ($1, $2, $3, $4, $5, $6, $7)
+        (id, build_set_iteration_id, pkgbase, architecture, project_gitlab_iid, gitlab_iid, gitlab_url, generation, status)
+        values ($1, $2, $3, $4, $5, $6, $7, $8, $9)
         "#,
         id,
         pipeline.build_set_iteration_id,
@@ -52,6 +75,8 @@ pub async fn create(pool: &SqlitePool, pipeline: CreateDbGitlabPipeline) -> Resu
         pipeline.project_gitlab_iid,
         pipeline.gitlab_iid,
         url,
+        pipeline.generation,
+        pipeline.status,
     )
     .execute(pool)
     .await?;
@@ -76,9 +101,13 @@ pub async fn read_by_iteration_and_pkgbase_and_architecture(
             architecture as "architecture: ConcreteArchitecture",
             project_gitlab_iid,
             gitlab_iid,
-            gitlab_url
+            gitlab_url,
+            generation,
+            status as "status: PipelineStatus"
         from gitlab_pipelines
         where build_set_iteration_id = $1 and pkgbase = $2 and architecture = $3
+        order by generation desc
+        limit 1
         "#,
         iteration_id,
         pkgbase,
@@ -88,3 +117,123 @@ pub async fn read_by_iteration_and_pkgbase_and_architecture(
     .await
     .wrap_err("Failed to read gitlab pipeline from DB")
 }
+
+pub async fn read_by_gitlab_iids(
+    pool: &SqlitePool,
+    project_gitlab_iid: i64,
+    gitlab_iid: i64,
+) -> Result<Option<DbGitlabPipeline>> {
+    sqlx::query_as!(
+        DbGitlabPipeline,
+        r#"
+        select
+            id as "id: uuid::fmt::Hyphenated",
+            build_set_iteration_id as "build_set_iteration_id: uuid::fmt::Hyphenated",
+            pkgbase,
+            architecture as "architecture: ConcreteArchitecture",
+            project_gitlab_iid,
+            gitlab_iid,
+            gitlab_url,
+            generation,
+            status as "status: PipelineStatus"
+        from gitlab_pipelines
+        where project_gitlab_iid = $1 and gitlab_iid = $2
+        order by generation desc
+        limit 1
+        "#,
+        project_gitlab_iid,
+        gitlab_iid
+    )
+    .fetch_optional(pool)
+    .await
+    .wrap_err("Failed to read gitlab pipeline from DB")
+}
+
+/// All generations of the pipeline for a build node, oldest first.
+pub async fn list_by_iteration_and_pkgbase_and_architecture(
+    pool: &SqlitePool,
+    iteration_id: Uuid,
+    pkgbase: &Pkgbase,
+    architecture: ConcreteArchitecture,
+) -> Result<Vec<DbGitlabPipeline>> {
+    let iteration_id = iteration_id.as_hyphenated();
+    sqlx::query_as!(
+        DbGitlabPipeline,
+        r#"
+        select
+            id as "id: uuid::fmt::Hyphenated",
+            build_set_iteration_id as "build_set_iteration_id: uuid::fmt::Hyphenated",
+            pkgbase,
+            architecture as "architecture: ConcreteArchitecture",
+            project_gitlab_iid,
+            gitlab_iid,
+            gitlab_url,
+            generation,
+            status as "status: PipelineStatus"
+        from gitlab_pipelines
+        where build_set_iteration_id = $1 and pkgbase = $2 and architecture = $3
+        order by generation
+        "#,
+        iteration_id,
+        pkgbase,
+        architecture
+    )
+    .fetch_all(pool)
+    .await
+    .wrap_err("Failed to list gitlab pipelines from DB")
+}
+
+pub async fn set_status(pool: &SqlitePool, id: Uuid, status: PipelineStatus) -> Result<()> {
+    let id = id.as_hyphenated();
+    sqlx::query!(
+        r#"
+        update gitlab_pipelines
+        set status = $2
+        where id = $1
+        "#,
+        id,
+        status,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Mark the logs of a pipeline generation as being archived.
+/// Returns `false` if they were already claimed by someone else.
+pub async fn claim_logs_archival(pool: &SqlitePool, id: Uuid) -> Result<bool> {
+    let id = id.as_hyphenated();
+    let now = time::OffsetDateTime::now_utc();
+    let result = sqlx::query!(
+        r#"
+        update gitlab_pipelines
+        set logs_archived_at = $2
+        where id = $1 and logs_archived_at is null
+        "#,
+        id,
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Allow archiving the logs of a pipeline generation again,
+/// e.g. after archiving them failed.
+pub async fn release_logs_archival(pool: &SqlitePool, id: Uuid) -> Result<()> {
+    let id = id.as_hyphenated();
+    sqlx::query!(
+        r#"
+        update gitlab_pipelines
+        set logs_archived_at = null
+        where id = $1
+        "#,
+        id,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
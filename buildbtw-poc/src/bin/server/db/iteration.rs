@@ -4,7 +4,9 @@ use color_eyre::Result;
 use sqlx::{SqlitePool, types::Json};
 
 use buildbtw_poc::{
-    BuildSetIteration, GitRepoRef, build_set_graph::BuildSetGraph, iteration::NewIterationReason,
+    BuildSetIteration, GitRepoRef, PackageBuildStatus, Pkgbase,
+    build_set_graph::{self, BuildSetGraph},
+    iteration::NewIterationReason,
     source_info::ConcreteArchitecture,
 };
 
@@ -17,6 +19,7 @@ pub(crate) struct DbBuildSetIteration {
     packages_to_be_built: Json<HashMap<ConcreteArchitecture, BuildSetGraph>>,
     origin_changesets: Json<Vec<GitRepoRef>>,
     create_reason: Json<NewIterationReason>,
+    parent_iteration_id: Option<uuid::fmt::Hyphenated>,
 }
 
 impl From<DbBuildSetIteration> for BuildSetIteration {
@@ -28,6 +31,7 @@ impl From<DbBuildSetIteration> for BuildSetIteration {
             origin_changesets: value.origin_changesets.0,
             create_reason: value.create_reason.0,
             namespace_id: value.namespace_id,
+            parent_iteration_id: value.parent_iteration_id.map(Into::into),
         }
     }
 }
@@ -40,19 +44,23 @@ pub(crate) async fn create(pool: &SqlitePool, iteration: BuildSetIteration) -> R
     let packages_to_be_built = Json(iteration.packages_to_be_built);
     let origin_changesets = Json(iteration.origin_changesets);
     let create_reason = Json(iteration.create_reason);
+    let parent_iteration_id = iteration
+        .parent_iteration_id
+        .map(|parent_iteration_id| parent_iteration_id.hyphenated());
 
     sqlx::query!(
         r#"
         insert into build_set_iterations
-        (id, created_at, namespace_id, packages_to_be_built, origin_changesets, create_reason)
-        values ($1, $2, $3, $4, $5, $6)
+        (id, created_at, namespace_id, packages_to_be_built, origin_changesets, create_reason, parent_iteration_id)
+        values ($1, $2, $3, $4, $5, $6, $7)
         "#,
         id,
         created_at,
         namespace_id,
         packages_to_be_built,
         origin_changesets,
-        create_reason
+        create_reason,
+        parent_iteration_id
     )
     .execute(pool)
     .await?;
@@ -74,7 +82,8 @@ pub(crate) async fn read_newest(
             namespace_id as "namespace_id: uuid::fmt::Hyphenated",
             packages_to_be_built as "packages_to_be_built: Json<HashMap<ConcreteArchitecture, BuildSetGraph>>",
             origin_changesets as "origin_changesets: Json<Vec<GitRepoRef>>",
-            create_reason as "create_reason: Json<NewIterationReason>"
+            create_reason as "create_reason: Json<NewIterationReason>",
+            parent_iteration_id as "parent_iteration_id: uuid::fmt::Hyphenated"
         from build_set_iterations
         where namespace_id = $1
         order by created_at desc
@@ -100,7 +109,8 @@ pub(crate) async fn read(pool: &SqlitePool, iteration_id: uuid::Uuid) -> Result<
             namespace_id as "namespace_id: uuid::fmt::Hyphenated",
             packages_to_be_built as "packages_to_be_built: Json<HashMap<ConcreteArchitecture, BuildSetGraph>>",
             origin_changesets as "origin_changesets: Json<Vec<GitRepoRef>>",
-            create_reason as "create_reason: Json<NewIterationReason>"
+            create_reason as "create_reason: Json<NewIterationReason>",
+            parent_iteration_id as "parent_iteration_id: uuid::fmt::Hyphenated"
         from build_set_iterations
         where id = $1
         order by created_at desc
@@ -125,7 +135,8 @@ pub(crate) async fn list(pool: &SqlitePool) -> Result<Vec<BuildSetIteration>> {
             namespace_id as "namespace_id: uuid::fmt::Hyphenated",
             packages_to_be_built as "packages_to_be_built: Json<HashMap<ConcreteArchitecture, BuildSetGraph>>",
             origin_changesets as "origin_changesets: Json<Vec<GitRepoRef>>",
-            create_reason as "create_reason: Json<NewIterationReason>"
+            create_reason as "create_reason: Json<NewIterationReason>",
+            parent_iteration_id as "parent_iteration_id: uuid::fmt::Hyphenated"
         from build_set_iterations
         order by created_at asc
         "#,
@@ -153,7 +164,8 @@ pub(crate) async fn list_for_namespace(
             namespace_id as "namespace_id: uuid::fmt::Hyphenated",
             packages_to_be_built as "packages_to_be_built: Json<HashMap<ConcreteArchitecture, BuildSetGraph>>",
             origin_changesets as "origin_changesets: Json<Vec<GitRepoRef>>",
-            create_reason as "create_reason: Json<NewIterationReason>"
+            create_reason as "create_reason: Json<NewIterationReason>",
+            parent_iteration_id as "parent_iteration_id: uuid::fmt::Hyphenated"
         from build_set_iterations
         where namespace_id = $1
         order by created_at asc
@@ -169,14 +181,56 @@ pub(crate) async fn list_for_namespace(
     Ok(iterations)
 }
 
-pub(crate) struct BuildSetIterationUpdate {
-    pub(crate) id: uuid::Uuid,
-    pub(crate) packages_to_be_built: HashMap<ConcreteArchitecture, BuildSetGraph>,
-}
+/// Set the status of a build node, based on the graph currently stored in the DB.
+///
+/// Build statuses are changed concurrently by the namespace loop, status polling,
+/// gitlab webhooks, workers and users. The graph is read and written back in a
+/// single write transaction, so none of these changes get lost.
+/// The status is only changed if `can_change` returns true for the node's
+/// current status. Returns whether the status was changed.
+pub(crate) async fn set_build_status(
+    pool: &SqlitePool,
+    iteration_id: uuid::Uuid,
+    architecture: ConcreteArchitecture,
+    pkgbase: &Pkgbase,
+    status: PackageBuildStatus,
+    can_change: impl FnOnce(PackageBuildStatus) -> bool,
+) -> Result<bool> {
+    let iteration_id = iteration_id.as_hyphenated();
+    // Take the write lock right away, so no one can write
+    // in between reading and writing the graph.
+    let mut transaction = pool.begin_with("begin immediate").await?;
 
-pub(crate) async fn update(pool: &SqlitePool, iteration: BuildSetIterationUpdate) -> Result<()> {
-    let iteration_id = iteration.id.as_hyphenated();
-    let packages_to_be_built = Json(iteration.packages_to_be_built);
+    let Json(mut packages_to_be_built) = sqlx::query_scalar!(
+        r#"
+        select packages_to_be_built as "packages_to_be_built: Json<HashMap<ConcreteArchitecture, BuildSetGraph>>"
+        from build_set_iterations
+        where id = $1
+        "#,
+        iteration_id,
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    let Some(graph) = packages_to_be_built.remove(&architecture) else {
+        return Ok(false);
+    };
+    let Some(current_status) = graph
+        .node_weights()
+        .find(|node| &node.pkgbase == pkgbase)
+        .map(|node| node.status)
+    else {
+        return Ok(false);
+    };
+    if current_status == status || !can_change(current_status) {
+        return Ok(false);
+    }
+
+    packages_to_be_built.insert(
+        architecture,
+        build_set_graph::set_build_status(graph, pkgbase, status),
+    );
+    let packages_to_be_built = Json(packages_to_be_built);
     sqlx::query!(
         r#"
         update build_set_iterations
@@ -186,8 +240,9 @@ pub(crate) async fn update(pool: &SqlitePool, iteration: BuildSetIterationUpdate
         iteration_id,
         packages_to_be_built,
     )
-    .execute(pool)
+    .execute(&mut *transaction)
     .await?;
+    transaction.commit().await?;
 
-    Ok(())
+    Ok(true)
 }
@@ -7,10 +7,19 @@ use sqlx::{
     sqlite::{SqliteConnectOptions, SqlitePoolOptions},
 };
 
+pub mod gitlab_commit_status;
+pub mod gitlab_merge_request;
 pub mod gitlab_pipeline;
 pub mod global_state;
 pub mod iteration;
 pub mod namespace;
+pub mod origin_changeset_rebase;
+pub mod package_signature;
+pub mod release;
+pub mod release_lock;
+pub mod release_override;
+pub mod retired_source_repo;
+pub mod srcinfo_check;
 
 static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
 
@@ -1,13 +1,21 @@
 use color_eyre::Result;
 use sqlx::{SqlitePool, types::Json};
 
-use buildbtw_poc::{BuildNamespace, BuildNamespaceStatus, GitRepoRef, UpdateBuildNamespace};
+use buildbtw_poc::{
+    BuildDispatch, BuildNamespace, BuildNamespaceStatus, GitRepoRef, UpdateBuildNamespace,
+    build_set_graph, iteration::ParentNamespaceContext, pacman_conf::BaseRepository,
+};
 
 use crate::response_error::{MapSqlxError, ResponseResult};
 
 pub struct CreateDbBuildNamespace {
     pub name: String,
     pub origin_changesets: Vec<GitRepoRef>,
+    pub base_repositories: Vec<BaseRepository>,
+    pub parent_namespace_id: Option<uuid::Uuid>,
+    pub bump_pkgrel: bool,
+    pub rebase_origin_changesets: bool,
+    pub build_dispatch: Option<BuildDispatch>,
 }
 
 pub(crate) async fn create(
@@ -17,23 +25,37 @@ pub(crate) async fn create(
     let created_at = time::OffsetDateTime::now_utc();
     let id = uuid::Uuid::new_v4().hyphenated();
     let origin_changesets = sqlx::types::Json(create.origin_changesets);
+    let base_repositories = sqlx::types::Json(create.base_repositories);
+    let parent_namespace_id = create
+        .parent_namespace_id
+        .map(|parent_namespace_id| parent_namespace_id.hyphenated());
     let namespace = sqlx::query_as!(
         DbBuildNamespace,
         r#"
         insert into build_namespaces
-        (id, name, status, origin_changesets, created_at)
-        values ($1, $2, $3, $4, $5)
+        (id, name, status, origin_changesets, base_repositories, parent_namespace_id, bump_pkgrel, rebase_origin_changesets, build_dispatch, created_at)
+        values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
         returning
             id as "id: uuid::fmt::Hyphenated",
             name,
             status as "status: DbBuildNamespaceStatus",
             origin_changesets as "origin_changesets: Json<Vec<GitRepoRef>>",
+            base_repositories as "base_repositories: Json<Vec<BaseRepository>>",
+            parent_namespace_id as "parent_namespace_id: uuid::fmt::Hyphenated",
+            bump_pkgrel as "bump_pkgrel: bool",
+            rebase_origin_changesets as "rebase_origin_changesets: bool",
+            build_dispatch as "build_dispatch: BuildDispatch",
             created_at as "created_at: time::OffsetDateTime"
         "#,
         id,
         create.name,
         DbBuildNamespaceStatus::Active,
         origin_changesets,
+        base_repositories,
+        parent_namespace_id,
+        create.bump_pkgrel,
+        create.rebase_origin_changesets,
+        create.build_dispatch,
         created_at
     )
     .fetch_one(pool)
@@ -73,6 +95,11 @@ pub(crate) struct DbBuildNamespace {
     name: String,
     status: DbBuildNamespaceStatus,
     origin_changesets: Json<Vec<GitRepoRef>>,
+    base_repositories: Json<Vec<BaseRepository>>,
+    parent_namespace_id: Option<uuid::fmt::Hyphenated>,
+    bump_pkgrel: bool,
+    rebase_origin_changesets: bool,
+    build_dispatch: Option<BuildDispatch>,
     created_at: time::OffsetDateTime,
 }
 
@@ -83,6 +110,11 @@ impl From<DbBuildNamespace> for BuildNamespace {
             name: value.name,
             status: value.status.into(),
             current_origin_changesets: value.origin_changesets.0,
+            base_repositories: value.base_repositories.0,
+            parent_id: value.parent_namespace_id.map(Into::into),
+            bump_pkgrel: value.bump_pkgrel,
+            rebase_origin_changesets: value.rebase_origin_changesets,
+            build_dispatch: value.build_dispatch,
             created_at: value.created_at,
         }
     }
@@ -98,6 +130,11 @@ pub(crate) async fn read(id: uuid::Uuid, pool: &SqlitePool) -> Result<BuildNames
             name,
             status as "status: DbBuildNamespaceStatus",
             origin_changesets as "origin_changesets: Json<Vec<GitRepoRef>>",
+            base_repositories as "base_repositories: Json<Vec<BaseRepository>>",
+            parent_namespace_id as "parent_namespace_id: uuid::fmt::Hyphenated",
+            bump_pkgrel as "bump_pkgrel: bool",
+            rebase_origin_changesets as "rebase_origin_changesets: bool",
+            build_dispatch as "build_dispatch: BuildDispatch",
             created_at as "created_at: time::OffsetDateTime"
         from build_namespaces
         where id = $1
@@ -120,6 +157,11 @@ pub(crate) async fn read_by_name(name: &str, pool: &SqlitePool) -> Result<BuildN
             name,
             status as "status: DbBuildNamespaceStatus",
             origin_changesets as "origin_changesets: Json<Vec<GitRepoRef>>",
+            base_repositories as "base_repositories: Json<Vec<BaseRepository>>",
+            parent_namespace_id as "parent_namespace_id: uuid::fmt::Hyphenated",
+            bump_pkgrel as "bump_pkgrel: bool",
+            rebase_origin_changesets as "rebase_origin_changesets: bool",
+            build_dispatch as "build_dispatch: BuildDispatch",
             created_at as "created_at: time::OffsetDateTime"
         from build_namespaces
         where name = $1
@@ -142,6 +184,11 @@ pub(crate) async fn read_latest(pool: &SqlitePool) -> Result<BuildNamespace> {
             name,
             status as "status: DbBuildNamespaceStatus",
             origin_changesets as "origin_changesets: Json<Vec<GitRepoRef>>",
+            base_repositories as "base_repositories: Json<Vec<BaseRepository>>",
+            parent_namespace_id as "parent_namespace_id: uuid::fmt::Hyphenated",
+            bump_pkgrel as "bump_pkgrel: bool",
+            rebase_origin_changesets as "rebase_origin_changesets: bool",
+            build_dispatch as "build_dispatch: BuildDispatch",
             created_at as "created_at: time::OffsetDateTime"
         from build_namespaces
         order by created_at desc
@@ -171,6 +218,11 @@ pub(crate) async fn update(
             name,
             status as "status: DbBuildNamespaceStatus",
             origin_changesets as "origin_changesets: Json<Vec<GitRepoRef>>",
+            base_repositories as "base_repositories: Json<Vec<BaseRepository>>",
+            parent_namespace_id as "parent_namespace_id: uuid::fmt::Hyphenated",
+            bump_pkgrel as "bump_pkgrel: bool",
+            rebase_origin_changesets as "rebase_origin_changesets: bool",
+            build_dispatch as "build_dispatch: BuildDispatch",
             created_at as "created_at: time::OffsetDateTime"
         "#,
         name,
@@ -191,6 +243,11 @@ pub(crate) async fn list(pool: &SqlitePool) -> Result<Vec<BuildNamespace>> {
             name,
             status as "status: DbBuildNamespaceStatus",
             origin_changesets as "origin_changesets: Json<Vec<GitRepoRef>>",
+            base_repositories as "base_repositories: Json<Vec<BaseRepository>>",
+            parent_namespace_id as "parent_namespace_id: uuid::fmt::Hyphenated",
+            bump_pkgrel as "bump_pkgrel: bool",
+            rebase_origin_changesets as "rebase_origin_changesets: bool",
+            build_dispatch as "build_dispatch: BuildDispatch",
             created_at as "created_at: time::OffsetDateTime"
         from build_namespaces
         "#,
@@ -217,6 +274,11 @@ pub(crate) async fn list_by_status(
             name,
             status as "status: DbBuildNamespaceStatus",
             origin_changesets as "origin_changesets: Json<Vec<GitRepoRef>>",
+            base_repositories as "base_repositories: Json<Vec<BaseRepository>>",
+            parent_namespace_id as "parent_namespace_id: uuid::fmt::Hyphenated",
+            bump_pkgrel as "bump_pkgrel: bool",
+            rebase_origin_changesets as "rebase_origin_changesets: bool",
+            build_dispatch as "build_dispatch: BuildDispatch",
             created_at as "created_at: time::OffsetDateTime"
         from build_namespaces
         where status = $1
@@ -231,3 +293,97 @@ pub(crate) async fn list_by_status(
 
     Ok(namespaces)
 }
+
+/// Record whether all tracked merge requests of the namespace were merged.
+/// Keeps the time the namespace first became ready.
+pub(crate) async fn set_ready_for_release(
+    pool: &SqlitePool,
+    id: uuid::Uuid,
+    ready: bool,
+) -> Result<()> {
+    let id = id.hyphenated();
+    let now = time::OffsetDateTime::now_utc();
+    sqlx::query!(
+        r#"
+        update build_namespaces
+        set ready_for_release_at = case when $2 then coalesce(ready_for_release_at, $3) else null end
+        where id = $1
+        "#,
+        id,
+        ready,
+        now,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub(crate) async fn read_ready_for_release_at(
+    pool: &SqlitePool,
+    id: uuid::Uuid,
+) -> Result<Option<time::OffsetDateTime>> {
+    let id = id.hyphenated();
+    let ready_for_release_at = sqlx::query_scalar!(
+        r#"
+        select ready_for_release_at as "ready_for_release_at: time::OffsetDateTime"
+        from build_namespaces
+        where id = $1
+        "#,
+        id,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(ready_for_release_at)
+}
+
+/// Gather what's needed from the parent namespaces to calculate
+/// a new iteration for the given namespace.
+/// Children follow the newest parent iteration in which all packages have been built,
+/// so they never build against a half-built parent repository.
+/// Returns `None` if the namespace has no parent, or the parent
+/// doesn't have a fully built iteration yet.
+pub(crate) async fn read_parent_context(
+    pool: &SqlitePool,
+    namespace: &BuildNamespace,
+) -> Result<Option<ParentNamespaceContext>> {
+    let Some(parent_id) = namespace.parent_id else {
+        return Ok(None);
+    };
+    let parent_iterations = super::iteration::list_for_namespace(pool, parent_id).await?;
+    let Some(parent_iteration) = parent_iterations.into_iter().rev().find(|iteration| {
+        iteration
+            .packages_to_be_built
+            .values()
+            .all(build_set_graph::is_fully_built)
+    }) else {
+        return Ok(None);
+    };
+
+    // Walk up the chain of iterations the parent was built on,
+    // so the changesets match the packages in the parent's repository.
+    // Closer ancestors take precedence.
+    let mut origin_changesets: Vec<GitRepoRef> = Vec::new();
+    let parent_iteration_id = parent_iteration.id;
+    let mut ancestor_iteration = Some(parent_iteration);
+    while let Some(iteration) = ancestor_iteration {
+        for changeset in iteration.origin_changesets {
+            if !origin_changesets
+                .iter()
+                .any(|(pkgbase, _)| pkgbase == &changeset.0)
+            {
+                origin_changesets.push(changeset);
+            }
+        }
+        ancestor_iteration = match iteration.parent_iteration_id {
+            Some(id) => Some(super::iteration::read(pool, id).await?),
+            None => None,
+        };
+    }
+
+    Ok(Some(ParentNamespaceContext {
+        iteration_id: parent_iteration_id,
+        origin_changesets,
+    }))
+}
@@ -0,0 +1,102 @@
+use color_eyre::eyre::{Context, Result};
+use sqlx::{SqlitePool, types::Json};
+use uuid::Uuid;
+
+use buildbtw_poc::{
+    CommitHash, Pkgbase,
+    rebase::{OriginChangesetRebase, RebaseOutcome},
+};
+
+#[derive(sqlx::FromRow)]
+struct DbOriginChangesetRebase {
+    pkgbase: Pkgbase,
+    branch_name: String,
+    onto_commit_hash: CommitHash,
+    outcome: Json<RebaseOutcome>,
+    updated_at: time::OffsetDateTime,
+}
+
+impl From<DbOriginChangesetRebase> for OriginChangesetRebase {
+    fn from(value: DbOriginChangesetRebase) -> Self {
+        OriginChangesetRebase {
+            pkgbase: value.pkgbase,
+            branch_name: value.branch_name,
+            onto_commit_hash: value.onto_commit_hash,
+            outcome: value.outcome.0,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+pub async fn list_for_namespace(
+    pool: &SqlitePool,
+    namespace_id: Uuid,
+) -> Result<Vec<OriginChangesetRebase>> {
+    let namespace_id = namespace_id.hyphenated();
+    let rebases = sqlx::query_as!(
+        DbOriginChangesetRebase,
+        r#"
+        select
+            pkgbase as "pkgbase: Pkgbase",
+            branch_name,
+            onto_commit_hash as "onto_commit_hash: CommitHash",
+            outcome as "outcome: Json<RebaseOutcome>",
+            updated_at as "updated_at: time::OffsetDateTime"
+        from origin_changeset_rebases
+        where namespace_id = $1
+        order by pkgbase
+        "#,
+        namespace_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rebases.into_iter().map(Into::into).collect())
+}
+
+/// Store the most recent rebase of an origin changeset branch.
+pub async fn set(
+    pool: &SqlitePool,
+    namespace_id: Uuid,
+    rebase: &OriginChangesetRebase,
+) -> Result<()> {
+    let namespace_id = namespace_id.hyphenated();
+    let outcome = Json(&rebase.outcome);
+    sqlx::query!(
+        r#"
+        insert into origin_changeset_rebases
+        (namespace_id, pkgbase, branch_name, onto_commit_hash, outcome, updated_at)
+        values ($1, $2, $3, $4, $5, $6)
+        on conflict (namespace_id, pkgbase)
+        do update set branch_name = $3, onto_commit_hash = $4, outcome = $5, updated_at = $6
+        "#,
+        namespace_id,
+        rebase.pkgbase,
+        rebase.branch_name,
+        rebase.onto_commit_hash,
+        outcome,
+        rebase.updated_at,
+    )
+    .execute(pool)
+    .await
+    .wrap_err("Failed to store origin changeset rebase")?;
+
+    Ok(())
+}
+
+pub async fn delete(pool: &SqlitePool, namespace_id: Uuid, pkgbase: &Pkgbase) -> Result<()> {
+    let namespace_id = namespace_id.hyphenated();
+    sqlx::query!(
+        r#"
+        delete from origin_changeset_rebases
+        where namespace_id = $1 and pkgbase = $2
+        "#,
+        namespace_id,
+        pkgbase,
+    )
+    .execute(pool)
+    .await
+    .wrap_err("Failed to delete origin changeset rebase")?;
+
+    Ok(())
+}
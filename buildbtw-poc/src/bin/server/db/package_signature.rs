@@ -0,0 +1,80 @@
+use color_eyre::eyre::{Context, Result};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use buildbtw_poc::{Pkgbase, signing::SigningStatus, source_info::ConcreteArchitecture};
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct DbPackageSignature {
+    pub pkgbase: Pkgbase,
+    pub architecture: ConcreteArchitecture,
+    pub file_name: String,
+    pub status: SigningStatus,
+    pub updated_at: time::OffsetDateTime,
+}
+
+pub struct SetDbPackageSignatureStatus {
+    pub build_set_iteration_id: Uuid,
+    pub pkgbase: Pkgbase,
+    pub architecture: ConcreteArchitecture,
+    pub file_name: String,
+    pub status: SigningStatus,
+}
+
+/// Record the signing status of a package file, creating the
+/// entry if it doesn't exist yet.
+pub async fn set_status(pool: &SqlitePool, signature: SetDbPackageSignatureStatus) -> Result<()> {
+    let id = uuid::Uuid::new_v4().hyphenated();
+    let iteration_id = signature.build_set_iteration_id.hyphenated();
+    let updated_at = time::OffsetDateTime::now_utc();
+
+    sqlx::query!(
+        r#"
+        insert into package_signatures
+        (id, build_set_iteration_id, pkgbase, architecture, file_name, status, updated_at)
+        values ($1, $2, $3, $4, $5, $6, $7)
+        on conflict (build_set_iteration_id, architecture, file_name)
+        do update set status = excluded.status, updated_at = excluded.updated_at
+        "#,
+        id,
+        iteration_id,
+        signature.pkgbase,
+        signature.architecture,
+        signature.file_name,
+        signature.status,
+        updated_at,
+    )
+    .execute(pool)
+    .await
+    .wrap_err("Failed to store package signing status")?;
+
+    Ok(())
+}
+
+pub async fn list_by_iteration_and_architecture(
+    pool: &SqlitePool,
+    iteration_id: Uuid,
+    architecture: ConcreteArchitecture,
+) -> Result<Vec<DbPackageSignature>> {
+    let iteration_id = iteration_id.as_hyphenated();
+    sqlx::query_as!(
+        DbPackageSignature,
+        r#"
+        select
+            pkgbase,
+            architecture as "architecture: ConcreteArchitecture",
+            file_name,
+            status as "status: SigningStatus",
+            updated_at as "updated_at: time::OffsetDateTime"
+        from package_signatures
+        where build_set_iteration_id = $1 and architecture = $2
+        order by file_name asc
+        "#,
+        iteration_id,
+        architecture
+    )
+    .fetch_all(pool)
+    .await
+    .wrap_err("Failed to read package signing statuses from DB")
+}
@@ -0,0 +1,227 @@
+use color_eyre::eyre::{Context, Result};
+use sqlx::{SqliteExecutor, SqlitePool, types::Json};
+use uuid::Uuid;
+
+use buildbtw_poc::release::{Release, ReleasePlan, ReleaseStatus};
+
+#[derive(sqlx::FromRow)]
+struct DbRelease {
+    id: uuid::fmt::Hyphenated,
+    namespace_id: uuid::fmt::Hyphenated,
+    plan: Json<ReleasePlan>,
+    status: ReleaseStatus,
+    error: Option<String>,
+    created_at: time::OffsetDateTime,
+    updated_at: time::OffsetDateTime,
+}
+
+impl From<DbRelease> for Release {
+    fn from(value: DbRelease) -> Self {
+        Release {
+            id: value.id.into(),
+            namespace_id: value.namespace_id.into(),
+            plan: value.plan.0,
+            status: value.status,
+            error: value.error,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+pub async fn create(pool: &SqlitePool, namespace_id: Uuid, plan: ReleasePlan) -> Result<Release> {
+    let id = Uuid::new_v4().hyphenated();
+    let namespace_id = namespace_id.hyphenated();
+    let iteration_id = plan.iteration_id.hyphenated();
+    let target_repository = plan.target_repository.clone();
+    let plan = Json(plan);
+    let now = time::OffsetDateTime::now_utc();
+
+    let release = sqlx::query_as!(
+        DbRelease,
+        r#"
+        insert into releases
+        (id, namespace_id, build_set_iteration_id, target_repository, plan, status, created_at, updated_at)
+        values ($1, $2, $3, $4, $5, $6, $7, $7)
+        returning
+            id as "id: uuid::fmt::Hyphenated",
+            namespace_id as "namespace_id: uuid::fmt::Hyphenated",
+            plan as "plan: Json<ReleasePlan>",
+            status as "status: ReleaseStatus",
+            error,
+            created_at as "created_at: time::OffsetDateTime",
+            updated_at as "updated_at: time::OffsetDateTime"
+        "#,
+        id,
+        namespace_id,
+        iteration_id,
+        target_repository,
+        plan,
+        ReleaseStatus::Planned,
+        now,
+    )
+    .fetch_one(pool)
+    .await
+    .wrap_err("Failed to store release")?;
+
+    Ok(release.into())
+}
+
+pub async fn read(pool: &SqlitePool, id: Uuid) -> Result<Release> {
+    let id = id.hyphenated();
+    let release = sqlx::query_as!(
+        DbRelease,
+        r#"
+        select
+            id as "id: uuid::fmt::Hyphenated",
+            namespace_id as "namespace_id: uuid::fmt::Hyphenated",
+            plan as "plan: Json<ReleasePlan>",
+            status as "status: ReleaseStatus",
+            error,
+            created_at as "created_at: time::OffsetDateTime",
+            updated_at as "updated_at: time::OffsetDateTime"
+        from releases
+        where id = $1
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(release.into())
+}
+
+pub async fn read_newest_for_namespace(
+    pool: &SqlitePool,
+    namespace_id: Uuid,
+) -> Result<Option<Release>> {
+    let namespace_id = namespace_id.hyphenated();
+    let release = sqlx::query_as!(
+        DbRelease,
+        r#"
+        select
+            id as "id: uuid::fmt::Hyphenated",
+            namespace_id as "namespace_id: uuid::fmt::Hyphenated",
+            plan as "plan: Json<ReleasePlan>",
+            status as "status: ReleaseStatus",
+            error,
+            created_at as "created_at: time::OffsetDateTime",
+            updated_at as "updated_at: time::OffsetDateTime"
+        from releases
+        where namespace_id = $1
+        order by created_at desc
+        limit 1
+        "#,
+        namespace_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(release.map(Release::from))
+}
+
+/// Releases in the given status, oldest first.
+pub async fn list_by_status(pool: &SqlitePool, status: ReleaseStatus) -> Result<Vec<Release>> {
+    let releases = sqlx::query_as!(
+        DbRelease,
+        r#"
+        select
+            id as "id: uuid::fmt::Hyphenated",
+            namespace_id as "namespace_id: uuid::fmt::Hyphenated",
+            plan as "plan: Json<ReleasePlan>",
+            status as "status: ReleaseStatus",
+            error,
+            created_at as "created_at: time::OffsetDateTime",
+            updated_at as "updated_at: time::OffsetDateTime"
+        from releases
+        where status = $1
+        order by created_at asc
+        "#,
+        status
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(Release::from)
+    .collect();
+
+    Ok(releases)
+}
+
+/// Releases into the target repository that finished after `since`.
+pub async fn list_released_since(
+    pool: &SqlitePool,
+    target_repository: &str,
+    since: time::OffsetDateTime,
+) -> Result<Vec<Release>> {
+    let releases = sqlx::query_as!(
+        DbRelease,
+        r#"
+        select
+            id as "id: uuid::fmt::Hyphenated",
+            namespace_id as "namespace_id: uuid::fmt::Hyphenated",
+            plan as "plan: Json<ReleasePlan>",
+            status as "status: ReleaseStatus",
+            error,
+            created_at as "created_at: time::OffsetDateTime",
+            updated_at as "updated_at: time::OffsetDateTime"
+        from releases
+        where target_repository = $1
+        and status = $2
+        and updated_at > $3
+        order by updated_at asc
+        "#,
+        target_repository,
+        ReleaseStatus::Released,
+        since
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(Release::from)
+    .collect();
+
+    Ok(releases)
+}
+
+pub async fn set_status(
+    executor: impl SqliteExecutor<'_>,
+    id: Uuid,
+    status: ReleaseStatus,
+    error: Option<String>,
+) -> Result<()> {
+    let id = id.hyphenated();
+    let updated_at = time::OffsetDateTime::now_utc();
+    sqlx::query!(
+        r#"
+        update releases
+        set status = $2, error = $3, updated_at = $4
+        where id = $1
+        "#,
+        id,
+        status,
+        error,
+        updated_at
+    )
+    .execute(executor)
+    .await
+    .wrap_err("Failed to update release status")?;
+
+    Ok(())
+}
+
+/// Move the release into a final status and release all its locks at once,
+/// so a finished release can never keep other releases waiting.
+pub async fn finish(
+    pool: &SqlitePool,
+    id: Uuid,
+    status: ReleaseStatus,
+    error: Option<String>,
+) -> Result<()> {
+    let mut transaction = pool.begin().await?;
+    set_status(&mut *transaction, id, status, error).await?;
+    super::release_lock::release_all(&mut *transaction, id).await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
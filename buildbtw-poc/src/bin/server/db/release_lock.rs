@@ -0,0 +1,167 @@
+use color_eyre::eyre::{Context, Result, bail};
+use sqlx::{Sqlite, SqliteExecutor, SqlitePool, Transaction, types::Json};
+use uuid::Uuid;
+
+use buildbtw_poc::{
+    Pkgbase,
+    release::{Release, ReleaseLock},
+};
+
+#[derive(sqlx::FromRow)]
+struct DbReleaseLock {
+    target_repository: String,
+    pkgbase: Pkgbase,
+    release_id: uuid::fmt::Hyphenated,
+    namespace_name: String,
+    acquired_at: time::OffsetDateTime,
+}
+
+impl From<DbReleaseLock> for ReleaseLock {
+    fn from(value: DbReleaseLock) -> Self {
+        ReleaseLock {
+            target_repository: value.target_repository,
+            pkgbase: value.pkgbase,
+            release_id: value.release_id.into(),
+            namespace_name: value.namespace_name,
+            acquired_at: value.acquired_at,
+        }
+    }
+}
+
+/// Try to lock all pkgbases of the release in its target repository.
+/// Either all locks are acquired, or none are.
+///
+/// Returns the locks held by other releases that prevent this.
+/// Locks already held by this release are kept, so this can be called repeatedly.
+pub async fn try_acquire(pool: &SqlitePool, release: &Release) -> Result<Vec<ReleaseLock>> {
+    // Take the write lock right away so no other release can acquire
+    // locks between our check and our inserts.
+    let mut transaction = pool.begin_with("begin immediate").await?;
+    let blocking_locks = try_acquire_in_transaction(&mut transaction, release).await?;
+    transaction.commit().await?;
+
+    Ok(blocking_locks)
+}
+
+/// Like [`try_acquire`], but as part of a transaction opened by the caller
+/// with `begin immediate`. The locks are only held once the caller commits.
+pub async fn try_acquire_in_transaction(
+    transaction: &mut Transaction<'_, Sqlite>,
+    release: &Release,
+) -> Result<Vec<ReleaseLock>> {
+    let blocking_locks = list_blocking(&mut **transaction, release).await?;
+    if !blocking_locks.is_empty() {
+        return Ok(blocking_locks);
+    }
+
+    let release_id = release.id.hyphenated();
+    let acquired_at = time::OffsetDateTime::now_utc();
+    for pkgbase in release.plan.pkgbases() {
+        // Keep locks this release already holds, but never take over another release's lock.
+        let result = sqlx::query!(
+            r#"
+            insert into release_locks
+            (target_repository, pkgbase, release_id, acquired_at)
+            values ($1, $2, $3, $4)
+            on conflict (target_repository, pkgbase) do update
+            set acquired_at = release_locks.acquired_at
+            where release_locks.release_id = excluded.release_id
+            "#,
+            release.plan.target_repository,
+            pkgbase,
+            release_id,
+            acquired_at,
+        )
+        .execute(&mut **transaction)
+        .await
+        .wrap_err("Failed to store release lock")?;
+        if result.rows_affected() != 1 {
+            bail!(
+                "Lock on {pkgbase} in {} is held by another release",
+                release.plan.target_repository
+            );
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+/// Locks held by other releases on pkgbases of the given release.
+pub async fn list_blocking(
+    executor: impl SqliteExecutor<'_>,
+    release: &Release,
+) -> Result<Vec<ReleaseLock>> {
+    let release_id = release.id.hyphenated();
+    let pkgbases = Json(release.plan.pkgbases());
+    let locks = sqlx::query_as!(
+        DbReleaseLock,
+        r#"
+        select
+            release_locks.target_repository,
+            release_locks.pkgbase as "pkgbase: Pkgbase",
+            release_locks.release_id as "release_id: uuid::fmt::Hyphenated",
+            build_namespaces.name as namespace_name,
+            release_locks.acquired_at as "acquired_at: time::OffsetDateTime"
+        from release_locks
+        join releases on releases.id = release_locks.release_id
+        join build_namespaces on build_namespaces.id = releases.namespace_id
+        where release_locks.target_repository = $1
+        and release_locks.release_id != $2
+        and release_locks.pkgbase in (select value from json_each($3))
+        order by release_locks.acquired_at asc, release_locks.pkgbase asc
+        "#,
+        release.plan.target_repository,
+        release_id,
+        pkgbases,
+    )
+    .fetch_all(executor)
+    .await?
+    .into_iter()
+    .map(ReleaseLock::from)
+    .collect();
+
+    Ok(locks)
+}
+
+/// All locks currently held, oldest first.
+pub async fn list(pool: &SqlitePool) -> Result<Vec<ReleaseLock>> {
+    let locks = sqlx::query_as!(
+        DbReleaseLock,
+        r#"
+        select
+            release_locks.target_repository,
+            release_locks.pkgbase as "pkgbase: Pkgbase",
+            release_locks.release_id as "release_id: uuid::fmt::Hyphenated",
+            build_namespaces.name as namespace_name,
+            release_locks.acquired_at as "acquired_at: time::OffsetDateTime"
+        from release_locks
+        join releases on releases.id = release_locks.release_id
+        join build_namespaces on build_namespaces.id = releases.namespace_id
+        order by release_locks.acquired_at asc, release_locks.pkgbase asc
+        "#,
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(ReleaseLock::from)
+    .collect();
+
+    Ok(locks)
+}
+
+/// Release all locks held by the given release.
+pub async fn release_all(executor: impl SqliteExecutor<'_>, release_id: Uuid) -> Result<()> {
+    let release_id = release_id.hyphenated();
+    sqlx::query!(
+        r#"
+        delete from release_locks
+        where release_id = $1
+        "#,
+        release_id
+    )
+    .execute(executor)
+    .await
+    .wrap_err("Failed to release locks")?;
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::{Context, Result};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use buildbtw_poc::{Pkgbase, release_selection::ReleaseOverride};
+
+pub async fn list_for_namespace(
+    pool: &SqlitePool,
+    namespace_id: Uuid,
+) -> Result<BTreeMap<Pkgbase, ReleaseOverride>> {
+    let namespace_id = namespace_id.hyphenated();
+    let overrides = sqlx::query!(
+        r#"
+        select
+            pkgbase as "pkgbase: Pkgbase",
+            release_override as "release_override: ReleaseOverride"
+        from release_overrides
+        where namespace_id = $1
+        "#,
+        namespace_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.pkgbase, row.release_override))
+    .collect();
+
+    Ok(overrides)
+}
+
+/// Set or, if `release_override` is `None`, remove the override for a pkgbase.
+pub async fn set(
+    pool: &SqlitePool,
+    namespace_id: Uuid,
+    pkgbase: &Pkgbase,
+    release_override: Option<ReleaseOverride>,
+) -> Result<()> {
+    let namespace_id = namespace_id.hyphenated();
+    match release_override {
+        Some(release_override) => {
+            sqlx::query!(
+                r#"
+                insert into release_overrides
+                (namespace_id, pkgbase, release_override)
+                values ($1, $2, $3)
+                on conflict (namespace_id, pkgbase) do update set release_override = $3
+                "#,
+                namespace_id,
+                pkgbase,
+                release_override,
+            )
+            .execute(pool)
+            .await
+        }
+        None => {
+            sqlx::query!(
+                r#"
+                delete from release_overrides
+                where namespace_id = $1 and pkgbase = $2
+                "#,
+                namespace_id,
+                pkgbase,
+            )
+            .execute(pool)
+            .await
+        }
+    }
+    .wrap_err("Failed to store release override")?;
+
+    Ok(())
+}
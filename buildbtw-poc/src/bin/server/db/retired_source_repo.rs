@@ -0,0 +1,64 @@
+use color_eyre::eyre::{Context, Result};
+use sqlx::SqlitePool;
+
+use buildbtw_poc::{
+    Pkgbase,
+    source_provider::{RetiredReason, RetiredSourceRepo},
+};
+
+pub async fn list(pool: &SqlitePool) -> Result<Vec<RetiredSourceRepo>> {
+    let retired = sqlx::query_as!(
+        RetiredSourceRepo,
+        r#"
+        select
+            pkgbase as "pkgbase: Pkgbase",
+            reason as "reason: RetiredReason",
+            retired_at as "retired_at: time::OffsetDateTime"
+        from retired_source_repos
+        order by pkgbase
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(retired)
+}
+
+/// Mark a source repository as retired.
+/// If it was retired before, the original date is kept.
+pub async fn set(pool: &SqlitePool, pkgbase: &Pkgbase, reason: RetiredReason) -> Result<()> {
+    let retired_at = time::OffsetDateTime::now_utc();
+    sqlx::query!(
+        r#"
+        insert into retired_source_repos
+        (pkgbase, reason, retired_at)
+        values ($1, $2, $3)
+        on conflict (pkgbase)
+        do update set reason = $2
+        "#,
+        pkgbase,
+        reason,
+        retired_at,
+    )
+    .execute(pool)
+    .await
+    .wrap_err("Failed to store retired source repo")?;
+
+    Ok(())
+}
+
+/// Include a source repository in build graphs again.
+/// Returns whether it was retired.
+pub async fn delete(pool: &SqlitePool, pkgbase: &Pkgbase) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        delete from retired_source_repos
+        where pkgbase = $1
+        "#,
+        pkgbase,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
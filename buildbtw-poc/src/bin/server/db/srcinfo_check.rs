@@ -0,0 +1,77 @@
+use color_eyre::eyre::{Context, Result};
+use sqlx::{SqlitePool, types::Json};
+use uuid::Uuid;
+
+use buildbtw_poc::{
+    CommitHash, Pkgbase,
+    srcinfo_check::{SrcinfoCheck, SrcinfoCheckOutcome},
+};
+
+#[derive(sqlx::FromRow)]
+struct DbSrcinfoCheck {
+    pkgbase: Pkgbase,
+    commit_hash: CommitHash,
+    outcome: Json<SrcinfoCheckOutcome>,
+    checked_at: time::OffsetDateTime,
+}
+
+impl From<DbSrcinfoCheck> for SrcinfoCheck {
+    fn from(value: DbSrcinfoCheck) -> Self {
+        SrcinfoCheck {
+            pkgbase: value.pkgbase,
+            commit_hash: value.commit_hash,
+            outcome: value.outcome.0,
+            checked_at: value.checked_at,
+        }
+    }
+}
+
+pub async fn list_for_namespace(
+    pool: &SqlitePool,
+    namespace_id: Uuid,
+) -> Result<Vec<SrcinfoCheck>> {
+    let namespace_id = namespace_id.hyphenated();
+    let checks = sqlx::query_as!(
+        DbSrcinfoCheck,
+        r#"
+        select
+            pkgbase as "pkgbase: Pkgbase",
+            commit_hash as "commit_hash: CommitHash",
+            outcome as "outcome: Json<SrcinfoCheckOutcome>",
+            checked_at as "checked_at: time::OffsetDateTime"
+        from srcinfo_checks
+        where namespace_id = $1
+        order by pkgbase
+        "#,
+        namespace_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(checks.into_iter().map(Into::into).collect())
+}
+
+/// Store the most recent `.SRCINFO` check of an origin changeset.
+pub async fn set(pool: &SqlitePool, namespace_id: Uuid, check: &SrcinfoCheck) -> Result<()> {
+    let namespace_id = namespace_id.hyphenated();
+    let outcome = Json(&check.outcome);
+    sqlx::query!(
+        r#"
+        insert into srcinfo_checks
+        (namespace_id, pkgbase, commit_hash, outcome, checked_at)
+        values ($1, $2, $3, $4, $5)
+        on conflict (namespace_id, pkgbase)
+        do update set commit_hash = $3, outcome = $4, checked_at = $5
+        "#,
+        namespace_id,
+        check.pkgbase,
+        check.commit_hash,
+        outcome,
+        check.checked_at,
+    )
+    .execute(pool)
+    .await
+    .wrap_err("Failed to store srcinfo check")?;
+
+    Ok(())
+}
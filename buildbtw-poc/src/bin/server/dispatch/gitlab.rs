@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{OptionExt, Result};
+use gitlab::AsyncGitlab;
+use sqlx::SqlitePool;
+
+use buildbtw_poc::{
+    PackageBuildStatus, ScheduleBuild,
+    build_logs::build_logs_dir_path,
+    gitlab::{
+        cancel_pipeline, create_pipeline,
+        job_logs::archive_pipeline_jobs,
+        pipeline_status::{GitlabGraphqlClient, get_pipeline_statuses},
+    },
+};
+
+use super::{BuildDispatcher, DispatchedBuild};
+use crate::{args, db, tasks::new_gitlab_client};
+
+/// Run builds in gitlab pipelines of the package source repositories.
+pub struct GitlabDispatcher {
+    args: args::Gitlab,
+    client: AsyncGitlab,
+    graphql_client: GitlabGraphqlClient,
+    server_port: u16,
+    last_poll: Mutex<Option<Instant>>,
+}
+
+impl GitlabDispatcher {
+    pub async fn new(args: args::Gitlab, server_port: u16) -> Result<Self> {
+        Ok(GitlabDispatcher {
+            client: new_gitlab_client(&args).await?,
+            graphql_client: GitlabGraphqlClient::new(
+                &args.base_url()?,
+                args.gitlab_token.expose_secret(),
+            )?,
+            args,
+            server_port,
+            last_poll: Mutex::new(None),
+        })
+    }
+
+    /// How often to query the status of all running pipelines.
+    /// Without webhooks, this happens every time we update the namespaces.
+    fn polling_interval(&self) -> Duration {
+        if self.args.gitlab_webhook_signing_token.is_some() {
+            Duration::from_secs(60 * 5)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Check whether the polling interval has passed, and if yes, restart it.
+    fn start_poll_if_due(&self) -> bool {
+        let mut last_poll = self.last_poll.lock().expect("Poisoned mutex");
+        let due = last_poll.is_none_or(|last| last.elapsed() >= self.polling_interval());
+        if due {
+            *last_poll = Some(Instant::now());
+        }
+        due
+    }
+}
+
+impl BuildDispatcher for GitlabDispatcher {
+    fn scheduled_status(&self) -> PackageBuildStatus {
+        // The server queries gitlab in the background to check
+        // if the pipeline has moved from "pending" to "building"
+        // and will update the status accordingly.
+        PackageBuildStatus::Scheduled
+    }
+
+    async fn schedule(
+        &self,
+        pool: &SqlitePool,
+        build: &ScheduleBuild,
+        namespace_name: &str,
+    ) -> Result<()> {
+        let pipeline_response = create_pipeline(
+            &self.client,
+            build,
+            namespace_name,
+            &self.args.gitlab_packages_group,
+            self.server_port,
+        )
+        .await?;
+        let db_pipeline = db::gitlab_pipeline::CreateDbGitlabPipeline {
+            build_set_iteration_id: build.iteration.into(),
+            pkgbase: build.source.pkgbase.clone(),
+            architecture: build.architecture,
+            project_gitlab_iid: pipeline_response.project_id.try_into()?,
+            gitlab_iid: pipeline_response.id.try_into()?,
+            gitlab_url: pipeline_response.web_url,
+            generation: 1,
+            status: pipeline_response.status,
+        };
+        db::gitlab_pipeline::create(pool, db_pipeline).await
+    }
+
+    /// Pipeline statuses are queried in batches, and only for builds
+    /// that have a pipeline associated.
+    async fn poll_statuses(
+        &self,
+        pool: &SqlitePool,
+        builds: &[DispatchedBuild],
+    ) -> Result<HashMap<DispatchedBuild, PackageBuildStatus>> {
+        if builds.is_empty() || !self.start_poll_if_due() {
+            return Ok(HashMap::new());
+        }
+
+        let mut in_flight = Vec::new();
+        for build in builds {
+            // Check if there's a gitlab pipeline we started
+            // If yes, we'll find it in the DB
+            let maybe_pipeline =
+                db::gitlab_pipeline::read_by_iteration_and_pkgbase_and_architecture(
+                    pool,
+                    build.iteration_id,
+                    &build.pkgbase,
+                    build.architecture,
+                )
+                .await?;
+            if let Some(pipeline) = maybe_pipeline {
+                let pipeline_id = pipeline.pipeline_id()?;
+                in_flight.push((build, pipeline, pipeline_id));
+            }
+        }
+        if in_flight.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let pipeline_ids: Vec<_> = in_flight
+            .iter()
+            .map(|(.., pipeline_id)| *pipeline_id)
+            .collect();
+        tracing::debug!("Querying status of {} pipeline(s)", pipeline_ids.len());
+        let pipeline_statuses =
+            get_pipeline_statuses(&self.graphql_client, &self.client, &pipeline_ids).await?;
+
+        let mut statuses = HashMap::new();
+        for (build, pipeline, pipeline_id) in in_flight {
+            let Some(pipeline_status) = pipeline_statuses.get(&pipeline_id) else {
+                continue;
+            };
+            if pipeline.status != Some(*pipeline_status) {
+                tracing::debug!(?pipeline_id, ?pipeline_status, "Pipeline status changed");
+                db::gitlab_pipeline::set_status(pool, pipeline.id, *pipeline_status).await?;
+            }
+            statuses.insert(build.clone(), (*pipeline_status).into());
+        }
+
+        Ok(statuses)
+    }
+
+    async fn cancel(&self, pool: &SqlitePool, build: &DispatchedBuild) -> Result<()> {
+        let pipeline = read_pipeline(pool, build).await?;
+        let pipeline_id = pipeline.pipeline_id()?;
+        let pipeline_status = cancel_pipeline(
+            &self.client,
+            pipeline_id.project_id,
+            pipeline_id.pipeline_id,
+        )
+        .await?;
+        db::gitlab_pipeline::set_status(pool, pipeline.id, pipeline_status).await?;
+        tracing::info!(
+            "Cancelled pipeline {}: {pipeline_status:?}",
+            pipeline.gitlab_url
+        );
+
+        Ok(())
+    }
+
+    async fn fetch_logs(&self, pool: &SqlitePool, build: &DispatchedBuild) -> Result<()> {
+        let pipeline = read_pipeline(pool, build).await?;
+        archive_pipeline_jobs_in_background(pool, &self.args, &pipeline).await
+    }
+}
+
+/// Read the newest pipeline generation of a build.
+async fn read_pipeline(
+    pool: &SqlitePool,
+    build: &DispatchedBuild,
+) -> Result<db::gitlab_pipeline::DbGitlabPipeline> {
+    db::gitlab_pipeline::read_by_iteration_and_pkgbase_and_architecture(
+        pool,
+        build.iteration_id,
+        &build.pkgbase,
+        build.architecture,
+    )
+    .await?
+    .ok_or_eyre("No gitlab pipeline found for build")
+}
+
+/// Download the job logs and artifacts of a finished pipeline, so they can
+/// be shown next to those of local builds. This runs in the background
+/// as artifacts may be large.
+/// Both the webhook and status polling notice finished pipelines,
+/// the logs of each pipeline generation are only archived once.
+pub(crate) async fn archive_pipeline_jobs_in_background(
+    pool: &SqlitePool,
+    gitlab_args: &args::Gitlab,
+    pipeline: &db::gitlab_pipeline::DbGitlabPipeline,
+) -> Result<()> {
+    if !db::gitlab_pipeline::claim_logs_archival(pool, pipeline.id).await? {
+        tracing::debug!(
+            "Jobs of pipeline {} are already archived",
+            pipeline.gitlab_url
+        );
+        return Ok(());
+    }
+
+    let pool = pool.clone();
+    let gitlab_args = gitlab_args.clone();
+    let id = pipeline.id;
+    let pipeline_id = pipeline.pipeline_id()?;
+    let logs_dir = build_logs_dir_path(
+        pipeline.build_set_iteration_id,
+        pipeline.architecture,
+        &pipeline.pkgbase,
+    );
+    tokio::spawn(async move {
+        let result = async {
+            let client = new_gitlab_client(&gitlab_args).await?;
+            archive_pipeline_jobs(
+                &client,
+                &gitlab_args.base_url()?,
+                gitlab_args.gitlab_token.expose_secret(),
+                pipeline_id.project_id,
+                pipeline_id.pipeline_id,
+                &logs_dir,
+            )
+            .await
+        }
+        .await;
+        if let Err(e) = result {
+            tracing::error!("Failed to archive jobs of pipeline {pipeline_id:?}: {e:?}");
+            // Allow trying again the next time the pipeline finishes
+            if let Err(e) = db::gitlab_pipeline::release_logs_archival(&pool, id).await {
+                tracing::error!("{e:?}");
+            }
+        }
+    });
+
+    Ok(())
+}
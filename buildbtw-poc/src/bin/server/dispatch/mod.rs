@@ -0,0 +1,212 @@
+//! Hand builds to the executors that run them.
+//!
+//! The scheduling loop in [`crate::tasks`] only talks to executors via the
+//! [`BuildDispatcher`] trait, so adding a new executor means implementing
+//! the trait and registering it in [`BuildDispatchers`]. Currently, there are two:
+//!
+//! - [`WorkerDispatcher`] sends builds to a buildbtw worker instance.
+//! - [`GitlabDispatcher`] runs builds in gitlab pipelines.
+//!
+//! Which one is used is configured per namespace, falling back to the
+//! server's default for the build's architecture and then to the server's default.
+
+use std::{collections::HashMap, future::Future};
+
+use color_eyre::eyre::{Result, eyre};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+use buildbtw_poc::{
+    BuildDispatch, BuildNamespace, PackageBuildStatus, Pkgbase, ScheduleBuild,
+    source_info::ConcreteArchitecture,
+};
+
+use crate::args;
+
+mod gitlab;
+mod worker;
+
+pub(crate) use gitlab::{GitlabDispatcher, archive_pipeline_jobs_in_background};
+pub(crate) use worker::WorkerDispatcher;
+
+/// Identifies the build of a node in an iteration's build graph.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DispatchedBuild {
+    pub iteration_id: Uuid,
+    pub architecture: ConcreteArchitecture,
+    pub pkgbase: Pkgbase,
+}
+
+/// Something that can run package builds.
+pub trait BuildDispatcher {
+    /// Status of a build node right after its build was handed to this dispatcher.
+    fn scheduled_status(&self) -> PackageBuildStatus;
+
+    /// Start building a package.
+    fn schedule(
+        &self,
+        pool: &SqlitePool,
+        build: &ScheduleBuild,
+        namespace_name: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Query the current status of builds in flight.
+    /// Builds that weren't dispatched by this dispatcher, or that report their
+    /// status on their own, are omitted from the result.
+    fn poll_statuses(
+        &self,
+        pool: &SqlitePool,
+        builds: &[DispatchedBuild],
+    ) -> impl Future<Output = Result<HashMap<DispatchedBuild, PackageBuildStatus>>> + Send;
+
+    /// Stop a build in flight.
+    fn cancel(
+        &self,
+        pool: &SqlitePool,
+        build: &DispatchedBuild,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Store the logs of a finished build in [`buildbtw_poc::build_logs::build_logs_dir_path`].
+    fn fetch_logs(
+        &self,
+        pool: &SqlitePool,
+        build: &DispatchedBuild,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// All available dispatchers, for choosing one at runtime.
+#[allow(clippy::large_enum_variant)]
+pub enum Dispatcher {
+    Worker(WorkerDispatcher),
+    Gitlab(GitlabDispatcher),
+}
+
+impl BuildDispatcher for Dispatcher {
+    fn scheduled_status(&self) -> PackageBuildStatus {
+        match self {
+            Dispatcher::Worker(dispatcher) => dispatcher.scheduled_status(),
+            Dispatcher::Gitlab(dispatcher) => dispatcher.scheduled_status(),
+        }
+    }
+
+    async fn schedule(
+        &self,
+        pool: &SqlitePool,
+        build: &ScheduleBuild,
+        namespace_name: &str,
+    ) -> Result<()> {
+        match self {
+            Dispatcher::Worker(dispatcher) => {
+                dispatcher.schedule(pool, build, namespace_name).await
+            }
+            Dispatcher::Gitlab(dispatcher) => {
+                dispatcher.schedule(pool, build, namespace_name).await
+            }
+        }
+    }
+
+    async fn poll_statuses(
+        &self,
+        pool: &SqlitePool,
+        builds: &[DispatchedBuild],
+    ) -> Result<HashMap<DispatchedBuild, PackageBuildStatus>> {
+        match self {
+            Dispatcher::Worker(dispatcher) => dispatcher.poll_statuses(pool, builds).await,
+            Dispatcher::Gitlab(dispatcher) => dispatcher.poll_statuses(pool, builds).await,
+        }
+    }
+
+    async fn cancel(&self, pool: &SqlitePool, build: &DispatchedBuild) -> Result<()> {
+        match self {
+            Dispatcher::Worker(dispatcher) => dispatcher.cancel(pool, build).await,
+            Dispatcher::Gitlab(dispatcher) => dispatcher.cancel(pool, build).await,
+        }
+    }
+
+    async fn fetch_logs(&self, pool: &SqlitePool, build: &DispatchedBuild) -> Result<()> {
+        match self {
+            Dispatcher::Worker(dispatcher) => dispatcher.fetch_logs(pool, build).await,
+            Dispatcher::Gitlab(dispatcher) => dispatcher.fetch_logs(pool, build).await,
+        }
+    }
+}
+
+/// The dispatchers the server is configured for.
+pub struct BuildDispatchers {
+    default: BuildDispatch,
+    /// Overrides `default` for builds of an architecture.
+    architecture_defaults: HashMap<ConcreteArchitecture, BuildDispatch>,
+    dispatchers: HashMap<BuildDispatch, Dispatcher>,
+}
+
+impl BuildDispatchers {
+    /// The worker dispatcher is always available,
+    /// the gitlab dispatcher only if the gitlab integration is configured.
+    pub async fn new(args: &args::Args, server_port: u16) -> Result<Self> {
+        let default = args.build_dispatch;
+        let architecture_defaults: HashMap<_, _> = args
+            .build_dispatch_for_architecture
+            .iter()
+            .copied()
+            .collect();
+        let mut dispatchers = HashMap::from([(
+            BuildDispatch::Worker,
+            Dispatcher::Worker(WorkerDispatcher::new(args.worker_url.clone())),
+        )]);
+        let gitlab_args = args.gitlab.as_ref();
+        if let Some(gitlab_args) = gitlab_args {
+            dispatchers.insert(
+                BuildDispatch::Gitlab,
+                Dispatcher::Gitlab(GitlabDispatcher::new(gitlab_args.clone(), server_port).await?),
+            );
+        }
+        if !dispatchers.contains_key(&default) {
+            return Err(eyre!(
+                "Dispatching builds to {default} by default requires the {default} integration"
+            ));
+        }
+        for (architecture, dispatch) in &architecture_defaults {
+            if !dispatchers.contains_key(dispatch) {
+                return Err(eyre!(
+                    "Dispatching {architecture} builds to {dispatch} requires the {dispatch} integration"
+                ));
+            }
+        }
+
+        Ok(BuildDispatchers {
+            default,
+            architecture_defaults,
+            dispatchers,
+        })
+    }
+
+    pub fn is_available(&self, dispatch: BuildDispatch) -> bool {
+        self.dispatchers.contains_key(&dispatch)
+    }
+
+    /// The dispatcher running the builds of a namespace for an architecture.
+    /// The namespace's own choice takes precedence over the
+    /// default for the architecture, which takes precedence over the global default.
+    pub fn for_build(
+        &self,
+        namespace: &BuildNamespace,
+        architecture: ConcreteArchitecture,
+    ) -> Result<&Dispatcher> {
+        let dispatch = namespace.build_dispatch.unwrap_or_else(|| {
+            self.architecture_defaults
+                .get(&architecture)
+                .copied()
+                .unwrap_or(self.default)
+        });
+        self.dispatchers.get(&dispatch).ok_or_else(|| {
+            eyre!(
+                r#"Namespace "{}" dispatches {architecture} builds to {dispatch}, which isn't configured"#,
+                namespace.name
+            )
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&BuildDispatch, &Dispatcher)> {
+        self.dispatchers.iter()
+    }
+}
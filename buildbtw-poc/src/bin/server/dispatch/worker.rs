@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use color_eyre::eyre::{Context, Result, bail};
+use sqlx::SqlitePool;
+use url::Url;
+
+use buildbtw_poc::{PackageBuildStatus, ScheduleBuild};
+
+use super::{BuildDispatcher, DispatchedBuild};
+
+/// Send builds to a buildbtw worker instance.
+///
+/// The worker reports build statuses and uploads logs to the server on its own,
+/// so there's nothing to poll or fetch.
+pub struct WorkerDispatcher {
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl WorkerDispatcher {
+    /// `url` is the base URL of the worker, e.g. "http://0.0.0.0:8090".
+    pub fn new(url: Url) -> Self {
+        WorkerDispatcher {
+            client: reqwest::Client::new(),
+            url,
+        }
+    }
+}
+
+impl BuildDispatcher for WorkerDispatcher {
+    fn scheduled_status(&self) -> PackageBuildStatus {
+        // The worker has no concept of "pending" builds
+        // and will instead start building instantly.
+        PackageBuildStatus::Building
+    }
+
+    async fn schedule(
+        &self,
+        _pool: &SqlitePool,
+        build: &ScheduleBuild,
+        _namespace_name: &str,
+    ) -> Result<()> {
+        self.client
+            .post(self.url.join("/build/schedule")?)
+            .json(build)
+            .send()
+            .await
+            .wrap_err("Failed to send to worker")?
+            .error_for_status()
+            .wrap_err("Worker didn't accept the build")?;
+
+        Ok(())
+    }
+
+    async fn poll_statuses(
+        &self,
+        _pool: &SqlitePool,
+        _builds: &[DispatchedBuild],
+    ) -> Result<HashMap<DispatchedBuild, PackageBuildStatus>> {
+        Ok(HashMap::new())
+    }
+
+    async fn cancel(&self, _pool: &SqlitePool, build: &DispatchedBuild) -> Result<()> {
+        bail!(
+            "The worker doesn't support cancelling builds, {} will keep running",
+            build.pkgbase
+        )
+    }
+
+    async fn fetch_logs(&self, _pool: &SqlitePool, _build: &DispatchedBuild) -> Result<()> {
+        Ok(())
+    }
+}
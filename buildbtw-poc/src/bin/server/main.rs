@@ -1,26 +1,31 @@
-use std::net::{SocketAddr, TcpListener};
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+};
 
 use axum::{
     Router,
     response::Redirect,
-    routing::{get, patch, post},
+    routing::{get, patch, post, put},
 };
 use axum_extra::handler::HandlerCallWithExtractors;
 use clap::Parser;
 use color_eyre::Result;
 use listenfd::ListenFd;
 use sqlx::SqlitePool;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{Mutex, mpsc::UnboundedSender};
 use tower_http::{services::ServeDir, trace::TraceLayer};
 use url::Url;
 use with_content_type::{ApplicationJson, with_content_type};
 
 use crate::routes::{
-    create_build_namespace, create_namespace_iteration, home_html, list_namespaces_json,
-    render_build_namespace_graph, render_latest_namespace, set_build_status,
+    cancel_pipeline, cancel_release, confirm_release, create_build_namespace,
+    create_namespace_iteration, create_release, gitlab_webhook, home_html, list_namespaces_json,
+    list_release_locks, list_retired_source_repos, render_build_namespace_graph,
+    render_latest_namespace, retry_pipeline, set_build_status, set_release_override,
     show_build_namespace_html, show_build_namespace_iteration_architecture_json,
-    show_build_namespace_iteration_json, show_build_namespace_json, update_namespace,
-    upload_package,
+    show_build_namespace_iteration_json, show_build_namespace_json, show_build_pacman_conf,
+    show_newest_release, show_pacman_conf, update_namespace, upload_build_log, upload_package,
 };
 use crate::{
     args::{Args, Command},
@@ -28,11 +33,15 @@ use crate::{
         show_build_namespace_iteration_architecture_html, show_build_namespace_iteration_html,
     },
 };
-use buildbtw_poc::pacman_repo::REPO_DIR;
+use buildbtw_poc::{
+    build_logs::BUILD_LOGS_DIR, git::PackagingRemote, pacman_repo::REPO_DIR,
+    release::RELEASE_REPO_DIR, signing::SignerBackend, source_repos::SourceRepos,
+};
 
 mod args;
 pub mod assets;
 mod db;
+mod dispatch;
 pub mod response_error;
 mod routes;
 pub mod stream_to_file;
@@ -48,6 +57,10 @@ struct AppState {
     db_pool: SqlitePool,
     base_url: Url,
     gitlab_args: Option<args::Gitlab>,
+    packaging_remote: Option<PackagingRemote>,
+    build_dispatchers: Arc<dispatch::BuildDispatchers>,
+    signer: Option<SignerBackend>,
+    namespace_update_options: Arc<tasks::NamespaceUpdateOptions>,
 }
 
 #[tokio::main]
@@ -60,7 +73,7 @@ async fn main() -> Result<()> {
 
     tracing::debug!("{args:#?}");
 
-    match args.command {
+    match args.command.clone() {
         Command::Run {
             interface,
             port,
@@ -73,7 +86,26 @@ async fn main() -> Result<()> {
 
             sqlx::migrate!("./migrations").run(&db_pool).await?;
 
-            let worker_sender = tasks::start(db_pool.clone(), args.gitlab.clone(), port).await?;
+            let signer = args.signing.signer();
+            let packaging_remote = args.sources.packaging_remote(args.gitlab.as_ref());
+            let build_dispatchers = Arc::new(dispatch::BuildDispatchers::new(&args, port).await?);
+            let source_repos = SourceRepos::new().await?;
+            let namespace_update_options = Arc::new(tasks::NamespaceUpdateOptions {
+                credentials: args.sources.credentials(),
+                stale_srcinfo: args.stale_srcinfo,
+                source_repo_changes: source_repos.change_notifier(),
+                source_repos: Arc::new(Mutex::new(source_repos)),
+            });
+            let worker_sender = tasks::start(
+                db_pool.clone(),
+                args.gitlab.clone(),
+                args.sources.clone(),
+                namespace_update_options.clone(),
+                build_dispatchers.clone(),
+                base_url.clone(),
+                signer.clone(),
+            )
+            .await?;
             let app = Router::new()
                 .route("/", get(|| async {Redirect::to("/namespace")}))
                 .route(
@@ -87,6 +119,28 @@ async fn main() -> Result<()> {
                     "/namespace/{name}/iteration",
                     post(create_namespace_iteration),
                 )
+                .route("/namespace/{name}/pacman.conf", get(show_pacman_conf))
+                .route(
+                    "/namespace/{name}/release",
+                    post(create_release).get(show_newest_release),
+                )
+                .route("/namespace/{name}/release/cancel", post(cancel_release))
+                .route(
+                    "/namespace/{name}/release/{release_id}/confirm",
+                    post(confirm_release),
+                )
+                .route(
+                    "/namespace/{name}/release-override/{pkgbase}",
+                    put(set_release_override),
+                )
+                .route(
+                    "/namespace/{name}/pipeline/{architecture}/{pkgbase}/retry",
+                    post(retry_pipeline),
+                )
+                .route(
+                    "/namespace/{name}/pipeline/{architecture}/{pkgbase}/cancel",
+                    post(cancel_pipeline),
+                )
                 .route("/namespace/{name}", get(with_content_type::<ApplicationJson, _>(show_build_namespace_json).or(show_build_namespace_html)))
                 .route("/namespace/{name}/{iteration}", get(with_content_type::<ApplicationJson, _>(show_build_namespace_iteration_json).or(show_build_namespace_iteration_html)))
                 .route("/namespace/{name}/{iteration}/{architecture}", get(with_content_type::<ApplicationJson, _>(show_build_namespace_iteration_architecture_json).or(show_build_namespace_iteration_architecture_html)))
@@ -94,6 +148,9 @@ async fn main() -> Result<()> {
                     "/namespace/{name}/{iteration_id}/{architecture}/graph",
                     get(render_build_namespace_graph),
                 )
+                .route("/release-lock", get(list_release_locks))
+                .route("/source-repo/retired", get(list_retired_source_repos))
+                .route("/gitlab/webhook", post(gitlab_webhook))
                 .route("/latest_namespace", get(render_latest_namespace))
                 .route("/namespace/{name}", patch(update_namespace))
                 .route(
@@ -101,18 +158,32 @@ async fn main() -> Result<()> {
                     patch(set_build_status),
                 )
                 .route(
-                    "/iteration/{iteration_id}/pkgbase/{pkgbase}/pkgname/{pkgname}/architecture/{architecture}/package",
+                    "/iteration/{iteration_id}/architecture/{architecture}/pacman.conf",
+                    get(show_build_pacman_conf),
+                )
+                .route(
+                    "/iteration/{iteration_id}/pkgbase/{pkgbase}/architecture/{architecture}/package/{file_name}",
                     post(upload_package),
                 )
+                .route(
+                    "/iteration/{iteration_id}/pkgbase/{pkgbase}/architecture/{architecture}/log/{file_name}",
+                    post(upload_build_log),
+                )
                 .route("/assets/{*path}", get(assets::static_handler))
                 .nest_service("/repo", ServeDir::new(REPO_DIR.as_path()))
+                .nest_service("/release-repo", ServeDir::new(RELEASE_REPO_DIR.as_path()))
+                .nest_service("/logs", ServeDir::new(BUILD_LOGS_DIR.as_path()))
                 .layer(TraceLayer::new_for_http())
                 .with_state(AppState {
                     worker_sender,
                     jinja_env,
                     db_pool: db_pool.clone(),
                     base_url,
-                    gitlab_args: args.gitlab
+                    gitlab_args: args.gitlab,
+                    packaging_remote,
+                    build_dispatchers,
+                    signer,
+                    namespace_update_options,
                 });
 
             let mut listenfd = ListenFd::from_env();
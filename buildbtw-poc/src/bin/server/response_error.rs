@@ -16,6 +16,8 @@ pub enum ResponseError {
     NotFound(&'static str),
     #[error("Invalid input: {0}")]
     InvalidInput(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
     #[error("Unsupported content type: {0}")]
     UnsupportedContentType(String),
 }
@@ -29,6 +31,7 @@ impl IntoResponse for ResponseError {
             ResponseError::IO(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ResponseError::UnsupportedContentType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
             ResponseError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            ResponseError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
         };
         (status, self.to_string()).into_response()
     }
@@ -1,51 +1,83 @@
+use std::collections::BTreeMap;
+
 use axum::{
-    Json, debug_handler,
-    extract::{Path, Request, State},
-    response::Html,
+    Json,
+    body::Bytes,
+    debug_handler,
+    extract::{Path, Query, Request, State},
+    http::HeaderMap,
+    response::{Html, IntoResponse, Response},
 };
+use camino::Utf8Path;
 use color_eyre::eyre::{OptionExt, Result, WrapErr};
 use layout::backends::svg::SVGWriter;
 use layout::gv::{GraphBuilder, parser::DotParser};
 use minijinja::context;
 use petgraph::visit::{EdgeRef, NodeRef};
 use reqwest::StatusCode;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use time::macros::format_description;
 use tokio::fs;
 use url::Url;
 use uuid::Uuid;
 
-use buildbtw_poc::gitlab::commit_web_url;
-use buildbtw_poc::source_repos::SourceRepos;
+use buildbtw_poc::git::clone_or_fetch_repositories;
+use buildbtw_poc::gitlab::{
+    commit_web_url,
+    merge_requests::{self, TrackedMergeRequest},
+    webhook::{self, WebhookEvent},
+};
 use buildbtw_poc::{
-    BuildNamespace, BuildSetIteration, CreateBuildNamespace, PackageBuildStatus, Pkgbase, Pkgname,
+    BuildNamespace, BuildSetIteration, CreateBuildNamespace, PackageBuildStatus, Pkgbase,
     SetBuildStatus, UpdateBuildNamespace,
 };
 use buildbtw_poc::{
     BuildNamespaceStatus,
-    build_set_graph::{BuildPackageNode, BuildSetGraph, calculate_packages_to_be_built},
+    build_set_graph::{BuildPackageNode, BuildSetGraph},
+    iteration::{NewIterationReason, calculate_packages_to_build, create_build_set_iteration},
 };
 use buildbtw_poc::{
     GitRepoRef,
-    api::ShowNamespaceJson,
-    pacman_repo::{add_to_repo, repo_dir_path},
+    api::{
+        BlockingSrcinfoChecksJson, ConfirmReleaseJson, CreateNamespaceJson, PipelineAttempt,
+        ShowNamespaceJson,
+    },
+    build_logs::{
+        build_logs_dir_path, build_logs_path, is_valid_log_file_name, list_build_log_files,
+    },
+    conflicts::{NamespacePkgbases, OverlapMatrix, find_overlaps},
+    git::{clone_packaging_repository, is_commit_hash, package_source_path, pin_git_ref},
+    pacman_conf::{
+        BaseRepository, default_base_repositories, is_valid_repository_name,
+        render_build_pacman_conf, repo_sig_level,
+    },
+    pacman_repo::{
+        add_to_debug_repo, add_to_repo, debug_repo_db_name, debug_repo_dir_path, debug_repo_name,
+        latest_debug_repo_name, latest_repo_name, list_package_files, repo_db_name, repo_dir_path,
+    },
+    pkgrel_bump::rebuild_branch_name,
+    release::{CreateRelease, Release, ReleaseLock, ReleaseStatus, compute_release_plan},
+    release_selection::{ReleaseOverride, SetReleaseOverride},
 };
 use buildbtw_poc::{
     api::ArchitectureIteration,
-    source_info::{ConcreteArchitecture, package_file_name, package_for_architecture},
+    signing::{SigningStatus, sign_file, signature_path},
+    source_info::{ConcreteArchitecture, is_debug_package_file, package_for_file_name},
+    source_provider::RetiredSourceRepo,
 };
 
+use crate::args;
 use crate::db::namespace::CreateDbBuildNamespace;
+use crate::dispatch::{BuildDispatcher, DispatchedBuild};
 use crate::response_error::ResponseError::{self};
 use crate::response_error::ResponseResult;
-use crate::{AppState, db, stream_to_file::stream_to_file};
-use crate::{args, db::iteration::BuildSetIterationUpdate};
+use crate::{AppState, db, stream_to_file::stream_to_file, tasks};
 
 #[debug_handler]
 pub(crate) async fn create_build_namespace(
     State(state): State<AppState>,
     Json(body): Json<CreateBuildNamespace>,
-) -> Result<Json<BuildNamespace>, ResponseError> {
+) -> Result<Json<CreateNamespaceJson>, ResponseError> {
     let name = body.name.unwrap_or(
         body.origin_changesets
             .first()
@@ -53,11 +85,113 @@ pub(crate) async fn create_build_namespace(
             .0
             .to_string(),
     );
+    // Namespace names end up in repository paths and `pacman.conf` sections.
+    if !is_valid_repository_name(&name) {
+        return Err(ResponseError::InvalidInput(format!(
+            "Invalid namespace name: {name:?}"
+        )));
+    }
+
+    let mut base_repositories = if body.base_repositories.is_empty() {
+        default_base_repositories()
+    } else {
+        body.base_repositories
+    };
+
+    // Builds in child namespaces install packages from the parent's repository
+    // before falling back to the other base repositories.
+    let parent = match &body.parent {
+        Some(parent_name) => {
+            let parent = db::namespace::read_by_name(parent_name, &state.db_pool)
+                .await
+                .map_err(|_| {
+                    ResponseError::InvalidInput(format!(
+                        "Parent namespace {parent_name} does not exist"
+                    ))
+                })?;
+            let parent_repository = BaseRepository::Namespace(parent.name.clone());
+            if !base_repositories.contains(&parent_repository) {
+                base_repositories.insert(0, parent_repository);
+            }
+            Some(parent)
+        }
+        None => None,
+    };
+    // Namespaces can only build on top of namespaces that already exist.
+    for repository in &base_repositories {
+        if let BaseRepository::Namespace(base_name) = repository {
+            if base_name == &name {
+                return Err(ResponseError::InvalidInput(
+                    "A namespace cannot build on top of itself".to_string(),
+                ));
+            }
+            if db::namespace::read_by_name(base_name, &state.db_pool)
+                .await
+                .is_err()
+            {
+                return Err(ResponseError::InvalidInput(format!(
+                    "Base namespace {base_name} does not exist"
+                )));
+            }
+        }
+    }
+
+    if let Some(build_dispatch) = body
+        .build_dispatch
+        .filter(|build_dispatch| !state.build_dispatchers.is_available(*build_dispatch))
+    {
+        return Err(ResponseError::InvalidInput(format!(
+            "Dispatching builds to {build_dispatch} isn't configured on this server"
+        )));
+    }
+    if body.merge_requests && state.gitlab_args.is_none() {
+        return Err(ResponseError::InvalidInput(
+            "Tracking merge requests requires the gitlab integration".to_string(),
+        ));
+    }
+    // Tags and commit hashes are pinned, so the namespace keeps building the same commits.
+    let mut origin_changesets = Vec::new();
+    for (pkgbase, git_ref) in body.origin_changesets {
+        let is_cloned = package_source_path(&pkgbase).join(".git").exists();
+        if let (false, Some(remote)) = (is_cloned, &state.packaging_remote) {
+            clone_packaging_repository(pkgbase.clone(), remote.clone()).await?;
+        }
+        let pinned = pin_git_ref(pkgbase.clone(), git_ref.clone())
+            .await
+            .map_err(|e| ResponseError::InvalidInput(format!("{e:#}")))?;
+        if pinned != git_ref {
+            tracing::info!("Pinned origin changeset {pkgbase}/{git_ref} to {pinned}");
+        }
+        origin_changesets.push((pkgbase, pinned));
+    }
+    // Changesets on the target branch are already merged,
+    // and pinned commits have no branch to merge.
+    let merge_request_changesets: Vec<_> = origin_changesets
+        .iter()
+        .filter(|(_, branch_name)| {
+            branch_name != merge_requests::TARGET_BRANCH && !is_commit_hash(branch_name)
+        })
+        .cloned()
+        .collect();
+
     let create = CreateDbBuildNamespace {
         name,
-        origin_changesets: body.origin_changesets,
+        origin_changesets,
+        base_repositories,
+        parent_namespace_id: parent.map(|parent| parent.id),
+        bump_pkgrel: body.bump_pkgrel,
+        rebase_origin_changesets: body.rebase_origin_changesets,
+        build_dispatch: body.build_dispatch,
     };
     let namespace = db::namespace::create(create, &state.db_pool).await?;
+    if body.merge_requests {
+        db::gitlab_merge_request::create_requested(
+            &state.db_pool,
+            namespace.id,
+            &merge_request_changesets,
+        )
+        .await?;
+    }
 
     let base_url = state
         .base_url
@@ -65,7 +199,42 @@ pub(crate) async fn create_build_namespace(
         .wrap_err("Failed to parse URL")?;
     tracing::info!("Namespace overview available at: {base_url}",);
 
-    Ok(Json(namespace))
+    // The namespace doesn't have a build graph yet, so this only
+    // catches overlaps with its origin changesets.
+    let overlapping_namespaces = find_overlaps(
+        &NamespacePkgbases::new(&namespace, None),
+        &active_namespace_pkgbases(&state.db_pool).await?,
+    );
+    for overlap in &overlapping_namespaces {
+        tracing::warn!(
+            "Namespace {} overlaps with namespace {} in pkgbases: {:?}",
+            namespace.name,
+            overlap.namespace_name,
+            overlap.pkgbases
+        );
+    }
+
+    Ok(Json(CreateNamespaceJson {
+        namespace,
+        overlapping_namespaces,
+    }))
+}
+
+/// Pkgbases touched by each active namespace, sorted by namespace name.
+async fn active_namespace_pkgbases(pool: &sqlx::SqlitePool) -> Result<Vec<NamespacePkgbases>> {
+    let mut namespaces = db::namespace::list_by_status(pool, BuildNamespaceStatus::Active).await?;
+    namespaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut namespace_pkgbases = Vec::new();
+    for namespace in namespaces {
+        let newest_iteration = db::iteration::read_newest(pool, namespace.id).await.ok();
+        namespace_pkgbases.push(NamespacePkgbases::new(
+            &namespace,
+            newest_iteration.as_ref(),
+        ));
+    }
+
+    Ok(namespace_pkgbases)
 }
 
 #[derive(Serialize)]
@@ -122,13 +291,26 @@ pub(crate) async fn home_html(State(state): State<AppState>) -> ResponseResult<H
         }
     }
 
+    // Only show namespaces in the matrix that overlap with at least one other namespace.
+    let namespace_pkgbases = active_namespace_pkgbases(&state.db_pool).await?;
+    let overlapping_namespaces: Vec<_> = namespace_pkgbases
+        .iter()
+        .filter(|namespace| !find_overlaps(namespace, &namespace_pkgbases).is_empty())
+        .cloned()
+        .collect();
+    let overlap_matrix = OverlapMatrix::new(&overlapping_namespaces);
+
+    let release_locks = db::release_lock::list(&state.db_pool).await?;
+
     let template = state.jinja_env.get_template("home").unwrap();
 
     let rendered = template
         .render(context! {
             active_namespaces => active_namespaces,
             cancelled_namespaces => cancelled_namespaces,
-            running_builds_table => running_builds_table
+            running_builds_table => running_builds_table,
+            overlap_matrix => overlap_matrix,
+            release_locks => release_locks,
         })
         .unwrap();
 
@@ -206,17 +388,34 @@ struct PipelineTableEntry {
     pkgbase: Pkgbase,
     commit_hash: String,
     commit_gitlab_url: Option<Url>,
+    /// All generations of the build's gitlab pipeline, if it has been retried.
+    pipeline_attempts: Vec<PipelineAttempt>,
+    /// Path below `/logs` that the build's log files are served under.
+    logs_path: String,
+    log_files: Vec<String>,
 }
 
 impl PipelineTableEntry {
     fn try_new(
         node: &BuildPackageNode,
-        gitlab_url: Option<String>,
+        pipeline_attempts: Vec<PipelineAttempt>,
+        logs_path: String,
+        log_files: Vec<String>,
         gitlab_args: &Option<args::Gitlab>,
     ) -> Result<Self> {
         let mut commit_hash = node.commit_hash.to_string();
         commit_hash.truncate(8);
 
+        // Link the newest attempt, and only list attempts if there are several.
+        let gitlab_url = pipeline_attempts
+            .last()
+            .map(|attempt| attempt.gitlab_url.clone());
+        let pipeline_attempts = if pipeline_attempts.len() > 1 {
+            pipeline_attempts
+        } else {
+            Vec::new()
+        };
+
         let commit_gitlab_url = gitlab_args
             .as_ref()
             .map(|args| {
@@ -233,10 +432,13 @@ impl PipelineTableEntry {
             status_icon: node.status.as_icon().to_string(),
             status_description: node.status.as_description(),
             gitlab_url,
+            pipeline_attempts,
             pkgbase: node.pkgbase.clone(),
             status: node.status,
             commit_hash,
             commit_gitlab_url,
+            logs_path,
+            log_files,
         })
     }
 }
@@ -292,6 +494,20 @@ impl IterationView {
     }
 }
 
+#[derive(Serialize)]
+struct DebugPackagesView {
+    repo_name: String,
+    file_names: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PackageSignatureView {
+    pkgbase: Pkgbase,
+    file_name: String,
+    status: SigningStatus,
+    status_icon: &'static str,
+}
+
 fn default_architecture_for_namespace(
     architecture: Option<ConcreteArchitecture>,
     current_iteration: Option<&BuildSetIteration>,
@@ -336,8 +552,34 @@ pub(crate) async fn show_build_namespace_iteration_architecture_html(
 ) -> Result<Html<String>, ResponseError> {
     let namespace = db::namespace::read_by_name(&namespace_name, &state.db_pool).await?;
     let iterations = db::iteration::list_for_namespace(&state.db_pool, namespace.id).await?;
+    let release = db::release::read_newest_for_namespace(&state.db_pool, namespace.id).await?;
+    let release_overrides =
+        db::release_override::list_for_namespace(&state.db_pool, namespace.id).await?;
+    let blocking_release_locks = match &release {
+        Some(release) if release.status == ReleaseStatus::Confirmed => {
+            db::release_lock::list_blocking(&state.db_pool, release).await?
+        }
+        _ => Vec::new(),
+    };
+    let parent_namespace_name = match namespace.parent_id {
+        Some(parent_id) => Some(db::namespace::read(parent_id, &state.db_pool).await?.name),
+        None => None,
+    };
+    let merge_requests = read_tracked_merge_requests(&state.db_pool, namespace.id).await?;
+    let ready_for_release_at =
+        db::namespace::read_ready_for_release_at(&state.db_pool, namespace.id)
+            .await?
+            .map(|time| time.format(FORMAT))
+            .transpose()
+            .wrap_err("Failed to format date")?;
+    let origin_changeset_rebases =
+        db::origin_changeset_rebase::list_for_namespace(&state.db_pool, namespace.id).await?;
+    let srcinfo_checks =
+        db::srcinfo_check::list_for_namespace(&state.db_pool, namespace.id).await?;
 
     let mut pipeline_table = None;
+    let mut debug_packages = None;
+    let mut package_signatures = Vec::new();
     let current_iteration = if let Some(id) = iteration_id {
         Some(db::iteration::read(&state.db_pool, id).await?)
     } else {
@@ -358,17 +600,20 @@ pub(crate) async fn show_build_namespace_iteration_architecture_html(
         for node in build_graph.node_weights() {
             // Many small queries are efficient in sqlite:
             // https://sqlite.org/np1queryprob.html
-            let gitlab_url = db::gitlab_pipeline::read_by_iteration_and_pkgbase_and_architecture(
-                &state.db_pool,
+            let pipeline_attempts =
+                list_pipeline_attempts(&state, current_iteration.id, architecture, &node.pkgbase)
+                    .await?;
+            let log_files = list_build_log_files(&build_logs_dir_path(
                 current_iteration.id,
-                &node.pkgbase,
                 architecture,
-            )
-            .await?
-            .map(|p| p.gitlab_url);
+                &node.pkgbase,
+            ))
+            .await?;
             table_entries.push(PipelineTableEntry::try_new(
                 node,
-                gitlab_url,
+                pipeline_attempts,
+                build_logs_path(current_iteration.id, architecture, &node.pkgbase).to_string(),
+                log_files,
                 &state.gitlab_args,
             )?);
         }
@@ -383,6 +628,30 @@ pub(crate) async fn show_build_namespace_iteration_architecture_html(
         });
 
         pipeline_table = Some(table_entries);
+
+        // Debug packages are only available for pkgbases that produced
+        // them, so list whatever has been uploaded so far.
+        let debug_repo_path =
+            debug_repo_dir_path(&namespace.name, current_iteration.id, architecture);
+        debug_packages = Some(DebugPackagesView {
+            repo_name: debug_repo_name(&namespace.name, current_iteration.id).to_string(),
+            file_names: list_package_files(&debug_repo_path).await?,
+        });
+
+        package_signatures = db::package_signature::list_by_iteration_and_architecture(
+            &state.db_pool,
+            current_iteration.id,
+            architecture,
+        )
+        .await?
+        .into_iter()
+        .map(|signature| PackageSignatureView {
+            status_icon: signature.status.as_icon(),
+            pkgbase: signature.pkgbase,
+            file_name: signature.file_name,
+            status: signature.status,
+        })
+        .collect();
     }
 
     let template = state
@@ -393,9 +662,24 @@ pub(crate) async fn show_build_namespace_iteration_architecture_html(
     let rendered = template
         .render(context! {
             namespace => namespace,
+            parent_namespace_name => parent_namespace_name,
+            rebuild_branch_name => rebuild_branch_name(&namespace.name),
+            release_status_icon => release.as_ref().map(|release| release.status.as_icon()),
+            release => release,
+            blocking_release_locks => blocking_release_locks,
+            release_overrides => release_overrides,
+            ready_for_release_at => ready_for_release_at,
+            merge_requests => merge_requests,
+            origin_changeset_rebases => origin_changeset_rebases,
+            srcinfo_checks => srcinfo_checks,
             iteration_table => iteration_table,
             current_iteration => current_iteration.as_ref().map(IterationView::from_iteration).transpose()?,
             pipeline_table => pipeline_table,
+            debug_packages => debug_packages,
+            repo_db_name => repo_db_name(&namespace.name),
+            debug_repo_db_name => debug_repo_db_name(&namespace.name),
+            sig_level => repo_sig_level(state.signer.is_some()),
+            package_signatures => package_signatures,
             base_url => state.base_url,
             architecture => architecture,
         })
@@ -413,6 +697,14 @@ pub(crate) async fn show_build_namespace_iteration_architecture_json(
     State(state): State<AppState>,
 ) -> ResponseResult<Json<ShowNamespaceJson>> {
     let namespace = db::namespace::read_by_name(&namespace_name, &state.db_pool).await?;
+    let merge_requests = read_tracked_merge_requests(&state.db_pool, namespace.id).await?;
+    let ready_for_release = db::namespace::read_ready_for_release_at(&state.db_pool, namespace.id)
+        .await?
+        .is_some();
+    let origin_changeset_rebases =
+        db::origin_changeset_rebase::list_for_namespace(&state.db_pool, namespace.id).await?;
+    let srcinfo_checks =
+        db::srcinfo_check::list_for_namespace(&state.db_pool, namespace.id).await?;
 
     let iterations = db::iteration::list_for_namespace(&state.db_pool, namespace.id).await?;
 
@@ -427,6 +719,10 @@ pub(crate) async fn show_build_namespace_iteration_architecture_json(
             return Ok(Json(ShowNamespaceJson {
                 architecture_iteration: None,
                 namespace,
+                merge_requests,
+                ready_for_release,
+                origin_changeset_rebases,
+                srcinfo_checks,
             }));
         }
     };
@@ -446,9 +742,24 @@ pub(crate) async fn show_build_namespace_iteration_architecture_json(
             build_graph,
         }),
         namespace,
+        merge_requests,
+        ready_for_release,
+        origin_changeset_rebases,
+        srcinfo_checks,
     }))
 }
 
+async fn read_tracked_merge_requests(
+    pool: &sqlx::SqlitePool,
+    namespace_id: Uuid,
+) -> Result<Vec<TrackedMergeRequest>> {
+    db::gitlab_merge_request::list_for_namespace(pool, namespace_id)
+        .await?
+        .into_iter()
+        .map(|merge_request| merge_request.into_tracked())
+        .collect()
+}
+
 #[debug_handler]
 pub(crate) async fn render_build_namespace_graph(
     Path((_namespace_name, iteration_id, architecture)): Path<(String, Uuid, ConcreteArchitecture)>,
@@ -516,47 +827,72 @@ pub async fn create_namespace_iteration(
     Path(namespace_name): Path<String>,
     State(state): State<AppState>,
     Json(body): Json<()>,
-) -> Result<Json<BuildSetIteration>, StatusCode> {
+) -> Result<Response, StatusCode> {
     let namespace = db::namespace::read_by_name(&namespace_name, &state.db_pool)
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
+    if state.namespace_update_options.stale_srcinfo == crate::args::StaleSrcinfo::Block {
+        let blocking = crate::tasks::blocking_srcinfo_checks(&state.db_pool, &namespace)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        if !blocking.is_empty() {
+            tracing::info!(
+                r#"Not creating an iteration for "{namespace_name}", .SRCINFO checks are pending or failed: {blocking:?}"#
+            );
+            return Ok((
+                StatusCode::CONFLICT,
+                Json(BlockingSrcinfoChecksJson { blocking }),
+            )
+                .into_response());
+        }
+    }
+
     // TODO calculate build graph for new iteration in the background
-    let mut source_repos = SourceRepos::new()
+    let mut source_repos = state.namespace_update_options.source_repos.lock().await;
+    source_repos
+        .refresh()
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let new_iteration = BuildSetIteration {
-        id: Uuid::new_v4(),
-        created_at: time::OffsetDateTime::now_utc(),
-        origin_changesets: namespace.current_origin_changesets.clone(),
-        packages_to_be_built: calculate_packages_to_be_built(&namespace, &mut source_repos)
+    let parent = db::namespace::read_parent_context(&state.db_pool, &namespace)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let packages_to_build =
+        calculate_packages_to_build(&namespace, parent.as_ref(), &mut source_repos)
             .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
-        create_reason: buildbtw_poc::iteration::NewIterationReason::CreatedByUser,
-        namespace_id: namespace.id,
-    };
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    drop(source_repos);
+    let new_iteration = create_build_set_iteration(
+        &namespace,
+        parent.as_ref(),
+        packages_to_build,
+        NewIterationReason::CreatedByUser,
+        &state.namespace_update_options.credentials,
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
     db::iteration::create(&state.db_pool, new_iteration.clone())
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     tracing::debug!(r#"Updated build namespace "{namespace_name}": {body:?}"#);
 
-    Ok(Json(new_iteration))
+    Ok(Json(new_iteration).into_response())
 }
 
 pub async fn upload_package(
-    Path((iteration_id, pkgbase, pkgname, architecture)): Path<(
+    Path((iteration_id, pkgbase, architecture, file_name)): Path<(
         Uuid,
         Pkgbase,
-        Pkgname,
         ConcreteArchitecture,
+        String,
     )>,
     State(state): State<AppState>,
     request: Request,
 ) -> ResponseResult<()> {
     // Read version info from the database
-    // And verify that pkgbase, pkgname and architecture actually exist
-    // in the given iteration
+    // And verify that pkgbase, architecture and the package file name
+    // actually exist in the given iteration
     let iteration = db::iteration::read(&state.db_pool, iteration_id).await?;
     let namespace = db::namespace::read(iteration.namespace_id, &state.db_pool).await?;
 
@@ -572,18 +908,29 @@ pub async fn upload_package(
         .ok_or(ResponseError::NotFound("pkgbase"))?
         .weight;
 
-    let package = package_for_architecture(&node.srcinfo, architecture, &pkgname)
-        .ok_or(ResponseError::NotFound("pkgname"))?;
+    // Only accept file names matching one of the split packages or the
+    // debug package, with any compression supported by makepkg.
+    // Debug packages go into a separate repository.
+    let is_debug_package = is_debug_package_file(&node.srcinfo, architecture, &file_name);
+    if !is_debug_package {
+        let package = package_for_file_name(&node.srcinfo, architecture, &file_name)
+            .ok_or(ResponseError::NotFound("package file name"))?;
+        tracing::debug!("Receiving package file {file_name} for {}", package.name);
+    }
 
     // Calculate path for writing the file
     // This should only use safe inputs such as those read from the DB,
     // or enums like `ConcreteArchitecture`
-    let repo_path = repo_dir_path(&namespace.name, iteration.id, architecture);
+    let repo_path = if is_debug_package {
+        debug_repo_dir_path(&namespace.name, iteration.id, architecture)
+    } else {
+        repo_dir_path(&namespace.name, iteration.id, architecture)
+    };
     fs::create_dir_all(&repo_path).await?;
 
     // TODO this is probably paranoid, but I think a version like `../../../../../etc/passwd` might actually be valid
     // An attack like that would require a malicious .SRCINFO, though
-    let path = repo_path.join(package_file_name(&package, &node.srcinfo)?);
+    let path = repo_path.join(&file_name);
     if tokio::fs::try_exists(&path).await? {
         // This should only happen if a builder was temporarily unreachable
         // so the build got scheduled elsewhere as well
@@ -593,11 +940,547 @@ pub async fn upload_package(
     // TODO ensure no package exists for the given build yet
     stream_to_file(&path, request.into_body().into_data_stream()).await?;
 
-    add_to_repo(&repo_path, &package, &node.srcinfo).await?;
+    // Sign the package before it becomes visible in the repository.
+    // If signing is enabled, any signing failure (of the package or of the
+    // repository database) fails the upload, as pacman would reject the
+    // unsigned files anyway. The package file is removed in that case,
+    // so that the next upload attempt starts over.
+    if let Err(e) = sign_and_add_package(
+        &state,
+        &iteration,
+        &namespace.name,
+        &pkgbase,
+        architecture,
+        &repo_path,
+        &file_name,
+        is_debug_package,
+    )
+    .await
+    {
+        for path in [path.clone(), signature_path(&path)] {
+            match fs::remove_file(&path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    tracing::error!("Failed to remove {path}: {e:?}");
+                }
+                _ => {}
+            }
+        }
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn sign_and_add_package(
+    state: &AppState,
+    iteration: &BuildSetIteration,
+    namespace_name: &str,
+    pkgbase: &Pkgbase,
+    architecture: ConcreteArchitecture,
+    repo_path: &Utf8Path,
+    file_name: &str,
+    is_debug_package: bool,
+) -> color_eyre::Result<()> {
+    let Some(signer) = &state.signer else {
+        return if is_debug_package {
+            add_to_debug_repo(repo_path, namespace_name, file_name, None).await
+        } else {
+            add_to_repo(repo_path, namespace_name, file_name, None).await
+        };
+    };
+
+    let set_signing_status = async |status| {
+        db::package_signature::set_status(
+            &state.db_pool,
+            db::package_signature::SetDbPackageSignatureStatus {
+                build_set_iteration_id: iteration.id,
+                pkgbase: pkgbase.clone(),
+                architecture,
+                file_name: file_name.to_string(),
+                status,
+            },
+        )
+        .await
+    };
+    set_signing_status(SigningStatus::Pending).await?;
+
+    let result = async {
+        sign_file(signer, &repo_path.join(file_name)).await?;
+        if is_debug_package {
+            add_to_debug_repo(repo_path, namespace_name, file_name, Some(signer)).await
+        } else {
+            add_to_repo(repo_path, namespace_name, file_name, Some(signer)).await
+        }
+    }
+    .await;
+
+    let status = if result.is_ok() {
+        SigningStatus::Signed
+    } else {
+        SigningStatus::Failed
+    };
+    set_signing_status(status).await?;
+
+    result
+}
+
+/// Receive a log file of a local build.
+pub async fn upload_build_log(
+    Path((iteration_id, pkgbase, architecture, file_name)): Path<(
+        Uuid,
+        Pkgbase,
+        ConcreteArchitecture,
+        String,
+    )>,
+    State(state): State<AppState>,
+    request: Request,
+) -> ResponseResult<()> {
+    if !is_valid_log_file_name(&file_name) {
+        return Err(ResponseError::InvalidInput(format!(
+            "Invalid log file name: {file_name}"
+        )));
+    }
+
+    // Only accept logs for builds that exist in the given iteration
+    let iteration = db::iteration::read(&state.db_pool, iteration_id).await?;
+    let graph = iteration
+        .packages_to_be_built
+        .get(&architecture)
+        .ok_or(ResponseError::NotFound("architecture"))?;
+    let node = &graph
+        .raw_nodes()
+        .iter()
+        .find(|node| node.weight.pkgbase == pkgbase)
+        .ok_or(ResponseError::NotFound("pkgbase"))?
+        .weight;
+
+    let dir = build_logs_dir_path(iteration.id, architecture, &node.pkgbase);
+    fs::create_dir_all(&dir).await?;
+    // Workers include the build attempt in the file name,
+    // see `local_build_log_file_name`, so earlier attempts are kept.
+    stream_to_file(
+        &dir.join(&file_name),
+        request.into_body().into_data_stream(),
+    )
+    .await?;
 
     Ok(())
 }
 
+#[derive(Deserialize)]
+pub struct PacmanConfQuery {
+    /// If omitted, pacman's `$arch` variable is used instead.
+    architecture: Option<ConcreteArchitecture>,
+}
+
+/// Render a `pacman.conf` snippet for the stable "latest" repository
+/// of a namespace, which doesn't change between iterations.
+pub(crate) async fn show_pacman_conf(
+    Path(namespace_name): Path<String>,
+    Query(query): Query<PacmanConfQuery>,
+    State(state): State<AppState>,
+) -> ResponseResult<String> {
+    let namespace = db::namespace::read_by_name(&namespace_name, &state.db_pool).await?;
+
+    let template = state.jinja_env.get_template("pacman_conf").unwrap();
+    let rendered = template
+        .render(context! {
+            repo_db_name => repo_db_name(&namespace.name),
+            debug_repo_db_name => debug_repo_db_name(&namespace.name),
+            namespace_name => namespace.name,
+            repo_name => latest_repo_name(&namespace.name),
+            debug_repo_name => latest_debug_repo_name(&namespace.name),
+            architecture => query
+                .architecture
+                .map(|architecture| architecture.to_string())
+                .unwrap_or("$arch".to_string()),
+            sig_level => repo_sig_level(state.signer.is_some()),
+            base_url => state.base_url,
+        })
+        .wrap_err("Failed to render pacman.conf snippet")?;
+
+    Ok(rendered)
+}
+
+#[derive(Deserialize)]
+pub struct BuildPacmanConfQuery {
+    /// URL under which the build environment can reach the repositories
+    /// served by this server. Defaults to `{base_url}/repo/`.
+    repo_base_url: Option<Url>,
+}
+
+/// Render the `pacman.conf` to use for building packages in the given iteration,
+/// based on the base repositories of its namespace.
+pub(crate) async fn show_build_pacman_conf(
+    Path((iteration_id, architecture)): Path<(Uuid, ConcreteArchitecture)>,
+    Query(query): Query<BuildPacmanConfQuery>,
+    State(state): State<AppState>,
+) -> ResponseResult<String> {
+    let iteration = db::iteration::read(&state.db_pool, iteration_id).await?;
+    let namespace = db::namespace::read(iteration.namespace_id, &state.db_pool).await?;
+
+    let repo_base_url = match query.repo_base_url {
+        Some(url) => url,
+        None => state
+            .base_url
+            .join("repo/")
+            .wrap_err("Failed to parse URL")?,
+    };
+
+    let parent_name = match namespace.parent_id {
+        Some(parent_id) => Some(db::namespace::read(parent_id, &state.db_pool).await?.name),
+        None => None,
+    };
+    let parent_iteration = parent_name.as_deref().zip(iteration.parent_iteration_id);
+
+    Ok(render_build_pacman_conf(
+        &repo_base_url,
+        &namespace.name,
+        iteration.id,
+        architecture,
+        &namespace.base_repositories,
+        parent_iteration,
+        state.signer.is_some(),
+    ))
+}
+
+/// Compute a release plan from the newest iteration of the namespace.
+/// The plan has to be confirmed before anything is released.
+pub(crate) async fn create_release(
+    Path(namespace_name): Path<String>,
+    State(state): State<AppState>,
+    Json(body): Json<CreateRelease>,
+) -> ResponseResult<Json<Release>> {
+    let namespace = db::namespace::read_by_name(&namespace_name, &state.db_pool).await?;
+    let tracks_merge_requests =
+        !db::gitlab_merge_request::list_for_namespace(&state.db_pool, namespace.id)
+            .await?
+            .is_empty();
+    if tracks_merge_requests
+        && db::namespace::read_ready_for_release_at(&state.db_pool, namespace.id)
+            .await?
+            .is_none()
+    {
+        return Err(ResponseError::InvalidInput(
+            "Not all merge requests of this namespace are merged yet".to_string(),
+        ));
+    }
+    let newest_release =
+        db::release::read_newest_for_namespace(&state.db_pool, namespace.id).await?;
+    if newest_release.is_some_and(|release| {
+        matches!(
+            release.status,
+            ReleaseStatus::Confirmed | ReleaseStatus::Releasing
+        )
+    }) {
+        return Err(ResponseError::InvalidInput(
+            "This namespace is already being released".to_string(),
+        ));
+    }
+
+    let iteration = db::iteration::read_newest(&state.db_pool, namespace.id)
+        .await
+        .map_err(|_| ResponseError::NotFound("iteration"))?;
+    let overrides = db::release_override::list_for_namespace(&state.db_pool, namespace.id).await?;
+    let plan = compute_release_plan(
+        &namespace.name,
+        &iteration,
+        &body.target_repository,
+        body.strategy,
+        &overrides,
+    )
+    .await
+    .map_err(|e| ResponseError::InvalidInput(format!("{e:#}")))?;
+
+    let release = db::release::create(&state.db_pool, namespace.id, plan).await?;
+    tracing::info!("{}", release.plan.summary());
+
+    Ok(Json(release))
+}
+
+pub(crate) async fn show_newest_release(
+    Path(namespace_name): Path<String>,
+    State(state): State<AppState>,
+) -> ResponseResult<Json<Release>> {
+    let namespace = db::namespace::read_by_name(&namespace_name, &state.db_pool).await?;
+    let release = db::release::read_newest_for_namespace(&state.db_pool, namespace.id)
+        .await?
+        .ok_or(ResponseError::NotFound("release"))?;
+
+    Ok(Json(release))
+}
+
+/// Add a planned release to the release queue and lock its pkgbases in the target repository.
+/// If other releases hold some of these locks, the release waits in the queue until they're free.
+pub(crate) async fn confirm_release(
+    Path((namespace_name, release_id)): Path<(String, Uuid)>,
+    State(state): State<AppState>,
+) -> ResponseResult<Json<ConfirmReleaseJson>> {
+    let namespace = db::namespace::read_by_name(&namespace_name, &state.db_pool).await?;
+    let release = db::release::read(&state.db_pool, release_id).await?;
+    if release.namespace_id != namespace.id {
+        return Err(ResponseError::NotFound("release"));
+    }
+    if release.status != ReleaseStatus::Planned {
+        return Err(ResponseError::InvalidInput(format!(
+            "Only planned releases can be confirmed, this release is {:?}",
+            release.status
+        )));
+    }
+
+    // Don't release outdated packages if the namespace moved on since planning.
+    let newest_iteration = db::iteration::read_newest(&state.db_pool, namespace.id).await?;
+    if newest_iteration.id != release.plan.iteration_id {
+        return Err(ResponseError::InvalidInput(
+            "The namespace has a new iteration since this release was planned, please create a new release plan".to_string(),
+        ));
+    }
+
+    // Confirm and lock in one transaction so a release is never left confirmed
+    // after failing to acquire its locks.
+    let mut transaction = state.db_pool.begin_with("begin immediate").await?;
+    db::release::set_status(
+        &mut *transaction,
+        release.id,
+        ReleaseStatus::Confirmed,
+        None,
+    )
+    .await?;
+    let blocking_locks =
+        db::release_lock::try_acquire_in_transaction(&mut transaction, &release).await?;
+    transaction.commit().await?;
+    let release = db::release::read(&state.db_pool, release.id).await?;
+
+    Ok(Json(ConfirmReleaseJson {
+        release,
+        blocking_locks,
+    }))
+}
+
+/// Cancel the newest release of the namespace if it hasn't started moving packages yet,
+/// freeing its place in the release queue.
+pub(crate) async fn cancel_release(
+    Path(namespace_name): Path<String>,
+    State(state): State<AppState>,
+) -> ResponseResult<Json<Release>> {
+    let namespace = db::namespace::read_by_name(&namespace_name, &state.db_pool).await?;
+    let release = db::release::read_newest_for_namespace(&state.db_pool, namespace.id)
+        .await?
+        .ok_or(ResponseError::NotFound("release"))?;
+    if !matches!(
+        release.status,
+        ReleaseStatus::Planned | ReleaseStatus::Confirmed
+    ) {
+        return Err(ResponseError::InvalidInput(format!(
+            "Only planned or confirmed releases can be cancelled, this release is {:?}",
+            release.status
+        )));
+    }
+
+    db::release::finish(&state.db_pool, release.id, ReleaseStatus::Cancelled, None).await?;
+    let release = db::release::read(&state.db_pool, release.id).await?;
+
+    Ok(Json(release))
+}
+
+/// Include or exclude a pkgbase in future releases of the namespace,
+/// regardless of the release strategy. Can be changed while builds are running.
+pub(crate) async fn set_release_override(
+    Path((namespace_name, pkgbase)): Path<(String, Pkgbase)>,
+    State(state): State<AppState>,
+    Json(body): Json<SetReleaseOverride>,
+) -> ResponseResult<Json<BTreeMap<Pkgbase, ReleaseOverride>>> {
+    let namespace = db::namespace::read_by_name(&namespace_name, &state.db_pool).await?;
+    db::release_override::set(
+        &state.db_pool,
+        namespace.id,
+        &pkgbase,
+        body.release_override,
+    )
+    .await?;
+    let overrides = db::release_override::list_for_namespace(&state.db_pool, namespace.id).await?;
+
+    Ok(Json(overrides))
+}
+
+/// Retry the gitlab pipeline of a failed build in the namespace's newest iteration.
+pub(crate) async fn retry_pipeline(
+    Path((namespace_name, architecture, pkgbase)): Path<(String, ConcreteArchitecture, Pkgbase)>,
+    State(state): State<AppState>,
+) -> ResponseResult<Json<Vec<PipelineAttempt>>> {
+    let (gitlab_args, iteration, pipeline) =
+        read_newest_pipeline(&state, &namespace_name, architecture, &pkgbase).await?;
+    let status = node_status(&iteration, architecture, &pkgbase)?;
+    if status != PackageBuildStatus::Failed {
+        return Err(ResponseError::InvalidInput(format!(
+            "Only failed builds can be retried, {pkgbase} is {status:?}"
+        )));
+    }
+
+    let client = tasks::new_gitlab_client(&gitlab_args).await?;
+    let pipeline_id = pipeline.pipeline_id()?;
+    let pipeline_status = buildbtw_poc::gitlab::retry_pipeline(
+        &client,
+        pipeline_id.project_id,
+        pipeline_id.pipeline_id,
+    )
+    .await?;
+    tracing::info!(
+        "Retried pipeline {}: {pipeline_status:?}",
+        pipeline.gitlab_url
+    );
+
+    db::gitlab_pipeline::create(
+        &state.db_pool,
+        db::gitlab_pipeline::CreateDbGitlabPipeline {
+            build_set_iteration_id: iteration.id.into(),
+            pkgbase: pkgbase.clone(),
+            architecture,
+            project_gitlab_iid: pipeline.project_gitlab_iid,
+            gitlab_iid: pipeline.gitlab_iid,
+            gitlab_url: Url::parse(&pipeline.gitlab_url).wrap_err("Invalid pipeline URL")?,
+            generation: pipeline.generation + 1,
+            status: pipeline_status,
+        },
+    )
+    .await?;
+
+    // Track the node via its pipeline again, unless it was changed in the meantime
+    db::iteration::set_build_status(
+        &state.db_pool,
+        iteration.id,
+        architecture,
+        &pkgbase,
+        pipeline_status.into(),
+        |current| current == PackageBuildStatus::Failed,
+    )
+    .await?;
+
+    Ok(Json(
+        list_pipeline_attempts(&state, iteration.id, architecture, &pkgbase).await?,
+    ))
+}
+
+/// Cancel a running build in the namespace's newest iteration,
+/// using the dispatcher that runs the namespace's builds.
+/// For gitlab pipelines, the build is marked as failed once gitlab reports the pipeline as canceled.
+pub(crate) async fn cancel_pipeline(
+    Path((namespace_name, architecture, pkgbase)): Path<(String, ConcreteArchitecture, Pkgbase)>,
+    State(state): State<AppState>,
+) -> ResponseResult<Json<Vec<PipelineAttempt>>> {
+    let namespace = db::namespace::read_by_name(&namespace_name, &state.db_pool).await?;
+    let iteration = db::iteration::read_newest(&state.db_pool, namespace.id).await?;
+    let status = node_status(&iteration, architecture, &pkgbase)?;
+    if !matches!(
+        status,
+        PackageBuildStatus::Building | PackageBuildStatus::Scheduled
+    ) {
+        return Err(ResponseError::InvalidInput(format!(
+            "Only running builds can be cancelled, {pkgbase} is {status:?}"
+        )));
+    }
+
+    state
+        .build_dispatchers
+        .for_build(&namespace, architecture)?
+        .cancel(
+            &state.db_pool,
+            &DispatchedBuild {
+                iteration_id: iteration.id,
+                architecture,
+                pkgbase: pkgbase.clone(),
+            },
+        )
+        .await?;
+
+    Ok(Json(
+        list_pipeline_attempts(&state, iteration.id, architecture, &pkgbase).await?,
+    ))
+}
+
+/// Read the newest pipeline generation of a build node in the namespace's newest iteration.
+async fn read_newest_pipeline(
+    state: &AppState,
+    namespace_name: &str,
+    architecture: ConcreteArchitecture,
+    pkgbase: &Pkgbase,
+) -> ResponseResult<(
+    args::Gitlab,
+    BuildSetIteration,
+    db::gitlab_pipeline::DbGitlabPipeline,
+)> {
+    let Some(gitlab_args) = state.gitlab_args.clone() else {
+        return Err(ResponseError::NotFound("gitlab integration"));
+    };
+    let namespace = db::namespace::read_by_name(namespace_name, &state.db_pool).await?;
+    let iteration = db::iteration::read_newest(&state.db_pool, namespace.id).await?;
+    let pipeline = db::gitlab_pipeline::read_by_iteration_and_pkgbase_and_architecture(
+        &state.db_pool,
+        iteration.id,
+        pkgbase,
+        architecture,
+    )
+    .await?
+    .ok_or(ResponseError::NotFound("gitlab pipeline"))?;
+
+    Ok((gitlab_args, iteration, pipeline))
+}
+
+fn node_status(
+    iteration: &BuildSetIteration,
+    architecture: ConcreteArchitecture,
+    pkgbase: &Pkgbase,
+) -> ResponseResult<PackageBuildStatus> {
+    iteration
+        .packages_to_be_built
+        .get(&architecture)
+        .ok_or(ResponseError::NotFound("architecture"))?
+        .node_weights()
+        .find(|node| &node.pkgbase == pkgbase)
+        .map(|node| node.status)
+        .ok_or(ResponseError::NotFound("pkgbase"))
+}
+
+async fn list_pipeline_attempts(
+    state: &AppState,
+    iteration_id: Uuid,
+    architecture: ConcreteArchitecture,
+    pkgbase: &Pkgbase,
+) -> Result<Vec<PipelineAttempt>> {
+    Ok(
+        db::gitlab_pipeline::list_by_iteration_and_pkgbase_and_architecture(
+            &state.db_pool,
+            iteration_id,
+            pkgbase,
+            architecture,
+        )
+        .await?
+        .into_iter()
+        .map(|pipeline| PipelineAttempt {
+            generation: pipeline.generation,
+            status: pipeline.status,
+            gitlab_url: pipeline.gitlab_url,
+        })
+        .collect(),
+    )
+}
+
+pub(crate) async fn list_release_locks(
+    State(state): State<AppState>,
+) -> ResponseResult<Json<Vec<ReleaseLock>>> {
+    let locks = db::release_lock::list(&state.db_pool).await?;
+    Ok(Json(locks))
+}
+
+/// Source repositories excluded from build graphs, and why.
+pub(crate) async fn list_retired_source_repos(
+    State(state): State<AppState>,
+) -> ResponseResult<Json<Vec<RetiredSourceRepo>>> {
+    let retired = db::retired_source_repo::list(&state.db_pool).await?;
+    Ok(Json(retired))
+}
+
 pub async fn set_build_status(
     Path((iteration_id, pkgbase, architecture)): Path<(Uuid, Pkgbase, ConcreteArchitecture)>,
     State(state): State<AppState>,
@@ -610,14 +1493,142 @@ pub async fn set_build_status(
         body.status
     );
     let iteration = db::iteration::read(&state.db_pool, iteration_id).await?;
+    node_status(&iteration, architecture, &pkgbase)?;
+
+    db::iteration::set_build_status(
+        &state.db_pool,
+        iteration.id,
+        architecture,
+        &pkgbase,
+        body.status,
+        |_| true,
+    )
+    .await?;
 
-    let iteration = iteration.set_build_status(architecture, pkgbase, body.status)?;
-    let update = BuildSetIterationUpdate {
-        id: iteration.id,
-        packages_to_be_built: iteration.packages_to_be_built,
+    Ok(())
+}
+
+/// Receive push, tag push and pipeline events from GitLab.
+/// Push events fetch the affected source repository, pipeline events
+/// update the status of the corresponding build node.
+pub(crate) async fn gitlab_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ResponseResult<()> {
+    let Some(gitlab_args) = state.gitlab_args else {
+        return Err(ResponseError::NotFound("gitlab integration"));
     };
+    let Some(signing_token) = &gitlab_args.gitlab_webhook_signing_token else {
+        return Err(ResponseError::NotFound("gitlab webhook"));
+    };
+
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| ResponseError::Unauthorized(format!("Missing {name} header")))
+    };
+    webhook::verify_signature(
+        signing_token.expose_secret(),
+        header(webhook::WEBHOOK_ID_HEADER)?,
+        header(webhook::WEBHOOK_TIMESTAMP_HEADER)?,
+        header(webhook::WEBHOOK_SIGNATURE_HEADER)?,
+        &body,
+        time::OffsetDateTime::now_utc(),
+    )
+    .map_err(|e| ResponseError::Unauthorized(format!("{e:#}")))?;
 
-    db::iteration::update(&state.db_pool, update).await?;
+    let event: WebhookEvent = serde_json::from_slice(&body)
+        .map_err(|e| ResponseError::InvalidInput(format!("Invalid webhook event: {e}")))?;
+    tracing::debug!("Received gitlab webhook event: {event:?}");
+
+    match event {
+        WebhookEvent::Push { project } | WebhookEvent::TagPush { project } => {
+            if !project.is_in_group(&gitlab_args.gitlab_packages_group) {
+                return Ok(());
+            }
+            let Some(packaging_remote) = state.packaging_remote else {
+                return Ok(());
+            };
+            // GitLab expects a quick response, so fetch in the background.
+            // The namespace update loop will pick up the changes.
+            let source_repo_changes = state.namespace_update_options.source_repo_changes.clone();
+            tokio::spawn(async move {
+                let pkgbase: Pkgbase = project.name.into();
+                match clone_or_fetch_repositories(vec![pkgbase.clone()], packaging_remote).await {
+                    Ok(()) => source_repo_changes.notify([pkgbase]),
+                    Err(e) => {
+                        tracing::error!("Failed to fetch source repository after push: {e:?}")
+                    }
+                }
+            });
+        }
+        WebhookEvent::Pipeline {
+            project,
+            object_attributes,
+        } => {
+            // Ignore pipelines we didn't create.
+            let Some(pipeline) = db::gitlab_pipeline::read_by_gitlab_iids(
+                &state.db_pool,
+                project.id.try_into().wrap_err("Invalid project id")?,
+                object_attributes
+                    .id
+                    .try_into()
+                    .wrap_err("Invalid pipeline id")?,
+            )
+            .await?
+            else {
+                return Ok(());
+            };
+            tasks::update_build_set_graph_from_pipeline_status(
+                &state.db_pool,
+                &gitlab_args,
+                &pipeline,
+                object_attributes.status,
+            )
+            .await?;
+        }
+        WebhookEvent::MergeRequest {
+            project,
+            object_attributes,
+        } => {
+            if !project.is_in_group(&gitlab_args.gitlab_packages_group) {
+                return Ok(());
+            }
+            // Project names can't be mapped back to pkgbases, so compare project paths.
+            let tracked = db::gitlab_merge_request::list_by_iid(
+                &state.db_pool,
+                object_attributes
+                    .iid
+                    .try_into()
+                    .wrap_err("Invalid merge request id")?,
+            )
+            .await?
+            .into_iter()
+            .filter(|merge_request| {
+                merge_requests::project_path(
+                    &gitlab_args.gitlab_packages_group,
+                    &merge_request.pkgbase,
+                ) == project.path_with_namespace
+            });
+            for mut merge_request in tracked {
+                let new_state = object_attributes.state.into();
+                if merge_request.state == new_state {
+                    continue;
+                }
+                tracing::info!(
+                    "Merge request {} of {} is {new_state:?} now",
+                    object_attributes.iid,
+                    merge_request.pkgbase
+                );
+                merge_request.state = new_state;
+                db::gitlab_merge_request::update(&state.db_pool, &merge_request).await?;
+                tasks::update_ready_for_release(&state.db_pool, merge_request.namespace_id).await?;
+            }
+        }
+        WebhookEvent::Other => {}
+    }
 
     Ok(())
 }
@@ -1,19 +1,42 @@
-use std::time::Duration;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use ::gitlab::{AsyncGitlab, GitlabBuilder};
-use buildbtw_poc::source_repos::SourceRepos;
-use color_eyre::eyre::{Context, Result};
+use buildbtw_poc::source_repos::{SourceRepoChangeNotifier, SourceRepos};
+use color_eyre::eyre::{Context, OptionExt, Result};
 use sqlx::SqlitePool;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::{
+    sync::{Mutex, mpsc::UnboundedSender},
+    task::{JoinError, JoinSet},
+};
+use url::Url;
 use uuid::Uuid;
 
 use buildbtw_poc::{BuildNamespace, BuildSetIteration, ScheduleBuild, ScheduleBuildResult};
 use buildbtw_poc::{
     BuildNamespaceStatus, PackageBuildStatus,
     build_set_graph::{self, schedule_next_build_in_graph},
-    gitlab::{fetch_all_source_repo_changes, set_all_projects_ci_config},
-    iteration::{NewBuildIterationResult, new_build_set_iteration_is_needed},
+    git::{GitCredentials, is_commit_hash, read_branch_commit_hash},
+    gitlab::{
+        CommitBuildStatus, CommitStatus, PipelineStatus,
+        merge_requests::{
+            ArchitectureSummary, TrackedMergeRequestState, find_or_create_merge_request,
+            get_merge_request, ready_for_release, render_summary, upsert_summary_note,
+        },
+        set_all_projects_ci_config, set_commit_status,
+    },
+    iteration::{
+        NewBuildIterationResult, create_build_set_iteration, new_build_set_iteration_is_needed,
+    },
     pacman_repo,
+    rebase::{OriginChangesetRebase, REBASE_ONTO_BRANCH, RebaseOutcome, rebase_branch_onto},
+    release::{ReleaseStatus, execute_release_plan},
+    signing::SignerBackend,
+    source_info::ConcreteArchitecture,
+    source_provider::{
+        GitSourceProvider, GitlabSourceProvider, SourceProvider, SourceProviderBackend,
+        SourceRepoChanges,
+    },
+    srcinfo_check::{BlockingSrcinfoCheck, SrcinfoCheck, SrcinfoCheckOutcome, check_srcinfo},
 };
 
 use crate::{
@@ -22,22 +45,25 @@ use crate::{
         self,
         global_state::{get_gitlab_last_updated, set_gitlab_last_updated},
     },
+    dispatch::{
+        BuildDispatcher, BuildDispatchers, DispatchedBuild, Dispatcher,
+        archive_pipeline_jobs_in_background,
+    },
 };
 
 pub enum Message {}
 
-/// If the `dispatch_builds_to_gitlab` option is `true`, we'll create this struct
-/// to encapsulate all options needed to dispatch those builds.
-/// TODO: rename to something that reflects the implicit connection to that option
-struct GitlabContext {
-    args: args::Gitlab,
-    client: gitlab::AsyncGitlab,
-}
+/// Each .SRCINFO check runs makepkg, so only run a few at a time.
+const MAX_CONCURRENT_SRCINFO_CHECKS: usize = 4;
 
 pub async fn start(
     pool: SqlitePool,
     gitlab_args: Option<args::Gitlab>,
-    server_port: u16,
+    sources: args::Sources,
+    options: Arc<NamespaceUpdateOptions>,
+    build_dispatchers: Arc<BuildDispatchers>,
+    base_url: Url,
+    signer: Option<SignerBackend>,
 ) -> Result<UnboundedSender<Message>> {
     tracing::info!("Starting server tasks");
 
@@ -50,77 +76,109 @@ pub async fn start(
     //     }
     // });
 
-    if let Some(args) = &gitlab_args {
-        fetch_source_repo_changes_in_loop(pool.clone(), args.clone()).await?;
+    if let Some(provider) = new_source_provider(gitlab_args.as_ref(), &sources).await? {
+        fetch_source_repo_changes_in_loop(
+            pool.clone(),
+            provider,
+            source_repo_polling_interval(gitlab_args.as_ref()),
+            options.source_repo_changes.clone(),
+        );
+    }
 
+    if let Some(args) = &gitlab_args {
         update_project_ci_settings_in_loop(args.clone()).await?;
+
+        if args.report_commit_statuses_to_gitlab {
+            report_commit_statuses_in_loop(pool.clone(), args.clone(), base_url.clone()).await?;
+        }
+
+        sync_merge_requests_in_loop(pool.clone(), args.clone(), base_url).await?;
     }
 
-    update_and_build_all_namespaces_in_loop(pool.clone(), gitlab_args, server_port).await?;
+    if options.stale_srcinfo != args::StaleSrcinfo::Off {
+        check_srcinfos_in_loop(pool.clone());
+    }
+    update_and_build_all_namespaces_in_loop(pool.clone(), build_dispatchers, options);
+
+    execute_confirmed_releases_in_loop(pool.clone(), signer);
 
     Ok(sender)
 }
 
-async fn new_gitlab_client(args: &args::Gitlab) -> Result<AsyncGitlab> {
-    GitlabBuilder::new(
+pub(crate) async fn new_gitlab_client(args: &args::Gitlab) -> Result<AsyncGitlab> {
+    let mut builder = GitlabBuilder::new(
         args.gitlab_domain.clone(),
         args.gitlab_token.expose_secret(),
-    )
-    .build_async()
-    .await
-    .wrap_err("Failed to create gitlab client")
+    );
+    if args.gitlab_insecure {
+        builder.insecure();
+    }
+    builder
+        .build_async()
+        .await
+        .wrap_err("Failed to create gitlab client")
 }
 
-async fn update_and_build_all_namespaces_in_loop(
+/// Settings and shared state for updating namespaces.
+pub struct NamespaceUpdateOptions {
+    /// Shared with the server routes so the .SRCINFO cache is only kept once.
+    /// Only hold the lock while calculating build graphs, as routes wait for it.
+    pub source_repos: Arc<Mutex<SourceRepos>>,
+    /// Notifier of `source_repos`, for reporting fetched and pushed source repos.
+    pub source_repo_changes: SourceRepoChangeNotifier,
+    /// For pushing rebased branches and pkgrel bumps.
+    pub credentials: GitCredentials,
+    pub stale_srcinfo: args::StaleSrcinfo,
+}
+
+fn update_and_build_all_namespaces_in_loop(
     pool: SqlitePool,
-    maybe_gitlab_args: Option<args::Gitlab>,
-    server_port: u16,
-) -> Result<()> {
-    let maybe_gitlab_context = if let Some(args) = maybe_gitlab_args {
-        if args.run_builds_on_gitlab {
-            Some(GitlabContext {
-                client: new_gitlab_client(&args).await?,
-                args,
-            })
-        } else {
-            None
-        }
-    } else {
-        None
-    };
+    build_dispatchers: Arc<BuildDispatchers>,
+    options: Arc<NamespaceUpdateOptions>,
+) {
     tokio::spawn(async move {
         loop {
-            match update_and_build_all_namespaces(&pool, maybe_gitlab_context.as_ref(), server_port)
-                .await
-            {
+            match update_and_build_all_namespaces(&pool, &build_dispatchers, &options).await {
                 Ok(_) => {}
                 Err(e) => tracing::error!("Error while updating build namespaces: {e:?}"),
             };
             tokio::time::sleep(Duration::from_secs(10)).await
         }
     });
-
-    Ok(())
 }
 
-/// If given a gitlab client, dispatch builds to gitlab.
-/// Otherwise, dispatch them to the local build client.
+/// Update the status of builds in flight, then create new iterations
+/// and dispatch builds for all active namespaces.
 async fn update_and_build_all_namespaces(
     pool: &SqlitePool,
-    maybe_gitlab_context: Option<&GitlabContext>,
-    server_port: u16,
+    build_dispatchers: &BuildDispatchers,
+    options: &NamespaceUpdateOptions,
 ) -> Result<()> {
-    // Update gitlab pipeline status for all iterations in all namespaces.
-    if let Some(gitlab_context) = maybe_gitlab_context {
-        let all_iterations = db::iteration::list(pool).await?;
-        let iteration_count = all_iterations.len();
-        tracing::info!("Updating gitlab pipeline statuses in {iteration_count} iteration(s)...");
+    // Update the status of builds in all iterations with builds in flight,
+    // including those of cancelled namespaces.
+    let in_flight_iterations: Vec<_> = db::iteration::list(pool)
+        .await?
+        .into_iter()
+        .filter(|iteration| {
+            iteration.packages_to_be_built.values().any(|graph| {
+                graph.node_weights().any(|node| {
+                    matches!(
+                        node.status,
+                        PackageBuildStatus::Building | PackageBuildStatus::Scheduled
+                    )
+                })
+            })
+        })
+        .collect();
+    if !in_flight_iterations.is_empty() {
+        let iteration_count = in_flight_iterations.len();
+        tracing::info!("Updating build statuses in {iteration_count} iteration(s)...");
 
         if let Err(e) =
-            update_build_set_graphs_from_gitlab_pipelines(pool, all_iterations, gitlab_context)
+            update_build_set_graphs_from_dispatchers(pool, in_flight_iterations, build_dispatchers)
                 .await
         {
-            tracing::error!(r#"Error updating gitlab pipeline statuses: {e:?}"#);
+            tracing::error!(r#"Error updating build statuses: {e:?}"#);
         }
     }
 
@@ -130,18 +188,21 @@ async fn update_and_build_all_namespaces(
     let namespace_count = active_namespaces.len();
     tracing::info!("Updating and dispatching builds for {namespace_count} active namespace(s)...");
 
-    let mut source_repos = SourceRepos::new().await?;
+    {
+        let mut source_repos = options.source_repos.lock().await;
+        source_repos.refresh().await?;
+        source_repos.set_excluded(
+            db::retired_source_repo::list(pool)
+                .await?
+                .into_iter()
+                .map(|retired| retired.pkgbase),
+        );
+    }
 
     for namespace in active_namespaces {
         // Try to build all namespaces, and continue on failures.
-        if let Err(e) = update_and_build_active_namespace(
-            pool,
-            maybe_gitlab_context,
-            &namespace,
-            server_port,
-            &mut source_repos,
-        )
-        .await
+        if let Err(e) =
+            update_and_build_active_namespace(pool, build_dispatchers, &namespace, options).await
         {
             tracing::error!(
                 r#"Error updating namespace "{name}": {e:?}"#,
@@ -157,51 +218,366 @@ async fn update_and_build_all_namespaces(
 
 async fn update_and_build_active_namespace(
     pool: &sqlx::Pool<sqlx::Sqlite>,
-    maybe_gitlab_context: Option<&GitlabContext>,
+    build_dispatchers: &BuildDispatchers,
     namespace: &BuildNamespace,
-    server_port: u16,
-    source_repos: &mut SourceRepos,
+    options: &NamespaceUpdateOptions,
 ) -> Result<()> {
-    create_new_namespace_iteration_if_needed(pool, namespace, source_repos).await?;
-    schedule_next_build_if_needed(pool, namespace, maybe_gitlab_context, server_port).await?;
+    if namespace.rebase_origin_changesets {
+        // Failed pushes shouldn't keep the namespace from building
+        let result = rebase_origin_changesets(pool, namespace, options).await;
+        if let Err(e) = result {
+            tracing::error!(
+                r#"Error rebasing origin changesets of namespace "{}": {e:?}"#,
+                namespace.name
+            );
+        }
+    }
+    let blocking_srcinfos = match options.stale_srcinfo {
+        args::StaleSrcinfo::Off | args::StaleSrcinfo::Warn => Vec::new(),
+        args::StaleSrcinfo::Block => blocking_srcinfo_checks(pool, namespace).await?,
+    };
+    if blocking_srcinfos.is_empty() {
+        create_new_namespace_iteration_if_needed(pool, namespace, options).await?;
+        schedule_next_build_if_needed(pool, namespace, build_dispatchers).await?;
+    } else {
+        tracing::warn!(
+            r#"Not building namespace "{}" until the .SRCINFO of {} is checked and up to date"#,
+            namespace.name,
+            blocking_srcinfos
+                .iter()
+                .map(|check| check.pkgbase.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    update_latest_repos(pool, namespace).await?;
 
     Ok(())
 }
 
-pub async fn fetch_source_repo_changes_in_loop(
-    db_pool: SqlitePool,
-    gitlab_args: args::Gitlab,
+fn check_srcinfos_in_loop(pool: SqlitePool) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = check_origin_changeset_srcinfos(&pool).await {
+                tracing::error!("Error while checking .SRCINFO files: {e:?}");
+            }
+            tokio::time::sleep(Duration::from_secs(10)).await
+        }
+    });
+}
+
+/// Compare the committed .SRCINFO of each origin changeset in active namespaces with
+/// its PKGBUILD. As generating the .SRCINFO is slow, this runs separately from the
+/// namespace loop, a few checks at a time. Each commit is only checked once,
+/// unless the check failed, e.g. because makepkg timed out.
+async fn check_origin_changeset_srcinfos(pool: &SqlitePool) -> Result<()> {
+    let mut join_set = JoinSet::new();
+    for namespace in db::namespace::list_by_status(pool, BuildNamespaceStatus::Active).await? {
+        let previous_checks = match db::srcinfo_check::list_for_namespace(pool, namespace.id).await
+        {
+            Ok(checks) => checks,
+            Err(e) => {
+                tracing::error!(
+                    r#"Failed to read .SRCINFO checks of namespace "{}": {e:?}"#,
+                    namespace.name
+                );
+                continue;
+            }
+        };
+        for (pkgbase, git_ref) in namespace.current_origin_changesets {
+            // The source repo might not have been fetched yet
+            let Ok(commit_hash) = read_branch_commit_hash(pkgbase.clone(), git_ref.clone()).await
+            else {
+                continue;
+            };
+            let checked = previous_checks.iter().any(|check| {
+                check.pkgbase == pkgbase
+                    && check.commit_hash == commit_hash
+                    && !matches!(check.outcome, SrcinfoCheckOutcome::Failed { .. })
+            });
+            if checked {
+                continue;
+            }
+
+            let pool = pool.clone();
+            join_set.spawn(async move {
+                let check = SrcinfoCheck {
+                    outcome: check_srcinfo(&pkgbase, &commit_hash).await,
+                    pkgbase: pkgbase.clone(),
+                    commit_hash,
+                    checked_at: time::OffsetDateTime::now_utc(),
+                };
+                match &check.outcome {
+                    SrcinfoCheckOutcome::UpToDate => {}
+                    SrcinfoCheckOutcome::Stale { .. } => tracing::warn!(
+                        "Committed .SRCINFO of {pkgbase}/{git_ref} doesn't match its PKGBUILD"
+                    ),
+                    SrcinfoCheckOutcome::Failed { error } => {
+                        tracing::warn!("Failed to check .SRCINFO of {pkgbase}/{git_ref}: {error}")
+                    }
+                }
+                db::srcinfo_check::set(&pool, namespace.id, &check).await
+            });
+            while join_set.len() >= MAX_CONCURRENT_SRCINFO_CHECKS {
+                log_srcinfo_check_error(join_set.join_next().await.unwrap());
+            }
+        }
+    }
+    while let Some(output) = join_set.join_next().await {
+        log_srcinfo_check_error(output);
+    }
+
+    Ok(())
+}
+
+/// Don't let a single failed check keep the checks of other namespaces from being stored.
+fn log_srcinfo_check_error(output: Result<Result<()>, JoinError>) {
+    let result = output
+        .wrap_err("Failed to join .SRCINFO check")
+        .and_then(|result| result);
+    if let Err(e) = result {
+        tracing::error!("Failed to store .SRCINFO check: {e:?}");
+    }
+}
+
+/// Origin changesets whose .SRCINFO at the current commit wasn't checked yet,
+/// couldn't be checked or is stale.
+pub async fn blocking_srcinfo_checks(
+    pool: &SqlitePool,
+    namespace: &BuildNamespace,
+) -> Result<Vec<BlockingSrcinfoCheck>> {
+    let checks = db::srcinfo_check::list_for_namespace(pool, namespace.id).await?;
+    let mut blocking = Vec::new();
+    for (pkgbase, git_ref) in &namespace.current_origin_changesets {
+        let commit_hash = read_branch_commit_hash(pkgbase.clone(), git_ref.clone())
+            .await
+            .ok();
+        let outcome = checks
+            .iter()
+            .find(|check| {
+                &check.pkgbase == pkgbase && Some(&check.commit_hash) == commit_hash.as_ref()
+            })
+            .map(|check| check.outcome.clone());
+        if outcome != Some(SrcinfoCheckOutcome::UpToDate) {
+            blocking.push(BlockingSrcinfoCheck {
+                pkgbase: pkgbase.clone(),
+                git_ref: git_ref.clone(),
+                outcome,
+            });
+        }
+    }
+
+    Ok(blocking)
+}
+
+/// Rebase origin changeset branches that are behind `main`.
+/// Rebased branches have a new commit hash, which causes a new iteration.
+/// Conflicting rebases aren't retried until `main` moves again.
+async fn rebase_origin_changesets(
+    pool: &SqlitePool,
+    namespace: &BuildNamespace,
+    options: &NamespaceUpdateOptions,
 ) -> Result<()> {
-    let client = new_gitlab_client(&gitlab_args).await?;
+    let previous_rebases =
+        db::origin_changeset_rebase::list_for_namespace(pool, namespace.id).await?;
+    for (pkgbase, branch) in &namespace.current_origin_changesets {
+        // Pinned commits aren't branches that could be rebased
+        if branch == REBASE_ONTO_BRANCH || is_commit_hash(branch) {
+            continue;
+        }
+        let onto =
+            match read_branch_commit_hash(pkgbase.clone(), REBASE_ONTO_BRANCH.to_string()).await {
+                Ok(onto) => onto,
+                Err(e) => {
+                    tracing::warn!("Can't rebase {pkgbase}/{branch}, failed to read main: {e:?}");
+                    continue;
+                }
+            };
+        let previous = previous_rebases
+            .iter()
+            .find(|rebase| &rebase.pkgbase == pkgbase && &rebase.branch_name == branch);
+        let known_conflict = previous.is_some_and(|previous| {
+            previous.onto_commit_hash == onto
+                && matches!(previous.outcome, RebaseOutcome::Conflict { .. })
+        });
+        if known_conflict {
+            continue;
+        }
+
+        // Failures are recorded and retried, without keeping the namespace from building.
+        let outcome = rebase_branch_onto(
+            pkgbase.clone(),
+            branch.clone(),
+            onto.clone(),
+            options.credentials.clone(),
+        )
+        .await
+        .unwrap_or_else(|e| RebaseOutcome::Failed {
+            error: format!("{e:#}"),
+        });
+        if matches!(outcome, RebaseOutcome::Rebased { .. }) {
+            options.source_repo_changes.notify([pkgbase.clone()]);
+        }
+        match outcome {
+            // The packager resolved an earlier conflict
+            RebaseOutcome::UpToDate => {
+                if previous.is_some_and(|previous| {
+                    !matches!(previous.outcome, RebaseOutcome::Rebased { .. })
+                }) {
+                    db::origin_changeset_rebase::delete(pool, namespace.id, pkgbase).await?;
+                }
+            }
+            RebaseOutcome::Rebased { .. }
+            | RebaseOutcome::Conflict { .. }
+            | RebaseOutcome::Failed { .. } => {
+                match &outcome {
+                    RebaseOutcome::Conflict { paths } => tracing::warn!(
+                        "Can't rebase {pkgbase}/{branch} in namespace {} onto {onto}, conflicting files: {paths:?}",
+                        namespace.name
+                    ),
+                    RebaseOutcome::Failed { error } => tracing::error!(
+                        "Failed to rebase {pkgbase}/{branch} in namespace {} onto {onto}: {error}",
+                        namespace.name
+                    ),
+                    _ => {}
+                }
+                let rebase = OriginChangesetRebase {
+                    pkgbase: pkgbase.clone(),
+                    branch_name: branch.clone(),
+                    onto_commit_hash: onto,
+                    outcome,
+                    updated_at: time::OffsetDateTime::now_utc(),
+                };
+                db::origin_changeset_rebase::set(pool, namespace.id, &rebase).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// For each architecture, point the namespace's "latest" repository
+/// to the newest iteration in which all packages have been built.
+async fn update_latest_repos(pool: &SqlitePool, namespace: &BuildNamespace) -> Result<()> {
+    let iterations = db::iteration::list_for_namespace(pool, namespace.id).await?;
+
+    let mut latest_iterations = HashMap::new();
+    for iteration in &iterations {
+        for (architecture, graph) in &iteration.packages_to_be_built {
+            if build_set_graph::is_fully_built(graph) {
+                // Iterations are sorted by creation date, so newer
+                // iterations overwrite older ones.
+                latest_iterations.insert(*architecture, iteration.id);
+            }
+        }
+    }
+
+    for (architecture, iteration_id) in latest_iterations {
+        if pacman_repo::update_latest_repo(&namespace.name, iteration_id, architecture).await? {
+            tracing::info!(
+                "Latest {architecture} repository of namespace {} now points to iteration {iteration_id}",
+                namespace.name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Choose how to discover changes to source repositories.
+/// Without a configured remote, source repositories aren't updated automatically.
+async fn new_source_provider(
+    gitlab_args: Option<&args::Gitlab>,
+    sources: &args::Sources,
+) -> Result<Option<SourceProviderBackend>> {
+    let Some(remote) = sources.packaging_remote(gitlab_args) else {
+        return Ok(None);
+    };
+    let kind = sources.source_provider.unwrap_or(if gitlab_args.is_some() {
+        args::SourceProviderKind::Gitlab
+    } else {
+        args::SourceProviderKind::Git
+    });
+
+    let provider = match kind {
+        args::SourceProviderKind::Gitlab => {
+            let gitlab_args = gitlab_args
+                .ok_or_eyre("The gitlab source provider requires the gitlab integration")?;
+            SourceProviderBackend::Gitlab(GitlabSourceProvider::new(
+                new_gitlab_client(gitlab_args).await?,
+                gitlab_args.gitlab_packages_group.clone(),
+                remote,
+            ))
+        }
+        args::SourceProviderKind::Git => {
+            let pkgbases = match &sources.source_repo_pkgbases_file {
+                Some(path) => GitSourceProvider::read_pkgbases_file(path).await?,
+                None => Vec::new(),
+            };
+            SourceProviderBackend::Git(GitSourceProvider::new(pkgbases, remote))
+        }
+    };
+
+    Ok(Some(provider))
+}
+
+pub fn fetch_source_repo_changes_in_loop(
+    db_pool: SqlitePool,
+    provider: SourceProviderBackend,
+    polling_interval: Duration,
+    source_repo_changes: SourceRepoChangeNotifier,
+) {
     tokio::spawn(async move {
         // TODO maybe we should be stricter about errors here
         let mut last_fetched = get_gitlab_last_updated(&db_pool).await.ok().flatten();
         loop {
-            match fetch_all_source_repo_changes(
-                &client,
-                last_fetched,
-                gitlab_args.gitlab_domain.clone(),
-                gitlab_args.gitlab_packages_group.clone(),
-            )
-            .await
-            {
-                Ok(Some(new_last_fetched)) => {
-                    if let Err(e) = set_gitlab_last_updated(&db_pool, new_last_fetched).await {
-                        tracing::info!("Failed to set gitlab updated date: {e:?}");
+            match provider.fetch_changes(last_fetched).await {
+                Ok(changes) => {
+                    source_repo_changes.notify(changes.fetched.iter().cloned());
+                    // No updated packages found if this is `None`.
+                    if let Some(new_last_fetched) = changes.last_fetched {
+                        if let Err(e) = set_gitlab_last_updated(&db_pool, new_last_fetched).await {
+                            tracing::info!("Failed to set gitlab updated date: {e:?}");
+                        }
+                        last_fetched = Some(new_last_fetched);
+                    }
+                    if let Err(e) = update_retired_source_repos(&db_pool, &changes).await {
+                        tracing::error!("Failed to update retired source repos: {e:?}");
                     }
-                    last_fetched = Some(new_last_fetched);
                 }
-                // No updated packages found.
-                Ok(None) => {}
                 Err(e) => tracing::info!("{e:?}"),
             }
 
-            tokio::time::sleep(tokio::time::Duration::from_secs(60 * 5)).await;
+            tokio::time::sleep(polling_interval).await;
         }
     });
+}
+
+/// Record source repos of archived or removed projects, so they're excluded
+/// from build graphs, and include them again once they're back in use.
+async fn update_retired_source_repos(pool: &SqlitePool, changes: &SourceRepoChanges) -> Result<()> {
+    for pkgbase in &changes.active {
+        if db::retired_source_repo::delete(pool, pkgbase).await? {
+            tracing::info!("Source repo {pkgbase} is in use again");
+        }
+    }
+    for (pkgbase, reason) in &changes.retired {
+        tracing::info!("Retiring source repo {pkgbase}: {reason:?}");
+        db::retired_source_repo::set(pool, pkgbase, *reason).await?;
+    }
+
     Ok(())
 }
 
+/// With webhooks, polling only serves to catch events we missed, so it can happen less often.
+fn source_repo_polling_interval(gitlab_args: Option<&args::Gitlab>) -> Duration {
+    if gitlab_args.is_some_and(|args| args.gitlab_webhook_signing_token.is_some()) {
+        Duration::from_secs(60 * 30)
+    } else {
+        Duration::from_secs(60 * 5)
+    }
+}
+
 pub async fn update_project_ci_settings_in_loop(gitlab_args: args::Gitlab) -> Result<()> {
     let client = new_gitlab_client(&gitlab_args.clone()).await?;
 
@@ -228,15 +604,359 @@ pub async fn update_project_ci_settings_in_loop(gitlab_args: args::Gitlab) -> Re
     Ok(())
 }
 
+/// Keep the commit statuses of origin changesets in gitlab in sync
+/// with the build status of their namespaces.
+async fn report_commit_statuses_in_loop(
+    pool: SqlitePool,
+    gitlab_args: args::Gitlab,
+    base_url: Url,
+) -> Result<()> {
+    let client = new_gitlab_client(&gitlab_args).await?;
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = report_commit_statuses(&pool, &client, &gitlab_args, &base_url).await {
+                tracing::error!("Error while reporting commit statuses to gitlab: {e:?}");
+            }
+            tokio::time::sleep(Duration::from_secs(10)).await
+        }
+    });
+    Ok(())
+}
+
+async fn report_commit_statuses(
+    pool: &SqlitePool,
+    client: &AsyncGitlab,
+    gitlab_args: &args::Gitlab,
+    base_url: &Url,
+) -> Result<()> {
+    let mut namespaces = db::namespace::list_by_status(pool, BuildNamespaceStatus::Active).await?;
+    namespaces.extend(db::namespace::list_by_status(pool, BuildNamespaceStatus::Cancelled).await?);
+    for namespace in namespaces {
+        let Ok(iteration) = db::iteration::read_newest(pool, namespace.id).await else {
+            continue;
+        };
+        let target_url = base_url.join(&format!("/namespace/{}", namespace.name))?;
+
+        for (architecture, graph) in &iteration.packages_to_be_built {
+            let mut status = CommitBuildStatus::from_graph(graph);
+            if namespace.status == BuildNamespaceStatus::Cancelled {
+                status = status.cancelled();
+            }
+            for (pkgbase, _) in &iteration.origin_changesets {
+                let Some(node) = graph.node_weights().find(|node| &node.pkgbase == pkgbase) else {
+                    continue;
+                };
+                let commit_status = CommitStatus {
+                    pkgbase: pkgbase.clone(),
+                    commit_hash: node.commit_hash.clone(),
+                    branch_name: node.branch_name.clone(),
+                    name: format!("buildbtw/{}/{architecture}", namespace.name),
+                    status,
+                    target_url: target_url.clone(),
+                };
+                // Try to report all statuses, and continue on failures.
+                if let Err(e) = report_commit_status(
+                    pool,
+                    client,
+                    gitlab_args,
+                    iteration.id,
+                    *architecture,
+                    &commit_status,
+                )
+                .await
+                {
+                    tracing::error!("Error reporting commit status {commit_status:?}: {e:?}");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Report a commit status to gitlab, unless it was already reported.
+async fn report_commit_status(
+    pool: &SqlitePool,
+    client: &AsyncGitlab,
+    gitlab_args: &args::Gitlab,
+    iteration_id: Uuid,
+    architecture: ConcreteArchitecture,
+    commit_status: &CommitStatus,
+) -> Result<()> {
+    let CommitStatus {
+        pkgbase, status, ..
+    } = commit_status;
+    let reported_status =
+        db::gitlab_commit_status::read(pool, iteration_id, pkgbase, architecture).await?;
+    if reported_status == Some(*status) {
+        return Ok(());
+    }
+
+    tracing::debug!("Reporting commit status: {commit_status:?}");
+    set_commit_status(client, &gitlab_args.gitlab_packages_group, commit_status).await?;
+    db::gitlab_commit_status::set(pool, iteration_id, pkgbase, architecture, *status).await?;
+
+    Ok(())
+}
+
+async fn sync_merge_requests_in_loop(
+    pool: SqlitePool,
+    gitlab_args: args::Gitlab,
+    base_url: Url,
+) -> Result<()> {
+    let client = new_gitlab_client(&gitlab_args).await?;
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = sync_merge_requests(&pool, &client, &gitlab_args, &base_url).await {
+                tracing::error!("Error while syncing merge requests with gitlab: {e:?}");
+            }
+            tokio::time::sleep(Duration::from_secs(30)).await
+        }
+    });
+    Ok(())
+}
+
+/// Open the merge requests requested by active namespaces, notice when they
+/// get merged, closed or reopened, and keep their summary comments up to date.
+async fn sync_merge_requests(
+    pool: &SqlitePool,
+    client: &AsyncGitlab,
+    gitlab_args: &args::Gitlab,
+    base_url: &Url,
+) -> Result<()> {
+    let active_namespaces =
+        db::namespace::list_by_status(pool, BuildNamespaceStatus::Active).await?;
+    for namespace in active_namespaces {
+        // Try to sync all namespaces, and continue on failures.
+        if let Err(e) =
+            sync_namespace_merge_requests(pool, client, gitlab_args, base_url, &namespace).await
+        {
+            tracing::error!(
+                r#"Error syncing merge requests of namespace "{}": {e:?}"#,
+                namespace.name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn sync_namespace_merge_requests(
+    pool: &SqlitePool,
+    client: &AsyncGitlab,
+    gitlab_args: &args::Gitlab,
+    base_url: &Url,
+    namespace: &BuildNamespace,
+) -> Result<()> {
+    let group = &gitlab_args.gitlab_packages_group;
+    let merge_requests = db::gitlab_merge_request::list_for_namespace(pool, namespace.id).await?;
+    if merge_requests.is_empty() {
+        return Ok(());
+    }
+
+    let summary = match db::iteration::read_newest(pool, namespace.id).await {
+        Ok(iteration) => {
+            let architectures: Vec<_> = iteration
+                .packages_to_be_built
+                .iter()
+                .map(|(architecture, graph)| ArchitectureSummary::from_graph(*architecture, graph))
+                .collect();
+            Some(render_summary(
+                &namespace.name,
+                &base_url.join(&format!("/namespace/{}", namespace.name))?,
+                &base_url.join(&format!("/namespace/{}/pacman.conf", namespace.name))?,
+                &iteration.id,
+                &architectures,
+            ))
+        }
+        Err(_) => None,
+    };
+
+    for mut merge_request in merge_requests {
+        let gitlab_merge_request = match (merge_request.state, merge_request.gitlab_iid) {
+            (TrackedMergeRequestState::Requested, _) => {
+                find_or_create_merge_request(
+                    client,
+                    group,
+                    &merge_request.pkgbase,
+                    &merge_request.branch_name,
+                    &format!(
+                        "{} (buildbtw namespace {})",
+                        merge_request.branch_name, namespace.name
+                    ),
+                )
+                .await?
+            }
+            // Closed merge requests can be reopened, merged ones are final.
+            (TrackedMergeRequestState::Opened | TrackedMergeRequestState::Closed, Some(iid)) => {
+                get_merge_request(client, group, &merge_request.pkgbase, iid.try_into()?).await?
+            }
+            _ => continue,
+        };
+        merge_request.state = gitlab_merge_request.state.into();
+        merge_request.gitlab_iid = Some(gitlab_merge_request.iid.try_into()?);
+        merge_request.web_url = Some(gitlab_merge_request.web_url.to_string());
+
+        let changed_summary = summary.as_ref().filter(|summary| {
+            merge_request.state == TrackedMergeRequestState::Opened
+                && merge_request.reported_summary.as_ref() != Some(*summary)
+        });
+        if let Some(summary) = changed_summary {
+            let note_id = upsert_summary_note(
+                client,
+                group,
+                &merge_request.pkgbase,
+                gitlab_merge_request.iid,
+                merge_request.note_id.map(TryInto::try_into).transpose()?,
+                summary,
+            )
+            .await?;
+            merge_request.note_id = Some(note_id.try_into()?);
+            merge_request.reported_summary = Some(summary.clone());
+        }
+
+        db::gitlab_merge_request::update(pool, &merge_request).await?;
+    }
+
+    update_ready_for_release(pool, namespace.id).await
+}
+
+/// Record whether all tracked merge requests of the namespace were merged,
+/// which is required for releasing it.
+pub(crate) async fn update_ready_for_release(pool: &SqlitePool, namespace_id: Uuid) -> Result<()> {
+    let merge_requests = db::gitlab_merge_request::list_for_namespace(pool, namespace_id)
+        .await?
+        .into_iter()
+        .map(|merge_request| merge_request.into_tracked())
+        .collect::<Result<Vec<_>>>()?;
+    db::namespace::set_ready_for_release(pool, namespace_id, ready_for_release(&merge_requests))
+        .await
+}
+
+/// Work through the release queue, releasing one namespace at a time
+/// in the order the releases were created in.
+/// Releases waiting for locks held by other releases are skipped until the locks are free.
+fn execute_confirmed_releases_in_loop(pool: SqlitePool, signer: Option<SignerBackend>) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = execute_confirmed_releases(&pool, signer.as_ref()).await {
+                tracing::error!("Error while executing releases: {e:?}");
+            }
+            tokio::time::sleep(Duration::from_secs(10)).await
+        }
+    });
+}
+
+/// Releases are only executed one at a time by this loop, so a release still marked as
+/// releasing at the start of a pass was interrupted by a restart or a database error
+/// and may have moved only some of its packages.
+/// Mark it as failed and free its locks so the queue can continue.
+async fn fail_interrupted_releases(pool: &SqlitePool) -> Result<()> {
+    for release in db::release::list_by_status(pool, ReleaseStatus::Releasing).await? {
+        tracing::warn!(
+            "Release of {} into {} was interrupted",
+            release.plan.namespace_name,
+            release.plan.target_repository
+        );
+        db::release::finish(
+            pool,
+            release.id,
+            ReleaseStatus::Failed,
+            Some("Releasing was interrupted, some packages may not have been moved. Plan a new release to release the remaining packages.".to_string()),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn execute_confirmed_releases(
+    pool: &SqlitePool,
+    signer: Option<&SignerBackend>,
+) -> Result<()> {
+    fail_interrupted_releases(pool).await?;
+
+    for release in db::release::list_by_status(pool, ReleaseStatus::Confirmed).await? {
+        // While a release is confirmed, `updated_at` is the time it was confirmed at.
+        // If another namespace released some of our pkgbases since then,
+        // this namespace was built against outdated packages.
+        let pkgbases = release.plan.pkgbases();
+        let released_since = db::release::list_released_since(
+            pool,
+            &release.plan.target_repository,
+            release.updated_at,
+        )
+        .await?;
+        if let Some(other) = released_since
+            .iter()
+            .find(|other| !other.plan.pkgbases().is_disjoint(&pkgbases))
+        {
+            let error = format!(
+                "Namespace {} released some of the same pkgbases into {} while this release was waiting. Rebuild this namespace on top of them and plan a new release.",
+                other.plan.namespace_name, release.plan.target_repository
+            );
+            tracing::warn!("Not releasing {}: {error}", release.plan.namespace_name);
+            db::release::finish(pool, release.id, ReleaseStatus::Failed, Some(error)).await?;
+            continue;
+        }
+
+        let blocking_locks = db::release_lock::try_acquire(pool, &release).await?;
+        if let Some(lock) = blocking_locks.first() {
+            tracing::debug!(
+                "Release of {} is waiting for {} lock(s), e.g. {} held by {}",
+                release.plan.namespace_name,
+                blocking_locks.len(),
+                lock.pkgbase,
+                lock.namespace_name
+            );
+            continue;
+        }
+
+        tracing::info!(
+            "Releasing namespace {} into {}",
+            release.plan.namespace_name,
+            release.plan.target_repository
+        );
+        let result = async {
+            db::release::set_status(pool, release.id, ReleaseStatus::Releasing, None).await?;
+            execute_release_plan(&release.plan, signer).await
+        }
+        .await;
+
+        match result {
+            Ok(()) => {
+                db::release::finish(pool, release.id, ReleaseStatus::Released, None).await?;
+            }
+            Err(e) => {
+                tracing::error!("Failed to release {}: {e:?}", release.plan.namespace_name);
+                db::release::finish(
+                    pool,
+                    release.id,
+                    ReleaseStatus::Failed,
+                    Some(format!("{e:#}")),
+                )
+                .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 async fn create_new_namespace_iteration_if_needed(
     pool: &SqlitePool,
     namespace: &BuildNamespace,
-    source_repos: &mut SourceRepos,
+    options: &NamespaceUpdateOptions,
 ) -> Result<()> {
     let newest_iteration = db::iteration::read_newest(pool, namespace.id).await.ok();
-    let new_iteration =
-        new_build_set_iteration_is_needed(namespace, newest_iteration.as_ref(), source_repos)
-            .await?;
+    let parent = db::namespace::read_parent_context(pool, namespace).await?;
+    let new_iteration = new_build_set_iteration_is_needed(
+        namespace,
+        parent.as_ref(),
+        newest_iteration.as_ref(),
+        &mut *options.source_repos.lock().await,
+    )
+    .await?;
 
     match new_iteration {
         NewBuildIterationResult::NewIterationNeeded {
@@ -248,15 +968,14 @@ async fn create_new_namespace_iteration_if_needed(
                 "Creating new build iteration for namespace {namespace_name}, reason: {reason:?}"
             );
 
-            let new_iteration = BuildSetIteration {
-                id: Uuid::new_v4(),
-                created_at: time::OffsetDateTime::now_utc(),
-                origin_changesets: namespace.current_origin_changesets.clone(),
-                packages_to_be_built: packages_to_build,
-                create_reason: reason,
-                namespace_id: namespace.id,
-            };
-
+            let new_iteration = create_build_set_iteration(
+                namespace,
+                parent.as_ref(),
+                packages_to_build,
+                reason,
+                &options.credentials,
+            )
+            .await?;
             db::iteration::create(pool, new_iteration).await?;
         }
         NewBuildIterationResult::NoNewIterationNeeded => {}
@@ -265,81 +984,120 @@ async fn create_new_namespace_iteration_if_needed(
     Ok(())
 }
 
-/// For all in-progress nodes in all iterations, query
-/// gitlab to check if the pipeline is now finished, and if yes, update the status
+/// For all in-progress nodes in all iterations, ask each dispatcher
+/// whether the build has progressed, and if yes, update the status
 /// in the build graph.
-async fn update_build_set_graphs_from_gitlab_pipelines(
+/// Once a build is finished, its logs are fetched by the dispatcher that ran it.
+async fn update_build_set_graphs_from_dispatchers(
     pool: &SqlitePool,
     iterations: Vec<BuildSetIteration>,
-    gitlab_context: &GitlabContext,
+    build_dispatchers: &BuildDispatchers,
 ) -> Result<()> {
-    // Visit all build nodes in all iterations
-    for iteration in iterations {
-        let mut new_packages_to_be_built = iteration.packages_to_be_built.clone();
-        for (architecture, graph) in iteration.packages_to_be_built {
+    // Collect all in-progress build nodes.
+    let mut in_flight = Vec::new();
+    for iteration in &iterations {
+        for (architecture, graph) in &iteration.packages_to_be_built {
             for node in graph.node_weights() {
-                // Only check nodes that are currently building or scheduled.
-                match node.status {
-                    PackageBuildStatus::Building | PackageBuildStatus::Scheduled => {}
-                    _ => continue,
+                if matches!(
+                    node.status,
+                    PackageBuildStatus::Building | PackageBuildStatus::Scheduled
+                ) {
+                    in_flight.push(DispatchedBuild {
+                        iteration_id: iteration.id,
+                        architecture: *architecture,
+                        pkgbase: node.pkgbase.clone(),
+                    });
                 }
+            }
+        }
+    }
 
-                // Check if there's a gitlab pipeline we started
-                // If yes, we'll find it in the DB
-                let maybe_pipeline =
-                    db::gitlab_pipeline::read_by_iteration_and_pkgbase_and_architecture(
-                        pool,
-                        iteration.id,
-                        &node.pkgbase,
-                        architecture,
-                    )
-                    .await?;
-                // We're only concerned with build nodes that have a gitlab pipeline
-                // associated
-                let Some(pipeline) = maybe_pipeline else {
-                    continue;
-                };
+    for (dispatch, dispatcher) in build_dispatchers.iter() {
+        let statuses = match dispatcher.poll_statuses(pool, &in_flight).await {
+            Ok(statuses) => statuses,
+            Err(e) => {
+                tracing::error!("Error polling build statuses from {dispatch}: {e:?}");
+                continue;
+            }
+        };
 
-                // Query current pipeline status in gitlab
-                let pkgbase = &node.pkgbase;
-                tracing::debug!(
-                    pipeline.gitlab_url,
-                    ?pkgbase,
-                    ?architecture,
-                    "Checking pipeline...",
-                );
-                let current_pipeline_status = buildbtw_poc::gitlab::get_pipeline_status(
-                    &gitlab_context.client,
-                    pipeline.project_gitlab_iid.try_into()?,
-                    pipeline.gitlab_iid.try_into()?,
-                )
-                .await?;
+        for (build, status) in statuses {
+            // If it's changed, update the in-progress build node to reflect this.
+            // The node may have been changed since we read it, e.g. via webhook.
+            let changed = db::iteration::set_build_status(
+                pool,
+                build.iteration_id,
+                build.architecture,
+                &build.pkgbase,
+                status,
+                |current| {
+                    matches!(
+                        current,
+                        PackageBuildStatus::Building | PackageBuildStatus::Scheduled
+                    )
+                },
+            )
+            .await?;
+            if !changed {
+                continue;
+            }
+            tracing::debug!(?build, ?status, "Build status changed");
 
-                // If it's changed, update the in-progress build node to reflect this
-                if !current_pipeline_status.matches_package_build_status(node.status) {
-                    tracing::debug!(pipeline.gitlab_url, "Pipeline is finished");
-                    // Set new status of node, and mark nodes depending on this one
-                    // as pending
-                    let new_graph = build_set_graph::set_build_status(
-                        graph.clone(),
-                        pkgbase,
-                        current_pipeline_status.into(),
-                    );
-                    new_packages_to_be_built.insert(architecture, new_graph);
-                } else {
-                    tracing::debug!(pipeline.gitlab_url, "Pipeline status is up to date");
-                }
+            if matches!(
+                status,
+                PackageBuildStatus::Built | PackageBuildStatus::Failed
+            ) {
+                dispatcher
+                    .fetch_logs(pool, &build)
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::error!("Failed to fetch logs of {build:?}: {e:?}")
+                    });
             }
         }
-        // Persist the updated build set graph
-        db::iteration::update(
-            pool,
-            db::iteration::BuildSetIterationUpdate {
-                id: iteration.id,
-                packages_to_be_built: new_packages_to_be_built,
-            },
-        )
-        .await?;
+    }
+
+    Ok(())
+}
+
+/// Store the pipeline status and update the build node the pipeline belongs to
+/// after gitlab reported a new pipeline status via webhook.
+pub async fn update_build_set_graph_from_pipeline_status(
+    pool: &SqlitePool,
+    gitlab_args: &args::Gitlab,
+    pipeline: &db::gitlab_pipeline::DbGitlabPipeline,
+    pipeline_status: PipelineStatus,
+) -> Result<()> {
+    let node_changed = db::iteration::set_build_status(
+        pool,
+        pipeline.build_set_iteration_id,
+        pipeline.architecture,
+        &pipeline.pkgbase,
+        pipeline_status.into(),
+        // Only nodes that are building or scheduled are tracked via gitlab pipelines.
+        |current| {
+            matches!(
+                current,
+                PackageBuildStatus::Building | PackageBuildStatus::Scheduled
+            ) && !pipeline_status.matches_package_build_status(current)
+        },
+    )
+    .await?;
+
+    // The status of each attempt is shown on its own, so store it even if
+    // the node doesn't change, e.g. when a pipeline goes from created to running.
+    let pipeline_changed = pipeline.status != Some(pipeline_status);
+    if pipeline_changed {
+        tracing::debug!(
+            pipeline.gitlab_url,
+            ?pipeline_status,
+            "Pipeline status changed"
+        );
+        db::gitlab_pipeline::set_status(pool, pipeline.id, pipeline_status).await?;
+    }
+
+    if (node_changed || pipeline_changed) && pipeline_status.is_finished() {
+        archive_pipeline_jobs_in_background(pool, gitlab_args, pipeline).await?;
     }
 
     Ok(())
@@ -349,50 +1107,42 @@ async fn update_build_set_graphs_from_gitlab_pipelines(
 async fn schedule_next_build_if_needed(
     pool: &SqlitePool,
     namespace: &BuildNamespace,
-    maybe_gitlab_context: Option<&GitlabContext>,
-    server_port: u16,
+    build_dispatchers: &BuildDispatchers,
 ) -> Result<()> {
     if namespace.status == BuildNamespaceStatus::Cancelled {
         return Ok(());
     }
 
-    let scheduled_status = match maybe_gitlab_context {
-        // If we dispatch builds to gitlab, set the status to "scheduled".
-        // Later on, the server queries gitlab in the background to check
-        // if the pipeline has moved from "pending" to "building"
-        // and will update the status accordingly.
-        Some(_) => PackageBuildStatus::Scheduled,
-        // If we build using our own worker, set the status to "building"
-        // directly, as the worker has no concept of "pending" builds
-        // and will instead start building instantly.
-        None => PackageBuildStatus::Building,
-    };
-
     // -> schedule build
-    let mut iteration = db::iteration::read_newest(pool, namespace.id).await?;
-    for (architecture, graph) in iteration.packages_to_be_built.clone() {
+    let iteration = db::iteration::read_newest(pool, namespace.id).await?;
+    for (architecture, graph) in &iteration.packages_to_be_built {
+        let dispatcher = build_dispatchers.for_build(namespace, *architecture)?;
         let build = schedule_next_build_in_graph(
-            &graph,
+            graph,
             namespace.id,
             iteration.id,
-            architecture,
-            scheduled_status,
+            *architecture,
+            dispatcher.scheduled_status(),
         );
         match build {
             // TODO: distinguish between no pending packages and failed graph
             ScheduleBuildResult::NoPendingPackages => {}
             ScheduleBuildResult::Scheduled(response) => {
-                let new_packages_to_be_built = response.updated_build_set_graph.clone();
-                match schedule_build(pool, &response, maybe_gitlab_context, server_port).await {
+                match schedule_build(pool, &response, namespace, dispatcher).await {
                     Ok(_) => {
-                        iteration
-                            .packages_to_be_built
-                            .insert(architecture, new_packages_to_be_built);
-                        db::iteration::update(
+                        // The build may have reported its status already,
+                        // only mark it as scheduled if it hasn't.
+                        db::iteration::set_build_status(
                             pool,
-                            db::iteration::BuildSetIterationUpdate {
-                                id: iteration.id,
-                                packages_to_be_built: iteration.packages_to_be_built.clone(),
+                            iteration.id,
+                            *architecture,
+                            &response.source.pkgbase,
+                            dispatcher.scheduled_status(),
+                            |status| {
+                                matches!(
+                                    status,
+                                    PackageBuildStatus::Pending | PackageBuildStatus::Blocked
+                                )
                             },
                         )
                         .await?;
@@ -412,40 +1162,14 @@ async fn schedule_next_build_if_needed(
 async fn schedule_build(
     pool: &SqlitePool,
     build: &ScheduleBuild,
-    maybe_gitlab_context: Option<&GitlabContext>,
-    server_port: u16,
+    namespace: &BuildNamespace,
+    dispatcher: &Dispatcher,
 ) -> Result<()> {
     tracing::info!("Building pending package: {:?}", build.source);
-    let namespace_name = db::namespace::read(build.namespace, pool).await?.name;
 
-    pacman_repo::ensure_repo_exists(&namespace_name, build.iteration, build.architecture).await?;
+    pacman_repo::ensure_repo_exists(&namespace.name, build.iteration, build.architecture).await?;
 
-    if let Some(gitlab_context) = maybe_gitlab_context {
-        let pipeline_response = buildbtw_poc::gitlab::create_pipeline(
-            &gitlab_context.client,
-            build,
-            &namespace_name,
-            &gitlab_context.args.gitlab_packages_group,
-            server_port,
-        )
-        .await?;
-        let db_pipeline = db::gitlab_pipeline::CreateDbGitlabPipeline {
-            build_set_iteration_id: build.iteration.into(),
-            pkgbase: build.source.pkgbase.clone(),
-            architecture: build.architecture,
-            project_gitlab_iid: pipeline_response.project_id.try_into()?,
-            gitlab_iid: pipeline_response.id.try_into()?,
-            gitlab_url: pipeline_response.web_url,
-        };
-        db::gitlab_pipeline::create(pool, db_pipeline).await?
-    } else {
-        let _response = reqwest::Client::new()
-            .post("http://0.0.0.0:8090/build/schedule".to_string())
-            .json(build)
-            .send()
-            .await
-            .wrap_err("Failed to send to worker")?;
-    }
+    dispatcher.schedule(pool, build, &namespace.name).await?;
 
     tracing::info!("Scheduled build: {:?}", build.source);
     Ok(())
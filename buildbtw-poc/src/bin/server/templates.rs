@@ -6,6 +6,7 @@ const TEMPLATES: &[&str] = &[
     "show_build_namespace",
     "render_build_namespace_graph",
     "home",
+    "pacman_conf",
 ];
 
 #[derive(rust_embed::Embed)]
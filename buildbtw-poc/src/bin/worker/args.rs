@@ -2,6 +2,7 @@ use std::net::IpAddr;
 
 use clap::{Parser, Subcommand, command};
 use color_eyre::Result;
+use url::Url;
 
 /// Checks whether an interface is valid, i.e. it can be parsed into an IP address
 fn parse_interface(src: &str) -> Result<IpAddr, std::net::AddrParseError> {
@@ -41,5 +42,10 @@ pub enum Command {
         /// Allow automatically importing public keys for verifying sources.
         #[arg(long, default_value = "false")]
         modify_gpg_keyring: bool,
+
+        /// The URL to contact the server at, for fetching build configuration
+        /// and uploading results.
+        #[arg(long, env, default_value = "http://0.0.0.0:8080")]
+        server_url: Url,
     },
 }
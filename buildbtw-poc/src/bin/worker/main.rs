@@ -1,15 +1,21 @@
 use std::net::{SocketAddr, TcpListener};
 
 use axum::{Json, Router, debug_handler, extract::State, routing::post};
+use camino::Utf8Path;
 use clap::Parser;
-use color_eyre::eyre::{Context, Result};
+use color_eyre::eyre::{Context, Result, eyre};
 use listenfd::ListenFd;
 use reqwest::Body;
+use time::OffsetDateTime;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio_util::codec::{BytesCodec, FramedRead};
+use url::Url;
 
 use buildbtw_poc::{
-    PipelineTarget, ScheduleBuild, build_package::build_path, source_info::package_file_name,
+    PipelineTarget, ScheduleBuild,
+    build_logs::{LOCAL_BUILD_LOG_FILES, local_build_log_file_name},
+    build_package::build_path,
+    source_info::{debug_package_file_stem, find_package_file, package_file_stem},
 };
 
 use crate::args::{Args, Command};
@@ -50,8 +56,9 @@ async fn main() -> Result<()> {
             interface,
             port,
             modify_gpg_keyring,
+            server_url,
         } => {
-            let worker_sender = tasks::start(modify_gpg_keyring);
+            let worker_sender = tasks::start(modify_gpg_keyring, server_url);
             let app = Router::new()
                 .route("/build/schedule", post(schedule_build))
                 .with_state(AppState { worker_sender });
@@ -76,6 +83,7 @@ async fn main() -> Result<()> {
 }
 
 async fn set_build_status(
+    server_url: &Url,
     status: buildbtw_poc::PackageBuildStatus,
     ScheduleBuild {
         iteration,
@@ -88,9 +96,9 @@ async fn set_build_status(
     let PipelineTarget { pkgbase, .. } = source;
 
     reqwest::Client::new()
-        .patch(format!(
-            "http://0.0.0.0:8080/iteration/{iteration}/pkgbase/{pkgbase}/architecture/{architecture}/status"
-        ))
+        .patch(server_url.join(&format!(
+            "/iteration/{iteration}/pkgbase/{pkgbase}/architecture/{architecture}/status"
+        ))?)
         .json(&data)
         .send()
         .await
@@ -102,8 +110,32 @@ async fn set_build_status(
     Ok(())
 }
 
-async fn upload_packages(
+/// Fetch the `pacman.conf` to build with, which contains the namespace's base repositories.
+async fn fetch_build_pacman_conf(
+    server_url: &Url,
     ScheduleBuild {
+        iteration,
+        architecture,
+        ..
+    }: &ScheduleBuild,
+) -> Result<String> {
+    let pacman_conf = reqwest::Client::new()
+        .get(server_url.join(&format!(
+            "/iteration/{iteration}/architecture/{architecture}/pacman.conf"
+        ))?)
+        .send()
+        .await
+        .wrap_err("Failed to send to server")?
+        .error_for_status()?
+        .text()
+        .await?;
+
+    Ok(pacman_conf)
+}
+
+async fn upload_packages(
+    server_url: &Url,
+    schedule @ ScheduleBuild {
         iteration,
         source,
         architecture,
@@ -111,24 +143,99 @@ async fn upload_packages(
         ..
     }: &ScheduleBuild,
 ) -> Result<()> {
+    // Build path to the directory containing the package files
+    let dir = build_path(*iteration, &source.pkgbase);
     for package in srcinfo.packages_for_architecture(*architecture.as_ref()) {
-        // Build path to the file we'll send
-        let dir = build_path(*iteration, &source.pkgbase);
-        let path = dir.join(package_file_name(&package, srcinfo)?);
+        // makepkg may use any compression for the package file,
+        // so find out which file was actually produced.
+        let stem = package_file_stem(&package, srcinfo);
+        let path = find_package_file(&dir, &stem)
+            .await?
+            .ok_or_else(|| eyre!("No package file found for {stem} in {dir}"))?;
+        upload_package_file(server_url, schedule, &path).await?;
+    }
 
-        // Convert path into async stream body
-        let file = tokio::fs::File::open(&path).await.wrap_err(path)?;
-        let stream = FramedRead::new(file, BytesCodec::new());
-        let body = Body::wrap_stream(stream);
+    // Debug packages are only produced if the `debug` option is enabled
+    // and the package contains binaries, so they're optional.
+    let debug_stem = debug_package_file_stem(srcinfo, *architecture);
+    if let Some(path) = find_package_file(&dir, &debug_stem).await? {
+        upload_package_file(server_url, schedule, &path).await?;
+    }
 
-        let pkgname = package.name;
-        let PipelineTarget { pkgbase, .. } = source;
+    Ok(())
+}
+
+/// Upload the logs of a build to the server, so they're available
+/// after the build directory has been cleaned up.
+/// `started_at` identifies the build attempt, see [`local_build_log_file_name`].
+async fn upload_build_logs(
+    server_url: &Url,
+    ScheduleBuild {
+        iteration,
+        source,
+        architecture,
+        ..
+    }: &ScheduleBuild,
+    started_at: OffsetDateTime,
+) -> Result<()> {
+    let PipelineTarget { pkgbase, .. } = source;
+    let dir = build_path(*iteration, pkgbase);
+    for file_name in LOCAL_BUILD_LOG_FILES {
+        let path = dir.join(file_name);
+        // The build may have failed before pkgctl was started
+        if !tokio::fs::try_exists(&path).await? {
+            continue;
+        }
+        let file = tokio::fs::File::open(&path)
+            .await
+            .wrap_err(path.to_string())?;
+        let body = Body::wrap_stream(FramedRead::new(file, BytesCodec::new()));
+        let file_name = local_build_log_file_name(started_at, file_name);
 
         reqwest::Client::new()
-        .post(format!(
-            "http://0.0.0.0:8080/iteration/{iteration}/pkgbase/{pkgbase}/pkgname/{pkgname}/architecture/{architecture}/package"
-        )).body(body).send().await?.error_for_status()?;
+            .post(server_url.join(&format!(
+                "/iteration/{iteration}/pkgbase/{pkgbase}/architecture/{architecture}/log/{file_name}"
+            ))?)
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
     }
 
     Ok(())
 }
+
+async fn upload_package_file(
+    server_url: &Url,
+    ScheduleBuild {
+        iteration,
+        source,
+        architecture,
+        ..
+    }: &ScheduleBuild,
+    path: &Utf8Path,
+) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| eyre!("Invalid package file path {path}"))?;
+
+    // Convert path into async stream body
+    let file = tokio::fs::File::open(&path)
+        .await
+        .wrap_err(path.to_string())?;
+    let stream = FramedRead::new(file, BytesCodec::new());
+    let body = Body::wrap_stream(stream);
+
+    let PipelineTarget { pkgbase, .. } = source;
+
+    reqwest::Client::new()
+        .post(server_url.join(&format!(
+            "/iteration/{iteration}/pkgbase/{pkgbase}/architecture/{architecture}/package/{file_name}"
+        ))?)
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
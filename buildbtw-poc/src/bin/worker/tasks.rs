@@ -1,13 +1,15 @@
+use time::OffsetDateTime;
 use tokio::sync::mpsc::UnboundedSender;
+use url::Url;
 
-use crate::{set_build_status, upload_packages};
+use crate::{fetch_build_pacman_conf, set_build_status, upload_build_logs, upload_packages};
 use buildbtw_poc::{PackageBuildStatus, ScheduleBuild, build_package::build_package};
 
 pub enum Message {
     BuildPackage(ScheduleBuild),
 }
 
-pub fn start(modify_gpg_keyring: bool) -> UnboundedSender<Message> {
+pub fn start(modify_gpg_keyring: bool, server_url: Url) -> UnboundedSender<Message> {
     tracing::info!("Starting worker tasks");
 
     let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<Message>();
@@ -16,16 +18,31 @@ pub fn start(modify_gpg_keyring: bool) -> UnboundedSender<Message> {
             match msg {
                 Message::BuildPackage(schedule) => {
                     tracing::info!("🕑 Building package {}", schedule.source.pkgbase);
-                    let mut result_status = build_package(&schedule, modify_gpg_keyring).await;
+                    let started_at = OffsetDateTime::now_utc();
+                    let mut result_status =
+                        match fetch_build_pacman_conf(&server_url, &schedule).await {
+                            Ok(pacman_conf) => {
+                                build_package(&schedule, &pacman_conf, modify_gpg_keyring).await
+                            }
+                            Err(err) => {
+                                tracing::error!("Fetching pacman.conf failed: {err:?}");
+                                PackageBuildStatus::Failed
+                            }
+                        };
 
                     tracing::info!(
                         "build result for {:?}: {result_status:?}",
                         schedule.source.pkgbase
                     );
 
+                    // Missing logs don't make the build fail
+                    if let Err(err) = upload_build_logs(&server_url, &schedule, started_at).await {
+                        tracing::error!("Uploading build logs failed: {err:?}");
+                    }
+
                     // TODO we might want to guarantee some kind of transactionality
                     // for the upload + status update operations
-                    if let Err(err) = upload_packages(&schedule).await {
+                    if let Err(err) = upload_packages(&server_url, &schedule).await {
                         result_status = PackageBuildStatus::Failed;
                         tracing::error!(
                             "Uploading package failed (marking build as failed): {err:?}"
@@ -33,7 +50,8 @@ pub fn start(modify_gpg_keyring: bool) -> UnboundedSender<Message> {
                     }
 
                     // TODO: retry with exponential backoff
-                    if let Err(err) = set_build_status(result_status, &schedule).await {
+                    if let Err(err) = set_build_status(&server_url, result_status, &schedule).await
+                    {
                         tracing::error!("❌ Failed to set build status: {err:?}");
                     }
                 }
@@ -0,0 +1,123 @@
+//! Logs and artifacts of build attempts.
+//!
+//! Both local workers and gitlab pipelines hand their logs to the server,
+//! which keeps them next to the namespace data. This way they survive the
+//! worker's build directory being cleaned up or gitlab's artifact retention,
+//! and can be shown in the UI regardless of where a package was built.
+
+use std::sync::LazyLock;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::Result;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{NAMESPACE_DATA_DIR, Pkgbase, source_info::ConcreteArchitecture};
+
+pub static BUILD_LOGS_DIR: LazyLock<Utf8PathBuf> =
+    LazyLock::new(|| NAMESPACE_DATA_DIR.join("logs"));
+
+/// Log files written by local builds, see [`crate::build_package`].
+pub const LOCAL_BUILD_LOG_FILES: [&str; 2] = ["stdout.log", "stderr.log"];
+
+/// Name under which a log file of a local build attempt is stored.
+/// Like gitlab job logs, which contain the job ID, the name includes
+/// the attempt, so retrying a build doesn't replace earlier logs.
+pub fn local_build_log_file_name(attempt_started_at: OffsetDateTime, file_name: &str) -> String {
+    format!(
+        "attempt-{}-{file_name}",
+        attempt_started_at.unix_timestamp()
+    )
+}
+
+/// Directory containing the logs of a package build, relative to [`BUILD_LOGS_DIR`].
+/// This is also the path the logs are served under.
+pub fn build_logs_path(
+    iteration_id: Uuid,
+    architecture: ConcreteArchitecture,
+    pkgbase: &Pkgbase,
+) -> Utf8PathBuf {
+    Utf8PathBuf::from(iteration_id.to_string())
+        .join(architecture.to_string())
+        .join(pkgbase.as_ref())
+}
+
+pub fn build_logs_dir_path(
+    iteration_id: Uuid,
+    architecture: ConcreteArchitecture,
+    pkgbase: &Pkgbase,
+) -> Utf8PathBuf {
+    BUILD_LOGS_DIR.join(build_logs_path(iteration_id, architecture, pkgbase))
+}
+
+/// Only allow plain file names so uploaded logs can't escape their directory.
+pub fn is_valid_log_file_name(file_name: &str) -> bool {
+    !file_name.is_empty()
+        && !file_name.starts_with('.')
+        && file_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Turn an arbitrary name (e.g. a gitlab job name) into a valid log file name component.
+pub fn sanitize_log_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>()
+        .trim_start_matches('.')
+        .to_string()
+}
+
+/// List the log and artifact files stored for a package build.
+pub async fn list_build_log_files(dir: &Utf8Path) -> Result<Vec<String>> {
+    if !tokio::fs::try_exists(dir).await? {
+        return Ok(Vec::new());
+    }
+
+    let mut file_names = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let Ok(file_name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if is_valid_log_file_name(&file_name) {
+            file_names.push(file_name);
+        }
+    }
+    file_names.sort();
+
+    Ok(file_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("stdout.log", true)]
+    #[case("attempt-1723000000-stdout.log", true)]
+    #[case("job-12-build-x86_64.log", true)]
+    #[case("../stdout.log", false)]
+    #[case("logs/stdout.log", false)]
+    #[case(".hidden", false)]
+    #[case("", false)]
+    fn test_is_valid_log_file_name(#[case] file_name: &str, #[case] expected: bool) {
+        assert_eq!(is_valid_log_file_name(file_name), expected);
+    }
+
+    #[rstest]
+    #[case("build: x86_64", "build--x86_64")]
+    #[case("../evil", "-evil")]
+    fn test_sanitize_log_file_name(#[case] name: &str, #[case] expected: &str) {
+        let sanitized = sanitize_log_file_name(name);
+        assert_eq!(sanitized, expected);
+        assert!(is_valid_log_file_name(&sanitized));
+    }
+}
@@ -1,4 +1,8 @@
 //! Build a package locally by essentially running `pkgctl build`.
+//!
+//! Instead of the chroots managed by `pkgctl`, which use the official repositories,
+//! each iteration gets its own chroot that is created from the `pacman.conf`
+//! generated by the server, so builds use the namespace's base repositories.
 
 use std::process::Stdio;
 
@@ -13,12 +17,19 @@ use git2::{Oid, Repository, Status, build::CheckoutBuilder};
 use uuid::Uuid;
 
 use crate::{
-    BUILD_DIR, PackageBuildStatus, Pkgbase, ScheduleBuild, git::package_source_path,
-    source_info::package_architectures,
+    BUILD_DIR, PackageBuildStatus, Pkgbase, ScheduleBuild,
+    git::package_source_path,
+    source_info::{ConcreteArchitecture, package_architectures},
 };
 
-pub async fn build_package(schedule: &ScheduleBuild, import_gpg_keys: bool) -> PackageBuildStatus {
-    match build_package_inner(schedule, import_gpg_keys).await {
+/// Build the package in a chroot using the given `pacman.conf`,
+/// see [`crate::pacman_conf::render_build_pacman_conf`].
+pub async fn build_package(
+    schedule: &ScheduleBuild,
+    pacman_conf: &str,
+    import_gpg_keys: bool,
+) -> PackageBuildStatus {
+    match build_package_inner(schedule, pacman_conf, import_gpg_keys).await {
         Ok(status) => status,
         Err(e) => {
             tracing::error!("Error building package: {e:?}");
@@ -29,6 +40,7 @@ pub async fn build_package(schedule: &ScheduleBuild, import_gpg_keys: bool) -> P
 
 async fn build_package_inner(
     schedule: &ScheduleBuild,
+    pacman_conf: &str,
     modify_gpg_keyring: bool,
 ) -> Result<PackageBuildStatus> {
     // Copy the source repo from cache to build dir so we can easily remove
@@ -45,10 +57,13 @@ async fn build_package_inner(
         tracing::debug!("modify_gpg_keyring not set, skipping key import");
     }
 
-    // Prepare pkgctl invocation
-    let mut cmd = Command::new("pkgctl");
+    let chroot_path = prepare_chroot(schedule, pacman_conf).await?;
 
-    cmd.args(["build"]).args([build_path.clone()]);
+    // Prepare makechrootpkg invocation, which is what `pkgctl build` runs as well
+    let mut cmd = Command::new("makechrootpkg");
+    cmd.args(["-c", "-r"])
+        .arg(&chroot_path)
+        .current_dir(&build_path);
 
     // Log stdout and stderr to files
     let stdout_log_path = build_path.join("stdout.log");
@@ -59,7 +74,7 @@ async fn build_package_inner(
     let stderr_log_file = File::create(&stderr_log_path).await?.into_std().await;
     cmd.stderr(Stdio::from(stderr_log_file));
 
-    tracing::info!("Spawning pkgctl: ${cmd:?}");
+    tracing::info!("Spawning makechrootpkg: ${cmd:?}");
     tracing::info!("Piping stdout to {stdout_log_path}");
     tracing::info!("Piping stderr to {stderr_log_path}");
     let mut child = cmd.spawn()?;
@@ -79,6 +94,53 @@ async fn build_package_inner(
     Ok(status)
 }
 
+/// Create the chroot of the build's iteration and architecture from `pacman_conf`,
+/// or update it if it exists already. Returns the path of the chroot.
+async fn prepare_chroot(schedule: &ScheduleBuild, pacman_conf: &str) -> Result<Utf8PathBuf> {
+    let chroot_path = chroot_path(schedule.iteration, schedule.architecture);
+    tokio::fs::create_dir_all(&chroot_path).await?;
+    let pacman_conf_path = chroot_path.join("pacman.conf");
+    fs::write(&pacman_conf_path, pacman_conf).await?;
+
+    let root_path = chroot_path.join("root");
+    // mkarchroot, arch-nspawn and makechrootpkg re-run themselves with sudo.
+    let mut cmd = if fs::try_exists(&root_path).await? {
+        let mut cmd = Command::new("arch-nspawn");
+        cmd.arg("-C").arg(&pacman_conf_path).arg(&root_path).args([
+            "pacman",
+            "-Syuu",
+            "--noconfirm",
+        ]);
+        cmd
+    } else {
+        let mut cmd = Command::new("mkarchroot");
+        cmd.arg("-C")
+            .arg(&pacman_conf_path)
+            .arg("-M")
+            .arg(format!(
+                "/usr/share/devtools/makepkg.conf.d/{}.conf",
+                schedule.architecture
+            ))
+            .arg(&root_path)
+            .arg("base-devel");
+        cmd
+    };
+    tracing::info!("Preparing chroot: {cmd:?}");
+    let status = cmd.status().await.wrap_err("Failed to prepare chroot")?;
+    if !status.success() {
+        bail!("Failed to prepare chroot {chroot_path}");
+    }
+
+    Ok(chroot_path)
+}
+
+/// Chroots are shared by all builds of an iteration, as they use the same `pacman.conf`.
+fn chroot_path(iteration_id: Uuid, architecture: ConcreteArchitecture) -> Utf8PathBuf {
+    BUILD_DIR
+        .join("chroots")
+        .join(format!("{iteration_id}-{architecture}"))
+}
+
 async fn import_gpg_keys(build_dir: &Utf8Path) -> Result<()> {
     let keys_dir = build_dir.join("keys/pgp");
     if !keys_dir.is_dir() {
@@ -97,12 +159,12 @@ async fn import_gpg_keys(build_dir: &Utf8Path) -> Result<()> {
     Ok(())
 }
 
-/// Make HEAD point to the commit at `repo_ref`, and update working tree and index to match that commit
+/// Make HEAD point to the resolved commit of the build, and update working tree and index to match that commit
 async fn checkout_build_git_ref(path: &Utf8Path, schedule: &ScheduleBuild) -> Result<()> {
-    let crate::PipelineTarget { branch_name, .. } = &schedule.source;
+    let crate::PipelineTarget { commit_hash, .. } = &schedule.source;
     let repo = Repository::open(path)?;
 
-    repo.set_head_detached(Oid::from_str(branch_name)?)?;
+    repo.set_head_detached(Oid::from_str(commit_hash.as_ref())?)?;
     repo.checkout_head(Some(CheckoutBuilder::default().force()))
         .wrap_err("Failed to checkout HEAD")?;
 
@@ -111,8 +111,13 @@ pub struct BuildPackageNode {
 // - Diff two graphs (already is custom functionality built on top)
 pub type BuildSetGraph = Graph<BuildPackageNode, PackageBuildDependency, Directed>;
 
+/// Calculate the build graph for each architecture, starting from the
+/// origin changesets of the namespace.
+/// `parent_origin_changesets` are used for resolving package sources,
+/// but not as roots of the build graph, as these packages are built in the parent namespace.
 pub async fn calculate_packages_to_be_built(
     namespace: &BuildNamespace,
+    parent_origin_changesets: &[GitRepoRef],
     source_repos: &mut SourceRepos,
 ) -> Result<HashMap<ConcreteArchitecture, BuildSetGraph>> {
     tracing::debug!(
@@ -121,10 +126,23 @@ pub async fn calculate_packages_to_be_built(
     );
     let start_time = Instant::now();
 
-    let packages_metadata =
-        gather_packages_metadata(namespace.current_origin_changesets.clone(), source_repos)
-            .await
-            .wrap_err("Error mapping package names to srcinfo")?;
+    // The namespace's own changesets take precedence over the parent's.
+    let mut origin_changesets = namespace.current_origin_changesets.clone();
+    origin_changesets.extend(
+        parent_origin_changesets
+            .iter()
+            .filter(|(pkgbase, _)| {
+                !namespace
+                    .current_origin_changesets
+                    .iter()
+                    .any(|(own_pkgbase, _)| own_pkgbase == pkgbase)
+            })
+            .cloned(),
+    );
+
+    let packages_metadata = gather_packages_metadata(origin_changesets, source_repos)
+        .await
+        .wrap_err("Error mapping package names to srcinfo")?;
     let global_graphs = build_global_dependency_graphs(&packages_metadata)
         .wrap_err("Failed to build global graph of dependents")?;
 
@@ -439,6 +457,7 @@ pub fn schedule_next_build_in_graph(
                 source: crate::PipelineTarget {
                     pkgbase: node.pkgbase.clone(),
                     branch_name: node.branch_name.clone(),
+                    commit_hash: node.commit_hash.clone(),
                 },
                 updated_build_set_graph,
             };
@@ -491,6 +510,14 @@ impl Diff {
     }
 }
 
+/// Whether all packages in the graph have been built successfully,
+/// i.e. the corresponding pacman repository is consistent.
+pub fn is_fully_built(graph: &BuildSetGraph) -> bool {
+    graph
+        .node_weights()
+        .all(|node| node.status == PackageBuildStatus::Built)
+}
+
 pub fn set_build_status(
     mut graph: BuildSetGraph,
     pkgbase: &Pkgbase,
@@ -0,0 +1,159 @@
+//! Detect active namespaces that touch the same pkgbases.
+//!
+//! If two namespaces rebuild the same package from different branches,
+//! releasing both will lead to clashing pkgrels. We can't prevent this,
+//! but we can warn about it early.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BuildNamespace, BuildSetIteration, Pkgbase};
+
+/// Pkgbases touched by a single namespace.
+#[derive(Debug, Clone)]
+pub struct NamespacePkgbases {
+    pub namespace_name: String,
+    pub pkgbases: BTreeSet<Pkgbase>,
+}
+
+impl NamespacePkgbases {
+    /// Collect the pkgbases in the origin changesets of the namespace, and,
+    /// if available, all pkgbases in the build graphs of its newest iteration.
+    pub fn new(namespace: &BuildNamespace, newest_iteration: Option<&BuildSetIteration>) -> Self {
+        let mut pkgbases: BTreeSet<Pkgbase> = namespace
+            .current_origin_changesets
+            .iter()
+            .map(|(pkgbase, _)| pkgbase.clone())
+            .collect();
+
+        if let Some(iteration) = newest_iteration {
+            for graph in iteration.packages_to_be_built.values() {
+                pkgbases.extend(graph.node_weights().map(|node| node.pkgbase.clone()));
+            }
+        }
+
+        NamespacePkgbases {
+            namespace_name: namespace.name.clone(),
+            pkgbases,
+        }
+    }
+}
+
+/// Pkgbases that are touched by another namespace as well.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NamespaceOverlap {
+    pub namespace_name: String,
+    pub pkgbases: Vec<Pkgbase>,
+}
+
+/// Find all namespaces in `others` that touch pkgbases of `namespace`.
+pub fn find_overlaps(
+    namespace: &NamespacePkgbases,
+    others: &[NamespacePkgbases],
+) -> Vec<NamespaceOverlap> {
+    others
+        .iter()
+        .filter(|other| other.namespace_name != namespace.namespace_name)
+        .filter_map(|other| {
+            let pkgbases: Vec<_> = namespace
+                .pkgbases
+                .intersection(&other.pkgbases)
+                .cloned()
+                .collect();
+            (!pkgbases.is_empty()).then(|| NamespaceOverlap {
+                namespace_name: other.namespace_name.clone(),
+                pkgbases,
+            })
+        })
+        .collect()
+}
+
+/// Overlapping pkgbases between each pair of namespaces.
+#[derive(Serialize, Debug, Clone)]
+pub struct OverlapMatrix {
+    pub namespace_names: Vec<String>,
+    /// `cells[i][j]` contains the pkgbases touched by both namespace `i` and `j`.
+    /// Cells on the diagonal are left empty.
+    pub cells: Vec<Vec<Vec<Pkgbase>>>,
+}
+
+impl OverlapMatrix {
+    pub fn new(namespaces: &[NamespacePkgbases]) -> Self {
+        let cells = namespaces
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                namespaces
+                    .iter()
+                    .enumerate()
+                    .map(|(j, column)| {
+                        if i == j {
+                            return Vec::new();
+                        }
+                        row.pkgbases
+                            .intersection(&column.pkgbases)
+                            .cloned()
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        OverlapMatrix {
+            namespace_names: namespaces
+                .iter()
+                .map(|namespace| namespace.namespace_name.clone())
+                .collect(),
+            cells,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn namespace(name: &str, pkgbases: &[&str]) -> NamespacePkgbases {
+        NamespacePkgbases {
+            namespace_name: name.to_string(),
+            pkgbases: pkgbases
+                .iter()
+                .map(|pkgbase| Pkgbase::from(pkgbase.to_string()))
+                .collect(),
+        }
+    }
+
+    #[rstest]
+    fn test_find_overlaps() {
+        let new = namespace("openssl", &["openssl", "curl", "python"]);
+        let others = [
+            namespace("openssl", &["openssl"]),
+            namespace("python", &["python", "python-requests"]),
+            namespace("linux", &["linux"]),
+        ];
+
+        assert_eq!(
+            find_overlaps(&new, &others),
+            vec![NamespaceOverlap {
+                namespace_name: "python".to_string(),
+                pkgbases: vec!["python".to_string().into()],
+            }]
+        );
+    }
+
+    #[rstest]
+    fn test_overlap_matrix() {
+        let matrix = OverlapMatrix::new(&[
+            namespace("a", &["gzip", "sed"]),
+            namespace("b", &["sed"]),
+            namespace("c", &["linux"]),
+        ]);
+
+        assert_eq!(matrix.cells[0][1], vec![Pkgbase::from("sed".to_string())]);
+        assert_eq!(matrix.cells[1][0], vec![Pkgbase::from("sed".to_string())]);
+        assert!(matrix.cells[0][0].is_empty());
+        assert!(matrix.cells[0][2].is_empty());
+    }
+}
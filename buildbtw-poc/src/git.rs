@@ -1,35 +1,120 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use camino::Utf8PathBuf;
-use color_eyre::eyre::{Context, Result};
+use color_eyre::eyre::{Context, Result, bail, eyre};
 use git2::build::RepoBuilder;
-use git2::{BranchType, FetchOptions, RemoteCallbacks, Repository};
+use git2::{BranchType, ErrorCode, FetchOptions, Oid, PushOptions, RemoteCallbacks, Repository};
 use tokio::task::JoinSet;
 
 use crate::source_info::SourceInfo;
-use crate::{CommitHash, GitRef, Pkgbase};
+use crate::{BranchName, CommitHash, GitRef, Pkgbase};
+
+/// Where package source repositories are cloned from.
+#[derive(Debug, Clone)]
+pub struct PackagingRemote {
+    url_template: String,
+    credentials: GitCredentials,
+}
+
+impl PackagingRemote {
+    /// Clone via SSH from the packages group of a gitlab instance.
+    pub fn gitlab(gitlab_domain: &str, gitlab_packages_group: &str) -> Self {
+        PackagingRemote::new(
+            format!("git@{gitlab_domain}:{gitlab_packages_group}/{{project}}.git"),
+            GitCredentials::SshAgent,
+        )
+    }
+
+    /// Clone from the URL given by `url_template`, with `{pkgbase}` replaced by the
+    /// pkgbase and `{project}` replaced by the gitlab project path of the pkgbase,
+    /// e.g. "https://example.org/packages/{pkgbase}.git" or "file:///srv/mirror/{project}.git".
+    pub fn new(url_template: String, credentials: GitCredentials) -> Self {
+        PackagingRemote {
+            url_template,
+            credentials,
+        }
+    }
+
+    pub fn repository_url(&self, pkgbase: &Pkgbase) -> String {
+        let project_path = crate::gitlab::gitlab_project_name_to_path(pkgbase.as_ref());
+        self.url_template
+            .replace("{pkgbase}", pkgbase.as_ref())
+            .replace("{project}", &project_path)
+    }
+
+    pub fn credentials(&self) -> &GitCredentials {
+        &self.credentials
+    }
+}
+
+/// Whether a remote URL template contains a placeholder for the package,
+/// see [`PackagingRemote::new`].
+pub fn is_valid_url_template(url_template: &str) -> bool {
+    url_template.contains("{pkgbase}") || url_template.contains("{project}")
+}
+
+/// How to authenticate against remotes of package source repositories.
+#[derive(Debug, Clone)]
+pub enum GitCredentials {
+    /// Don't authenticate, e.g. for `file://` remotes or public HTTPS remotes.
+    None,
+    /// Use the keys of the running SSH agent.
+    SshAgent,
+    /// Use an SSH private key file.
+    SshKey {
+        private_key: Utf8PathBuf,
+        passphrase: Option<redact::Secret<String>>,
+    },
+    /// Use a username and password or access token, for HTTPS remotes.
+    UserPassword {
+        username: String,
+        password: redact::Secret<String>,
+    },
+}
+
+impl GitCredentials {
+    pub fn remote_callbacks(&self) -> RemoteCallbacks<'_> {
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(|_, username_from_url, _| {
+            let username = username_from_url.unwrap_or("git");
+            match self {
+                GitCredentials::None => Err(git2::Error::from_str(
+                    "Remote requires authentication, but no credentials are configured",
+                )),
+                GitCredentials::SshAgent => git2::Cred::ssh_key_from_agent(username),
+                GitCredentials::SshKey {
+                    private_key,
+                    passphrase,
+                } => git2::Cred::ssh_key(
+                    username,
+                    None,
+                    private_key.as_std_path(),
+                    passphrase
+                        .as_ref()
+                        .map(|passphrase| passphrase.expose_secret().as_str()),
+                ),
+                GitCredentials::UserPassword { username, password } => {
+                    git2::Cred::userpass_plaintext(username, password.expose_secret())
+                }
+            }
+        });
+        callbacks
+    }
+}
 
 pub async fn clone_packaging_repository(
     pkgbase: Pkgbase,
-    gitlab_domain: String,
-    gitlab_packages_group: String,
+    remote: PackagingRemote,
 ) -> Result<git2::Repository> {
     tokio::task::spawn_blocking(move || {
         tracing::info!("Cloning {pkgbase}");
 
-        // Convert pkgbase to project path
-        let project_path = crate::gitlab::gitlab_project_name_to_path(pkgbase.as_ref());
-
-        // Set up the callbacks to use SSH credentials
-        let mut callbacks = RemoteCallbacks::new();
-        callbacks.credentials(|_, _, _| git2::Cred::ssh_key_from_agent("git"));
-
-        // Configure fetch options to use the callbacks
+        // Configure fetch options to use the remote's credentials
         let mut fetch_options = FetchOptions::new();
-        fetch_options.remote_callbacks(callbacks);
+        fetch_options.remote_callbacks(remote.credentials().remote_callbacks());
 
         let repo = RepoBuilder::new().fetch_options(fetch_options).clone(
-            &format!("git@{gitlab_domain}:{gitlab_packages_group}/{project_path}.git"),
+            &remote.repository_url(&pkgbase),
             package_source_path(&pkgbase).as_std_path(),
         )?;
 
@@ -40,16 +125,11 @@ pub async fn clone_packaging_repository(
 
 pub async fn clone_or_fetch_repositories(
     pkgbases: Vec<Pkgbase>,
-    gitlab_domain: String,
-    gitlab_packages_group: String,
+    remote: PackagingRemote,
 ) -> Result<()> {
     let mut join_set = JoinSet::new();
     for pkgbase in pkgbases {
-        join_set.spawn(clone_or_fetch_repository(
-            pkgbase,
-            gitlab_domain.clone(),
-            gitlab_packages_group.clone(),
-        ));
+        join_set.spawn(clone_or_fetch_repository(pkgbase, remote.clone()));
         while join_set.len() >= 50 {
             join_set.join_next().await.unwrap()??;
         }
@@ -60,48 +140,56 @@ pub async fn clone_or_fetch_repositories(
     Ok(())
 }
 
-pub async fn fetch_repository(pkgbase: Pkgbase) -> Result<()> {
+pub async fn fetch_repository(pkgbase: Pkgbase, remote: PackagingRemote) -> Result<()> {
     tokio::task::spawn_blocking(move || {
         tracing::debug!("Fetching repository {:?}", &pkgbase);
         let repo = git2::Repository::open(package_source_path(&pkgbase))?;
+        fetch_origin(
+            &repo,
+            &remote.repository_url(&pkgbase),
+            remote.credentials(),
+        )
+    })
+    .await?
+}
 
-        // Set up the callbacks to use SSH credentials
-        let mut callbacks = RemoteCallbacks::new();
-        callbacks.credentials(|_, _, _| git2::Cred::ssh_key_from_agent("git"));
-
-        // Configure fetch options to use the callbacks and download tags
-        let mut fetch_options = git2::FetchOptions::new();
-        fetch_options.download_tags(git2::AutotagOption::All);
-        fetch_options.remote_callbacks(callbacks);
+/// Fetch all branches and tags of `origin`, pointing it to `url` first.
+/// This way, changes to the configured remote are picked up,
+/// e.g. switching from HTTPS to SSH.
+fn fetch_origin(repo: &Repository, url: &str, credentials: &GitCredentials) -> Result<()> {
+    if repo.find_remote("origin")?.url() != Some(url) {
+        tracing::info!("Changing the remote URL of {:?} to {url}", repo.path());
+        repo.remote_set_url("origin", url)?;
+    }
 
-        // Find remote to fetch from
-        let mut remote = repo.find_remote("origin")?;
+    // Configure fetch options to use the credentials and download tags.
+    // Remote-tracking branches deleted on the remote are removed.
+    let mut fetch_options = git2::FetchOptions::new();
+    fetch_options.download_tags(git2::AutotagOption::All);
+    fetch_options.prune(git2::FetchPrune::On);
+    fetch_options.remote_callbacks(credentials.remote_callbacks());
 
-        // Fetch everything from the remote
-        remote.fetch(
-            &["+refs/heads/*:refs/remotes/origin/*"],
-            Some(&mut fetch_options),
-            None,
-        )?;
-        // TODO: cleanup remote branches that are orphan
-        Ok(())
-    })
-    .await?
+    // Fetch everything from the remote
+    repo.find_remote("origin")?.fetch(
+        &["+refs/heads/*:refs/remotes/origin/*"],
+        Some(&mut fetch_options),
+        None,
+    )?;
+    Ok(())
 }
 
 pub async fn clone_or_fetch_repository(
     pkgbase: Pkgbase,
-    gitlab_domain: String,
-    gitlab_packages_group: String,
+    remote: PackagingRemote,
 ) -> Result<git2::Repository> {
     let maybe_repo = git2::Repository::open(package_source_path(&pkgbase));
     let repo = if let Ok(repo) = maybe_repo {
-        fetch_repository(pkgbase.clone())
+        fetch_repository(pkgbase.clone(), remote)
             .await
-            .expect("Failed to fetch repository");
+            .wrap_err_with(|| format!("Failed to fetch repository {pkgbase}"))?;
         repo
     } else {
-        clone_packaging_repository(pkgbase, gitlab_domain, gitlab_packages_group).await?
+        clone_packaging_repository(pkgbase, remote).await?
     };
     Ok(repo)
 }
@@ -109,39 +197,419 @@ pub async fn clone_or_fetch_repository(
 pub async fn retrieve_srcinfo_from_remote_repository(
     pkgbase: Pkgbase,
     branch: &GitRef,
-    gitlab_domain: String,
-    gitlab_packages_group: String,
+    remote: PackagingRemote,
 ) -> Result<SourceInfo> {
-    let repo =
-        clone_or_fetch_repository(pkgbase.clone(), gitlab_domain, gitlab_packages_group).await?;
+    let repo = clone_or_fetch_repository(pkgbase.clone(), remote).await?;
 
-    // TODO srcinfo might not be up-to-date due to pkgbuild changes not automatically changing srcinfo
+    // The srcinfo might not be up-to-date with the PKGBUILD, see `srcinfo_check`
     read_srcinfo_from_repo(&repo, branch)
         .wrap_err("Failed to read srcinfo")
         .wrap_err(pkgbase)
 }
 
-pub fn get_branch_commit_sha(repo: &Repository, branch: &str) -> Result<CommitHash> {
-    let branch = repo.find_branch(&format!("origin/{branch}"), BranchType::Remote)?;
-    Ok(CommitHash(branch.get().peel_to_commit()?.id().to_string()))
+/// Branch heads of a remote repository and their commit hashes, like `git ls-remote --heads`.
+pub fn list_remote_heads(
+    url: &str,
+    credentials: &GitCredentials,
+) -> Result<HashMap<BranchName, CommitHash>> {
+    let mut remote = git2::Remote::create_detached(url)?;
+    let mut connection = remote
+        .connect_auth(
+            git2::Direction::Fetch,
+            Some(credentials.remote_callbacks()),
+            None,
+        )
+        .wrap_err_with(|| format!("Failed to connect to {url}"))?;
+    let heads = connection
+        .remote()
+        .list()?
+        .iter()
+        .filter_map(|head| {
+            let branch = head.name().strip_prefix("refs/heads/")?;
+            Some((branch.to_string(), CommitHash(head.oid().to_string())))
+        })
+        .collect();
+
+    Ok(heads)
 }
 
-pub fn read_srcinfo_from_repo(repo: &Repository, branch: &str) -> Result<SourceInfo> {
-    let branch = repo.find_branch(&format!("origin/{branch}"), BranchType::Remote)?;
-    let file_oid = branch
-        .get()
-        .peel_to_tree()?
-        .get_path(Path::new(".SRCINFO"))?
-        .id();
+/// Remote-tracking branches of a local clone and their commit hashes.
+pub fn list_local_remote_heads(repo: &Repository) -> Result<HashMap<BranchName, CommitHash>> {
+    let mut heads = HashMap::new();
+    for branch in repo.branches(Some(BranchType::Remote))? {
+        let (branch, _) = branch?;
+        let (Some(name), Some(oid)) = (branch.name()?, branch.get().target()) else {
+            // Skip symbolic references such as `origin/HEAD`
+            continue;
+        };
+        if let Some(name) = name.strip_prefix("origin/") {
+            heads.insert(name.to_string(), CommitHash(oid.to_string()));
+        }
+    }
 
-    let file_blob = repo.find_blob(file_oid)?;
+    Ok(heads)
+}
+
+/// Whether the remote has branches that are missing or outdated in the local clone.
+/// Branches deleted on the remote are ignored, as fetching doesn't remove them locally.
+pub fn has_new_remote_heads(
+    remote_heads: &HashMap<BranchName, CommitHash>,
+    local_heads: &HashMap<BranchName, CommitHash>,
+) -> bool {
+    remote_heads
+        .iter()
+        .any(|(branch, commit_hash)| local_heads.get(branch) != Some(commit_hash))
+}
+
+/// Resolve a git ref to a commit in the local clone of a source repository.
+/// Branch names refer to remote-tracking branches and are looked up first,
+/// then tags, full and abbreviated commit hashes and any other revspec.
+pub fn resolve_commit<'repo>(
+    repo: &'repo Repository,
+    git_ref: &str,
+) -> Result<git2::Commit<'repo>> {
+    if let Ok(branch) = repo.find_branch(&format!("origin/{git_ref}"), BranchType::Remote) {
+        return Ok(branch.get().peel_to_commit()?);
+    }
+    let object = repo
+        .revparse_single(git_ref)
+        .wrap_err_with(|| format!("Failed to resolve git ref {git_ref}"))?;
+    Ok(object.peel_to_commit()?)
+}
+
+pub fn resolve_commit_hash(repo: &Repository, git_ref: &str) -> Result<CommitHash> {
+    Ok(CommitHash(resolve_commit(repo, git_ref)?.id().to_string()))
+}
+
+/// Whether `git_ref` is a full commit hash, as opposed to a branch, tag or abbreviated hash.
+pub fn is_commit_hash(git_ref: &str) -> bool {
+    matches!(git_ref.len(), 40 | 64) && git_ref.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Read the commit hash of a git ref in the local clone of `pkgbase`, see [`resolve_commit`].
+pub async fn read_branch_commit_hash(pkgbase: Pkgbase, git_ref: GitRef) -> Result<CommitHash> {
+    tokio::task::spawn_blocking(move || {
+        let repo = Repository::open(package_source_path(&pkgbase))?;
+        resolve_commit_hash(&repo, &git_ref)
+    })
+    .await?
+}
+
+/// Pin an origin changeset to the commit it currently refers to.
+///
+/// Branches are kept, as namespaces are meant to follow them. Tags and
+/// abbreviated hashes are replaced by the full commit hash, so moving a tag
+/// doesn't change what a namespace builds.
+/// Fails if the source repository isn't cloned or the git ref can't be resolved.
+pub async fn pin_git_ref(pkgbase: Pkgbase, git_ref: GitRef) -> Result<GitRef> {
+    tokio::task::spawn_blocking(move || {
+        let repo = Repository::open(package_source_path(&pkgbase))
+            .wrap_err_with(|| format!("Source repository of {pkgbase} isn't cloned"))?;
+        let is_branch = repo
+            .find_branch(&format!("origin/{git_ref}"), BranchType::Remote)
+            .is_ok();
+        if is_branch {
+            return Ok(git_ref);
+        }
+        let commit_hash = resolve_commit_hash(&repo, &git_ref)
+            .wrap_err_with(|| format!("Unknown git ref {pkgbase}/{git_ref}"))?;
+        Ok(commit_hash.into())
+    })
+    .await?
+}
+
+/// Find a tag or branch of origin that contains `commit_hash`, for running
+/// CI pipelines of pinned commits, which can only run on branches and tags.
+/// Prefers tags pointing to the commit, then `main`, then other branches.
+pub async fn find_ref_containing_commit(
+    pkgbase: Pkgbase,
+    commit_hash: CommitHash,
+) -> Result<GitRef> {
+    tokio::task::spawn_blocking(move || {
+        let repo = Repository::open(package_source_path(&pkgbase))?;
+        ref_containing_commit(&repo, Oid::from_str(commit_hash.as_ref())?)?
+            .ok_or_else(|| eyre!("No branch or tag of {pkgbase} contains {commit_hash}"))
+    })
+    .await?
+}
 
-    assert!(!file_blob.is_binary());
+fn ref_containing_commit(repo: &Repository, commit: Oid) -> Result<Option<GitRef>> {
+    for tag in repo.tag_names(None)?.iter().flatten() {
+        let tag_commit = repo.revparse_single(&format!("refs/tags/{tag}^{{commit}}"))?;
+        if tag_commit.id() == commit {
+            return Ok(Some(tag.to_string()));
+        }
+    }
+
+    let mut branches = Vec::new();
+    for branch in repo.branches(Some(BranchType::Remote))? {
+        let (branch, _) = branch?;
+        let Some(name) = branch.name()?.and_then(|name| name.strip_prefix("origin/")) else {
+            continue;
+        };
+        let Some(branch_commit) = branch.get().target() else {
+            continue;
+        };
+        if branch_commit == commit || repo.graph_descendant_of(branch_commit, commit)? {
+            branches.push(name.to_string());
+        }
+    }
+    branches.sort_by_key(|branch| branch != "main");
+    Ok(branches.into_iter().next())
+}
+
+pub fn read_srcinfo_from_repo(repo: &Repository, git_ref: &str) -> Result<SourceInfo> {
+    let tree = resolve_commit(repo, git_ref)?.tree()?;
+    let srcinfo = read_file_from_tree(repo, &tree, ".SRCINFO")?;
+    parse_srcinfo(&srcinfo)
+}
 
-    let parsed = SourceInfo::from_string(&String::from_utf8(file_blob.content().to_vec())?)?;
+pub fn parse_srcinfo(srcinfo: &str) -> Result<SourceInfo> {
+    let parsed = SourceInfo::from_string(srcinfo)?;
     parsed.source_info().wrap_err("Failed to parse SRCINFO")
 }
 
+/// Read a text file from the root of a git tree.
+pub fn read_file_from_tree(repo: &Repository, tree: &git2::Tree, path: &str) -> Result<String> {
+    let file_oid = tree.get_path(Path::new(path))?.id();
+    let file_blob = repo.find_blob(file_oid)?;
+
+    if file_blob.is_binary() {
+        bail!("{path} is a binary file");
+    }
+
+    String::from_utf8(file_blob.content().to_vec()).wrap_err_with(|| format!("{path} isn't UTF-8"))
+}
+
+/// Force-push the local `branch` to origin and update the
+/// corresponding remote-tracking branch.
+///
+/// Like `git push --force-with-lease`, this aborts if the branch on origin doesn't point to
+/// the commit of the remote-tracking branch anymore, e.g. because a packager pushed since
+/// the last fetch. Branches without a remote-tracking branch must not exist on origin yet.
+pub fn push_branch(repo: &Repository, branch: &str, credentials: &GitCredentials) -> Result<()> {
+    tracing::debug!("Pushing branch {branch} of {:?}", repo.path());
+
+    let remote_ref = format!("refs/heads/{branch}");
+    let expected_remote_commit = match repo.find_reference(&format!("refs/remotes/origin/{branch}"))
+    {
+        Ok(reference) => reference.peel_to_commit()?.id(),
+        Err(e) if e.code() == ErrorCode::NotFound => Oid::zero(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut rejection = None;
+    let mut callbacks = credentials.remote_callbacks();
+    callbacks.push_negotiation(|updates| {
+        for update in updates {
+            if update.dst_refname() == Some(remote_ref.as_str())
+                && update.src() != expected_remote_commit
+            {
+                return Err(git2::Error::from_str(&format!(
+                    "{branch} was updated on origin since it was last fetched"
+                )));
+            }
+        }
+        Ok(())
+    });
+    callbacks.push_update_reference(|_, status| {
+        rejection = status.map(str::to_string);
+        Ok(())
+    });
+    let mut push_options = PushOptions::new();
+    push_options.remote_callbacks(callbacks);
+
+    let mut remote = repo.find_remote("origin")?;
+    remote
+        .push(
+            &[format!("+refs/heads/{branch}:{remote_ref}")],
+            Some(&mut push_options),
+        )
+        .wrap_err_with(|| format!("Failed to push branch {branch}"))?;
+    drop(push_options);
+    if let Some(rejection) = rejection {
+        bail!("Origin rejected push of branch {branch}: {rejection}");
+    }
+
+    let commit = repo.find_reference(&remote_ref)?.peel_to_commit()?;
+    repo.reference(
+        &format!("refs/remotes/origin/{branch}"),
+        commit.id(),
+        true,
+        "buildbtw: push",
+    )?;
+    Ok(())
+}
+
+/// Signature for commits created by buildbtw, taken from the git config if available.
+pub fn commit_signature(repo: &Repository) -> Result<git2::Signature<'static>> {
+    Ok(repo
+        .signature()
+        .or_else(|_| git2::Signature::now("buildbtw", "buildbtw@localhost"))?)
+}
+
 pub fn package_source_path(pkgbase: &Pkgbase) -> Utf8PathBuf {
     Utf8PathBuf::from(format!("./source_repos/{pkgbase}"))
 }
+
+/// Commit files to the root of the tree of `parent`, for setting up repositories
+/// in tests. If `update_ref` is given, it's pointed to the new commit.
+#[cfg(any(test, feature = "gitlab-mock"))]
+pub fn commit_files(
+    repo: &Repository,
+    update_ref: Option<&str>,
+    parent: Option<Oid>,
+    files: &[(&str, &str)],
+    message: &str,
+) -> Result<Oid> {
+    let parent = parent.map(|parent| repo.find_commit(parent)).transpose()?;
+    let parent_tree = parent.as_ref().map(|parent| parent.tree()).transpose()?;
+    let mut tree_builder = repo.treebuilder(parent_tree.as_ref())?;
+    for (path, content) in files {
+        tree_builder.insert(path, repo.blob(content.as_bytes())?, 0o100644)?;
+    }
+    let tree = repo.find_tree(tree_builder.write()?)?;
+    let signature = git2::Signature::now("packager", "packager@localhost")?;
+    let parents: Vec<_> = parent.iter().collect();
+    Ok(repo.commit(update_ref, &signature, &signature, message, &tree, &parents)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn commit(repo: &Repository, parent: Option<Oid>, content: &str) -> Oid {
+        commit_files(repo, None, parent, &[("PKGBUILD", content)], content).unwrap()
+    }
+
+    #[rstest]
+    fn test_resolve_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let tagged = commit(&repo, None, "pkgver=1");
+        let main = commit(&repo, Some(tagged), "pkgver=2");
+        repo.reference("refs/remotes/origin/main", main, true, "test")
+            .unwrap();
+        repo.tag_lightweight("1-1", &repo.find_object(tagged, None).unwrap(), false)
+            .unwrap();
+
+        let resolve = |git_ref: &str| resolve_commit(&repo, git_ref).unwrap().id();
+        assert_eq!(resolve("main"), main);
+        assert_eq!(resolve("1-1"), tagged);
+        assert_eq!(resolve(&tagged.to_string()), tagged);
+        assert_eq!(resolve(&tagged.to_string()[..8]), tagged);
+        assert!(resolve_commit(&repo, "missing").is_err());
+    }
+
+    #[rstest]
+    fn test_ref_containing_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let first = commit(&repo, None, "pkgver=1");
+        let second = commit(&repo, Some(first), "pkgver=2");
+        let third = commit(&repo, Some(second), "pkgver=3");
+        let unreachable = commit(&repo, None, "pkgver=4");
+        repo.reference("refs/remotes/origin/main", second, true, "test")
+            .unwrap();
+        repo.reference("refs/remotes/origin/fix", third, true, "test")
+            .unwrap();
+        repo.tag_lightweight("2-1", &repo.find_object(second, None).unwrap(), false)
+            .unwrap();
+
+        let find = |commit| ref_containing_commit(&repo, commit).unwrap();
+        assert_eq!(find(second), Some("2-1".to_string()));
+        assert_eq!(find(first), Some("main".to_string()));
+        assert_eq!(find(third), Some("fix".to_string()));
+        assert_eq!(find(unreachable), None);
+    }
+
+    #[rstest]
+    fn test_read_binary_file_from_tree_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let files = [("PKGBUILD", "pkgver=1"), ("logo.png", "\0\x01")];
+        let commit = commit_files(&repo, None, None, &files, "Add logo").unwrap();
+        let tree = repo.find_commit(commit).unwrap().tree().unwrap();
+
+        assert_eq!(
+            read_file_from_tree(&repo, &tree, "PKGBUILD").unwrap(),
+            "pkgver=1"
+        );
+        assert!(read_file_from_tree(&repo, &tree, "logo.png").is_err());
+    }
+
+    #[rstest]
+    fn test_push_branch_lease() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = Repository::init_bare(dir.path().join("origin")).unwrap();
+        let initial = commit(&origin, None, "pkgver=1");
+        origin
+            .reference("refs/heads/fix", initial, true, "test")
+            .unwrap();
+        let clone = Repository::clone(
+            dir.path().join("origin").to_str().unwrap(),
+            dir.path().join("clone"),
+        )
+        .unwrap();
+
+        let rebased = commit(&clone, None, "pkgver=2");
+        clone
+            .reference("refs/heads/fix", rebased, true, "test")
+            .unwrap();
+        push_branch(&clone, "fix", &GitCredentials::None).unwrap();
+        assert_eq!(origin.refname_to_id("refs/heads/fix").unwrap(), rebased);
+
+        // A packager pushes after the last fetch
+        let pushed = commit(&origin, Some(rebased), "pkgver=3");
+        origin
+            .reference("refs/heads/fix", pushed, true, "test")
+            .unwrap();
+        let rebased_again = commit(&clone, None, "pkgver=4");
+        clone
+            .reference("refs/heads/fix", rebased_again, true, "test")
+            .unwrap();
+        assert!(push_branch(&clone, "fix", &GitCredentials::None).is_err());
+        assert_eq!(origin.refname_to_id("refs/heads/fix").unwrap(), pushed);
+    }
+
+    #[rstest]
+    fn test_fetch_origin_follows_remote_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_origin = Repository::init_bare(dir.path().join("old")).unwrap();
+        let old_main = commit(&old_origin, None, "pkgver=1");
+        old_origin
+            .reference("refs/heads/main", old_main, true, "test")
+            .unwrap();
+        let new_origin = Repository::init_bare(dir.path().join("new")).unwrap();
+        let new_main = commit(&new_origin, None, "pkgver=2");
+        new_origin
+            .reference("refs/heads/main", new_main, true, "test")
+            .unwrap();
+        let clone = Repository::clone(
+            dir.path().join("old").to_str().unwrap(),
+            dir.path().join("clone"),
+        )
+        .unwrap();
+
+        let new_url = dir.path().join("new").to_str().unwrap().to_string();
+        fetch_origin(&clone, &new_url, &GitCredentials::None).unwrap();
+        assert_eq!(
+            clone.find_remote("origin").unwrap().url(),
+            Some(new_url.as_str())
+        );
+        assert_eq!(
+            clone.refname_to_id("refs/remotes/origin/main").unwrap(),
+            new_main
+        );
+    }
+
+    #[rstest]
+    #[case("main", false)]
+    #[case("1.0-1", false)]
+    #[case("a1b2c3d", false)]
+    #[case("0123456789abcdef0123456789abcdef01234567", true)]
+    fn test_is_commit_hash(#[case] git_ref: &str, #[case] expected: bool) {
+        assert_eq!(is_commit_hash(git_ref), expected);
+    }
+}
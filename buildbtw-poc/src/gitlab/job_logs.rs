@@ -0,0 +1,117 @@
+//! Archive the job logs and artifacts of finished gitlab pipelines,
+//! so they're available after gitlab's retention period has passed.
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::{Context, Result};
+use futures::TryStreamExt;
+use gitlab::{
+    AsyncGitlab,
+    api::{AsyncQuery, Pagination},
+};
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::build_logs::sanitize_log_file_name;
+
+#[derive(Deserialize, Debug)]
+struct PipelineJob {
+    id: u64,
+    name: String,
+    /// Only present if the job uploaded an artifacts archive.
+    artifacts_file: Option<ArtifactsFile>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ArtifactsFile {
+    #[allow(dead_code)]
+    filename: String,
+}
+
+/// Store the trace and artifacts of all jobs of a pipeline in `dir`.
+/// Jobs that were retried are included, so every attempt is kept.
+///
+/// Traces and artifacts are streamed to disk, as artifacts archives may be large.
+/// The gitlab crate only supports buffering raw responses in memory,
+/// so they're downloaded via reqwest instead.
+pub async fn archive_pipeline_jobs(
+    client: &AsyncGitlab,
+    gitlab_url: &Url,
+    gitlab_token: &str,
+    project_id: u64,
+    pipeline_id: u64,
+    dir: &Utf8Path,
+) -> Result<()> {
+    let endpoint = gitlab::api::projects::pipelines::PipelineJobs::builder()
+        .project(project_id)
+        .pipeline(pipeline_id)
+        .include_retried(true)
+        .build()?;
+    let jobs: Vec<PipelineJob> = gitlab::api::paged(endpoint, Pagination::All)
+        .query_async(client)
+        .await
+        .wrap_err("Failed to list pipeline jobs")?;
+
+    let http = reqwest::Client::new();
+    let download = async |api_path: String, file_name: String| {
+        let url = gitlab_url.join(&format!("/api/v4/{api_path}"))?;
+        download_to_file(&http, url, gitlab_token, &dir.join(file_name)).await
+    };
+
+    tokio::fs::create_dir_all(dir).await?;
+    for job in jobs {
+        let file_stem = format!("job-{}-{}", job.id, sanitize_log_file_name(&job.name));
+
+        // See https://docs.gitlab.com/api/jobs/#get-a-log-file
+        download(
+            format!("projects/{project_id}/jobs/{}/trace", job.id),
+            format!("{file_stem}.log"),
+        )
+        .await
+        .wrap_err_with(|| format!("Failed to download trace of job {}", job.id))?;
+
+        if job.artifacts_file.is_some() {
+            // See https://docs.gitlab.com/api/job_artifacts/#get-job-artifacts
+            download(
+                format!("projects/{project_id}/jobs/{}/artifacts", job.id),
+                format!("{file_stem}-artifacts.zip"),
+            )
+            .await
+            .wrap_err_with(|| format!("Failed to download artifacts of job {}", job.id))?;
+        }
+    }
+
+    tracing::info!("Archived jobs of pipeline {pipeline_id} in {dir}");
+
+    Ok(())
+}
+
+/// Stream a response body into a file.
+/// The body is written to a temporary file first, so an interrupted
+/// download doesn't leave a truncated file behind.
+async fn download_to_file(
+    http: &reqwest::Client,
+    url: Url,
+    token: &str,
+    path: &Utf8Path,
+) -> Result<()> {
+    let response = http
+        .get(url)
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let partial_path = Utf8PathBuf::from(format!("{path}.part"));
+    let mut file = tokio::io::BufWriter::new(tokio::fs::File::create(&partial_path).await?);
+    let mut body = response.bytes_stream();
+    while let Some(chunk) = body.try_next().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    tokio::fs::rename(&partial_path, path)
+        .await
+        .wrap_err_with(|| format!("Failed to move download to {path}"))?;
+
+    Ok(())
+}
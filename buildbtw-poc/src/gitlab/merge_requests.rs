@@ -0,0 +1,281 @@
+//! Track the origin changesets of a namespace as GitLab merge requests.
+//!
+//! For each origin changeset branch, we open a merge request (or attach to an
+//! existing one), keep a summary comment about the namespace's builds up to date,
+//! and notice when the merge request was merged. Once all merge requests of a
+//! namespace are merged, the namespace is ready for release.
+
+use color_eyre::eyre::{Context, Result};
+use gitlab::{
+    AsyncGitlab,
+    api::{
+        AsyncQuery,
+        projects::merge_requests::{
+            CreateMergeRequest, MergeRequest, MergeRequestState, MergeRequests,
+            notes::{CreateMergeRequestNote, EditMergeRequestNote},
+        },
+    },
+};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::{CommitBuildStatus, gitlab_project_name_to_path};
+use crate::{
+    PackageBuildStatus, Pkgbase, build_set_graph::BuildSetGraph, source_info::ConcreteArchitecture,
+};
+
+/// Branch that merge requests for origin changesets target.
+pub const TARGET_BRANCH: &str = "main";
+
+/// State of a merge request tracked by a namespace.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+pub enum TrackedMergeRequestState {
+    /// We haven't opened or found the merge request yet
+    Requested,
+    Opened,
+    Merged,
+    Closed,
+}
+
+/// A merge request for an origin changeset of a namespace.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrackedMergeRequest {
+    pub pkgbase: Pkgbase,
+    pub branch_name: String,
+    pub state: TrackedMergeRequestState,
+    pub web_url: Option<Url>,
+}
+
+/// Whether all merge requests of a namespace were merged.
+/// Namespaces without merge requests are never considered ready.
+pub fn ready_for_release(merge_requests: &[TrackedMergeRequest]) -> bool {
+    !merge_requests.is_empty()
+        && merge_requests
+            .iter()
+            .all(|merge_request| merge_request.state == TrackedMergeRequestState::Merged)
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GitlabMergeRequestState {
+    Opened,
+    Closed,
+    Locked,
+    Merged,
+}
+
+impl From<GitlabMergeRequestState> for TrackedMergeRequestState {
+    fn from(value: GitlabMergeRequestState) -> Self {
+        match value {
+            GitlabMergeRequestState::Opened | GitlabMergeRequestState::Locked => {
+                TrackedMergeRequestState::Opened
+            }
+            GitlabMergeRequestState::Closed => TrackedMergeRequestState::Closed,
+            GitlabMergeRequestState::Merged => TrackedMergeRequestState::Merged,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MergeRequestResponse {
+    pub iid: u64,
+    pub web_url: Url,
+    pub state: GitlabMergeRequestState,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NoteResponse {
+    pub id: u64,
+}
+
+/// Path of the gitlab project of `pkgbase`, e.g. `archlinux/packaging/packages/libsigcplusplus`.
+pub fn project_path(gitlab_packages_group: &str, pkgbase: &Pkgbase) -> String {
+    format!(
+        "{gitlab_packages_group}/{}",
+        gitlab_project_name_to_path(pkgbase.as_ref())
+    )
+}
+
+/// Find an open merge request for `branch_name`, or open a new one.
+pub async fn find_or_create_merge_request(
+    client: &AsyncGitlab,
+    gitlab_packages_group: &str,
+    pkgbase: &Pkgbase,
+    branch_name: &str,
+    title: &str,
+) -> Result<MergeRequestResponse> {
+    let project = project_path(gitlab_packages_group, pkgbase);
+    let existing: Vec<MergeRequestResponse> = MergeRequests::builder()
+        .project(project.as_str())
+        .source_branch(branch_name)
+        .target_branch(TARGET_BRANCH)
+        .state(MergeRequestState::Opened)
+        .build()?
+        .query_async(client)
+        .await
+        .wrap_err("Error querying merge requests")?;
+    if let Some(merge_request) = existing.into_iter().next() {
+        return Ok(merge_request);
+    }
+
+    CreateMergeRequest::builder()
+        .project(project.as_str())
+        .source_branch(branch_name)
+        .target_branch(TARGET_BRANCH)
+        .title(title)
+        .build()?
+        .query_async(client)
+        .await
+        .wrap_err("Error creating merge request")
+}
+
+pub async fn get_merge_request(
+    client: &AsyncGitlab,
+    gitlab_packages_group: &str,
+    pkgbase: &Pkgbase,
+    merge_request_iid: u64,
+) -> Result<MergeRequestResponse> {
+    MergeRequest::builder()
+        .project(project_path(gitlab_packages_group, pkgbase))
+        .merge_request(merge_request_iid)
+        .build()?
+        .query_async(client)
+        .await
+        .wrap_err("Error querying merge request")
+}
+
+/// Create the summary comment on a merge request, or update it if `note_id` is given.
+/// Returns the ID of the comment.
+pub async fn upsert_summary_note(
+    client: &AsyncGitlab,
+    gitlab_packages_group: &str,
+    pkgbase: &Pkgbase,
+    merge_request_iid: u64,
+    note_id: Option<u64>,
+    body: &str,
+) -> Result<u64> {
+    let project = project_path(gitlab_packages_group, pkgbase);
+    let response: NoteResponse = match note_id {
+        Some(note_id) => EditMergeRequestNote::builder()
+            .project(project.as_str())
+            .merge_request(merge_request_iid)
+            .note(note_id)
+            .body(body)
+            .build()?
+            .query_async(client)
+            .await
+            .wrap_err("Error updating merge request comment")?,
+        None => CreateMergeRequestNote::builder()
+            .project(project.as_str())
+            .merge_request(merge_request_iid)
+            .body(body)
+            .build()?
+            .query_async(client)
+            .await
+            .wrap_err("Error creating merge request comment")?,
+    };
+
+    Ok(response.id)
+}
+
+/// Build results of a namespace for a single architecture, for the summary comment.
+#[derive(Debug, Clone)]
+pub struct ArchitectureSummary {
+    pub architecture: ConcreteArchitecture,
+    pub status: CommitBuildStatus,
+    pub pkgbases: Vec<Pkgbase>,
+    pub failed_pkgbases: Vec<Pkgbase>,
+}
+
+impl ArchitectureSummary {
+    pub fn from_graph(architecture: ConcreteArchitecture, graph: &BuildSetGraph) -> Self {
+        let pkgbases = graph
+            .node_weights()
+            .map(|node| node.pkgbase.clone())
+            .sorted()
+            .collect();
+        let failed_pkgbases = graph
+            .node_weights()
+            .filter(|node| node.status == PackageBuildStatus::Failed)
+            .map(|node| node.pkgbase.clone())
+            .sorted()
+            .collect();
+        ArchitectureSummary {
+            architecture,
+            status: CommitBuildStatus::from_graph(graph),
+            pkgbases,
+            failed_pkgbases,
+        }
+    }
+}
+
+/// Render the markdown summary comment for the merge requests of a namespace.
+pub fn render_summary(
+    namespace_name: &str,
+    namespace_url: &Url,
+    pacman_conf_url: &Url,
+    iteration_id: &uuid::Uuid,
+    architectures: &[ArchitectureSummary],
+) -> String {
+    let mut summary = format!(
+        "This branch is built in the buildbtw namespace [{namespace_name}]({namespace_url}) (iteration `{iteration_id}`).\n\n"
+    );
+    for architecture in architectures {
+        summary.push_str(&format!(
+            "**{}**: {:?}, {} package(s) in the build set\n",
+            architecture.architecture,
+            architecture.status,
+            architecture.pkgbases.len()
+        ));
+        if !architecture.failed_pkgbases.is_empty() {
+            summary.push_str(&format!(
+                "- Failed: {}\n",
+                architecture
+                    .failed_pkgbases
+                    .iter()
+                    .map(|pkgbase| format!("`{pkgbase}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        summary.push_str(&format!(
+            "<details><summary>Build set</summary>\n\n{}\n</details>\n\n",
+            architecture
+                .pkgbases
+                .iter()
+                .map(|pkgbase| format!("- `{pkgbase}`"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ));
+    }
+    summary.push_str(&format!(
+        "To test the packages, add the repository from [pacman.conf]({pacman_conf_url}) to your `/etc/pacman.conf`.\n"
+    ));
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn merge_request(state: TrackedMergeRequestState) -> TrackedMergeRequest {
+        TrackedMergeRequest {
+            pkgbase: "openssl".to_string().into(),
+            branch_name: "openssl-3.5".to_string(),
+            state,
+            web_url: None,
+        }
+    }
+
+    #[rstest]
+    #[case(vec![], false)]
+    #[case(vec![TrackedMergeRequestState::Merged], true)]
+    #[case(vec![TrackedMergeRequestState::Merged, TrackedMergeRequestState::Opened], false)]
+    #[case(vec![TrackedMergeRequestState::Closed], false)]
+    fn test_ready_for_release(#[case] states: Vec<TrackedMergeRequestState>, #[case] ready: bool) {
+        let merge_requests: Vec<_> = states.into_iter().map(merge_request).collect();
+        assert_eq!(ready_for_release(&merge_requests), ready);
+    }
+}
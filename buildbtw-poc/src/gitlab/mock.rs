@@ -0,0 +1,640 @@
+//! A stand-in for the parts of the gitlab API that buildbtw uses,
+//! for testing without a real gitlab instance.
+//!
+//! The mock serves the REST and GraphQL endpoints we call from an in-process
+//! axum app. Package source repositories are bare git repositories on disk
+//! that can be cloned via `file://` URLs, see [`MockGitlab::packaging_remote`].
+//! Pipelines don't run anything, tests set their status via
+//! [`MockGitlab::set_pipeline_status`] instead.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::{Context, Result, eyre};
+use git2::{Repository, RepositoryInitOptions};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tokio::{net::TcpListener, task::JoinHandle};
+use url::Url;
+
+use super::{PipelineStatus, gitlab_project_name_to_path, pipeline_status::GitlabGraphqlClient};
+use crate::{
+    CommitHash,
+    git::{GitCredentials, PackagingRemote, commit_files},
+};
+
+/// Token the mock accepts, both as `PRIVATE-TOKEN` and as bearer token.
+pub const MOCK_TOKEN: &str = "mock-token";
+
+#[derive(Debug, Clone)]
+pub struct MockProject {
+    pub id: u64,
+    pub name: String,
+    pub path: String,
+    pub updated_at: OffsetDateTime,
+    pub ci_config_path: String,
+    pub archived: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockPipeline {
+    pub id: u64,
+    pub project_id: u64,
+    pub git_ref: String,
+    pub variables: HashMap<String, String>,
+    pub status: PipelineStatus,
+    /// Log of the pipeline's single job.
+    pub trace: String,
+}
+
+#[derive(Default)]
+struct MockState {
+    projects: Vec<MockProject>,
+    pipelines: Vec<MockPipeline>,
+    next_id: u64,
+}
+
+impl MockState {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Find a project by its numerical ID or its full path.
+    /// Like gitlab, accept both the project name and path in the full path.
+    fn project(&self, group: &str, id_or_path: &str) -> Option<&MockProject> {
+        self.projects.iter().find(|project| {
+            id_or_path == project.id.to_string()
+                || id_or_path == format!("{group}/{}", project.path)
+                || id_or_path == format!("{group}/{}", project.name)
+        })
+    }
+}
+
+#[derive(Clone)]
+struct MockContext {
+    state: Arc<Mutex<MockState>>,
+    group: String,
+    base_url: Url,
+}
+
+impl MockContext {
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().expect("Mock gitlab state poisoned")
+    }
+
+    fn project(&self, id_or_path: &str) -> Result<MockProject, StatusCode> {
+        self.lock()
+            .project(&self.group, id_or_path)
+            .cloned()
+            .ok_or(StatusCode::NOT_FOUND)
+    }
+
+    fn pipeline_json(&self, pipeline: &MockPipeline) -> Value {
+        let project_path = self
+            .lock()
+            .projects
+            .iter()
+            .find(|project| project.id == pipeline.project_id)
+            .map(|project| project.path.clone())
+            .unwrap_or_default();
+        json!({
+            "id": pipeline.id,
+            "iid": pipeline.id,
+            "project_id": pipeline.project_id,
+            "status": pipeline.status,
+            "ref": pipeline.git_ref,
+            "web_url": self.base_url.join(&format!(
+                "{}/{project_path}/-/pipelines/{}",
+                self.group, pipeline.id
+            )).ok(),
+        })
+    }
+}
+
+pub struct MockGitlab {
+    address: SocketAddr,
+    repos_dir: Utf8PathBuf,
+    context: MockContext,
+    server: JoinHandle<()>,
+}
+
+impl Drop for MockGitlab {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+impl MockGitlab {
+    /// Start serving the mock on a random local port.
+    /// Source repositories of the `group` are stored in `repos_dir`.
+    pub async fn start(repos_dir: &Utf8Path, group: &str) -> Result<Self> {
+        tokio::fs::create_dir_all(repos_dir).await?;
+        let repos_dir = repos_dir.canonicalize_utf8()?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let context = MockContext {
+            state: Default::default(),
+            group: group.to_string(),
+            base_url: Url::parse(&format!("http://{address}"))?,
+        };
+
+        let app = Router::new()
+            .route("/api/v4/user", get(current_user))
+            .route("/api/graphql", post(graphql))
+            .route("/api/v4/groups/{group}/projects", get(group_projects))
+            .route(
+                "/api/v4/projects/{project}",
+                axum::routing::put(edit_project),
+            )
+            .route("/api/v4/projects/{project}/pipeline", post(create_pipeline))
+            .route(
+                "/api/v4/projects/{project}/pipelines/{pipeline}",
+                get(show_pipeline),
+            )
+            .route(
+                "/api/v4/projects/{project}/pipelines/{pipeline}/retry",
+                post(retry_pipeline),
+            )
+            .route(
+                "/api/v4/projects/{project}/pipelines/{pipeline}/cancel",
+                post(cancel_pipeline),
+            )
+            .route(
+                "/api/v4/projects/{project}/pipelines/{pipeline}/jobs",
+                get(pipeline_jobs),
+            )
+            .route(
+                "/api/v4/projects/{project}/jobs/{job}/trace",
+                get(job_trace),
+            )
+            .layer(axum::middleware::from_fn(require_token))
+            .with_state(context.clone());
+
+        let server = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                tracing::error!("Mock gitlab stopped: {e:?}");
+            }
+        });
+
+        Ok(MockGitlab {
+            address,
+            repos_dir,
+            context,
+            server,
+        })
+    }
+
+    /// Pass this as gitlab domain, together with the option to use plain HTTP.
+    pub fn domain(&self) -> String {
+        self.address.to_string()
+    }
+
+    pub fn base_url(&self) -> Url {
+        self.context.base_url.clone()
+    }
+
+    pub fn group(&self) -> &str {
+        &self.context.group
+    }
+
+    /// Clone package source repositories from the mock's repositories on disk.
+    pub fn packaging_remote(&self) -> PackagingRemote {
+        PackagingRemote::new(self.source_repo_url_template(), GitCredentials::None)
+    }
+
+    /// URL template of the mock's source repositories, see [`PackagingRemote::new`].
+    pub fn source_repo_url_template(&self) -> String {
+        format!(
+            "file://{}/{}/{{project}}.git",
+            self.repos_dir, self.context.group
+        )
+    }
+
+    pub async fn client(&self) -> Result<gitlab::AsyncGitlab> {
+        gitlab::GitlabBuilder::new(self.domain(), MOCK_TOKEN)
+            .insecure()
+            .build_async()
+            .await
+            .wrap_err("Failed to create mock gitlab client")
+    }
+
+    pub fn graphql_client(&self) -> Result<GitlabGraphqlClient> {
+        GitlabGraphqlClient::new(&self.base_url(), MOCK_TOKEN)
+    }
+
+    pub fn projects(&self) -> Vec<MockProject> {
+        self.context.lock().projects.clone()
+    }
+
+    /// Archive or unarchive a project, which marks it as updated like on gitlab.
+    pub fn set_project_archived(&self, pkgbase: &str, archived: bool) -> Result<()> {
+        let mut state = self.context.lock();
+        let project = state
+            .projects
+            .iter_mut()
+            .find(|project| project.name == pkgbase)
+            .ok_or_else(|| eyre!("No project {pkgbase}"))?;
+        project.archived = archived;
+        project.updated_at = OffsetDateTime::now_utc();
+        Ok(())
+    }
+
+    pub fn pipelines(&self) -> Vec<MockPipeline> {
+        self.context.lock().pipelines.clone()
+    }
+
+    pub fn set_pipeline_status(&self, pipeline_id: u64, status: PipelineStatus) -> Result<()> {
+        let mut state = self.context.lock();
+        let pipeline = state
+            .pipelines
+            .iter_mut()
+            .find(|pipeline| pipeline.id == pipeline_id)
+            .ok_or_else(|| eyre!("No pipeline {pipeline_id}"))?;
+        pipeline.status = status;
+        Ok(())
+    }
+
+    /// Commit top-level files to a branch of a package's source repository,
+    /// creating the project if it doesn't exist yet.
+    /// This marks the project as updated, like a push to gitlab would.
+    pub fn commit_files(
+        &self,
+        pkgbase: &str,
+        branch: &str,
+        files: &[(&str, &str)],
+    ) -> Result<CommitHash> {
+        let path = gitlab_project_name_to_path(pkgbase);
+        let repo_path = self
+            .repos_dir
+            .join(&self.context.group)
+            .join(format!("{path}.git"));
+        let repo = if repo_path.exists() {
+            Repository::open_bare(&repo_path)?
+        } else {
+            Repository::init_opts(
+                &repo_path,
+                RepositoryInitOptions::new().bare(true).initial_head("main"),
+            )?
+        };
+
+        let reference = format!("refs/heads/{branch}");
+        let parent = repo.refname_to_id(&reference).ok();
+        let commit = commit_files(&repo, Some(&reference), parent, files, "Update package")?;
+
+        let mut state = self.context.lock();
+        let now = OffsetDateTime::now_utc();
+        match state
+            .projects
+            .iter_mut()
+            .find(|project| project.name == pkgbase)
+        {
+            Some(project) => project.updated_at = now,
+            None => {
+                let id = state.next_id();
+                state.projects.push(MockProject {
+                    id,
+                    name: pkgbase.to_string(),
+                    path,
+                    updated_at: now,
+                    ci_config_path: String::new(),
+                    archived: false,
+                });
+            }
+        }
+
+        Ok(CommitHash(commit.to_string()))
+    }
+}
+
+async fn require_token(request: axum::extract::Request, next: axum::middleware::Next) -> Response {
+    let headers = request.headers();
+    let private_token = headers
+        .get("private-token")
+        .and_then(|value| value.to_str().ok());
+    let bearer_token = headers
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if private_token != Some(MOCK_TOKEN) && bearer_token != Some(MOCK_TOKEN) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"message": "401 Unauthorized"})),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+async fn current_user(State(context): State<MockContext>) -> Json<Value> {
+    Json(json!({
+        "id": 1,
+        "username": "buildbtw",
+        "name": "buildbtw",
+        "state": "active",
+        "avatar_url": null,
+        "web_url": context.base_url.join("buildbtw").ok(),
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphqlRequest {
+    operation_name: String,
+    #[serde(default)]
+    variables: Value,
+}
+
+async fn graphql(
+    State(context): State<MockContext>,
+    Json(request): Json<GraphqlRequest>,
+) -> Result<Json<Value>, StatusCode> {
+    let data = match request.operation_name.as_str() {
+        "ChangedProjects" => {
+            if request.variables["group"].as_str() != Some(context.group.as_str()) {
+                return Ok(Json(json!({"data": {"group": null}})));
+            }
+            let mut projects = context.lock().projects.clone();
+            projects.sort_by_key(|project| std::cmp::Reverse(project.updated_at));
+            let nodes: Vec<_> = projects
+                .iter()
+                .map(|project| {
+                    json!({
+                        "name": project.name,
+                        "updatedAt": project.updated_at.format(&Rfc3339).ok(),
+                        "archived": project.archived,
+                    })
+                })
+                .collect();
+            json!({
+                "group": {
+                    "projects": {
+                        "nodes": nodes,
+                        "pageInfo": {"endCursor": null, "hasNextPage": false},
+                    }
+                }
+            })
+        }
+        "PipelineStatuses" => {
+            let per_project = request.variables["pipelinesPerProject"]
+                .as_u64()
+                .unwrap_or(100) as usize;
+            let project_ids: Vec<u64> = request.variables["projectIds"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|id| id.as_str()?.rsplit('/').next()?.parse().ok())
+                .collect();
+            let state = context.lock();
+            let nodes: Vec<_> = project_ids
+                .iter()
+                .filter(|id| state.projects.iter().any(|project| project.id == **id))
+                .map(|project_id| {
+                    let mut pipelines: Vec<_> = state
+                        .pipelines
+                        .iter()
+                        .filter(|pipeline| pipeline.project_id == *project_id)
+                        .collect();
+                    pipelines.sort_by_key(|pipeline| std::cmp::Reverse(pipeline.id));
+                    let pipelines: Vec<_> = pipelines
+                        .into_iter()
+                        .take(per_project)
+                        .map(|pipeline| {
+                            json!({
+                                "id": format!("gid://gitlab/Ci::Pipeline/{}", pipeline.id),
+                                "status": graphql_pipeline_status(pipeline.status),
+                            })
+                        })
+                        .collect();
+                    json!({
+                        "id": format!("gid://gitlab/Project/{project_id}"),
+                        "pipelines": {"nodes": pipelines},
+                    })
+                })
+                .collect();
+            json!({"projects": {"nodes": nodes}})
+        }
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    Ok(Json(json!({ "data": data })))
+}
+
+/// GraphQL uses the upper case names of the REST API's statuses.
+fn graphql_pipeline_status(status: PipelineStatus) -> String {
+    serde_json::to_value(status)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_uppercase))
+        .unwrap_or_default()
+}
+
+/// The gitlab crate sends form encoded bodies, but accept JSON as well.
+fn parse_body(headers: &HeaderMap, body: &Bytes) -> Vec<(String, String)> {
+    let is_json = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json {
+        return url::form_urlencoded::parse(body)
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+    }
+
+    let Ok(Value::Object(object)) = serde_json::from_slice(body) else {
+        return Vec::new();
+    };
+    let mut params = Vec::new();
+    for (key, value) in object {
+        match value {
+            Value::String(value) => params.push((key, value)),
+            Value::Array(items) => {
+                for item in items.iter().filter_map(Value::as_object) {
+                    for (item_key, item_value) in item {
+                        if let Some(item_value) = item_value.as_str() {
+                            params.push((format!("{key}[][{item_key}]"), item_value.to_string()));
+                        }
+                    }
+                }
+            }
+            other => params.push((key, other.to_string())),
+        }
+    }
+    params
+}
+
+async fn group_projects(
+    State(context): State<MockContext>,
+    Path(group): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    if group != context.group {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let mut projects = context.lock().projects.clone();
+    projects.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(Json(
+        projects
+            .iter()
+            .map(|project| {
+                json!({
+                    "id": project.id,
+                    "name": project.name,
+                    "path": project.path,
+                    "path_with_namespace": format!("{}/{}", context.group, project.path),
+                    "ci_config_path": project.ci_config_path,
+                })
+            })
+            .collect(),
+    ))
+}
+
+async fn edit_project(
+    State(context): State<MockContext>,
+    Path(project): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, StatusCode> {
+    let project = context.project(&project)?;
+    let params = parse_body(&headers, &body);
+    let mut state = context.lock();
+    let project = state
+        .projects
+        .iter_mut()
+        .find(|candidate| candidate.id == project.id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    if let Some((_, ci_config_path)) = params.iter().find(|(key, _)| key == "ci_config_path") {
+        project.ci_config_path = ci_config_path.clone();
+    }
+    Ok(Json(json!({
+        "id": project.id,
+        "ci_config_path": project.ci_config_path,
+    })))
+}
+
+async fn create_pipeline(
+    State(context): State<MockContext>,
+    Path(project): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let project = context.project(&project)?;
+    let params = parse_body(&headers, &body);
+    let git_ref = params
+        .iter()
+        .find(|(key, _)| key == "ref")
+        .map(|(_, value)| value.clone())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    // Variables are sent as a list of key/value pairs
+    let keys = params
+        .iter()
+        .filter(|(key, _)| key == "variables[][key]")
+        .map(|(_, value)| value.clone());
+    let values = params
+        .iter()
+        .filter(|(key, _)| key == "variables[][value]")
+        .map(|(_, value)| value.clone());
+    let variables = keys.zip(values).collect();
+
+    let pipeline = {
+        let mut state = context.lock();
+        let id = state.next_id();
+        let pipeline = MockPipeline {
+            id,
+            project_id: project.id,
+            git_ref,
+            variables,
+            status: PipelineStatus::Created,
+            trace: format!("Running pipeline {id} for {}\n", project.name),
+        };
+        state.pipelines.push(pipeline.clone());
+        pipeline
+    };
+    Ok((StatusCode::CREATED, Json(context.pipeline_json(&pipeline))))
+}
+
+fn find_pipeline(
+    context: &MockContext,
+    project: &str,
+    pipeline_id: u64,
+) -> Result<MockPipeline, StatusCode> {
+    let project = context.project(project)?;
+    context
+        .lock()
+        .pipelines
+        .iter()
+        .find(|pipeline| pipeline.id == pipeline_id && pipeline.project_id == project.id)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+fn update_pipeline_status(
+    context: &MockContext,
+    project: &str,
+    pipeline_id: u64,
+    status: PipelineStatus,
+) -> Result<Json<Value>, StatusCode> {
+    let mut pipeline = find_pipeline(context, project, pipeline_id)?;
+    pipeline.status = status;
+    context
+        .lock()
+        .pipelines
+        .iter_mut()
+        .filter(|candidate| candidate.id == pipeline_id)
+        .for_each(|candidate| candidate.status = status);
+    Ok(Json(context.pipeline_json(&pipeline)))
+}
+
+async fn show_pipeline(
+    State(context): State<MockContext>,
+    Path((project, pipeline)): Path<(String, u64)>,
+) -> Result<Json<Value>, StatusCode> {
+    let pipeline = find_pipeline(&context, &project, pipeline)?;
+    Ok(Json(context.pipeline_json(&pipeline)))
+}
+
+async fn retry_pipeline(
+    State(context): State<MockContext>,
+    Path((project, pipeline)): Path<(String, u64)>,
+) -> Result<(StatusCode, Json<Value>), StatusCode> {
+    let response = update_pipeline_status(&context, &project, pipeline, PipelineStatus::Pending)?;
+    Ok((StatusCode::CREATED, response))
+}
+
+async fn cancel_pipeline(
+    State(context): State<MockContext>,
+    Path((project, pipeline)): Path<(String, u64)>,
+) -> Result<Json<Value>, StatusCode> {
+    update_pipeline_status(&context, &project, pipeline, PipelineStatus::Canceled)
+}
+
+/// Each mock pipeline consists of a single job with the same ID.
+async fn pipeline_jobs(
+    State(context): State<MockContext>,
+    Path((project, pipeline)): Path<(String, u64)>,
+) -> Result<Json<Value>, StatusCode> {
+    let pipeline = find_pipeline(&context, &project, pipeline)?;
+    Ok(Json(json!([{
+        "id": pipeline.id,
+        "name": "build",
+        "status": pipeline.status,
+        "artifacts_file": null,
+    }])))
+}
+
+async fn job_trace(
+    State(context): State<MockContext>,
+    Path((project, job)): Path<(String, u64)>,
+) -> Result<String, StatusCode> {
+    Ok(find_pipeline(&context, &project, job)?.trace)
+}
@@ -2,7 +2,9 @@ use color_eyre::eyre::{Context, Result, eyre};
 use gitlab::{
     AsyncGitlab,
     api::{
-        AsyncQuery, groups::projects::GroupProjectsOrderBy, projects::pipelines::PipelineVariable,
+        AsyncQuery,
+        groups::projects::GroupProjectsOrderBy,
+        projects::{pipelines::PipelineVariable, repository::commits::CommitStatusState},
     },
 };
 use graphql_client::GraphQLQuery;
@@ -12,16 +14,30 @@ use time::{Duration, OffsetDateTime};
 use url::Url;
 
 use crate::{
-    CommitHash, PackageBuildStatus, Pkgbase, ScheduleBuild, git::clone_or_fetch_repositories,
-    pacman_repo::repo_dir_path, source_info::package_file_name,
+    CommitHash, PackageBuildStatus, Pkgbase, ScheduleBuild,
+    build_set_graph::BuildSetGraph,
+    git::{
+        PackagingRemote, clone_or_fetch_repositories, find_ref_containing_commit, is_commit_hash,
+    },
+    pacman_repo::repo_dir_path,
+    source_provider::{RetiredReason, SourceRepoChanges},
 };
 
+pub mod job_logs;
+pub mod merge_requests;
+#[cfg(feature = "gitlab-mock")]
+pub mod mock;
+pub mod pipeline_status;
+pub mod webhook;
+
+/// Fetch source repos of all projects that changed since `last_fetched`.
+/// Archived projects aren't fetched, but reported as retired.
 pub async fn fetch_all_source_repo_changes(
     client: &AsyncGitlab,
     mut last_fetched: Option<OffsetDateTime>,
-    gitlab_domain: String,
+    remote: PackagingRemote,
     gitlab_packages_group: String,
-) -> Result<Option<OffsetDateTime>> {
+) -> Result<SourceRepoChanges> {
     // Query which projects changed
     let result = get_changed_projects_since(client, last_fetched, &gitlab_packages_group).await?;
     if let Some(first_result) = result.first() {
@@ -39,11 +55,23 @@ pub async fn fetch_all_source_repo_changes(
             .map(|date| date - Duration::minutes(6));
     };
 
-    // Run git fetch for updated repos
-    let pkgbases = result.into_iter().map(|info| info.name.into()).collect();
-    clone_or_fetch_repositories(pkgbases, gitlab_domain, gitlab_packages_group).await?;
+    let (archived, active): (Vec<_>, Vec<_>) = result
+        .into_iter()
+        .partition(|project| project.archived == Some(true));
 
-    Ok(last_fetched)
+    // Run git fetch for updated repos
+    let active: Vec<Pkgbase> = active.into_iter().map(|info| info.name.into()).collect();
+    clone_or_fetch_repositories(active.clone(), remote).await?;
+
+    Ok(SourceRepoChanges {
+        last_fetched,
+        fetched: active.clone(),
+        active,
+        retired: archived
+            .into_iter()
+            .map(|info| (info.name.into(), RetiredReason::Archived))
+            .collect(),
+    })
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -119,8 +147,9 @@ pub async fn get_changed_projects_since(
     Ok(results)
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
 #[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
 pub enum PipelineStatus {
     Pending,
     Created,
@@ -157,6 +186,17 @@ impl PipelineStatus {
     pub fn matches_package_build_status(&self, build_status: PackageBuildStatus) -> bool {
         PackageBuildStatus::from(*self) == build_status
     }
+
+    /// Whether the pipeline won't change anymore, unless it's retried.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            PipelineStatus::Failed
+                | PipelineStatus::Canceled
+                | PipelineStatus::Skipped
+                | PipelineStatus::Success
+        )
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -181,15 +221,6 @@ pub async fn create_pipeline(
 ) -> Result<CreatePipelineResponse> {
     // Using graphQL for triggering pipelines is not yet possible:
     // https://gitlab.com/gitlab-org/gitlab/-/issues/401480
-    let package_file_names = build
-        .srcinfo
-        .packages_for_architecture(*build.architecture.as_ref())
-        .map(|p| package_file_name(&p, &build.srcinfo))
-        .collect::<Result<Vec<_>>>()?
-        .into_iter()
-        .map(String::from)
-        .collect::<Vec<_>>()
-        .join(" ");
     // Each of these will be prefixed with `CUSTOM_ENV_` by the gitlab runner.
     // E.g. `PKGBASE` will be available as `CUSTOM_ENV_PKGBASE` in buildbtw-executor.sh.
     // For more, see: https://docs.gitlab.com/runner/executors/custom/#stages
@@ -201,9 +232,13 @@ pub async fn create_pipeline(
         ("NAMESPACE_NAME", namespace_name.to_string()),
         ("ITERATION_ID", build.iteration.to_string()),
         ("PKGBASE", build.source.pkgbase.to_string()),
-        ("PACKAGE_FILE_NAMES", package_file_names),
         ("ARCHITECTURE", build.architecture.to_string()),
         ("SERVER_PORT", server_port.to_string()),
+        // The branch may have moved since the build graph was calculated,
+        // and pinned commits run on a branch or tag containing them.
+        ("COMMIT_HASH", build.source.commit_hash.to_string()),
+        // Fetch the full history, so the commit can be checked out
+        ("GIT_DEPTH", "0".to_string()),
     ]
     .into_iter()
     .map(|(key, val)| {
@@ -218,11 +253,21 @@ pub async fn create_pipeline(
         "{gitlab_packages_group}/{pkgbase}",
         pkgbase = build.source.pkgbase
     );
+    // Pipelines can only be created for branches and tags
+    let pipeline_ref = if is_commit_hash(&build.source.branch_name) {
+        find_ref_containing_commit(
+            build.source.pkgbase.clone(),
+            build.source.commit_hash.clone(),
+        )
+        .await?
+    } else {
+        build.source.branch_name.clone()
+    };
     let response: CreatePipelineResponse =
         gitlab::api::projects::pipelines::CreatePipeline::builder()
             // TODO remove hardcoded temporary test project
             .project(project_name)
-            .ref_(&build.source.branch_name)
+            .ref_(&pipeline_ref)
             .variables(vars.into_iter())
             .build()?
             .query_async(client)
@@ -255,6 +300,41 @@ pub async fn get_pipeline_status(
     Ok(response.status)
 }
 
+/// Retry the failed and canceled jobs of a pipeline.
+/// The pipeline keeps its ID, so this returns its new status.
+pub async fn retry_pipeline(
+    client: &AsyncGitlab,
+    project_iid: u64,
+    pipeline_iid: u64,
+) -> Result<PipelineStatus> {
+    let response: GetPipelineResponse = gitlab::api::projects::pipelines::RetryPipeline::builder()
+        .project(project_iid)
+        .pipeline(pipeline_iid)
+        .build()?
+        .query_async(client)
+        .await
+        .wrap_err("Error retrying Gitlab Pipeline")?;
+
+    Ok(response.status)
+}
+
+/// Cancel all running jobs of a pipeline.
+pub async fn cancel_pipeline(
+    client: &AsyncGitlab,
+    project_iid: u64,
+    pipeline_iid: u64,
+) -> Result<PipelineStatus> {
+    let response: GetPipelineResponse = gitlab::api::projects::pipelines::CancelPipeline::builder()
+        .project(project_iid)
+        .pipeline(pipeline_iid)
+        .build()?
+        .query_async(client)
+        .await
+        .wrap_err("Error cancelling Gitlab Pipeline")?;
+
+    Ok(response.status)
+}
+
 #[derive(Deserialize, Debug)]
 struct ProjectCiConfig {
     id: u64,
@@ -320,6 +400,116 @@ pub async fn set_project_ci_config(
     Ok(())
 }
 
+/// Overall state of building a namespace for an architecture,
+/// reported to gitlab as an external commit status
+/// on the commits of the origin changesets.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+pub enum CommitBuildStatus {
+    Pending,
+    Running,
+    Success,
+    Failed,
+    /// The namespace was cancelled before all packages were built
+    Canceled,
+}
+
+impl CommitBuildStatus {
+    pub fn from_graph(graph: &BuildSetGraph) -> Self {
+        let statuses: Vec<_> = graph.node_weights().map(|node| node.status).collect();
+        if statuses.contains(&PackageBuildStatus::Failed) {
+            CommitBuildStatus::Failed
+        } else if statuses
+            .iter()
+            .all(|status| *status == PackageBuildStatus::Built)
+        {
+            CommitBuildStatus::Success
+        } else if statuses.iter().any(|status| {
+            matches!(
+                status,
+                PackageBuildStatus::Scheduled
+                    | PackageBuildStatus::Building
+                    | PackageBuildStatus::Built
+            )
+        }) {
+            CommitBuildStatus::Running
+        } else {
+            CommitBuildStatus::Pending
+        }
+    }
+
+    /// Status to report for a namespace that was cancelled.
+    /// Builds that already finished keep their result.
+    pub fn cancelled(self) -> Self {
+        match self {
+            CommitBuildStatus::Pending | CommitBuildStatus::Running => CommitBuildStatus::Canceled,
+            status => status,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            CommitBuildStatus::Pending => "Waiting for builds to start",
+            CommitBuildStatus::Running => "Building the namespace",
+            CommitBuildStatus::Success => "All packages in the namespace were built",
+            CommitBuildStatus::Failed => "Some packages in the namespace failed to build",
+            CommitBuildStatus::Canceled => "The namespace was cancelled",
+        }
+    }
+}
+
+/// An external commit status on a commit of a package source repository.
+#[derive(Debug, Clone)]
+pub struct CommitStatus {
+    pub pkgbase: Pkgbase,
+    pub commit_hash: CommitHash,
+    pub branch_name: String,
+    /// Statuses with the same name on the same commit replace each other.
+    pub name: String,
+    pub status: CommitBuildStatus,
+    pub target_url: Url,
+}
+
+/// Create or update an external commit status in gitlab.
+pub async fn set_commit_status(
+    client: &AsyncGitlab,
+    gitlab_packages_group: &str,
+    commit_status: &CommitStatus,
+) -> Result<()> {
+    let CommitStatus {
+        pkgbase,
+        commit_hash,
+        branch_name,
+        name,
+        status,
+        target_url,
+    } = commit_status;
+    let state = match status {
+        CommitBuildStatus::Pending => CommitStatusState::Pending,
+        CommitBuildStatus::Running => CommitStatusState::Running,
+        CommitBuildStatus::Success => CommitStatusState::Success,
+        CommitBuildStatus::Failed => CommitStatusState::Failed,
+        CommitBuildStatus::Canceled => CommitStatusState::Canceled,
+    };
+    let endpoint = gitlab::api::projects::repository::commits::CreateCommitStatus::builder()
+        .project(format!(
+            "{gitlab_packages_group}/{}",
+            gitlab_project_name_to_path(pkgbase.as_ref())
+        ))
+        .commit(commit_hash.as_ref())
+        .state(state)
+        .ref_(branch_name)
+        .name(name)
+        .target_url(target_url.as_str())
+        .description(status.description())
+        .build()?;
+    gitlab::api::ignore(endpoint)
+        .query_async(client)
+        .await
+        .wrap_err("Error setting gitlab commit status")?;
+
+    Ok(())
+}
+
 pub fn commit_web_url(
     gitlab_domain: &str,
     gitlab_packages_group: &str,
@@ -0,0 +1,300 @@
+//! Query the status of many gitlab pipelines with few requests.
+//!
+//! Instead of one REST call per pipeline, the most recent pipelines of
+//! several projects are fetched in a single GraphQL query.
+//! GraphQL requests are sent directly via reqwest so we can read gitlab's
+//! rate limit headers and back off before gitlab starts rejecting us.
+//! Backing off doesn't block the caller: queries fail until the backoff is
+//! over, and callers retry on their next polling tick.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use color_eyre::eyre::{Context, OptionExt, Result, bail};
+use graphql_client::GraphQLQuery;
+use reqwest::{StatusCode, header::HeaderMap};
+use time::OffsetDateTime;
+use tokio::time::Instant;
+use url::Url;
+
+use super::{PipelineStatus, get_pipeline_status};
+
+/// Keep queries below gitlab's query complexity limit.
+const PROJECTS_PER_QUERY: usize = 10;
+/// Pipelines we're waiting for are usually among the newest ones of a project.
+/// If they're not, we fall back to querying them one by one.
+const PIPELINES_PER_PROJECT: i64 = 10;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 5);
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    query_path = "src/gitlab/gitlab_pipeline_statuses.graphql",
+    schema_path = "src/gitlab/gitlab_schema.json",
+    variables_derives = "Debug",
+    response_derives = "Debug"
+)]
+struct PipelineStatuses;
+
+/// A pipeline identified by the numerical gitlab IDs of its project and itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineId {
+    pub project_id: u64,
+    pub pipeline_id: u64,
+}
+
+pub struct GitlabGraphqlClient {
+    http: reqwest::Client,
+    endpoint: Url,
+    token: String,
+    backoff: Mutex<Backoff>,
+}
+
+#[derive(Debug)]
+struct Backoff {
+    /// Don't send queries before this point in time.
+    not_before: Option<Instant>,
+    /// Delay to use for the next failure if gitlab doesn't tell us how long to wait.
+    next_delay: Duration,
+}
+
+impl Backoff {
+    fn wait_until(&mut self, delay: Duration) -> Duration {
+        let delay = delay.min(MAX_BACKOFF);
+        self.not_before = Some(Instant::now() + delay);
+        delay
+    }
+}
+
+impl GitlabGraphqlClient {
+    /// `gitlab_url` is the base URL of the instance, e.g. "https://gitlab.archlinux.org".
+    pub fn new(gitlab_url: &Url, gitlab_token: &str) -> Result<Self> {
+        Ok(GitlabGraphqlClient {
+            http: reqwest::Client::new(),
+            endpoint: gitlab_url.join("/api/graphql")?,
+            token: gitlab_token.to_string(),
+            backoff: Mutex::new(Backoff {
+                not_before: None,
+                next_delay: INITIAL_BACKOFF,
+            }),
+        })
+    }
+
+    /// Send a GraphQL query.
+    /// If gitlab is rate limiting us or temporarily unavailable, this fails
+    /// without sending a request until the backoff period is over.
+    pub async fn query<Q: GraphQLQuery>(&self, variables: Q::Variables) -> Result<Q::ResponseData> {
+        if let Some(not_before) = self.backoff.lock().unwrap().not_before {
+            let now = Instant::now();
+            if now < not_before {
+                bail!(
+                    "Backing off from gitlab GraphQL API for another {:?}",
+                    not_before - now
+                );
+            }
+        }
+
+        let body = Q::build_query(variables);
+        let response = self
+            .http
+            .post(self.endpoint.clone())
+            .bearer_auth(&self.token)
+            .json(&body)
+            .send()
+            .await
+            .wrap_err("Failed to send GraphQL query to gitlab")?;
+        let status = response.status();
+        let delay = rate_limit_delay(status, response.headers(), OffsetDateTime::now_utc());
+
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            let mut backoff = self.backoff.lock().unwrap();
+            let next_delay = backoff.next_delay;
+            let delay = backoff.wait_until(delay.unwrap_or(next_delay));
+            backoff.next_delay = (backoff.next_delay * 2).min(MAX_BACKOFF);
+            bail!("Gitlab responded with {status}, backing off for {delay:?}");
+        }
+
+        {
+            let mut backoff = self.backoff.lock().unwrap();
+            backoff.next_delay = INITIAL_BACKOFF;
+            // We used up our requests for now, wait for the limit to reset
+            // so the next query doesn't get rejected.
+            match delay {
+                Some(delay) => {
+                    let delay = backoff.wait_until(delay);
+                    tracing::info!("Gitlab rate limit reached, backing off for {delay:?}");
+                }
+                None => backoff.not_before = None,
+            }
+        }
+
+        let response: graphql_client::Response<Q::ResponseData> =
+            response.error_for_status()?.json().await?;
+        if let Some(errors) = response.errors.filter(|errors| !errors.is_empty()) {
+            bail!("Gitlab GraphQL query failed: {errors:?}");
+        }
+        response
+            .data
+            .ok_or_eyre("Missing data in gitlab GraphQL response")
+    }
+}
+
+/// How long to wait before sending the next request, according to gitlab's
+/// rate limit headers. `None` if we don't have to wait.
+/// See https://docs.gitlab.com/security/rate_limits/#headers
+pub fn rate_limit_delay(
+    status: StatusCode,
+    headers: &HeaderMap,
+    now: OffsetDateTime,
+) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<i64>().ok())
+    };
+
+    if let Some(retry_after) = header("retry-after") {
+        return Some(Duration::from_secs(retry_after.max(0).unsigned_abs()));
+    }
+
+    let exhausted =
+        status == StatusCode::TOO_MANY_REQUESTS || header("ratelimit-remaining") == Some(0);
+    if !exhausted {
+        return None;
+    }
+    let reset_at = header("ratelimit-reset")?;
+    Some(Duration::from_secs(
+        (reset_at - now.unix_timestamp()).max(0).unsigned_abs(),
+    ))
+}
+
+fn parse_global_id(id: &str) -> Option<u64> {
+    id.rsplit('/').next()?.parse().ok()
+}
+
+fn pipeline_status_from_graphql(
+    status: &pipeline_statuses::PipelineStatusEnum,
+) -> Option<PipelineStatus> {
+    use pipeline_statuses::PipelineStatusEnum;
+    Some(match status {
+        PipelineStatusEnum::CREATED => PipelineStatus::Created,
+        PipelineStatusEnum::WAITING_FOR_RESOURCE | PipelineStatusEnum::WAITING_FOR_CALLBACK => {
+            PipelineStatus::WaitingForResource
+        }
+        PipelineStatusEnum::PREPARING => PipelineStatus::Preparing,
+        PipelineStatusEnum::PENDING => PipelineStatus::Pending,
+        // Canceling pipelines still have running jobs
+        PipelineStatusEnum::RUNNING | PipelineStatusEnum::CANCELING => PipelineStatus::Running,
+        PipelineStatusEnum::FAILED => PipelineStatus::Failed,
+        PipelineStatusEnum::SUCCESS => PipelineStatus::Success,
+        PipelineStatusEnum::CANCELED => PipelineStatus::Canceled,
+        PipelineStatusEnum::SKIPPED => PipelineStatus::Skipped,
+        PipelineStatusEnum::MANUAL => PipelineStatus::Manual,
+        PipelineStatusEnum::SCHEDULED => PipelineStatus::Scheduled,
+        PipelineStatusEnum::Other(_) => return None,
+    })
+}
+
+/// Query the current status of the given pipelines.
+/// Pipelines are fetched in batches via GraphQL, and pipelines that aren't
+/// among the newest ones of their project are queried one by one via REST.
+pub async fn get_pipeline_statuses(
+    graphql_client: &GitlabGraphqlClient,
+    rest_client: &gitlab::AsyncGitlab,
+    pipelines: &[PipelineId],
+) -> Result<HashMap<PipelineId, PipelineStatus>> {
+    let mut project_ids: Vec<_> = pipelines
+        .iter()
+        .map(|pipeline| pipeline.project_id)
+        .collect();
+    project_ids.sort();
+    project_ids.dedup();
+
+    let mut statuses = HashMap::new();
+    for batch in project_ids.chunks(PROJECTS_PER_QUERY) {
+        let data = graphql_client
+            .query::<PipelineStatuses>(pipeline_statuses::Variables {
+                project_ids: Some(
+                    batch
+                        .iter()
+                        .map(|id| format!("gid://gitlab/Project/{id}"))
+                        .collect(),
+                ),
+                pipelines_per_project: Some(PIPELINES_PER_PROJECT),
+            })
+            .await?;
+
+        let projects = data
+            .projects
+            .and_then(|projects| projects.nodes)
+            .unwrap_or_default();
+        for project in projects.into_iter().flatten() {
+            let Some(project_id) = parse_global_id(&project.id) else {
+                continue;
+            };
+            let project_pipelines = project
+                .pipelines
+                .and_then(|pipelines| pipelines.nodes)
+                .unwrap_or_default();
+            for pipeline in project_pipelines.into_iter().flatten() {
+                let (Some(pipeline_id), Some(status)) = (
+                    parse_global_id(&pipeline.id),
+                    pipeline_status_from_graphql(&pipeline.status),
+                ) else {
+                    continue;
+                };
+                statuses.insert(
+                    PipelineId {
+                        project_id,
+                        pipeline_id,
+                    },
+                    status,
+                );
+            }
+        }
+    }
+
+    // Only keep the pipelines we were asked for
+    statuses.retain(|pipeline, _| pipelines.contains(pipeline));
+
+    for pipeline in pipelines {
+        if statuses.contains_key(pipeline) {
+            continue;
+        }
+        tracing::debug!("Pipeline {pipeline:?} not found via GraphQL, querying it directly");
+        let status =
+            get_pipeline_status(rest_client, pipeline.project_id, pipeline.pipeline_id).await?;
+        statuses.insert(*pipeline, status);
+    }
+
+    Ok(statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case(StatusCode::OK, &[], None)]
+    #[case(StatusCode::OK, &[("ratelimit-remaining", "10"), ("ratelimit-reset", "1060")], None)]
+    #[case(StatusCode::OK, &[("ratelimit-remaining", "0"), ("ratelimit-reset", "1060")], Some(60))]
+    #[case(StatusCode::TOO_MANY_REQUESTS, &[("ratelimit-reset", "1030")], Some(30))]
+    #[case(StatusCode::TOO_MANY_REQUESTS, &[("retry-after", "20"), ("ratelimit-reset", "1060")], Some(20))]
+    #[case(StatusCode::TOO_MANY_REQUESTS, &[("ratelimit-reset", "900")], Some(0))]
+    fn test_rate_limit_delay(
+        #[case] status: StatusCode,
+        #[case] headers: &[(&'static str, &'static str)],
+        #[case] expected_seconds: Option<u64>,
+    ) {
+        let headers: HeaderMap = headers
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect();
+        let now = OffsetDateTime::from_unix_timestamp(1000).unwrap();
+        assert_eq!(
+            rate_limit_delay(status, &headers, now),
+            expected_seconds.map(Duration::from_secs)
+        );
+    }
+}
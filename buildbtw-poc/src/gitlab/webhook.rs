@@ -0,0 +1,156 @@
+//! Receive push, tag push, pipeline and merge request events from GitLab webhooks,
+//! so we don't have to wait for the next polling interval to notice them.
+//!
+//! Webhook requests are signed with a signing token configured in GitLab,
+//! following the [Standard Webhooks](https://www.standardwebhooks.com/) specification.
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use color_eyre::eyre::{Context, Result, bail, eyre};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use time::OffsetDateTime;
+
+use super::{PipelineStatus, merge_requests::GitlabMergeRequestState};
+
+pub const WEBHOOK_ID_HEADER: &str = "webhook-id";
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "webhook-timestamp";
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "webhook-signature";
+
+/// Reject requests signed longer ago than this, to prevent replay attacks.
+const TIMESTAMP_TOLERANCE: time::Duration = time::Duration::minutes(5);
+
+/// Prefix GitLab (and other Standard Webhooks implementations) use for signing tokens.
+const SIGNING_TOKEN_PREFIX: &str = "whsec_";
+
+/// Check that a webhook request was signed with `signing_token`.
+/// `signatures` is the value of the `webhook-signature` header,
+/// which can contain multiple space-separated signatures during key rotation.
+pub fn verify_signature(
+    signing_token: &str,
+    id: &str,
+    timestamp: &str,
+    signatures: &str,
+    body: &[u8],
+    now: OffsetDateTime,
+) -> Result<()> {
+    let signed_at = OffsetDateTime::from_unix_timestamp(
+        timestamp.parse().wrap_err("Invalid webhook timestamp")?,
+    )?;
+    if (now - signed_at).abs() > TIMESTAMP_TOLERANCE {
+        bail!("Webhook timestamp is too far from the current time");
+    }
+
+    let key = BASE64_STANDARD
+        .decode(
+            signing_token
+                .strip_prefix(SIGNING_TOKEN_PREFIX)
+                .unwrap_or(signing_token),
+        )
+        .wrap_err("Invalid webhook signing token")?;
+
+    for signature in signatures.split_whitespace() {
+        let Some(signature) = signature.strip_prefix("v1,") else {
+            continue;
+        };
+        let Ok(signature) = BASE64_STANDARD.decode(signature) else {
+            continue;
+        };
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key)?;
+        mac.update(id.as_bytes());
+        mac.update(b".");
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        if mac.verify_slice(&signature).is_ok() {
+            return Ok(());
+        }
+    }
+
+    Err(eyre!("No valid webhook signature found"))
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookProject {
+    pub id: u64,
+    pub name: String,
+    pub path_with_namespace: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookPipelineAttributes {
+    pub id: u64,
+    pub status: PipelineStatus,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookMergeRequestAttributes {
+    pub iid: u64,
+    pub state: GitlabMergeRequestState,
+}
+
+/// The subset of GitLab webhook events we're interested in.
+/// See https://docs.gitlab.com/user/project/integrations/webhook_events/
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "object_kind", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    Push {
+        project: WebhookProject,
+    },
+    TagPush {
+        project: WebhookProject,
+    },
+    Pipeline {
+        project: WebhookProject,
+        object_attributes: WebhookPipelineAttributes,
+    },
+    MergeRequest {
+        project: WebhookProject,
+        object_attributes: WebhookMergeRequestAttributes,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl WebhookProject {
+    /// Whether this project is a package source repository in the given group.
+    pub fn is_in_group(&self, gitlab_packages_group: &str) -> bool {
+        self.path_with_namespace
+            .strip_prefix(gitlab_packages_group)
+            .and_then(|path| path.strip_prefix('/'))
+            .is_some_and(|path| !path.contains('/'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    // Example from the Standard Webhooks specification
+    const SIGNING_TOKEN: &str = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw";
+    const ID: &str = "msg_p5jXN8AQM9LWM0D4loKWxJek";
+    const TIMESTAMP: &str = "1614265330";
+    const BODY: &[u8] = br#"{"test": 2432232314}"#;
+    const SIGNATURE: &str = "v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE=";
+
+    #[rstest]
+    #[case(SIGNATURE, BODY, 0, true)]
+    #[case(&format!("v1,aW52YWxpZA== {SIGNATURE}"), BODY, 0, true)]
+    #[case(SIGNATURE, br#"{"test": 1}"#, 0, false)]
+    #[case("v1,aW52YWxpZA==", BODY, 0, false)]
+    #[case(SIGNATURE, BODY, 60 * 10, false)]
+    fn test_verify_signature(
+        #[case] signatures: &str,
+        #[case] body: &[u8],
+        #[case] seconds_since_signing: i64,
+        #[case] valid: bool,
+    ) {
+        let now = OffsetDateTime::from_unix_timestamp(1614265330 + seconds_since_signing).unwrap();
+        assert_eq!(
+            verify_signature(SIGNING_TOKEN, ID, TIMESTAMP, signatures, body, now).is_ok(),
+            valid
+        );
+    }
+}
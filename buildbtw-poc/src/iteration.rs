@@ -2,10 +2,13 @@ use std::collections::{HashMap, HashSet};
 
 use color_eyre::eyre::Result;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::{
-    BuildNamespace, BuildNamespaceStatus, BuildSetIteration,
+    BuildNamespace, BuildNamespaceStatus, BuildSetIteration, GitRepoRef,
     build_set_graph::{self, BuildSetGraph, calculate_packages_to_be_built, diff_graphs},
+    git::GitCredentials,
+    pkgrel_bump::{apply_existing_pkgrel_bumps, bump_pkgrels_in_graphs},
     source_info::ConcreteArchitecture,
     source_repos::SourceRepos,
 };
@@ -16,6 +19,7 @@ pub enum NewIterationReason {
     OriginChangesetsChanged,
     BuildSetGraphChanged { diff: Box<IterationDiff> },
     CreatedByUser,
+    ParentIterationChanged,
 }
 
 impl NewIterationReason {
@@ -25,6 +29,9 @@ impl NewIterationReason {
             NewIterationReason::OriginChangesetsChanged => "Origin changesets changed",
             NewIterationReason::BuildSetGraphChanged { .. } => "Build set graph changed",
             NewIterationReason::CreatedByUser => "Manually created by user",
+            NewIterationReason::ParentIterationChanged => {
+                "Parent namespace finished building a new iteration"
+            }
         }
     }
 }
@@ -84,8 +91,64 @@ impl IterationDiff {
             && self.removed_architectures.is_empty()
     }
 }
+
+/// What a namespace needs to know about its parent namespace
+/// to calculate a new iteration.
+#[derive(Debug, Clone)]
+pub struct ParentNamespaceContext {
+    /// The newest fully built iteration of the parent namespace.
+    /// Builds install the parent's packages from this iteration's repository.
+    pub iteration_id: Uuid,
+    /// Origin changesets of the parent iteration and the ancestor iterations it was built on.
+    /// If a pkgbase appears multiple times, the entry closer to the parent wins.
+    pub origin_changesets: Vec<GitRepoRef>,
+}
+
+/// Calculate the build graphs for a new iteration of the namespace.
+/// Only pkgrel bumps that already exist are used, so this doesn't push anything.
+/// Missing pkgrel bumps are created by [`create_build_set_iteration`].
+pub async fn calculate_packages_to_build(
+    namespace: &BuildNamespace,
+    parent: Option<&ParentNamespaceContext>,
+    source_repos: &mut SourceRepos,
+) -> Result<HashMap<ConcreteArchitecture, BuildSetGraph>> {
+    let parent_origin_changesets = parent.map_or(&[][..], |parent| &parent.origin_changesets);
+    let mut packages_to_build =
+        calculate_packages_to_be_built(namespace, parent_origin_changesets, source_repos).await?;
+    if namespace.bump_pkgrel {
+        apply_existing_pkgrel_bumps(namespace, &mut packages_to_build).await?;
+    }
+
+    Ok(packages_to_build)
+}
+
+/// Create a new iteration from graphs calculated by [`calculate_packages_to_build`],
+/// creating and pushing the pkgrel bump branches the namespace needs.
+pub async fn create_build_set_iteration(
+    namespace: &BuildNamespace,
+    parent: Option<&ParentNamespaceContext>,
+    mut packages_to_build: HashMap<ConcreteArchitecture, BuildSetGraph>,
+    reason: NewIterationReason,
+    credentials: &GitCredentials,
+) -> Result<BuildSetIteration> {
+    if namespace.bump_pkgrel {
+        bump_pkgrels_in_graphs(namespace, &mut packages_to_build, credentials).await?;
+    }
+
+    Ok(BuildSetIteration {
+        id: Uuid::new_v4(),
+        created_at: time::OffsetDateTime::now_utc(),
+        origin_changesets: namespace.current_origin_changesets.clone(),
+        packages_to_be_built: packages_to_build,
+        create_reason: reason,
+        namespace_id: namespace.id,
+        parent_iteration_id: parent.map(|parent| parent.iteration_id),
+    })
+}
+
 pub async fn new_build_set_iteration_is_needed(
     namespace: &BuildNamespace,
+    parent: Option<&ParentNamespaceContext>,
     newest_iteration: Option<&BuildSetIteration>,
     source_repos: &mut SourceRepos,
 ) -> Result<NewBuildIterationResult> {
@@ -93,7 +156,13 @@ pub async fn new_build_set_iteration_is_needed(
         return Ok(NewBuildIterationResult::NoNewIterationNeeded);
     }
 
-    let packages_to_build = calculate_packages_to_be_built(namespace, source_repos).await?;
+    // Wait for the parent namespace to fully build its first iteration,
+    // there's nothing to build on top of otherwise.
+    if namespace.parent_id.is_some() && parent.is_none() {
+        return Ok(NewBuildIterationResult::NoNewIterationNeeded);
+    }
+
+    let packages_to_build = calculate_packages_to_build(namespace, parent, source_repos).await?;
 
     let previous_iteration = if let Some(it) = newest_iteration {
         it
@@ -111,6 +180,13 @@ pub async fn new_build_set_iteration_is_needed(
         });
     }
 
+    if previous_iteration.parent_iteration_id != parent.map(|parent| parent.iteration_id) {
+        return Ok(NewBuildIterationResult::NewIterationNeeded {
+            packages_to_build,
+            reason: NewIterationReason::ParentIterationChanged,
+        });
+    }
+
     let diff = IterationDiff::new(&previous_iteration.packages_to_be_built, &packages_to_build);
     if !diff.is_empty() {
         return Ok(NewBuildIterationResult::NewIterationNeeded {
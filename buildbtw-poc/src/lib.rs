@@ -3,22 +3,32 @@ use std::{collections::HashMap, sync::LazyLock};
 use build_set_graph::BuildSetGraph;
 use camino::Utf8PathBuf;
 use clap::ValueEnum;
-use color_eyre::eyre::{Result, bail};
 use derive_more::{AsRef, Display};
 use iteration::NewIterationReason;
+use pacman_conf::BaseRepository;
 use serde::{Deserialize, Serialize};
 use source_info::{ConcreteArchitecture, SourceInfo};
 use uuid::Uuid;
 
 pub mod api;
+pub mod build_logs;
 pub mod build_package;
 pub mod build_set_graph;
+pub mod conflicts;
 pub mod git;
 pub mod gitlab;
 pub mod iteration;
+pub mod pacman_conf;
 pub mod pacman_repo;
+pub mod pkgrel_bump;
+pub mod rebase;
+pub mod release;
+pub mod release_selection;
+pub mod signing;
 pub mod source_info;
+pub mod source_provider;
 pub mod source_repos;
+pub mod srcinfo_check;
 pub mod tracing;
 
 // TODO use git2::Oid instead?
@@ -31,7 +41,20 @@ pub type Pkgname = String;
 pub type GitRepoRef = (Pkgbase, GitRef);
 pub type BranchName = String;
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, AsRef, Display, sqlx::Type)]
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    AsRef,
+    Display,
+    sqlx::Type,
+)]
 #[sqlx(transparent)]
 #[serde(transparent)]
 pub struct Pkgbase(String);
@@ -49,7 +72,8 @@ impl From<String> for Pkgbase {
 }
 
 /// An unambiguous git commit hash.
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, AsRef, Display)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, AsRef, Display, sqlx::Type)]
+#[sqlx(transparent)]
 pub struct CommitHash(String);
 
 impl From<CommitHash> for GitRef {
@@ -69,6 +93,31 @@ pub static NAMESPACE_DATA_DIR: LazyLock<Utf8PathBuf> =
 pub struct CreateBuildNamespace {
     pub name: Option<String>,
     pub origin_changesets: Vec<GitRepoRef>,
+    /// Repositories to build on top of.
+    /// If empty, [`pacman_conf::default_base_repositories`] are used.
+    #[serde(default)]
+    pub base_repositories: Vec<BaseRepository>,
+    /// Name of a namespace to build on top of.
+    /// The parent's origin changesets are used when resolving package sources,
+    /// and its repository is added to the base repositories.
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// Open (or attach to) a GitLab merge request for each origin changeset branch
+    /// and keep it updated with the namespace's build results.
+    #[serde(default)]
+    pub merge_requests: bool,
+    /// Build dependents of the origin changesets from rebuild branches
+    /// with an automatic pkgrel bump, see [`pkgrel_bump`].
+    #[serde(default)]
+    pub bump_pkgrel: bool,
+    /// Rebase origin changeset branches onto `main` when it moves,
+    /// see [`rebase`].
+    #[serde(default)]
+    pub rebase_origin_changesets: bool,
+    /// Where to run the namespace's builds.
+    /// If omitted, the server's default is used.
+    #[serde(default)]
+    pub build_dispatch: Option<BuildDispatch>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -80,6 +129,8 @@ pub struct UpdateBuildNamespace {
 pub struct PipelineTarget {
     pub pkgbase: Pkgbase,
     pub branch_name: String,
+    /// Commit that `branch_name` resolved to when the build graph was calculated.
+    pub commit_hash: CommitHash,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -111,6 +162,30 @@ pub enum BuildNamespaceStatus {
     Cancelled,
 }
 
+/// Executor that runs the builds of a namespace.
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    ValueEnum,
+    strum::Display,
+    sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum BuildDispatch {
+    /// Send builds to a buildbtw worker instance
+    Worker,
+    /// Run builds in gitlab pipelines of the package source repositories
+    Gitlab,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BuildNamespace {
     pub id: Uuid,
@@ -118,6 +193,20 @@ pub struct BuildNamespace {
     pub current_origin_changesets: Vec<GitRepoRef>,
     pub created_at: time::OffsetDateTime,
     pub status: BuildNamespaceStatus,
+    /// Repositories that builds in this namespace install dependencies from,
+    /// in addition to the namespace's own repository.
+    pub base_repositories: Vec<BaseRepository>,
+    /// Namespace this namespace builds on top of.
+    pub parent_id: Option<Uuid>,
+    /// Whether dependents are built from rebuild branches with a pkgrel bump.
+    #[serde(default)]
+    pub bump_pkgrel: bool,
+    /// Whether origin changeset branches are rebased onto `main` when it moves.
+    #[serde(default)]
+    pub rebase_origin_changesets: bool,
+    /// Where builds run, if different from the server's default.
+    #[serde(default)]
+    pub build_dispatch: Option<BuildDispatch>,
     // gitlab group epic, state repo mr, ...
     // tracking_thing: String,
 }
@@ -179,21 +268,7 @@ pub struct BuildSetIteration {
     pub origin_changesets: Vec<GitRepoRef>,
     pub create_reason: NewIterationReason,
     pub namespace_id: Uuid,
-}
-
-impl BuildSetIteration {
-    pub fn set_build_status(
-        mut self,
-        architecture: ConcreteArchitecture,
-        pkgbase: Pkgbase,
-        status: PackageBuildStatus,
-    ) -> Result<Self> {
-        let Some(graph) = self.packages_to_be_built.remove(&architecture) else {
-            bail!("No build graph for architecture {architecture:?}");
-        };
-        let new_graph = build_set_graph::set_build_status(graph, &pkgbase, status);
-        self.packages_to_be_built.insert(architecture, new_graph);
-
-        Ok(self)
-    }
+    /// Fully built iteration of the parent namespace that this iteration builds on.
+    /// Builds install the parent's packages from its repository.
+    pub parent_iteration_id: Option<Uuid>,
 }
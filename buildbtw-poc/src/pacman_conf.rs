@@ -0,0 +1,248 @@
+//! Generate the `pacman.conf` used inside the build environment.
+//!
+//! Each namespace declares a list of base repositories that builds
+//! are run against, e.g. `core` and `extra`, or `core-testing` and `extra-testing`
+//! for staging rebuilds on top of testing.
+//! The namespace's own repository always takes precedence over the base repositories.
+
+use std::{fmt::Display, str::FromStr};
+
+use color_eyre::eyre::{Report, Result, bail};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    pacman_repo::{latest_repo_name, repo_db_name, repo_name},
+    source_info::ConcreteArchitecture,
+};
+
+/// Prefix used to refer to another namespace when parsing a [`BaseRepository`].
+const NAMESPACE_PREFIX: &str = "namespace:";
+
+/// A repository that builds in a namespace can install dependencies from.
+/// Serialized in the same format that is accepted by [`BaseRepository::from_str`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub enum BaseRepository {
+    /// An official Arch Linux repository such as `core` or `extra-testing`,
+    /// served by the mirrors in the build environment's mirrorlist.
+    Official(String),
+    /// The latest repository of another build namespace.
+    Namespace(String),
+}
+
+impl BaseRepository {
+    /// Name of the section for this repository in `pacman.conf`.
+    /// For namespaces, this matches the name of their repository database.
+    pub fn section_name(&self) -> String {
+        match self {
+            BaseRepository::Official(name) => name.clone(),
+            BaseRepository::Namespace(name) => repo_db_name(name),
+        }
+    }
+}
+
+impl FromStr for BaseRepository {
+    type Err = Report;
+
+    /// Parse either an official repository name like `core-testing`,
+    /// or another namespace like `namespace:openssl-3.5`.
+    fn from_str(value: &str) -> Result<Self> {
+        let (repository, name) = match value.strip_prefix(NAMESPACE_PREFIX) {
+            Some(name) => (BaseRepository::Namespace(name.to_string()), name),
+            None => (BaseRepository::Official(value.to_string()), value),
+        };
+
+        // Names end up in `pacman.conf` section headers and URLs.
+        if !is_valid_repository_name(name) {
+            bail!("Invalid base repository name: {value:?}");
+        }
+
+        Ok(repository)
+    }
+}
+
+/// Whether `name` can be used in `pacman.conf` section headers, URLs and paths.
+/// This allows all characters that are valid in a pkgbase,
+/// as namespaces are named after their first pkgbase by default.
+pub fn is_valid_repository_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['.', '-'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+' | '@'))
+}
+
+impl TryFrom<String> for BaseRepository {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<BaseRepository> for String {
+    fn from(value: BaseRepository) -> Self {
+        value.to_string()
+    }
+}
+
+impl Display for BaseRepository {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BaseRepository::Official(name) => write!(f, "{name}"),
+            BaseRepository::Namespace(name) => write!(f, "{NAMESPACE_PREFIX}{name}"),
+        }
+    }
+}
+
+/// Base repositories for namespaces that don't specify any.
+pub fn default_base_repositories() -> Vec<BaseRepository> {
+    vec![
+        BaseRepository::Official("core".to_string()),
+        BaseRepository::Official("extra".to_string()),
+    ]
+}
+
+/// `SigLevel` for repositories served by buildbtw.
+/// Packages and repository databases are only signed if signing is enabled on the server,
+/// in which case pacman must reject anything that isn't signed.
+pub fn repo_sig_level(signing_enabled: bool) -> &'static str {
+    if signing_enabled {
+        "Required DatabaseRequired"
+    } else {
+        "Never"
+    }
+}
+
+/// Render the `pacman.conf` for building packages of the given iteration.
+///
+/// `repo_base_url` is the URL under which [`crate::pacman_repo::REPO_DIR`] is served,
+/// as seen from the build environment.
+/// `parent_iteration` is the name of the parent namespace and the iteration of it
+/// that this iteration was calculated from. Its repository is used instead of the
+/// parent's latest repository, so builds don't pick up newer parent packages.
+/// `signing_enabled` determines the `SigLevel` of buildbtw repositories, see [`repo_sig_level`].
+pub fn render_build_pacman_conf(
+    repo_base_url: &Url,
+    namespace_name: &str,
+    iteration_id: Uuid,
+    architecture: ConcreteArchitecture,
+    base_repositories: &[BaseRepository],
+    parent_iteration: Option<(&str, Uuid)>,
+    signing_enabled: bool,
+) -> String {
+    let sig_level = repo_sig_level(signing_enabled);
+    let mut conf = format!(
+        "\
+[options]
+HoldPkg = pacman glibc
+Architecture = {architecture}
+CheckSpace
+SigLevel = Required DatabaseOptional
+LocalFileSigLevel = Optional
+
+[{db_name}]
+SigLevel = {sig_level}
+Server = {repo_base_url}{repo_name}/os/{architecture}
+",
+        db_name = repo_db_name(namespace_name),
+        repo_name = repo_name(namespace_name, iteration_id),
+    );
+
+    for repository in base_repositories {
+        let section_name = repository.section_name();
+        let section = match repository {
+            BaseRepository::Official(_) => {
+                format!("\n[{section_name}]\nInclude = /etc/pacman.d/mirrorlist\n")
+            }
+            BaseRepository::Namespace(name) => {
+                let repo_name = match parent_iteration {
+                    Some((parent_name, parent_iteration_id)) if parent_name == name => {
+                        repo_name(parent_name, parent_iteration_id)
+                    }
+                    _ => latest_repo_name(name),
+                };
+                format!(
+                    "\n[{section_name}]\nSigLevel = {sig_level}\nServer = {repo_base_url}{repo_name}/os/{architecture}\n",
+                )
+            }
+        };
+        conf.push_str(&section);
+    }
+
+    conf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("core", Some(BaseRepository::Official("core".to_string())))]
+    #[case("extra-testing", Some(BaseRepository::Official("extra-testing".to_string())))]
+    #[case("namespace:openssl", Some(BaseRepository::Namespace("openssl".to_string())))]
+    #[case("namespace:", None)]
+    #[case("", None)]
+    #[case("core]\nServer = evil", None)]
+    #[case("namespace:../etc", None)]
+    #[case("namespace:libsigc++", Some(BaseRepository::Namespace("libsigc++".to_string())))]
+    fn test_parse_base_repository(#[case] value: &str, #[case] expected: Option<BaseRepository>) {
+        let parsed = value.parse::<BaseRepository>().ok();
+        assert_eq!(parsed, expected);
+        if let Some(parsed) = parsed {
+            assert_eq!(parsed.to_string(), value);
+        }
+    }
+
+    #[rstest]
+    fn test_render_build_pacman_conf_pins_parent_iteration() {
+        let repo_base_url = Url::parse("http://localhost:8080/repo/").unwrap();
+        let parent_iteration_id = Uuid::new_v4();
+        let conf = render_build_pacman_conf(
+            &repo_base_url,
+            "child",
+            Uuid::new_v4(),
+            ConcreteArchitecture::X86_64,
+            &[
+                BaseRepository::Namespace("parent".to_string()),
+                BaseRepository::Namespace("other".to_string()),
+                BaseRepository::Official("core".to_string()),
+            ],
+            Some(("parent", parent_iteration_id)),
+            false,
+        );
+
+        assert!(conf.contains(&format!(
+            "Server = {repo_base_url}{}/os/x86_64",
+            repo_name("parent", parent_iteration_id)
+        )));
+        assert!(!conf.contains(&format!("{}/", latest_repo_name("parent"))));
+        assert!(conf.contains(&format!(
+            "Server = {repo_base_url}{}/os/x86_64",
+            latest_repo_name("other")
+        )));
+    }
+
+    #[rstest]
+    #[case(false, "SigLevel = Never")]
+    #[case(true, "SigLevel = Required DatabaseRequired")]
+    fn test_render_build_pacman_conf_sig_level(
+        #[case] signing_enabled: bool,
+        #[case] expected: &str,
+    ) {
+        let conf = render_build_pacman_conf(
+            &Url::parse("http://localhost:8080/repo/").unwrap(),
+            "child",
+            Uuid::new_v4(),
+            ConcreteArchitecture::X86_64,
+            &[BaseRepository::Namespace("parent".to_string())],
+            None,
+            signing_enabled,
+        );
+
+        assert_eq!(conf.matches(expected).count(), 2);
+    }
+}
@@ -1,19 +1,20 @@
 use std::sync::LazyLock;
 
-use alpm_srcinfo::MergedPackage;
 use camino::{Utf8Path, Utf8PathBuf};
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Context, Result, eyre};
 use tokio::process::Command;
 use uuid::Uuid;
 
 use crate::{
     NAMESPACE_DATA_DIR,
-    source_info::{ConcreteArchitecture, SourceInfo, package_file_name},
+    signing::{SignerBackend, sign_file},
+    source_info::{ConcreteArchitecture, strip_package_file_extension},
 };
 
 pub static REPO_DIR: LazyLock<Utf8PathBuf> = LazyLock::new(|| NAMESPACE_DATA_DIR.join("repos"));
 
-const REPO_FILE_EXTENSION: &str = "db.tar.zst";
+const REPO_ARCHIVE_EXTENSION: &str = "tar.zst";
+pub const REPO_FILE_EXTENSION: &str = "db.tar.zst";
 
 pub fn repo_dir_path(
     namespace_name: &str,
@@ -30,22 +31,228 @@ pub fn repo_name(namespace_name: &str, iteration_id: Uuid) -> Utf8PathBuf {
     format!("{namespace_name}_{iteration_id}").into()
 }
 
-pub fn repo_file_name() -> Utf8PathBuf {
-    format!("buildbtw-namespace.{REPO_FILE_EXTENSION}",).into()
+/// Name of the repository database of a namespace. pacman requests `{db_name}.db`
+/// for a `[{db_name}]` section, so this is used as the section name in `pacman.conf` as well.
+pub fn repo_db_name(namespace_name: &str) -> String {
+    format!("buildbtw-{namespace_name}")
 }
 
-/// Add a package to the pacman repository db in the given directory.
+pub fn repo_file_name(namespace_name: &str) -> Utf8PathBuf {
+    format!("{}.{REPO_FILE_EXTENSION}", repo_db_name(namespace_name)).into()
+}
+
+/// Debug packages are kept in separate repositories inside this directory
+/// of [`REPO_DIR`], so users only download them if they need symbols.
+/// Keeping them apart means their names can't collide with those of namespaces.
+const DEBUG_REPO_DIR: &str = "debug";
+
+pub fn debug_repo_dir_path(
+    namespace_name: &str,
+    iteration_id: Uuid,
+    architecture: ConcreteArchitecture,
+) -> Utf8PathBuf {
+    REPO_DIR
+        .join(debug_repo_name(namespace_name, iteration_id))
+        .join("os")
+        .join(architecture.to_string())
+}
+
+pub fn debug_repo_name(namespace_name: &str, iteration_id: Uuid) -> Utf8PathBuf {
+    Utf8Path::new(DEBUG_REPO_DIR).join(repo_name(namespace_name, iteration_id))
+}
+
+/// Databases of namespaces always start with `buildbtw-`, see [`repo_db_name`],
+/// so this can't collide with the database of another namespace.
+pub fn debug_repo_db_name(namespace_name: &str) -> String {
+    format!("buildbtw_debug-{namespace_name}")
+}
+
+pub fn debug_repo_file_name(namespace_name: &str) -> Utf8PathBuf {
+    format!(
+        "{}.{REPO_FILE_EXTENSION}",
+        debug_repo_db_name(namespace_name)
+    )
+    .into()
+}
+
+/// Name of the repository that always points to the newest
+/// consistent iteration of the namespace.
+pub fn latest_repo_name(namespace_name: &str) -> Utf8PathBuf {
+    format!("{namespace_name}_latest").into()
+}
+
+pub fn latest_debug_repo_name(namespace_name: &str) -> Utf8PathBuf {
+    Utf8Path::new(DEBUG_REPO_DIR).join(latest_repo_name(namespace_name))
+}
+
+/// Point the "latest" repository of the namespace to the given iteration.
+///
+/// The latest repository is a symlink per architecture, which is swapped
+/// atomically by renaming a new symlink over the old one.
+/// This way, pacman never sees a half-updated repository.
+/// Returns `false` if the repository already pointed to the given iteration.
+pub async fn update_latest_repo(
+    namespace_name: &str,
+    iteration_id: Uuid,
+    architecture: ConcreteArchitecture,
+) -> Result<bool> {
+    let updated = swap_latest_repo_symlink(
+        &latest_repo_name(namespace_name),
+        &repo_name(namespace_name, iteration_id),
+        architecture,
+    )
+    .await?;
+    swap_latest_repo_symlink(
+        &latest_debug_repo_name(namespace_name),
+        &debug_repo_name(namespace_name, iteration_id),
+        architecture,
+    )
+    .await?;
+
+    Ok(updated)
+}
+
+async fn swap_latest_repo_symlink(
+    latest_repo_name: &Utf8Path,
+    target_repo_name: &Utf8Path,
+    architecture: ConcreteArchitecture,
+) -> Result<bool> {
+    let os_dir = REPO_DIR.join(latest_repo_name).join("os");
+    tokio::fs::create_dir_all(&os_dir).await?;
+
+    let link_path = os_dir.join(architecture.to_string());
+    // Use a relative target so the data dir can be moved around.
+    // Go up from `os` and each component of the repository name to get to `REPO_DIR`.
+    let target: Utf8PathBuf = std::iter::repeat_n("..", latest_repo_name.components().count() + 1)
+        .chain([target_repo_name.as_str(), "os"])
+        .collect::<Utf8PathBuf>()
+        .join(architecture.to_string());
+
+    let current_target = tokio::fs::read_link(&link_path).await;
+    if current_target.is_ok_and(|current_target| current_target == target) {
+        return Ok(false);
+    }
+
+    let tmp_link_path = os_dir.join(format!(".{architecture}.{}", Uuid::new_v4()));
+    tokio::fs::symlink(&target, &tmp_link_path)
+        .await
+        .wrap_err_with(|| format!("Failed to create symlink {tmp_link_path}"))?;
+    tokio::fs::rename(&tmp_link_path, &link_path)
+        .await
+        .wrap_err_with(|| format!("Failed to replace {link_path}"))?;
+
+    Ok(true)
+}
+
+/// Add a package file to the pacman repository db in the given directory.
+/// The package file is expected to be inside the repository directory already.
+/// If a signer is given, the repository database is signed afterwards.
 pub async fn add_to_repo(
     repo_dir_path: &Utf8Path,
-    package: &MergedPackage,
-    srcinfo: &SourceInfo,
+    namespace_name: &str,
+    package_file_name: &str,
+    signer: Option<&SignerBackend>,
+) -> Result<()> {
+    add_to_repo_db(
+        repo_dir_path,
+        &repo_file_name(namespace_name),
+        package_file_name,
+        signer,
+    )
+    .await
+}
+
+/// Like [`add_to_repo`], but for the debug package repository.
+pub async fn add_to_debug_repo(
+    repo_dir_path: &Utf8Path,
+    namespace_name: &str,
+    package_file_name: &str,
+    signer: Option<&SignerBackend>,
+) -> Result<()> {
+    add_to_repo_db(
+        repo_dir_path,
+        &debug_repo_file_name(namespace_name),
+        package_file_name,
+        signer,
+    )
+    .await
+}
+
+async fn add_to_repo_db(
+    repo_dir_path: &Utf8Path,
+    db_filename: &Utf8Path,
+    package_file_name: &str,
+    signer: Option<&SignerBackend>,
 ) -> Result<()> {
+    // Note: if a detached signature exists next to the package file,
+    // repo-add embeds it into the repository database.
     let mut cmd = Command::new("repo-add");
-    let db_filename = repo_file_name();
     let db_path = format!("{repo_dir_path}/{db_filename}");
     cmd.arg(db_path);
-    cmd.arg(repo_dir_path.join(package_file_name(package, srcinfo)?));
-    cmd.status().await?;
+    cmd.arg(repo_dir_path.join(package_file_name));
+    let status = cmd.status().await.wrap_err("Failed to run repo-add")?;
+    if !status.success() {
+        return Err(eyre!("repo-add failed for {repo_dir_path}/{db_filename}"));
+    }
+
+    if let Some(signer) = signer {
+        sign_repo_db(repo_dir_path, db_filename, signer).await?;
+    }
+
+    Ok(())
+}
+
+/// Add multiple package files to the repository db called `db_filename`
+/// in a single `repo-add` invocation, replacing older versions of the packages.
+pub async fn add_packages_to_repo_db(
+    repo_dir_path: &Utf8Path,
+    db_filename: &Utf8Path,
+    package_file_names: &[String],
+    signer: Option<&SignerBackend>,
+) -> Result<()> {
+    let mut cmd = Command::new("repo-add");
+    cmd.arg(repo_dir_path.join(db_filename));
+    for file_name in package_file_names {
+        cmd.arg(repo_dir_path.join(file_name));
+    }
+    let status = cmd.status().await.wrap_err("Failed to run repo-add")?;
+    if !status.success() {
+        return Err(eyre!("repo-add failed for {repo_dir_path}/{db_filename}"));
+    }
+
+    if let Some(signer) = signer {
+        sign_repo_db(repo_dir_path, db_filename, signer).await?;
+    }
+
+    Ok(())
+}
+
+/// Sign the repository database and files database in the given directory.
+/// `repo-add` creates symlinks without the compression extension
+/// (e.g. `buildbtw-foo.db`), which is what pacman downloads,
+/// so create matching symlinks for the signatures as well.
+async fn sign_repo_db(
+    repo_dir_path: &Utf8Path,
+    db_filename: &Utf8Path,
+    signer: &SignerBackend,
+) -> Result<()> {
+    let files_db_filename = db_filename.as_str().replace(".db.", ".files.");
+    for filename in [db_filename.as_str(), files_db_filename.as_str()] {
+        let signature_path = sign_file(signer, &repo_dir_path.join(filename))
+            .await
+            .wrap_err("Failed to sign repository database")?;
+
+        let Some(link_name) = filename.strip_suffix(&format!(".{REPO_ARCHIVE_EXTENSION}")) else {
+            continue;
+        };
+        let link_path = repo_dir_path.join(format!("{link_name}.sig"));
+        if tokio::fs::symlink_metadata(&link_path).await.is_err() {
+            let target = signature_path
+                .file_name()
+                .ok_or_else(|| eyre!("Invalid signature path {signature_path}"))?;
+            tokio::fs::symlink(target, &link_path).await?;
+        }
+    }
 
     Ok(())
 }
@@ -59,7 +266,7 @@ pub async fn ensure_repo_exists(
 
     tokio::fs::create_dir_all(&repo_dir).await?;
 
-    let repo_file = repo_file_name();
+    let repo_file = repo_file_name(namespace_name);
     let db_path = format!("{repo_dir}/{repo_file}");
 
     if tokio::fs::try_exists(&db_path).await? {
@@ -67,8 +274,50 @@ pub async fn ensure_repo_exists(
     }
 
     let mut cmd = Command::new("repo-add");
-    cmd.arg(db_path);
-    cmd.status().await?;
+    cmd.arg(&db_path);
+    let status = cmd.status().await.wrap_err("Failed to run repo-add")?;
+    if !status.success() {
+        return Err(eyre!("repo-add failed to create {db_path}"));
+    }
 
     Ok(())
 }
+
+/// List the names of all package files in the given repository directory.
+/// Returns an empty list if the repository hasn't been created yet.
+pub async fn list_package_files(repo_dir_path: &Utf8Path) -> Result<Vec<String>> {
+    if !tokio::fs::try_exists(repo_dir_path).await? {
+        return Ok(Vec::new());
+    }
+
+    let mut file_names = Vec::new();
+    let mut entries = tokio::fs::read_dir(repo_dir_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let Ok(file_name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if strip_package_file_extension(&file_name).is_some() {
+            file_names.push(file_name);
+        }
+    }
+    file_names.sort();
+
+    Ok(file_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn test_debug_repo_names_dont_collide_with_namespaces() {
+        let iteration_id = Uuid::new_v4();
+        assert_ne!(debug_repo_db_name("foo"), repo_db_name("foo-debug"));
+        assert_ne!(latest_debug_repo_name("foo"), latest_repo_name("foo-debug"));
+        assert_ne!(
+            debug_repo_dir_path("foo", iteration_id, ConcreteArchitecture::X86_64),
+            repo_dir_path("foo-debug", iteration_id, ConcreteArchitecture::X86_64)
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! In a rebuild, every dependent of the origin changesets needs a pkgrel bump
+//! so the rebuilt packages are newer than the ones in the repositories.
+//!
+//! Instead of building the dependents from `main`, namespaces can create a
+//! rebuild branch in each dependent's source repository containing a single
+//! commit that bumps the pkgrel. The branch is pushed, so the whole rebuild
+//! is reproducible from git.
+
+use std::collections::{HashMap, HashSet};
+
+use color_eyre::eyre::{Context, Result, bail, eyre};
+use git2::{BranchType, Commit, Oid, Repository};
+use tokio::task::JoinSet;
+
+use crate::{
+    BuildNamespace, CommitHash, Pkgbase,
+    build_set_graph::BuildSetGraph,
+    git::{
+        GitCredentials, commit_signature, package_source_path, parse_srcinfo, push_branch,
+        read_file_from_tree,
+    },
+    source_info::{ConcreteArchitecture, SourceInfo},
+};
+
+/// Branch that origin changesets without a branch of their own are built from.
+const DEFAULT_BRANCH: &str = "main";
+
+/// Name of the branch containing the pkgrel bump for dependents in a namespace.
+pub fn rebuild_branch_name(namespace_name: &str) -> String {
+    format!("buildbtw/rebuild/{namespace_name}")
+}
+
+/// Increment the pkgrel for a rebuild, e.g. `1` -> `2`.
+/// Sub-releases are dropped, so `1.1` becomes `2` as well.
+pub fn bump_pkgrel(pkgrel: &str) -> Result<String> {
+    let major = pkgrel.split('.').next().unwrap_or(pkgrel);
+    let major: u64 = major
+        .parse()
+        .wrap_err_with(|| format!("Invalid pkgrel: {pkgrel}"))?;
+    Ok((major + 1).to_string())
+}
+
+/// Bump the top-level `pkgrel=` assignment in a PKGBUILD.
+/// Returns the new PKGBUILD and the new pkgrel.
+pub fn bump_pkgrel_in_pkgbuild(pkgbuild: &str) -> Result<(String, String)> {
+    let mut new_pkgrel = None;
+    let mut lines = Vec::new();
+    for line in pkgbuild.split_inclusive('\n') {
+        let Some(value) = line.strip_prefix("pkgrel=") else {
+            lines.push(line.to_string());
+            continue;
+        };
+        if new_pkgrel.is_some() {
+            bail!("PKGBUILD assigns pkgrel multiple times");
+        }
+        let value_end = value
+            .find(|c: char| c.is_whitespace() || c == '#')
+            .unwrap_or(value.len());
+        let (value, rest) = value.split_at(value_end);
+        let pkgrel = bump_pkgrel(value.trim_matches(|c| c == '"' || c == '\''))?;
+        lines.push(format!("pkgrel={pkgrel}{rest}"));
+        new_pkgrel = Some(pkgrel);
+    }
+
+    let new_pkgrel = new_pkgrel.ok_or_else(|| eyre!("PKGBUILD doesn't assign pkgrel"))?;
+    Ok((lines.concat(), new_pkgrel))
+}
+
+/// Set the pkgrel in a .SRCINFO file.
+/// For a change that only bumps the pkgrel, this results in the same file
+/// `makepkg --printsrcinfo` would generate, without having to run makepkg.
+pub fn set_pkgrel_in_srcinfo(srcinfo: &str, pkgrel: &str) -> Result<String> {
+    let mut found = false;
+    let srcinfo = srcinfo
+        .split_inclusive('\n')
+        .map(|line| {
+            if line.trim_start().starts_with("pkgrel = ") {
+                found = true;
+                let indent = &line[..line.len() - line.trim_start().len()];
+                let newline = if line.ends_with('\n') { "\n" } else { "" };
+                format!("{indent}pkgrel = {pkgrel}{newline}")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    if !found {
+        bail!(".SRCINFO doesn't contain a pkgrel");
+    }
+    Ok(srcinfo)
+}
+
+/// Which nodes of a namespace's build graphs get a pkgrel bump.
+/// Origin changesets carry the packager's own changes, and nodes built from
+/// other branches (e.g. a parent namespace's origin changesets) are left alone.
+fn needs_pkgrel_bump(namespace: &BuildNamespace, pkgbase: &Pkgbase, branch_name: &str) -> bool {
+    branch_name == DEFAULT_BRANCH
+        && !namespace
+            .current_origin_changesets
+            .iter()
+            .any(|(origin_pkgbase, _)| origin_pkgbase == pkgbase)
+}
+
+/// Build dependents of the origin changesets from rebuild branches with a pkgrel bump,
+/// creating and pushing these branches if necessary.
+pub async fn bump_pkgrels_in_graphs(
+    namespace: &BuildNamespace,
+    graphs: &mut HashMap<ConcreteArchitecture, BuildSetGraph>,
+    credentials: &GitCredentials,
+) -> Result<()> {
+    bump_pkgrels(namespace, graphs, Some(credentials)).await
+}
+
+/// Like [`bump_pkgrels_in_graphs`], but only use pkgrel bumps that were already pushed,
+/// leaving nodes without one untouched.
+/// This doesn't modify any repository, so it can be used to check whether
+/// the graphs changed compared to a previous iteration.
+pub async fn apply_existing_pkgrel_bumps(
+    namespace: &BuildNamespace,
+    graphs: &mut HashMap<ConcreteArchitecture, BuildSetGraph>,
+) -> Result<()> {
+    bump_pkgrels(namespace, graphs, None).await
+}
+
+/// Without credentials, no new branches are created.
+async fn bump_pkgrels(
+    namespace: &BuildNamespace,
+    graphs: &mut HashMap<ConcreteArchitecture, BuildSetGraph>,
+    credentials: Option<&GitCredentials>,
+) -> Result<()> {
+    let branch = rebuild_branch_name(&namespace.name);
+    let message = format!(
+        "Rebuild for {}\n\nAutomatic pkgrel bump by buildbtw namespace {}.",
+        namespace
+            .current_origin_changesets
+            .iter()
+            .map(|(pkgbase, branch)| format!("{pkgbase}/{branch}"))
+            .collect::<Vec<_>>()
+            .join(", "),
+        namespace.name
+    );
+
+    let mut seen = HashSet::new();
+    let mut join_set = JoinSet::new();
+    for node in graphs.values().flat_map(|graph| graph.node_weights()) {
+        if !needs_pkgrel_bump(namespace, &node.pkgbase, &node.branch_name)
+            || !seen.insert(node.pkgbase.clone())
+        {
+            continue;
+        }
+        let (pkgbase, base_commit) = (node.pkgbase.clone(), node.commit_hash.clone());
+        let (branch, message) = (branch.clone(), message.clone());
+        let credentials = credentials.cloned();
+        join_set.spawn_blocking(move || {
+            let bumped = pkgrel_bump(
+                &pkgbase,
+                &base_commit,
+                &branch,
+                &message,
+                credentials.as_ref(),
+            )
+            .wrap_err_with(|| format!("Failed to bump pkgrel of {pkgbase}"));
+            (pkgbase, bumped)
+        });
+    }
+
+    let mut bumped = HashMap::new();
+    while let Some(output) = join_set.join_next().await {
+        let (pkgbase, result) = output?;
+        if let Some(bump) = result? {
+            bumped.insert(pkgbase, bump);
+        }
+    }
+
+    for node in graphs
+        .values_mut()
+        .flat_map(|graph| graph.node_weights_mut())
+    {
+        if let Some((commit_hash, source_info)) = bumped.get(&node.pkgbase) {
+            node.branch_name = branch.clone();
+            node.commit_hash = commit_hash.clone();
+            node.srcinfo = source_info.clone();
+        }
+    }
+
+    Ok(())
+}
+
+/// Pkgrel bump of `pkgbase` on top of `base_commit`, see [`bump_pkgrels`].
+fn pkgrel_bump(
+    pkgbase: &Pkgbase,
+    base_commit: &CommitHash,
+    branch: &str,
+    message: &str,
+    credentials: Option<&GitCredentials>,
+) -> Result<Option<(CommitHash, SourceInfo)>> {
+    let repo = Repository::open(package_source_path(pkgbase))?;
+    let base_commit = repo.find_commit(Oid::from_str(base_commit.as_ref())?)?;
+    let bump = match credentials {
+        Some(credentials) => Some(create_pkgrel_bump_branch(
+            &repo,
+            &base_commit,
+            branch,
+            message,
+            credentials,
+        )?),
+        None => find_pkgrel_bump(&repo, &base_commit, branch)?,
+    };
+
+    bump.map(|(commit_hash, srcinfo)| Ok((commit_hash, parse_srcinfo(&srcinfo)?)))
+        .transpose()
+}
+
+/// Find the pkgrel bump commit on top of `base_commit` on the remote `branch`.
+/// Returns its commit hash and .SRCINFO.
+fn find_pkgrel_bump(
+    repo: &Repository,
+    base_commit: &Commit,
+    branch: &str,
+) -> Result<Option<(CommitHash, String)>> {
+    let Ok(existing) = repo.find_branch(&format!("origin/{branch}"), BranchType::Remote) else {
+        return Ok(None);
+    };
+    let existing = existing.get().peel_to_commit()?;
+    if !existing.parent_ids().eq([base_commit.id()]) {
+        return Ok(None);
+    }
+    let srcinfo = read_file_from_tree(repo, &existing.tree()?, ".SRCINFO")?;
+
+    Ok(Some((CommitHash(existing.id().to_string()), srcinfo)))
+}
+
+/// Make sure `branch` contains a single pkgrel bump commit on top of `base_commit`.
+/// If the branch already contains such a commit, it is reused so the
+/// commit hash stays stable across iterations.
+/// Returns the commit hash and .SRCINFO of the bump commit.
+fn create_pkgrel_bump_branch(
+    repo: &Repository,
+    base_commit: &Commit,
+    branch: &str,
+    message: &str,
+    credentials: &GitCredentials,
+) -> Result<(CommitHash, String)> {
+    if let Some(existing) = find_pkgrel_bump(repo, base_commit, branch)? {
+        return Ok(existing);
+    }
+
+    tracing::info!("Bumping pkgrel in {:?} on branch {branch}", repo.path());
+    let base_tree = base_commit.tree()?;
+    let (pkgbuild, pkgrel) =
+        bump_pkgrel_in_pkgbuild(&read_file_from_tree(repo, &base_tree, "PKGBUILD")?)?;
+    let srcinfo =
+        set_pkgrel_in_srcinfo(&read_file_from_tree(repo, &base_tree, ".SRCINFO")?, &pkgrel)?;
+
+    let mut tree_builder = repo.treebuilder(Some(&base_tree))?;
+    for (path, content) in [("PKGBUILD", &pkgbuild), (".SRCINFO", &srcinfo)] {
+        let filemode = base_tree
+            .get_name(path)
+            .ok_or_else(|| eyre!("Missing {path}"))?
+            .filemode();
+        tree_builder.insert(path, repo.blob(content.as_bytes())?, filemode)?;
+    }
+    let tree = repo.find_tree(tree_builder.write()?)?;
+
+    let signature = commit_signature(repo)?;
+    let commit_id = repo.commit(None, &signature, &signature, message, &tree, &[base_commit])?;
+    repo.reference(
+        &format!("refs/heads/{branch}"),
+        commit_id,
+        true,
+        "buildbtw: pkgrel bump",
+    )?;
+    push_branch(repo, branch, credentials)?;
+
+    Ok((CommitHash(commit_id.to_string()), srcinfo))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::commit_files;
+    use rstest::*;
+
+    #[rstest]
+    #[case(
+        "pkgname=foo\npkgver=1.0\npkgrel=1\n",
+        "pkgname=foo\npkgver=1.0\npkgrel=2\n",
+        "2"
+    )]
+    #[case("pkgrel='3' # comment\n", "pkgrel=4 # comment\n", "4")]
+    #[case("pkgrel=1.1", "pkgrel=2", "2")]
+    fn test_bump_pkgrel_in_pkgbuild(
+        #[case] pkgbuild: &str,
+        #[case] expected: &str,
+        #[case] expected_pkgrel: &str,
+    ) {
+        let (bumped, pkgrel) = bump_pkgrel_in_pkgbuild(pkgbuild).unwrap();
+        assert_eq!(bumped, expected);
+        assert_eq!(pkgrel, expected_pkgrel);
+    }
+
+    #[rstest]
+    #[case("pkgname=foo\n")]
+    #[case("pkgrel=$_rel\n")]
+    #[case("pkgrel=1\npkgrel=2\n")]
+    fn test_bump_pkgrel_in_pkgbuild_invalid(#[case] pkgbuild: &str) {
+        assert!(bump_pkgrel_in_pkgbuild(pkgbuild).is_err());
+    }
+
+    #[rstest]
+    fn test_set_pkgrel_in_srcinfo() {
+        let srcinfo = "pkgbase = foo\n\tpkgver = 1.0\n\tpkgrel = 1\n\npkgname = foo\n";
+        assert_eq!(
+            set_pkgrel_in_srcinfo(srcinfo, "2").unwrap(),
+            "pkgbase = foo\n\tpkgver = 1.0\n\tpkgrel = 2\n\npkgname = foo\n"
+        );
+    }
+
+    #[rstest]
+    fn test_create_pkgrel_bump_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let origin = Repository::init_bare(dir.path().join("origin")).unwrap();
+        let files = [
+            ("PKGBUILD", "pkgname=foo\npkgver=1.0\npkgrel=1\n"),
+            (
+                ".SRCINFO",
+                "pkgbase = foo\n\tpkgver = 1.0\n\tpkgrel = 1\n\npkgname = foo\n",
+            ),
+        ];
+        let base = commit_files(&origin, Some("refs/heads/main"), None, &files, "Initial").unwrap();
+        let clone = Repository::clone(
+            dir.path().join("origin").to_str().unwrap(),
+            dir.path().join("clone"),
+        )
+        .unwrap();
+        let base_commit = clone.find_commit(base).unwrap();
+        let branch = rebuild_branch_name("openssl");
+
+        assert!(
+            find_pkgrel_bump(&clone, &base_commit, &branch)
+                .unwrap()
+                .is_none()
+        );
+        let (commit_hash, srcinfo) = create_pkgrel_bump_branch(
+            &clone,
+            &base_commit,
+            &branch,
+            "Rebuild",
+            &GitCredentials::None,
+        )
+        .unwrap();
+
+        // The bump is a single commit on top of the base, pushed to the remote
+        let pushed = origin
+            .refname_to_id(&format!("refs/heads/{branch}"))
+            .unwrap();
+        assert_eq!(pushed.to_string(), commit_hash.to_string());
+        let bump_commit = origin.find_commit(pushed).unwrap();
+        assert!(bump_commit.parent_ids().eq([base]));
+        let pkgbuild = read_file_from_tree(&origin, &bump_commit.tree().unwrap(), "PKGBUILD");
+        assert_eq!(pkgbuild.unwrap(), "pkgname=foo\npkgver=1.0\npkgrel=2\n");
+        assert!(srcinfo.contains("\tpkgrel = 2\n"));
+
+        // The existing bump is reused instead of creating a new commit
+        let (reused_commit_hash, _) = create_pkgrel_bump_branch(
+            &clone,
+            &base_commit,
+            &branch,
+            "Rebuild",
+            &GitCredentials::None,
+        )
+        .unwrap();
+        assert_eq!(reused_commit_hash, commit_hash);
+        assert_eq!(
+            find_pkgrel_bump(&clone, &base_commit, &branch)
+                .unwrap()
+                .map(|(commit_hash, _)| commit_hash),
+            Some(commit_hash)
+        );
+    }
+}
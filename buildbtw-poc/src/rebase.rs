@@ -0,0 +1,169 @@
+//! Keep origin changeset branches up to date with `main`.
+//!
+//! When `main` moves while a namespace is building an origin changeset branch,
+//! the branch is rebased onto the new `main` if this is possible without conflicts,
+//! and the rebased branch is pushed. The changed commit hash causes a new iteration.
+
+use color_eyre::eyre::{Context, Result};
+use git2::{ErrorCode, Oid, RebaseOptions, Repository};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CommitHash, Pkgbase,
+    git::{GitCredentials, commit_signature, package_source_path, push_branch},
+};
+
+/// Branch that origin changesets are rebased onto.
+pub const REBASE_ONTO_BRANCH: &str = "main";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum RebaseOutcome {
+    /// The branch already contains the newest commit of `main`
+    UpToDate,
+    Rebased {
+        commit_hash: CommitHash,
+    },
+    /// Rebasing would result in conflicts in these files
+    Conflict {
+        paths: Vec<String>,
+    },
+    /// The branch couldn't be rebased or pushed, e.g. because a packager
+    /// pushed to it since it was last fetched. This is retried.
+    Failed {
+        error: String,
+    },
+}
+
+/// The most recent rebase of an origin changeset branch in a namespace.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OriginChangesetRebase {
+    pub pkgbase: Pkgbase,
+    pub branch_name: String,
+    pub onto_commit_hash: CommitHash,
+    pub outcome: RebaseOutcome,
+    pub updated_at: time::OffsetDateTime,
+}
+
+/// Rebase the commits of `branch` onto `onto` without touching any refs or the working tree.
+/// The rebased commits keep their authors, buildbtw is recorded as the committer.
+pub fn rebase_onto(repo: &Repository, branch: Oid, onto: Oid) -> Result<RebaseOutcome> {
+    if branch == onto || repo.graph_descendant_of(branch, onto)? {
+        return Ok(RebaseOutcome::UpToDate);
+    }
+
+    let mut options = RebaseOptions::new();
+    options.inmemory(true);
+    let mut rebase = repo.rebase(
+        Some(&repo.find_annotated_commit(branch)?),
+        Some(&repo.find_annotated_commit(onto)?),
+        None,
+        Some(&mut options),
+    )?;
+
+    let signature = commit_signature(repo)?;
+    let mut head = onto;
+    while let Some(operation) = rebase.next() {
+        operation?;
+        let index = rebase.inmemory_index()?;
+        if index.has_conflicts() {
+            let paths = index
+                .conflicts()?
+                .filter_map(|conflict| conflict.ok())
+                .filter_map(|conflict| conflict.our.or(conflict.their).or(conflict.ancestor))
+                .map(|entry| String::from_utf8_lossy(&entry.path).to_string())
+                .collect();
+            rebase.abort()?;
+            return Ok(RebaseOutcome::Conflict { paths });
+        }
+        match rebase.commit(None, &signature, None) {
+            Ok(commit) => head = commit,
+            // The changes of this commit are already contained in `onto`
+            Err(e) if e.code() == ErrorCode::Applied => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    rebase.finish(Some(&signature))?;
+
+    Ok(RebaseOutcome::Rebased {
+        commit_hash: CommitHash(head.to_string()),
+    })
+}
+
+/// Rebase the origin changeset `branch` of `pkgbase` onto the commit `onto`,
+/// and force-push the branch if it was rebased.
+/// The push is aborted if the branch moved on origin since it was last fetched.
+pub async fn rebase_branch_onto(
+    pkgbase: Pkgbase,
+    branch: String,
+    onto: CommitHash,
+    credentials: GitCredentials,
+) -> Result<RebaseOutcome> {
+    tokio::task::spawn_blocking(move || {
+        let repo = Repository::open(package_source_path(&pkgbase))?;
+        let branch_commit = repo
+            .find_branch(&format!("origin/{branch}"), git2::BranchType::Remote)?
+            .get()
+            .peel_to_commit()?
+            .id();
+        let outcome = rebase_onto(&repo, branch_commit, Oid::from_str(onto.as_ref())?)
+            .wrap_err_with(|| format!("Failed to rebase {pkgbase}/{branch}"))?;
+
+        if let RebaseOutcome::Rebased { commit_hash } = &outcome {
+            tracing::info!("Rebased {pkgbase}/{branch} onto {onto}");
+            repo.reference(
+                &format!("refs/heads/{branch}"),
+                Oid::from_str(commit_hash.as_ref())?,
+                true,
+                "buildbtw: rebase",
+            )?;
+            push_branch(&repo, &branch, &credentials)?;
+        }
+
+        Ok(outcome)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::commit_files;
+    use rstest::*;
+
+    fn commit_file(repo: &Repository, parent: Option<Oid>, path: &str, content: &str) -> Oid {
+        commit_files(repo, None, parent, &[(path, content)], path).unwrap()
+    }
+
+    #[rstest]
+    fn test_rebase_onto() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let base = commit_file(&repo, None, "PKGBUILD", "pkgver=1\n");
+        let branch = commit_file(&repo, Some(base), "fix.patch", "fix\n");
+        let main = commit_file(&repo, Some(base), "LICENSE", "license\n");
+
+        assert_eq!(
+            rebase_onto(&repo, branch, base).unwrap(),
+            RebaseOutcome::UpToDate
+        );
+
+        let RebaseOutcome::Rebased { commit_hash } = rebase_onto(&repo, branch, main).unwrap()
+        else {
+            panic!("Expected branch to be rebased");
+        };
+        let rebased = repo
+            .find_commit(Oid::from_str(commit_hash.as_ref()).unwrap())
+            .unwrap();
+        assert_eq!(rebased.parent_ids().collect::<Vec<_>>(), vec![main]);
+        assert!(rebased.tree().unwrap().get_name("fix.patch").is_some());
+        assert!(rebased.tree().unwrap().get_name("LICENSE").is_some());
+
+        let conflicting_main = commit_file(&repo, Some(base), "fix.patch", "other fix\n");
+        assert_eq!(
+            rebase_onto(&repo, branch, conflicting_main).unwrap(),
+            RebaseOutcome::Conflict {
+                paths: vec!["fix.patch".to_string()]
+            }
+        );
+    }
+}
@@ -0,0 +1,397 @@
+//! Promote the packages built in a namespace to a target repository.
+//!
+//! Releasing happens in three steps:
+//! 1. A [`ReleasePlan`] is computed from the newest iteration of a namespace,
+//!    listing the package files and versions that will end up in the target repository.
+//!    Which packages are part of the plan is decided by a [`ReleaseStrategy`]
+//!    and manual overrides, see [`crate::release_selection`].
+//! 2. A user reviews the summary of the plan and confirms it.
+//! 3. Confirmed plans are queued and executed one after another by [`execute_release_plan`].
+//!
+//! While a release is confirmed, it holds a [`ReleaseLock`] on each of its pkgbases
+//! in the target repository. Releases of other namespaces touching these pkgbases
+//! have to wait in the queue until the locks are released again. If one of them
+//! released packages while another release was waiting, the waiting namespace
+//! has to be rebuilt on top of them and released with a new plan.
+//!
+//! For now, target repositories are plain directories on the server (see [`RELEASE_REPO_DIR`]),
+//! so releasing can be tested without touching real infrastructure.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::LazyLock,
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::{Context, Result, bail, eyre};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    BuildSetIteration, NAMESPACE_DATA_DIR, PackageBuildStatus, Pkgbase,
+    pacman_repo::{
+        REPO_FILE_EXTENSION, add_packages_to_repo_db, list_package_files, repo_dir_path,
+    },
+    release_selection::{
+        ReleaseOverride, ReleaseStrategy, SelectionCandidate, SelectionReason,
+        read_package_file_metadata, read_repo_db_packages, select_packages,
+    },
+    signing::{SignerBackend, signature_path},
+    source_info::{ConcreteArchitecture, base_version, package_for_file_name},
+};
+
+pub static RELEASE_REPO_DIR: LazyLock<Utf8PathBuf> =
+    LazyLock::new(|| NAMESPACE_DATA_DIR.join("release-repos"));
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateRelease {
+    /// Name of the repository to release into, e.g. `extra-testing`.
+    pub target_repository: String,
+    #[serde(default)]
+    pub strategy: ReleaseStrategy,
+}
+
+/// Progress of releasing a namespace.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+pub enum ReleaseStatus {
+    /// The plan was computed and is waiting for confirmation
+    Planned,
+    /// The plan was confirmed and is waiting in the release queue
+    Confirmed,
+    /// Packages are being moved to the target repository
+    Releasing,
+    /// All packages are available in the target repository
+    Released,
+    /// Releasing failed, see the error message of the release
+    Failed,
+    /// The release was cancelled before any packages were moved
+    Cancelled,
+}
+
+impl ReleaseStatus {
+    pub fn as_icon(&self) -> &'static str {
+        match self {
+            Self::Planned => "📝",
+            Self::Confirmed => "📅",
+            Self::Releasing => "🚚",
+            Self::Released => "✅",
+            Self::Failed => "❌",
+            Self::Cancelled => "🚫",
+        }
+    }
+}
+
+/// Everything that will be released for a single pkgbase and architecture.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReleasePlanEntry {
+    pub pkgbase: Pkgbase,
+    pub architecture: ConcreteArchitecture,
+    /// Full version including epoch and pkgrel.
+    pub version: String,
+    /// Package files in the namespace repository, including all split packages.
+    pub package_file_names: Vec<String>,
+    #[serde(default)]
+    pub reason: SelectionReason,
+}
+
+/// A pkgbase of the namespace that won't be released.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SkippedPackage {
+    pub pkgbase: Pkgbase,
+    pub architecture: ConcreteArchitecture,
+    pub reason: SelectionReason,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReleasePlan {
+    pub namespace_name: String,
+    pub iteration_id: Uuid,
+    pub target_repository: String,
+    #[serde(default)]
+    pub strategy: ReleaseStrategy,
+    /// Packages to release.
+    pub entries: Vec<ReleasePlanEntry>,
+    #[serde(default)]
+    pub skipped: Vec<SkippedPackage>,
+}
+
+impl ReleasePlan {
+    /// Human-readable summary of the plan, for confirmation by the user.
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Release {count} package(s) of namespace {namespace} (iteration {iteration}) into {target} using the {strategy:?} strategy:\n",
+            count = self.entries.len(),
+            namespace = self.namespace_name,
+            iteration = self.iteration_id,
+            target = self.target_repository,
+            strategy = self.strategy,
+        );
+        for entry in &self.entries {
+            summary.push_str(&format!(
+                "  {pkgbase} {version} ({architecture}, {reason}): {files}\n",
+                pkgbase = entry.pkgbase,
+                version = entry.version,
+                architecture = entry.architecture,
+                reason = entry.reason.short_description(),
+                files = entry.package_file_names.join(", "),
+            ));
+        }
+        if !self.skipped.is_empty() {
+            summary.push_str(&format!("Skip {} package(s):\n", self.skipped.len()));
+        }
+        for skipped in &self.skipped {
+            summary.push_str(&format!(
+                "  {pkgbase} ({architecture}, {reason})\n",
+                pkgbase = skipped.pkgbase,
+                architecture = skipped.architecture,
+                reason = skipped.reason.short_description(),
+            ));
+        }
+        summary
+    }
+
+    /// All pkgbases released by this plan, regardless of architecture.
+    pub fn pkgbases(&self) -> BTreeSet<Pkgbase> {
+        self.entries
+            .iter()
+            .map(|entry| entry.pkgbase.clone())
+            .collect()
+    }
+}
+
+/// A release plan of a namespace, and the progress of executing it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Release {
+    pub id: Uuid,
+    pub namespace_id: Uuid,
+    pub plan: ReleasePlan,
+    pub status: ReleaseStatus,
+    /// Set if releasing failed.
+    pub error: Option<String>,
+    pub created_at: time::OffsetDateTime,
+    pub updated_at: time::OffsetDateTime,
+}
+
+/// A pkgbase in a target repository that is reserved by a release
+/// until it has been released or failed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReleaseLock {
+    pub target_repository: String,
+    pub pkgbase: Pkgbase,
+    pub release_id: Uuid,
+    pub namespace_name: String,
+    pub acquired_at: time::OffsetDateTime,
+}
+
+/// Check that `name` can safely be used as a repository name in paths and `pacman.conf`.
+pub fn validate_target_repository(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+    {
+        bail!("Invalid target repository name: {name:?}");
+    }
+    Ok(())
+}
+
+/// Compute what releasing the given iteration would do.
+/// Fails if any package in the iteration that isn't excluded by an override
+/// hasn't been built successfully.
+pub async fn compute_release_plan(
+    namespace_name: &str,
+    iteration: &BuildSetIteration,
+    target_repository: &str,
+    strategy: ReleaseStrategy,
+    overrides: &BTreeMap<Pkgbase, ReleaseOverride>,
+) -> Result<ReleasePlan> {
+    validate_target_repository(target_repository)?;
+
+    let mut architectures: Vec<_> = iteration.packages_to_be_built.keys().copied().collect();
+    architectures.sort();
+
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    for architecture in architectures {
+        let graph = &iteration.packages_to_be_built[&architecture];
+        let repo_dir = repo_dir_path(namespace_name, iteration.id, architecture);
+        let available_files = list_package_files(&repo_dir).await?;
+        let release_dir = release_repo_dir_path(target_repository, architecture);
+        let released_files = read_repo_db_packages(
+            &release_dir.join(format!("{target_repository}.{REPO_FILE_EXTENSION}")),
+        )
+        .await?;
+
+        // Dependencies have to be decided on before their dependents,
+        // so we know which sonames are bumped.
+        let nodes = petgraph::algo::toposort(graph, None)
+            .map_err(|_| eyre!("Build graph for {architecture} contains cycles"))?;
+
+        let mut candidates = Vec::new();
+        let mut package_file_names_by_candidate = Vec::new();
+        for index in nodes {
+            let node = &graph[index];
+            let release_override = overrides.get(&node.pkgbase);
+            let excluded = release_override == Some(&ReleaseOverride::Exclude);
+            if !excluded && node.status != PackageBuildStatus::Built {
+                bail!(
+                    "Cannot release namespace: {} ({architecture}) is {:?}",
+                    node.pkgbase,
+                    node.status
+                );
+            }
+
+            let package_file_names: Vec<_> = available_files
+                .iter()
+                .filter(|file_name| {
+                    package_for_file_name(&node.srcinfo, architecture, file_name).is_some()
+                })
+                .cloned()
+                .collect();
+            if !excluded && package_file_names.is_empty() {
+                bail!(
+                    "No package files found for {} ({architecture}) in {repo_dir}",
+                    node.pkgbase
+                );
+            }
+
+            // Only read package files if the heuristic needs them.
+            let mut built = Vec::new();
+            let mut released = Vec::new();
+            if strategy == ReleaseStrategy::Changed && release_override.is_none() {
+                for file_name in &package_file_names {
+                    let metadata = read_package_file_metadata(&repo_dir.join(file_name)).await?;
+                    if let Some(released_file_name) = released_files.get(&metadata.pkgname) {
+                        released.push(
+                            read_package_file_metadata(&release_dir.join(released_file_name))
+                                .await?,
+                        );
+                    }
+                    built.push(metadata);
+                }
+            }
+
+            candidates.push(SelectionCandidate {
+                pkgbase: node.pkgbase.clone(),
+                is_origin_changeset: iteration
+                    .origin_changesets
+                    .iter()
+                    .any(|(pkgbase, _)| pkgbase == &node.pkgbase),
+                built,
+                released,
+            });
+            package_file_names_by_candidate.push((node, package_file_names));
+        }
+
+        let selections = select_packages(strategy, &candidates, overrides);
+        for (selection, (node, package_file_names)) in
+            selections.into_iter().zip(package_file_names_by_candidate)
+        {
+            if selection.selected {
+                entries.push(ReleasePlanEntry {
+                    pkgbase: selection.pkgbase,
+                    architecture,
+                    version: base_version(&node.srcinfo).to_string(),
+                    package_file_names,
+                    reason: selection.reason,
+                });
+            } else {
+                skipped.push(SkippedPackage {
+                    pkgbase: selection.pkgbase,
+                    architecture,
+                    reason: selection.reason,
+                });
+            }
+        }
+    }
+
+    Ok(ReleasePlan {
+        namespace_name: namespace_name.to_string(),
+        iteration_id: iteration.id,
+        target_repository: target_repository.to_string(),
+        strategy,
+        entries,
+        skipped,
+    })
+}
+
+pub fn release_repo_dir_path(
+    target_repository: &str,
+    architecture: ConcreteArchitecture,
+) -> Utf8PathBuf {
+    RELEASE_REPO_DIR
+        .join(target_repository)
+        .join("os")
+        .join(architecture.to_string())
+}
+
+/// Copy all package files of the plan (and their signatures, if any)
+/// into the target repository and add them to its database.
+/// The namespace repository is left untouched.
+pub async fn execute_release_plan(
+    plan: &ReleasePlan,
+    signer: Option<&SignerBackend>,
+) -> Result<()> {
+    validate_target_repository(&plan.target_repository)?;
+
+    let mut architectures: Vec<_> = plan.entries.iter().map(|e| e.architecture).collect();
+    architectures.sort();
+    architectures.dedup();
+
+    for architecture in architectures {
+        let source_dir = repo_dir_path(&plan.namespace_name, plan.iteration_id, architecture);
+        let target_dir = release_repo_dir_path(&plan.target_repository, architecture);
+        tokio::fs::create_dir_all(&target_dir).await?;
+
+        let file_names: Vec<String> = plan
+            .entries
+            .iter()
+            .filter(|entry| entry.architecture == architecture)
+            .flat_map(|entry| entry.package_file_names.iter().cloned())
+            .collect();
+        for file_name in &file_names {
+            copy_package_file(&source_dir, &target_dir, file_name).await?;
+        }
+
+        let db_filename: Utf8PathBuf =
+            format!("{}.{REPO_FILE_EXTENSION}", plan.target_repository).into();
+        add_packages_to_repo_db(&target_dir, &db_filename, &file_names, signer).await?;
+    }
+
+    Ok(())
+}
+
+async fn copy_package_file(
+    source_dir: &Utf8Path,
+    target_dir: &Utf8Path,
+    file_name: &str,
+) -> Result<()> {
+    let source = source_dir.join(file_name);
+    let target = target_dir.join(file_name);
+    tokio::fs::copy(&source, &target)
+        .await
+        .wrap_err_with(|| format!("Failed to copy {source} to {target}"))?;
+
+    let source_signature = signature_path(&source);
+    if tokio::fs::try_exists(&source_signature).await? {
+        tokio::fs::copy(&source_signature, signature_path(&target)).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("extra-testing", true)]
+    #[case("core_staging", true)]
+    #[case("", false)]
+    #[case("../extra", false)]
+    #[case("extra]", false)]
+    fn test_validate_target_repository(#[case] name: &str, #[case] valid: bool) {
+        assert_eq!(validate_target_repository(name).is_ok(), valid);
+    }
+}
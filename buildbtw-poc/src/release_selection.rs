@@ -0,0 +1,526 @@
+//! Decide which packages of a namespace to release.
+//!
+//! Rebuilding a package's dependents doesn't always change them, e.g. when a library
+//! only had a bugfix release. Releasing these packages anyway wastes mirror bandwidth
+//! and makes users download identical packages, so by default we only release:
+//! - packages from the origin changesets, as their sources changed
+//! - packages that aren't in the target repository yet
+//! - packages whose files differ from the ones in the target repository
+//! - packages that link to a soname whose version changed in this release
+//!
+//! Users can override this decision for individual pkgbases,
+//! see [`ReleaseOverride`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    process::Stdio,
+};
+
+use camino::Utf8Path;
+use color_eyre::eyre::{Context, Result, eyre};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::Pkgbase;
+
+/// How to select the packages of a namespace to release.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReleaseStrategy {
+    /// Only release packages that changed, see the [module documentation](self).
+    #[default]
+    Changed,
+    /// Release every package of the namespace.
+    All,
+}
+
+/// Manual decision to release a pkgbase or not, regardless of the [`ReleaseStrategy`].
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, clap::ValueEnum,
+)]
+pub enum ReleaseOverride {
+    Include,
+    Exclude,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SetReleaseOverride {
+    /// `None` removes the override.
+    pub release_override: Option<ReleaseOverride>,
+}
+
+/// Why a pkgbase was selected for release or not.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub enum SelectionReason {
+    /// The [`ReleaseStrategy::All`] strategy was used
+    #[default]
+    ReleaseAll,
+    ManualOverride,
+    OriginChangeset,
+    NotInTargetRepository,
+    FilesChanged,
+    LinksBumpedSoname {
+        soname: String,
+    },
+    Unchanged,
+}
+
+impl SelectionReason {
+    pub fn short_description(&self) -> String {
+        match self {
+            SelectionReason::ReleaseAll => "releasing all packages".to_string(),
+            SelectionReason::ManualOverride => "manual override".to_string(),
+            SelectionReason::OriginChangeset => "origin changeset".to_string(),
+            SelectionReason::NotInTargetRepository => "not in target repository".to_string(),
+            SelectionReason::FilesChanged => "files changed".to_string(),
+            SelectionReason::LinksBumpedSoname { soname } => format!("links to bumped {soname}"),
+            SelectionReason::Unchanged => "unchanged".to_string(),
+        }
+    }
+}
+
+/// Metadata of a single package file, read from its `.PKGINFO` and `.MTREE`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageFileMetadata {
+    pub pkgname: String,
+    pub provides: Vec<String>,
+    pub depends: Vec<String>,
+    /// Path of each file in the package, mapped to its SHA-256 digest,
+    /// or the link target for symlinks.
+    pub file_digests: BTreeMap<String, String>,
+}
+
+impl PackageFileMetadata {
+    /// Sonames provided by this package, mapped to their version,
+    /// e.g. `libssl.so` => `3-64`.
+    /// `makepkg` adds these automatically for libraries listed in `provides`.
+    pub fn provided_sonames(&self) -> BTreeMap<&str, &str> {
+        self.provides
+            .iter()
+            .filter_map(|provide| provide.split_once('='))
+            .filter(|(name, _)| is_soname(name))
+            .collect()
+    }
+
+    /// Sonames this package links to, without their version.
+    pub fn linked_sonames(&self) -> BTreeSet<&str> {
+        self.depends
+            .iter()
+            .map(|depend| {
+                depend
+                    .split_once('=')
+                    .map_or(depend.as_str(), |(name, _)| name)
+            })
+            .filter(|name| is_soname(name))
+            .collect()
+    }
+}
+
+fn is_soname(name: &str) -> bool {
+    name.ends_with(".so") || name.contains(".so.")
+}
+
+/// A built pkgbase that could be released.
+#[derive(Debug, Clone)]
+pub struct SelectionCandidate {
+    pub pkgbase: Pkgbase,
+    pub is_origin_changeset: bool,
+    /// Metadata of the built package files, one for each split package.
+    pub built: Vec<PackageFileMetadata>,
+    /// Metadata of the package files with the same pkgnames
+    /// that are currently in the target repository.
+    pub released: Vec<PackageFileMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PackageSelection {
+    pub pkgbase: Pkgbase,
+    pub selected: bool,
+    pub reason: SelectionReason,
+}
+
+/// Decide which candidates to release.
+/// `candidates` have to be in topological order, so dependencies are
+/// decided on before their dependents.
+pub fn select_packages(
+    strategy: ReleaseStrategy,
+    candidates: &[SelectionCandidate],
+    overrides: &BTreeMap<Pkgbase, ReleaseOverride>,
+) -> Vec<PackageSelection> {
+    // Sonames whose version changes in the target repository
+    // with the packages we selected so far.
+    let mut bumped_sonames: BTreeSet<String> = BTreeSet::new();
+
+    let mut selections = Vec::new();
+    for candidate in candidates {
+        let (selected, reason) = if let Some(release_override) = overrides.get(&candidate.pkgbase) {
+            (
+                *release_override == ReleaseOverride::Include,
+                SelectionReason::ManualOverride,
+            )
+        } else {
+            match strategy {
+                ReleaseStrategy::All => (true, SelectionReason::ReleaseAll),
+                ReleaseStrategy::Changed => match change_reason(candidate, &bumped_sonames) {
+                    Some(reason) => (true, reason),
+                    None => (false, SelectionReason::Unchanged),
+                },
+            }
+        };
+
+        if selected {
+            bumped_sonames.extend(bumped_sonames_of(candidate));
+        }
+        selections.push(PackageSelection {
+            pkgbase: candidate.pkgbase.clone(),
+            selected,
+            reason,
+        });
+    }
+
+    selections
+}
+
+fn change_reason(
+    candidate: &SelectionCandidate,
+    bumped_sonames: &BTreeSet<String>,
+) -> Option<SelectionReason> {
+    if candidate.is_origin_changeset {
+        return Some(SelectionReason::OriginChangeset);
+    }
+
+    let released_by_name: HashMap<&str, &PackageFileMetadata> = candidate
+        .released
+        .iter()
+        .map(|package| (package.pkgname.as_str(), package))
+        .collect();
+    let mut files_changed = false;
+    for built in &candidate.built {
+        match released_by_name.get(built.pkgname.as_str()) {
+            None => return Some(SelectionReason::NotInTargetRepository),
+            Some(released) => files_changed |= released.file_digests != built.file_digests,
+        }
+    }
+    if files_changed {
+        return Some(SelectionReason::FilesChanged);
+    }
+
+    candidate
+        .built
+        .iter()
+        .flat_map(|built| built.linked_sonames())
+        .find(|soname| bumped_sonames.contains(*soname))
+        .map(|soname| SelectionReason::LinksBumpedSoname {
+            soname: soname.to_string(),
+        })
+}
+
+/// Sonames provided by the released packages of the candidate
+/// that are removed or have a different version in the built packages.
+fn bumped_sonames_of(candidate: &SelectionCandidate) -> Vec<String> {
+    let built: BTreeMap<&str, &str> = candidate
+        .built
+        .iter()
+        .flat_map(|package| package.provided_sonames())
+        .collect();
+    candidate
+        .released
+        .iter()
+        .flat_map(|package| package.provided_sonames())
+        .filter(|(name, version)| built.get(name) != Some(version))
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// Parse the `.PKGINFO` file of a package, ignoring fields we don't need.
+pub fn parse_pkginfo(pkginfo: &str) -> PackageFileMetadata {
+    let mut metadata = PackageFileMetadata::default();
+    for line in pkginfo.lines() {
+        let Some((key, value)) = line.split_once(" = ") else {
+            continue;
+        };
+        match key {
+            "pkgname" => metadata.pkgname = value.to_string(),
+            "provides" => metadata.provides.push(value.to_string()),
+            "depend" => metadata.depends.push(value.to_string()),
+            _ => {}
+        }
+    }
+    metadata
+}
+
+/// Parse the (decompressed) `.MTREE` file of a package into a map of file paths to
+/// their digests. Only digests and link targets are compared, so timestamps
+/// and other file attributes are ignored, and so are files that change with
+/// every build, see [`is_volatile_path`].
+pub fn parse_mtree_digests(mtree: &str) -> BTreeMap<String, String> {
+    mtree
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with('/'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let path = fields.next()?;
+            if is_volatile_path(path) {
+                return None;
+            }
+            let digest = fields.find_map(|field| {
+                field
+                    .strip_prefix("sha256digest=")
+                    .or_else(|| field.strip_prefix("link="))
+            })?;
+            Some((path.to_string(), digest.to_string()))
+        })
+        .collect()
+}
+
+/// Whether a file in a package differs between builds of the same sources,
+/// because it contains the build time or the build directory.
+/// These would make every rebuild look like a change.
+fn is_volatile_path(path: &str) -> bool {
+    // Package metadata contains the build date and build directory
+    matches!(path, "./.BUILDINFO" | "./.PKGINFO")
+        // Detached debug info and sources contain the build directory
+        || path.starts_with("./usr/lib/debug/")
+        || path.starts_with("./usr/src/debug/")
+        // Python bytecode contains the modification time of its source
+        || path.ends_with(".pyc")
+        // Perl module install logs contain the build time and build directory
+        || path.ends_with("/perllocal.pod")
+        || path.ends_with("/.packlist")
+}
+
+/// Parse the concatenated `desc` files of a repository database
+/// into a map of pkgnames to package file names.
+pub fn parse_repo_db_descs(descs: &str) -> HashMap<String, String> {
+    let mut packages = HashMap::new();
+    let mut file_name = None;
+    let mut lines = descs.lines();
+    while let Some(line) = lines.next() {
+        match line {
+            "%FILENAME%" => file_name = lines.next().map(str::to_string),
+            "%NAME%" => {
+                if let (Some(name), Some(file_name)) = (lines.next(), file_name.take()) {
+                    packages.insert(name.to_string(), file_name);
+                }
+            }
+            _ => {}
+        }
+    }
+    packages
+}
+
+/// Read the metadata of a package file using `bsdtar`.
+pub async fn read_package_file_metadata(path: &Utf8Path) -> Result<PackageFileMetadata> {
+    let pkginfo = extract_from_archive(path, ".PKGINFO").await?;
+    let mut metadata = parse_pkginfo(&String::from_utf8_lossy(&pkginfo));
+
+    let mtree = gunzip(&extract_from_archive(path, ".MTREE").await?).await?;
+    metadata.file_digests = parse_mtree_digests(&String::from_utf8_lossy(&mtree));
+
+    Ok(metadata)
+}
+
+/// Map the pkgnames in a repository database to the names of their package files.
+/// Returns an empty map if the database doesn't exist yet.
+pub async fn read_repo_db_packages(db_path: &Utf8Path) -> Result<HashMap<String, String>> {
+    if !tokio::fs::try_exists(db_path).await? {
+        return Ok(HashMap::new());
+    }
+    let descs = extract_from_archive(db_path, "*/desc").await?;
+    Ok(parse_repo_db_descs(&String::from_utf8_lossy(&descs)))
+}
+
+async fn extract_from_archive(archive: &Utf8Path, pattern: &str) -> Result<Vec<u8>> {
+    let output = Command::new("bsdtar")
+        .arg("-xOf")
+        .arg(archive)
+        .arg(pattern)
+        .output()
+        .await
+        .wrap_err("Failed to run bsdtar")?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Failed to extract {pattern} from {archive}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output.stdout)
+}
+
+async fn gunzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new("gzip")
+        .arg("-dc")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .wrap_err("Failed to run gzip")?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| eyre!("Failed to open gzip stdin"))?;
+    stdin.write_all(data).await?;
+    drop(stdin);
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(eyre!("Failed to decompress with gzip"));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn package(
+        pkgname: &str,
+        provides: &[&str],
+        depends: &[&str],
+        digest: &str,
+    ) -> PackageFileMetadata {
+        PackageFileMetadata {
+            pkgname: pkgname.to_string(),
+            provides: provides.iter().map(|p| p.to_string()).collect(),
+            depends: depends.iter().map(|d| d.to_string()).collect(),
+            file_digests: BTreeMap::from([(format!("./usr/lib/{pkgname}"), digest.to_string())]),
+        }
+    }
+
+    fn candidate(
+        pkgbase: &str,
+        is_origin_changeset: bool,
+        built: PackageFileMetadata,
+        released: Option<PackageFileMetadata>,
+    ) -> SelectionCandidate {
+        SelectionCandidate {
+            pkgbase: pkgbase.to_string().into(),
+            is_origin_changeset,
+            built: vec![built],
+            released: released.into_iter().collect(),
+        }
+    }
+
+    #[rstest]
+    fn test_select_packages() {
+        let candidates = [
+            candidate(
+                "openssl",
+                true,
+                package("openssl", &["libssl.so=4-64"], &[], "new"),
+                Some(package("openssl", &["libssl.so=3-64"], &[], "old")),
+            ),
+            candidate(
+                "curl",
+                false,
+                package("curl", &[], &["libssl.so=4-64"], "same"),
+                Some(package("curl", &[], &["libssl.so=3-64"], "same")),
+            ),
+            candidate(
+                "gzip",
+                false,
+                package("gzip", &[], &[], "same"),
+                Some(package("gzip", &[], &[], "same")),
+            ),
+            candidate(
+                "python",
+                false,
+                package("python", &[], &[], "new"),
+                Some(package("python", &[], &[], "old")),
+            ),
+            candidate(
+                "new-package",
+                false,
+                package("new-package", &[], &[], "new"),
+                None,
+            ),
+        ];
+        let overrides = BTreeMap::from([("python".to_string().into(), ReleaseOverride::Exclude)]);
+
+        let selections = select_packages(ReleaseStrategy::Changed, &candidates, &overrides);
+        let reasons: Vec<_> = selections
+            .iter()
+            .map(|selection| (selection.selected, selection.reason.clone()))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (true, SelectionReason::OriginChangeset),
+                (
+                    true,
+                    SelectionReason::LinksBumpedSoname {
+                        soname: "libssl.so".to_string()
+                    }
+                ),
+                (false, SelectionReason::Unchanged),
+                (false, SelectionReason::ManualOverride),
+                (true, SelectionReason::NotInTargetRepository),
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_rebuilt_packages_compare_equal() {
+        let mtree = |time: &str, buildinfo: &str, pyc: &str| {
+            format!(
+                "#mtree\n\
+                 /set type=file uid=0 gid=0 mode=644\n\
+                 ./.BUILDINFO time={time} size=10 sha256digest={buildinfo}\n\
+                 ./.PKGINFO time={time} size=10 sha256digest={buildinfo}\n\
+                 ./usr time={time} mode=755 type=dir\n\
+                 ./usr/bin/foo time={time} mode=755 size=10 sha256digest=bbb\n\
+                 ./usr/lib/python3.13/site-packages/__pycache__/foo.cpython-313.pyc time={time} size=10 sha256digest={pyc}\n\
+                 ./usr/lib/perl5/5.40/core_perl/perllocal.pod time={time} size=10 sha256digest={pyc}\n"
+            )
+        };
+        let built = |mtree: &str| PackageFileMetadata {
+            pkgname: "foo".to_string(),
+            file_digests: parse_mtree_digests(mtree),
+            ..Default::default()
+        };
+        let released = built(&mtree("1700000000.0", "aaa", "ccc"));
+        let rebuilt = built(&mtree("1750000000.0", "ddd", "eee"));
+        assert_eq!(released, rebuilt);
+
+        let candidate = candidate("foo", false, rebuilt, Some(released));
+        assert_eq!(change_reason(&candidate, &BTreeSet::new()), None);
+    }
+
+    #[rstest]
+    fn test_parse_package_metadata() {
+        let pkginfo = "# Generated by makepkg\npkgname = curl\npkgver = 8.14.1-1\nprovides = libcurl.so=4-64\ndepend = openssl\ndepend = libssl.so=3-64\n";
+        let metadata = parse_pkginfo(pkginfo);
+        assert_eq!(metadata.pkgname, "curl");
+        assert_eq!(
+            metadata.provided_sonames(),
+            BTreeMap::from([("libcurl.so", "4-64")])
+        );
+        assert_eq!(metadata.linked_sonames(), BTreeSet::from(["libssl.so"]));
+
+        let mtree = "#mtree\n/set type=file uid=0 gid=0 mode=644\n./.BUILDINFO time=1.0 size=10 sha256digest=aaa\n./usr time=1.0 mode=755 type=dir\n./usr/bin/curl time=1.0 mode=755 size=10 sha256digest=bbb\n./usr/lib/libcurl.so time=1.0 type=link link=libcurl.so.4\n";
+        assert_eq!(
+            parse_mtree_digests(mtree),
+            BTreeMap::from([
+                ("./usr/bin/curl".to_string(), "bbb".to_string()),
+                (
+                    "./usr/lib/libcurl.so".to_string(),
+                    "libcurl.so.4".to_string()
+                ),
+            ])
+        );
+
+        let descs = "%FILENAME%\ncurl-8.14.1-1-x86_64.pkg.tar.zst\n\n%NAME%\ncurl\n\n%BASE%\ncurl\n%FILENAME%\nlibcurl-compat-8.14.1-1-x86_64.pkg.tar.zst\n\n%NAME%\nlibcurl-compat\n";
+        assert_eq!(
+            parse_repo_db_descs(descs),
+            HashMap::from([
+                (
+                    "curl".to_string(),
+                    "curl-8.14.1-1-x86_64.pkg.tar.zst".to_string()
+                ),
+                (
+                    "libcurl-compat".to_string(),
+                    "libcurl-compat-8.14.1-1-x86_64.pkg.tar.zst".to_string()
+                ),
+            ])
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Create detached signatures for package files and repository databases.
+//!
+//! Eventually, signing will happen outside of the builder VMs via Signstar.
+//! To be able to swap out the way signatures are created, signing goes
+//! through the [`Signer`] trait. Currently, there are two backends:
+//!
+//! - [`GpgSigner`] uses a GnuPG keyring on the server.
+//! - [`HttpSigner`] sends files to a generic HTTP signing service.
+
+use std::future::Future;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tokio_util::codec::{BytesCodec, FramedRead};
+use url::Url;
+
+/// Something that can create detached OpenPGP signatures.
+pub trait Signer {
+    /// Create a binary detached signature for the file at `path`.
+    fn sign_detached(&self, path: &Utf8Path) -> impl Future<Output = Result<Vec<u8>>> + Send;
+}
+
+/// Sign files using the local GnuPG installation.
+#[derive(Debug, Clone)]
+pub struct GpgSigner {
+    /// Key ID or fingerprint to sign with. If omitted, gpg uses its default key.
+    pub key: Option<String>,
+    /// Use this directory instead of `~/.gnupg` as the keyring.
+    pub homedir: Option<Utf8PathBuf>,
+}
+
+impl Signer for GpgSigner {
+    async fn sign_detached(&self, path: &Utf8Path) -> Result<Vec<u8>> {
+        let mut cmd = Command::new("gpg");
+        cmd.args([
+            "--batch",
+            "--yes",
+            "--no-armor",
+            "--detach-sign",
+            "--output",
+            "-",
+        ]);
+        if let Some(homedir) = &self.homedir {
+            cmd.arg("--homedir").arg(homedir);
+        }
+        if let Some(key) = &self.key {
+            cmd.arg("--local-user").arg(key);
+        }
+        cmd.arg(path);
+
+        tracing::debug!("{cmd:?}");
+        let output = cmd.output().await.wrap_err("Failed to run gpg")?;
+        if !output.status.success() {
+            bail!(
+                "gpg failed to sign {path}: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(output.stdout)
+    }
+}
+
+/// Sign files by sending them to a HTTP signing service.
+///
+/// The file contents are sent as the body of a `POST` request to `url`.
+/// The service is expected to respond with the binary detached signature
+/// as the response body.
+/// If a token is given, it is sent as a bearer token.
+#[derive(Debug, Clone)]
+pub struct HttpSigner {
+    pub url: Url,
+    pub token: Option<redact::Secret<String>>,
+    pub client: reqwest::Client,
+}
+
+impl HttpSigner {
+    pub fn new(url: Url, token: Option<redact::Secret<String>>) -> Self {
+        HttpSigner {
+            url,
+            token,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl Signer for HttpSigner {
+    async fn sign_detached(&self, path: &Utf8Path) -> Result<Vec<u8>> {
+        // Stream the file instead of reading it into memory,
+        // packages can be large.
+        let file = tokio::fs::File::open(path)
+            .await
+            .wrap_err_with(|| format!("Failed to open {path}"))?;
+        let body = reqwest::Body::wrap_stream(FramedRead::new(file, BytesCodec::new()));
+
+        let mut request = self
+            .client
+            .post(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .body(body);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token.expose_secret());
+        }
+
+        let signature = request
+            .send()
+            .await
+            .wrap_err("Failed to send file to signing service")?
+            .error_for_status()
+            .wrap_err("Signing service returned an error")?
+            .bytes()
+            .await?;
+
+        if signature.is_empty() {
+            bail!("Signing service returned an empty signature for {path}");
+        }
+
+        Ok(signature.to_vec())
+    }
+}
+
+/// All available signing backends, for choosing one at runtime.
+#[derive(Debug, Clone)]
+pub enum SignerBackend {
+    Gpg(GpgSigner),
+    Http(HttpSigner),
+}
+
+impl Signer for SignerBackend {
+    async fn sign_detached(&self, path: &Utf8Path) -> Result<Vec<u8>> {
+        match self {
+            SignerBackend::Gpg(signer) => signer.sign_detached(path).await,
+            SignerBackend::Http(signer) => signer.sign_detached(path).await,
+        }
+    }
+}
+
+/// Signing progress of a single package file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+pub enum SigningStatus {
+    /// The package was uploaded, but not signed yet
+    Pending,
+    /// A detached signature was created next to the package file
+    Signed,
+    /// Signing failed, the package was not added to the repository
+    Failed,
+}
+
+impl SigningStatus {
+    pub fn as_icon(&self) -> &'static str {
+        match self {
+            Self::Pending => "🕑",
+            Self::Signed => "🔏",
+            Self::Failed => "❌",
+        }
+    }
+}
+
+/// Path of the detached signature pacman expects next to the given file.
+pub fn signature_path(path: &Utf8Path) -> Utf8PathBuf {
+    format!("{path}.sig").into()
+}
+
+/// Sign the file at `path` and write the detached signature next to it.
+/// Returns the path to the signature file.
+pub async fn sign_file(signer: &impl Signer, path: &Utf8Path) -> Result<Utf8PathBuf> {
+    let signature = signer
+        .sign_detached(path)
+        .await
+        .wrap_err_with(|| format!("Failed to sign {path}"))?;
+    let signature_path = signature_path(path);
+    tokio::fs::write(&signature_path, signature).await?;
+
+    Ok(signature_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Bytes, routing::post};
+
+    /// Stand-in for a signing service that "signs" files by reversing their contents.
+    async fn start_signing_service() -> Url {
+        let app = Router::new().route(
+            "/sign",
+            post(|body: Bytes| async move { body.iter().rev().copied().collect::<Vec<u8>>() }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Url::parse(&format!("http://{addr}/sign")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_http_signer_writes_detached_signature() {
+        let url = start_signing_service().await;
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let package_path = dir.join("gzip-1.13-2-x86_64.pkg.tar.zst");
+        tokio::fs::write(&package_path, b"package").await.unwrap();
+
+        let signer = SignerBackend::Http(HttpSigner::new(url, None));
+        let signature_path = sign_file(&signer, &package_path).await.unwrap();
+
+        assert_eq!(
+            signature_path,
+            dir.join("gzip-1.13-2-x86_64.pkg.tar.zst.sig")
+        );
+        assert_eq!(tokio::fs::read(signature_path).await.unwrap(), b"egakcap");
+    }
+}
@@ -1,6 +1,6 @@
 use alpm_srcinfo::{MergedPackage, SourceInfoV1, source_info::v1::package::Package};
 use alpm_types::Architecture;
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use color_eyre::Result;
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumString};
@@ -123,11 +123,30 @@ pub fn package_for_architecture(
         .find(|p| p.name.as_ref() == pkgname)
 }
 
+/// File extensions `makepkg` may use for package files,
+/// depending on the configured `PKGEXT` (see makepkg.conf(5)).
+pub const PACKAGE_FILE_EXTENSIONS: &[&str] = &[
+    ".pkg.tar",
+    ".pkg.tar.gz",
+    ".pkg.tar.bz2",
+    ".pkg.tar.xz",
+    ".pkg.tar.zst",
+    ".pkg.tar.lzo",
+    ".pkg.tar.lrz",
+    ".pkg.tar.lz4",
+    ".pkg.tar.lz",
+    ".pkg.tar.Z",
+];
+
 /// Take a split package for a specific architecture and predict the
-/// name of the package file `makepkg` will generate.
+/// name of the package file `makepkg` will generate, without the
+/// file extension, e.g. `gzip-1.13-2-x86_64`.
+/// The extension depends on the compression `makepkg` was configured
+/// with, so callers need to look for files with any of the
+/// [`PACKAGE_FILE_EXTENSIONS`] instead.
 /// Additionally takes a [SourceInfo] struct to find out if the package
 /// is for the `any` architecture.
-pub fn package_file_name(
+pub fn package_file_stem(
     MergedPackage {
         name,
         package_version,
@@ -137,7 +156,7 @@ pub fn package_file_name(
         ..
     }: &MergedPackage,
     srcinfo: &SourceInfo,
-) -> Result<Utf8PathBuf> {
+) -> String {
     // Find the architectures of this split package by checking the split package overrides and taking the base architectures as a fallback.
     let package_architectures = srcinfo
         .packages
@@ -147,27 +166,104 @@ pub fn package_file_name(
         .unwrap_or(&srcinfo.base.architectures);
     // The architecture from MergedPackage reflects the architecture of the whole build graph.
     // But for "any" packages, the filename will instead contain "any", even though the build graph will be for a [`ConcreteArchictecture`].
+    // Note: Don't use `ConcreteArchitecture` to determine the architecture in the filename as the filename will contain `any` instead of the concrete architecture
     let actual_architecture = if package_architectures.contains(&Architecture::Any) {
         &Architecture::Any
     } else {
         architecture
     };
-    // TODO: make it work for all compression formats
-    // We'll probably have to pass in a directory to search for package files
-    // here, similar to `find_cached_package` in devtools
-    // (parsing makepkg output seems like an ugly alternative)
-    // Note: Don't use `ConcreteArchitecture` to determine the architecture in the filename as the filename will contain `any` instead of the concrete architecture
     let version = alpm_types::Version::new(
         package_version.clone(),
         *epoch,
         Some(package_release.clone()),
     );
-    Ok(alpm_types::PackageFileName::new(
-        name.clone(),
-        version,
-        *actual_architecture,
-        Some(alpm_types::CompressionAlgorithmFileExtension::Zstd),
-    )?
-    .to_string()
-    .into())
+    format!("{name}-{version}-{actual_architecture}")
+}
+
+/// If `file_name` ends with one of the [`PACKAGE_FILE_EXTENSIONS`],
+/// return the file name without that extension.
+pub fn strip_package_file_extension(file_name: &str) -> Option<&str> {
+    PACKAGE_FILE_EXTENSIONS
+        .iter()
+        .find_map(|extension| file_name.strip_suffix(extension))
+}
+
+/// Find the split package of `srcinfo` that `makepkg` writes to a file called
+/// `file_name` when building for the given architecture, regardless of the compression used.
+pub fn package_for_file_name(
+    srcinfo: &SourceInfo,
+    architecture: ConcreteArchitecture,
+    file_name: &str,
+) -> Option<MergedPackage> {
+    let stem = strip_package_file_extension(file_name)?;
+    srcinfo
+        .packages_for_architecture(*architecture.as_ref())
+        .find(|package| package_file_stem(package, srcinfo) == stem)
+}
+
+/// Predict the name of the debug package file `makepkg` generates for a pkgbase
+/// when the `debug` option is enabled, without the file extension,
+/// e.g. `gzip-debug-1.13-2-x86_64`.
+/// Debug packages are named after the pkgbase and use its version.
+/// As they only contain symbols of binaries, they're never built for the `any` architecture.
+pub fn debug_package_file_stem(srcinfo: &SourceInfo, architecture: ConcreteArchitecture) -> String {
+    format!(
+        "{pkgbase}-debug-{version}-{architecture}",
+        pkgbase = srcinfo.base.name,
+        version = base_version(srcinfo),
+    )
+}
+
+/// Full version of the pkgbase, including epoch and pkgrel.
+pub fn base_version(srcinfo: &SourceInfo) -> alpm_types::Version {
+    alpm_types::Version::new(
+        srcinfo.base.package_version.clone(),
+        srcinfo.base.epoch,
+        Some(srcinfo.base.package_release.clone()),
+    )
+}
+
+/// Check whether `file_name` is the debug package `makepkg` generates
+/// for `srcinfo` when building for the given architecture, regardless of the compression used.
+pub fn is_debug_package_file(
+    srcinfo: &SourceInfo,
+    architecture: ConcreteArchitecture,
+    file_name: &str,
+) -> bool {
+    strip_package_file_extension(file_name)
+        == Some(debug_package_file_stem(srcinfo, architecture).as_str())
+}
+
+/// Look for a package file with the given stem (see [`package_file_stem`]
+/// and [`debug_package_file_stem`]) in `dir`, similar to `find_cached_package` in devtools.
+pub async fn find_package_file(dir: &Utf8Path, stem: &str) -> Result<Option<Utf8PathBuf>> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let Ok(file_name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if strip_package_file_extension(&file_name) == Some(stem) {
+            return Ok(Some(dir.join(file_name)));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("gzip-1.13-2-x86_64.pkg.tar.zst", Some("gzip-1.13-2-x86_64"))]
+    #[case("gzip-1.13-2-x86_64.pkg.tar.xz", Some("gzip-1.13-2-x86_64"))]
+    #[case("gzip-1.13-2-x86_64.pkg.tar.lz4", Some("gzip-1.13-2-x86_64"))]
+    #[case("gzip-1.13-2-x86_64.pkg.tar", Some("gzip-1.13-2-x86_64"))]
+    #[case("gzip-1:1.13-2-any.pkg.tar.gz", Some("gzip-1:1.13-2-any"))]
+    #[case("gzip-1.13-2-x86_64.pkg.tar.zst.sig", None)]
+    #[case("gzip-1.13.tar.gz", None)]
+    fn test_strip_package_file_extension(#[case] input: &str, #[case] expected: Option<&str>) {
+        assert_eq!(strip_package_file_extension(input), expected);
+    }
 }
@@ -0,0 +1,518 @@
+//! Where package source repositories come from, and how changes to them are discovered.
+//!
+//! Source repositories are cloned from a [`PackagingRemote`], which can be any git
+//! remote reachable via HTTPS, SSH or `file://` URLs. How changes are discovered
+//! depends on the [`SourceProvider`]:
+//!
+//! - [`GitlabSourceProvider`] asks the gitlab API which projects were updated.
+//! - [`GitSourceProvider`] works with any git remote, e.g. forks or local mirrors,
+//!   by comparing the heads listed by `git ls-remote` with the local clones.
+//!
+//! Source repositories of archived or removed projects stay on disk, but are
+//! reported as [retired](RetiredReason) so they can be left out of build graphs.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::{Context, Result};
+use gitlab::AsyncGitlab;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::task::{JoinSet, spawn_blocking};
+
+use crate::{
+    Pkgbase,
+    git::{
+        PackagingRemote, clone_or_fetch_repository, has_new_remote_heads, list_local_remote_heads,
+        list_remote_heads, package_source_path,
+    },
+    gitlab::{fetch_all_source_repo_changes, get_changed_projects_since},
+};
+
+/// Why a source repository isn't considered for builds anymore.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum RetiredReason {
+    /// The project was archived on the forge.
+    Archived,
+    /// The repository doesn't exist on the remote anymore.
+    Removed,
+}
+
+/// A source repository that is excluded from build graphs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RetiredSourceRepo {
+    pub pkgbase: Pkgbase,
+    pub reason: RetiredReason,
+    pub retired_at: OffsetDateTime,
+}
+
+/// What [`SourceProvider::fetch_changes`] found out about source repositories.
+#[derive(Debug, Default)]
+pub struct SourceRepoChanges {
+    /// Time to pass as `last_fetched` on the next call, for providers that keep track of it.
+    pub last_fetched: Option<OffsetDateTime>,
+    /// Source repositories that are known to be in use, e.g. because they changed.
+    pub active: Vec<Pkgbase>,
+    /// Source repositories that were cloned or fetched, so their branches might have moved.
+    pub fetched: Vec<Pkgbase>,
+    /// Source repositories that shouldn't be built anymore.
+    pub retired: Vec<(Pkgbase, RetiredReason)>,
+}
+
+/// Something that can discover and fetch changes to package source repositories.
+pub trait SourceProvider {
+    fn remote(&self) -> &PackagingRemote;
+
+    /// Clone or fetch all source repositories that changed since the last call.
+    /// `last_fetched` is the time returned by the previous call, for providers
+    /// that keep track of it. Providers comparing against the local clones return `None`.
+    fn fetch_changes(
+        &self,
+        last_fetched: Option<OffsetDateTime>,
+    ) -> impl Future<Output = Result<SourceRepoChanges>> + Send;
+}
+
+/// Counts how many times in a row each repository couldn't be found on the remote.
+///
+/// Servers may respond with "not found" during outages or when they're
+/// misconfigured, so a repository is only reported as removed if it
+/// couldn't be found several times in a row.
+#[derive(Default)]
+struct NotFoundCounter {
+    counts: Mutex<HashMap<Pkgbase, u32>>,
+}
+
+impl NotFoundCounter {
+    const THRESHOLD: u32 = 3;
+
+    /// Record which repositories were found on the remote and which weren't,
+    /// and return those that weren't found often enough to be considered removed.
+    fn confirm_removed(&self, found: &[Pkgbase], not_found: Vec<Pkgbase>) -> Vec<Pkgbase> {
+        let mut counts = self.counts.lock().expect("Poisoned mutex");
+        for pkgbase in found {
+            counts.remove(pkgbase);
+        }
+        not_found
+            .into_iter()
+            .filter(|pkgbase| {
+                let count = counts.entry(pkgbase.clone()).or_default();
+                *count += 1;
+                if *count < Self::THRESHOLD {
+                    tracing::warn!(
+                        "{pkgbase} not found on the remote ({count}/{} times)",
+                        Self::THRESHOLD
+                    );
+                }
+                *count >= Self::THRESHOLD
+            })
+            .collect()
+    }
+}
+
+/// Discover changes via the gitlab API, see [`fetch_all_source_repo_changes`].
+///
+/// Only changed projects are queried usually, so projects removed from the
+/// packages group are detected by listing all projects once a day.
+/// Like with [`GitSourceProvider`], projects are only reported as removed
+/// if they were missing from several listings in a row.
+pub struct GitlabSourceProvider {
+    client: AsyncGitlab,
+    packages_group: String,
+    remote: PackagingRemote,
+    last_reconciliation: Mutex<Option<Instant>>,
+    not_found: NotFoundCounter,
+}
+
+impl GitlabSourceProvider {
+    const RECONCILIATION_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+
+    pub fn new(client: AsyncGitlab, packages_group: String, remote: PackagingRemote) -> Self {
+        GitlabSourceProvider {
+            client,
+            packages_group,
+            remote,
+            last_reconciliation: Mutex::new(None),
+            not_found: NotFoundCounter::default(),
+        }
+    }
+
+    /// Check whether the reconciliation interval has passed since the last successful one.
+    fn reconciliation_due(&self) -> bool {
+        let last = self.last_reconciliation.lock().expect("Poisoned mutex");
+        last.is_none_or(|last| last.elapsed() >= Self::RECONCILIATION_INTERVAL)
+    }
+
+    fn finish_reconciliation(&self) {
+        *self.last_reconciliation.lock().expect("Poisoned mutex") = Some(Instant::now());
+    }
+
+    /// Compare all projects of the packages group with the local clones
+    /// to find those that were archived or removed.
+    async fn find_retired_projects(&self) -> Result<Vec<(Pkgbase, RetiredReason)>> {
+        let projects = get_changed_projects_since(&self.client, None, &self.packages_group).await?;
+        let mut retired = Vec::new();
+        let mut existing = HashSet::new();
+        for project in projects {
+            let pkgbase: Pkgbase = project.name.into();
+            if project.archived == Some(true) {
+                retired.push((pkgbase.clone(), RetiredReason::Archived));
+            }
+            existing.insert(pkgbase);
+        }
+        let (found, not_found) = list_cloned_pkgbases()
+            .await?
+            .into_iter()
+            .partition::<Vec<_>, _>(|pkgbase| existing.contains(pkgbase));
+        retired.extend(
+            self.not_found
+                .confirm_removed(&found, not_found)
+                .into_iter()
+                .map(|pkgbase| (pkgbase, RetiredReason::Removed)),
+        );
+
+        Ok(retired)
+    }
+}
+
+impl SourceProvider for GitlabSourceProvider {
+    fn remote(&self) -> &PackagingRemote {
+        &self.remote
+    }
+
+    async fn fetch_changes(
+        &self,
+        last_fetched: Option<OffsetDateTime>,
+    ) -> Result<SourceRepoChanges> {
+        let mut changes = fetch_all_source_repo_changes(
+            &self.client,
+            last_fetched,
+            self.remote.clone(),
+            self.packages_group.clone(),
+        )
+        .await?;
+        if self.reconciliation_due() {
+            changes.retired.extend(self.find_retired_projects().await?);
+            self.finish_reconciliation();
+        }
+
+        Ok(changes)
+    }
+}
+
+/// Discover changes by comparing the branch heads of each remote repository
+/// with the remote-tracking branches of its local clone.
+///
+/// As plain git remotes can't be asked for a list of repositories, this checks
+/// the given pkgbases as well as all repositories that were cloned before.
+/// Repositories that can't be found on the remote several times in a row
+/// are reported as removed.
+pub struct GitSourceProvider {
+    pkgbases: Vec<Pkgbase>,
+    remote: PackagingRemote,
+    not_found: NotFoundCounter,
+}
+
+impl GitSourceProvider {
+    pub fn new(pkgbases: Vec<Pkgbase>, remote: PackagingRemote) -> Self {
+        GitSourceProvider {
+            pkgbases,
+            remote,
+            not_found: NotFoundCounter::default(),
+        }
+    }
+
+    /// Read pkgbases to track from a file, one per line.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub async fn read_pkgbases_file(path: &Utf8Path) -> Result<Vec<Pkgbase>> {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .wrap_err_with(|| format!("Failed to read {path}"))?;
+        Ok(parse_pkgbases(&contents))
+    }
+}
+
+impl SourceProvider for GitSourceProvider {
+    fn remote(&self) -> &PackagingRemote {
+        &self.remote
+    }
+
+    async fn fetch_changes(
+        &self,
+        _last_fetched: Option<OffsetDateTime>,
+    ) -> Result<SourceRepoChanges> {
+        let mut pkgbases: BTreeSet<Pkgbase> = self.pkgbases.iter().cloned().collect();
+        pkgbases.extend(list_cloned_pkgbases().await?);
+        tracing::info!("Checking {} source repos for changes", pkgbases.len());
+
+        let mut join_set = JoinSet::new();
+        let mut changes = SourceRepoChanges::default();
+        let mut changed = Vec::new();
+        let mut not_found = Vec::new();
+        for pkgbase in pkgbases {
+            let remote = self.remote.clone();
+            join_set.spawn_blocking(move || {
+                let state = remote_repository_state(&pkgbase, &remote);
+                (pkgbase, state)
+            });
+            while join_set.len() >= 50 {
+                let output = join_set.join_next().await.unwrap()?;
+                collect_state(output, &mut changes, &mut changed, &mut not_found);
+            }
+        }
+        while let Some(output) = join_set.join_next().await {
+            collect_state(output?, &mut changes, &mut changed, &mut not_found);
+        }
+        let removed = self.not_found.confirm_removed(&changes.active, not_found);
+        changes.retired.extend(
+            removed
+                .into_iter()
+                .map(|pkgbase| (pkgbase, RetiredReason::Removed)),
+        );
+
+        if !changed.is_empty() {
+            tracing::info!(
+                "{} changed source repos found (first: {:?})",
+                changed.len(),
+                changed.first()
+            );
+        }
+        // Report repositories that were fetched even if others fail,
+        // as they won't show up as changed on the next call.
+        let mut join_set = JoinSet::new();
+        for pkgbase in changed {
+            let remote = self.remote.clone();
+            join_set.spawn(async move {
+                let result = clone_or_fetch_repository(pkgbase.clone(), remote).await;
+                (pkgbase, result)
+            });
+            while join_set.len() >= 50 {
+                collect_fetched(join_set.join_next().await.unwrap()?, &mut changes);
+            }
+        }
+        while let Some(output) = join_set.join_next().await {
+            collect_fetched(output?, &mut changes);
+        }
+
+        Ok(changes)
+    }
+}
+
+/// All available source providers, for choosing one at runtime.
+pub enum SourceProviderBackend {
+    Gitlab(GitlabSourceProvider),
+    Git(GitSourceProvider),
+}
+
+impl SourceProvider for SourceProviderBackend {
+    fn remote(&self) -> &PackagingRemote {
+        match self {
+            SourceProviderBackend::Gitlab(provider) => provider.remote(),
+            SourceProviderBackend::Git(provider) => provider.remote(),
+        }
+    }
+
+    async fn fetch_changes(
+        &self,
+        last_fetched: Option<OffsetDateTime>,
+    ) -> Result<SourceRepoChanges> {
+        match self {
+            SourceProviderBackend::Gitlab(provider) => provider.fetch_changes(last_fetched).await,
+            SourceProviderBackend::Git(provider) => provider.fetch_changes(last_fetched).await,
+        }
+    }
+}
+
+fn parse_pkgbases(contents: &str) -> Vec<Pkgbase> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string().into())
+        .collect()
+}
+
+/// Pkgbases of all source repositories that have been cloned already.
+async fn list_cloned_pkgbases() -> Result<Vec<Pkgbase>> {
+    spawn_blocking(|| {
+        let source_repos_dir = Utf8PathBuf::from("./source_repos");
+        if !source_repos_dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut pkgbases = Vec::new();
+        for entry in source_repos_dir.read_dir_utf8()? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                pkgbases.push(entry.file_name().to_string().into());
+            }
+        }
+        Ok(pkgbases)
+    })
+    .await?
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RemoteRepositoryState {
+    Unchanged,
+    /// The remote has changes that aren't in the local clone yet.
+    Changed,
+    NotFound,
+}
+
+/// Compare the remote repository of `pkgbase` with its local clone.
+/// Repositories that haven't been cloned yet count as changed.
+fn remote_repository_state(
+    pkgbase: &Pkgbase,
+    remote: &PackagingRemote,
+) -> Result<RemoteRepositoryState> {
+    let remote_heads =
+        match list_remote_heads(&remote.repository_url(pkgbase), remote.credentials()) {
+            Ok(remote_heads) => remote_heads,
+            Err(e) if is_not_found(&e) => return Ok(RemoteRepositoryState::NotFound),
+            Err(e) => return Err(e),
+        };
+    let Ok(repo) = git2::Repository::open(package_source_path(pkgbase)) else {
+        return Ok(RemoteRepositoryState::Changed);
+    };
+    let local_heads = list_local_remote_heads(&repo)?;
+
+    if has_new_remote_heads(&remote_heads, &local_heads) {
+        Ok(RemoteRepositoryState::Changed)
+    } else {
+        Ok(RemoteRepositoryState::Unchanged)
+    }
+}
+
+/// Whether connecting to a remote failed because the repository doesn't exist.
+/// libgit2 doesn't report this consistently across transports, so this
+/// looks at the error messages of `file://` paths, HTTP and SSH servers.
+fn is_not_found(error: &color_eyre::Report) -> bool {
+    let Some(error) = error.downcast_ref::<git2::Error>() else {
+        return false;
+    };
+    let message = error.message().to_lowercase();
+    error.code() == git2::ErrorCode::NotFound
+        || (error.class() == git2::ErrorClass::Os && message.contains("no such file"))
+        || (error.class() == git2::ErrorClass::Http && message.contains("404"))
+        || (error.class() == git2::ErrorClass::Ssh && message.contains("not found"))
+        || message.contains("could not be found")
+        || message.contains("does not appear to be a git repository")
+}
+
+fn collect_fetched(output: (Pkgbase, Result<git2::Repository>), changes: &mut SourceRepoChanges) {
+    match output {
+        (pkgbase, Ok(_)) => changes.fetched.push(pkgbase),
+        (pkgbase, Err(e)) => tracing::warn!("Failed to fetch {pkgbase}: {e:?}"),
+    }
+}
+
+fn collect_state(
+    output: (Pkgbase, Result<RemoteRepositoryState>),
+    changes: &mut SourceRepoChanges,
+    changed: &mut Vec<Pkgbase>,
+    not_found: &mut Vec<Pkgbase>,
+) {
+    match output {
+        (pkgbase, Ok(RemoteRepositoryState::Changed)) => {
+            changed.push(pkgbase.clone());
+            changes.active.push(pkgbase);
+        }
+        (pkgbase, Ok(RemoteRepositoryState::Unchanged)) => changes.active.push(pkgbase),
+        (pkgbase, Ok(RemoteRepositoryState::NotFound)) => not_found.push(pkgbase),
+        // Don't let a single unreachable repository stop others from being updated
+        (pkgbase, Err(e)) => tracing::warn!("Failed to check {pkgbase} for changes: {e:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::{GitCredentials, commit_files};
+    use git2::Repository;
+    use rstest::*;
+
+    fn commit_file(repo: &Repository, branch: &str, content: &str) {
+        let reference = format!("refs/heads/{branch}");
+        let parent = repo.refname_to_id(&reference).ok();
+        let files = [("PKGBUILD", content)];
+        commit_files(repo, Some(&reference), parent, &files, "Update").unwrap();
+    }
+
+    #[rstest]
+    fn test_detects_new_remote_heads() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let remote_repo = Repository::init_bare(dir.join("remote/foo.git")).unwrap();
+        commit_file(&remote_repo, "main", "pkgver=1");
+
+        let remote = PackagingRemote::new(
+            format!("file://{dir}/remote/{{pkgbase}}.git"),
+            GitCredentials::None,
+        );
+        let url = remote.repository_url(&"foo".to_string().into());
+        let clone = Repository::clone(&url, dir.join("clone")).unwrap();
+        let remote_heads = || list_remote_heads(&url, remote.credentials()).unwrap();
+        let local_heads = || list_local_remote_heads(&clone).unwrap();
+        assert!(!has_new_remote_heads(&remote_heads(), &local_heads()));
+
+        // New commits and new branches are detected
+        commit_file(&remote_repo, "main", "pkgver=2");
+        assert!(has_new_remote_heads(&remote_heads(), &local_heads()));
+        clone
+            .find_remote("origin")
+            .unwrap()
+            .fetch(&["+refs/heads/*:refs/remotes/origin/*"], None, None)
+            .unwrap();
+        assert!(!has_new_remote_heads(&remote_heads(), &local_heads()));
+
+        commit_file(&remote_repo, "rebuild", "pkgver=2");
+        assert!(has_new_remote_heads(&remote_heads(), &local_heads()));
+    }
+
+    #[rstest]
+    fn test_detects_removed_repositories() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let remote = PackagingRemote::new(
+            format!("file://{dir}/{{pkgbase}}.git"),
+            GitCredentials::None,
+        );
+
+        let state = remote_repository_state(&"foo".to_string().into(), &remote).unwrap();
+        assert_eq!(state, RemoteRepositoryState::NotFound);
+    }
+
+    #[rstest]
+    fn test_removed_repositories_need_consecutive_not_found() {
+        let counter = NotFoundCounter::default();
+        let foo: Pkgbase = "foo".to_string().into();
+
+        for _ in 1..NotFoundCounter::THRESHOLD {
+            assert!(counter.confirm_removed(&[], vec![foo.clone()]).is_empty());
+        }
+        // Finding the repository again resets the count
+        assert!(
+            counter
+                .confirm_removed(std::slice::from_ref(&foo), Vec::new())
+                .is_empty()
+        );
+        for _ in 1..NotFoundCounter::THRESHOLD {
+            assert!(counter.confirm_removed(&[], vec![foo.clone()]).is_empty());
+        }
+        assert_eq!(counter.confirm_removed(&[], vec![foo.clone()]), vec![foo]);
+    }
+
+    #[rstest]
+    fn test_parse_pkgbases() {
+        assert_eq!(
+            parse_pkgbases("# packages\nfoo\n\n  bar  \n"),
+            vec![Pkgbase::from("foo".to_string()), "bar".to_string().into()]
+        );
+    }
+}
@@ -7,94 +7,248 @@
 //! branches is relatively slow, and it needs to happen every few seconds
 //! for every build namespace. To speed this up, we cache the contents
 //! of .SRCINFO files in this module.
+//!
+//! The server keeps a single [`SourceRepos`] around and calls
+//! [`SourceRepos::refresh`] before using it. Whatever fetches or pushes to source
+//! repositories reports them through a [`SourceRepoChangeNotifier`], so refreshing
+//! only has to open these repositories to drop cache entries of branches that moved.
+//! The cache is also written to [`SRCINFO_CACHE_DIR`], so it survives restarts.
 
-use std::{collections::HashMap, time::Instant};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+    time::Instant,
+};
 
 use camino::{Utf8Path, Utf8PathBuf};
 use color_eyre::{
     Section,
     eyre::{Context, Result},
 };
-use tokio::task::spawn_blocking;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    task::spawn_blocking,
+};
 
 use crate::{
     BranchName, CommitHash, Pkgbase,
-    git::{get_branch_commit_sha, read_srcinfo_from_repo},
+    git::{read_srcinfo_from_repo, resolve_commit_hash},
     source_info::SourceInfo,
 };
 
+/// Parsed .SRCINFO files of each source repository, one JSON file per pkgbase.
+pub static SRCINFO_CACHE_DIR: LazyLock<Utf8PathBuf> =
+    LazyLock::new(|| Utf8PathBuf::from("./srcinfo_cache"));
+
 pub struct SourceRepos {
     source_repos: HashMap<Pkgbase, SourceRepo>,
+    /// Source repos that are skipped by [`SourceRepos::all_repos_mut`].
+    excluded: HashSet<Pkgbase>,
+    source_repos_dir: Utf8PathBuf,
+    cache_dir: Utf8PathBuf,
+    changes_sender: UnboundedSender<Pkgbase>,
+    changes_receiver: UnboundedReceiver<Pkgbase>,
+}
+
+/// Reports source repos whose branches might have moved, e.g. after fetching or pushing.
+/// Doesn't need to wait for the lock around [`SourceRepos`].
+#[derive(Clone)]
+pub struct SourceRepoChangeNotifier(UnboundedSender<Pkgbase>);
+
+impl SourceRepoChangeNotifier {
+    pub fn notify(&self, pkgbases: impl IntoIterator<Item = Pkgbase>) {
+        for pkgbase in pkgbases {
+            // Only fails if the source repos were dropped, so nobody cares anymore
+            let _ = self.0.send(pkgbase);
+        }
+    }
 }
 
 pub struct SourceRepo {
     source_infos: HashMap<BranchName, BranchInfo>,
     path: Utf8PathBuf,
+    /// Whether `source_infos` changed since it was written to the cache dir.
+    dirty: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BranchInfo {
     pub source_info: SourceInfo,
     pub commit_hash: CommitHash,
 }
 
 impl SourceRepos {
-    /// Read all git repositories in "./source_repos" and record their
+    /// Read all git repositories in "./source_repos", using the
+    /// .SRCINFO cache in [`SRCINFO_CACHE_DIR`].
+    pub async fn new() -> Result<Self> {
+        Self::open(
+            Utf8PathBuf::from("./source_repos"),
+            SRCINFO_CACHE_DIR.clone(),
+        )
+        .await
+    }
+
+    /// Read all git repositories in `source_repos_dir` and record their
     /// paths in a HashMap indexed by the directory name.
     /// It is assumed that the directory name equals the pkgbase
     /// of the package inside each git repository.
-    pub async fn new() -> Result<Self> {
+    pub async fn open(source_repos_dir: Utf8PathBuf, cache_dir: Utf8PathBuf) -> Result<Self> {
+        let (changes_sender, changes_receiver) = unbounded_channel();
+        let mut source_repos = SourceRepos {
+            source_repos: HashMap::new(),
+            excluded: HashSet::new(),
+            source_repos_dir,
+            cache_dir,
+            changes_sender,
+            changes_receiver,
+        };
+        source_repos.refresh().await?;
+        Ok(source_repos)
+    }
+
+    /// Pick up source repos that were cloned or deleted, and drop cached
+    /// .SRCINFOs of branches that moved since they were read.
+    /// Only source repos that were just picked up or reported through a
+    /// [`SourceRepoChangeNotifier`] are opened.
+    /// Changed cache entries are written to the cache dir.
+    pub async fn refresh(&mut self) -> Result<()> {
         let start_time = Instant::now();
-        let mut source_repos = HashMap::new();
-        for dir in Utf8PathBuf::from("./source_repos").read_dir_utf8()? {
-            let dir = dir?;
-            if !dir.file_type()?.is_dir() {
-                // Allow arbitrary files that are not git repos
-                // inside the source_repos dir, such as
-                // CACHEDIR.TAG (https://bford.info/cachedir/)
-                continue;
-            }
-            let pkgbase: Pkgbase = dir.file_name().to_string().into();
-            let source_repo = SourceRepo {
-                source_infos: HashMap::new(),
-                path: dir.into_path(),
-            };
-            source_repos.insert(pkgbase, source_repo);
+        let source_repos_dir = self.source_repos_dir.clone();
+        let cache_dir = self.cache_dir.clone();
+        let mut source_repos = std::mem::take(&mut self.source_repos);
+        let mut changed = HashSet::new();
+        while let Ok(pkgbase) = self.changes_receiver.try_recv() {
+            changed.insert(pkgbase);
         }
 
-        // Prime the cache with main branch infos as
-        // these are read most of the time.
-        // Doing it here allows us to batch lots
-        // of synchronous work in a single spawn_blocking
-        // call for performance.
-        let source_repos = spawn_blocking(move || {
-            for source_repo in source_repos.values_mut() {
-                let branch_info = read_branch_info_from_disk(&source_repo.path, "main");
-                // Ignore any errors, e.g. invalid SRCINFO files
-                if let Ok(branch_info) = branch_info {
-                    source_repo
-                        .source_infos
-                        .insert("main".to_string(), branch_info);
+        // Doing all of this in a single spawn_blocking call
+        // allows us to batch lots of synchronous work for performance.
+        let (source_repos, checked_count) = spawn_blocking(move || -> Result<_> {
+            let dirs = list_source_repo_dirs(&source_repos_dir)?;
+            source_repos.retain(|pkgbase, _| {
+                let exists = dirs.contains_key(pkgbase);
+                if !exists {
+                    // Ignore errors, the cache file might not have been written yet
+                    let _ = std::fs::remove_file(cache_file_path(&cache_dir, pkgbase));
                 }
+                exists
+            });
+            for (pkgbase, path) in dirs {
+                // The cache of a new source repo might be from before the last restart
+                if !source_repos.contains_key(&pkgbase) {
+                    changed.insert(pkgbase.clone());
+                }
+                source_repos
+                    .entry(pkgbase)
+                    .or_insert_with_key(|pkgbase| SourceRepo::load(path, &cache_dir, pkgbase));
+            }
+
+            let mut checked_count = 0;
+            for pkgbase in changed {
+                let Some(source_repo) = source_repos.get_mut(&pkgbase) else {
+                    continue;
+                };
+                source_repo.invalidate_moved_branches();
+                // Prime the cache with main branch infos as
+                // these are read most of the time.
+                source_repo.read_branch_info_if_missing("main");
+                if source_repo.dirty {
+                    source_repo.write_cache(&cache_dir, &pkgbase);
+                }
+                checked_count += 1;
             }
-            source_repos
+            Ok((source_repos, checked_count))
         })
-        .await?;
+        .await??;
+        self.source_repos = source_repos;
 
         tracing::debug!(
-            count = source_repos.len(),
+            count = self.source_repos.len(),
+            checked_count,
             elapsed_time = ?start_time.elapsed(),
-            "Opened all source repos and read .SRCINFOs in main branches"
+            "Refreshed source repos and .SRCINFOs in main branches"
         );
 
-        Ok(SourceRepos { source_repos })
+        Ok(())
+    }
+
+    pub fn change_notifier(&self) -> SourceRepoChangeNotifier {
+        SourceRepoChangeNotifier(self.changes_sender.clone())
+    }
+
+    /// Skip these source repos from now on, e.g. because their projects were archived.
+    /// Replaces previously excluded source repos.
+    pub fn set_excluded(&mut self, pkgbases: impl IntoIterator<Item = Pkgbase>) {
+        self.excluded = pkgbases.into_iter().collect();
     }
 
     pub fn all_repos_mut(&mut self) -> impl Iterator<Item = (&Pkgbase, &mut SourceRepo)> {
-        self.source_repos.iter_mut()
+        self.source_repos
+            .iter_mut()
+            .filter(|(pkgbase, _)| !self.excluded.contains(*pkgbase))
     }
 }
 
 impl SourceRepo {
+    /// Create a source repo, using the cached .SRCINFOs of previous runs if there are any.
+    fn load(path: Utf8PathBuf, cache_dir: &Utf8Path, pkgbase: &Pkgbase) -> Self {
+        let source_infos = std::fs::read(cache_file_path(cache_dir, pkgbase))
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default();
+        SourceRepo {
+            source_infos,
+            path,
+            dirty: false,
+        }
+    }
+
+    fn write_cache(&mut self, cache_dir: &Utf8Path, pkgbase: &Pkgbase) {
+        let result = std::fs::create_dir_all(cache_dir)
+            .map_err(Into::into)
+            .and_then(|_| serde_json::to_vec(&self.source_infos).map_err(Into::into))
+            .and_then(|contents| {
+                std::fs::write(cache_file_path(cache_dir, pkgbase), contents).map_err(Into::into)
+            });
+        match result {
+            Ok(()) => self.dirty = false,
+            Err::<_, color_eyre::Report>(e) => {
+                tracing::warn!("Failed to write .SRCINFO cache of {pkgbase}: {e:?}")
+            }
+        }
+    }
+
+    /// Drop cache entries of branches that point to a different commit now,
+    /// or that don't exist anymore.
+    fn invalidate_moved_branches(&mut self) {
+        if self.source_infos.is_empty() {
+            return;
+        }
+        let count = self.source_infos.len();
+        match git2::Repository::open(self.path.as_std_path()) {
+            Ok(repo) => self.source_infos.retain(|branch, branch_info| {
+                resolve_commit_hash(&repo, branch)
+                    .is_ok_and(|commit_hash| commit_hash == branch_info.commit_hash)
+            }),
+            Err(_) => self.source_infos.clear(),
+        }
+        if self.source_infos.len() != count {
+            self.dirty = true;
+        }
+    }
+
+    fn read_branch_info_if_missing(&mut self, branch: &str) {
+        if self.source_infos.contains_key(branch) {
+            return;
+        }
+        // Ignore any errors, e.g. invalid SRCINFO files
+        if let Ok(branch_info) = read_branch_info_from_disk(&self.path, branch) {
+            self.source_infos.insert(branch.to_string(), branch_info);
+            self.dirty = true;
+        }
+    }
+
     /// Get a SourceInfo struct for the given pkgbase and branch name.
     /// if it does not exist, read it from its git repository instead
     /// and insert it into the cache.
@@ -112,6 +266,7 @@ impl SourceRepo {
                     spawn_blocking(move || read_branch_info_from_disk(&path, &branch))
                         .await
                         .wrap_err("Failed to spawn source info read task")??;
+                self.dirty = true;
                 let branch_info = vacant_entry.insert(branch_info);
                 Ok(branch_info)
             }
@@ -119,14 +274,118 @@ impl SourceRepo {
     }
 }
 
+/// Directories of all git repositories in `source_repos_dir`, indexed by pkgbase.
+fn list_source_repo_dirs(source_repos_dir: &Utf8Path) -> Result<HashMap<Pkgbase, Utf8PathBuf>> {
+    let mut dirs = HashMap::new();
+    // Nothing has been cloned yet
+    if !source_repos_dir.exists() {
+        return Ok(dirs);
+    }
+    for dir in source_repos_dir.read_dir_utf8()? {
+        let dir = dir?;
+        if !dir.file_type()?.is_dir() {
+            // Allow arbitrary files that are not git repos
+            // inside the source_repos dir, such as
+            // CACHEDIR.TAG (https://bford.info/cachedir/)
+            continue;
+        }
+        dirs.insert(dir.file_name().to_string().into(), dir.into_path());
+    }
+    Ok(dirs)
+}
+
+fn cache_file_path(cache_dir: &Utf8Path, pkgbase: &Pkgbase) -> Utf8PathBuf {
+    cache_dir.join(format!("{pkgbase}.json"))
+}
+
 fn read_branch_info_from_disk(path: &Utf8Path, branch: &str) -> Result<BranchInfo> {
     let git_repo = git2::Repository::open(path.as_std_path())
         .wrap_err("Failed to open git repository")
         .with_note(|| path.to_string())?;
     let source_info = read_srcinfo_from_repo(&git_repo, branch)?;
-    let commit_hash = get_branch_commit_sha(&git_repo, branch)?;
+    let commit_hash = resolve_commit_hash(&git_repo, branch)?;
     Ok(BranchInfo {
         source_info,
         commit_hash,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::commit_files;
+    use rstest::*;
+
+    fn commit_srcinfo(repo: &git2::Repository, pkgver: &str) -> git2::Oid {
+        let srcinfo = format!(
+            "pkgbase = foo
+\tpkgdesc = Test package
+\tpkgver = {pkgver}
+\tpkgrel = 1
+\turl = https://example.com
+\tarch = x86_64
+\tlicense = MIT
+
+pkgname = foo
+"
+        );
+        let commit = commit_files(repo, None, None, &[(".SRCINFO", &srcinfo)], pkgver).unwrap();
+        repo.reference("refs/remotes/origin/main", commit, true, "test")
+            .unwrap();
+        commit
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_refresh_invalidates_moved_branches() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let (source_repos_dir, cache_dir) = (dir.join("source_repos"), dir.join("cache"));
+        let repo = git2::Repository::init(source_repos_dir.join("foo")).unwrap();
+        let first = commit_srcinfo(&repo, "1.0");
+
+        let mut source_repos = SourceRepos::open(source_repos_dir.clone(), cache_dir.clone())
+            .await
+            .unwrap();
+        let pkgbase: Pkgbase = "foo".to_string().into();
+        assert!(cache_file_path(&cache_dir, &pkgbase).exists());
+
+        // A new instance reads the cache written by the previous one
+        let mut cached = SourceRepos::open(source_repos_dir, cache_dir)
+            .await
+            .unwrap();
+        let (_, source_repo) = cached.all_repos_mut().next().unwrap();
+        assert!(!source_repo.dirty);
+        let branch_info = source_repo
+            .get_branch_info("main".to_string())
+            .await
+            .unwrap();
+        assert_eq!(branch_info.commit_hash.as_ref(), &first.to_string());
+
+        let second = commit_srcinfo(&repo, "1.1");
+        // Source repos are only checked for moved branches after a change was reported
+        source_repos.refresh().await.unwrap();
+        let (_, source_repo) = source_repos.all_repos_mut().next().unwrap();
+        let branch_info = source_repo
+            .get_branch_info("main".to_string())
+            .await
+            .unwrap();
+        assert_eq!(branch_info.commit_hash.as_ref(), &first.to_string());
+
+        source_repos.change_notifier().notify([pkgbase.clone()]);
+        source_repos.refresh().await.unwrap();
+        let (_, source_repo) = source_repos.all_repos_mut().next().unwrap();
+        let branch_info = source_repo
+            .get_branch_info("main".to_string())
+            .await
+            .unwrap();
+        assert_eq!(branch_info.commit_hash.as_ref(), &second.to_string());
+        assert_eq!(
+            branch_info.source_info.base.package_version.to_string(),
+            "1.1"
+        );
+
+        source_repos.set_excluded([pkgbase]);
+        assert!(source_repos.all_repos_mut().next().is_none());
+    }
+}
@@ -0,0 +1,270 @@
+//! Detect `.SRCINFO` files that don't match their PKGBUILD.
+//!
+//! Build graphs are calculated from the `.SRCINFO` files committed to source
+//! repositories. Packagers generate these from the PKGBUILD, so they're out of
+//! date if a PKGBUILD change was committed without regenerating the `.SRCINFO`.
+//!
+//! For origin changesets, the `.SRCINFO` is regenerated with `makepkg --printsrcinfo`
+//! and compared with the committed one. As this sources the PKGBUILD, makepkg runs
+//! in a bubblewrap sandbox without network access, on a copy of the source repository.
+//! The sandbox only sees `/usr`, the makepkg config and the copy, and none of the
+//! server's environment variables, so a PKGBUILD can't read any credentials.
+
+use std::{collections::HashSet, process::Stdio, time::Duration};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::{Context, Result, bail};
+use git2::{Oid, Repository, build::CheckoutBuilder};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::{
+    BUILD_DIR, CommitHash, GitRef, Pkgbase,
+    git::{package_source_path, read_file_from_tree},
+};
+
+/// How long generating a `.SRCINFO` may take before giving up.
+const PRINTSRCINFO_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Outcomes are shown on the namespace page, so only keep this many
+/// differing lines of the untrusted makepkg output.
+const MAX_REPORTED_LINES: usize = 50;
+/// Lines and errors are cut off after this many characters.
+const MAX_REPORTED_LINE_LENGTH: usize = 200;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SrcinfoCheckOutcome {
+    /// The committed `.SRCINFO` matches the PKGBUILD
+    UpToDate,
+    /// The committed `.SRCINFO` differs from the one generated from the PKGBUILD
+    Stale {
+        /// Lines that are only in the generated `.SRCINFO`
+        missing_lines: Vec<String>,
+        /// Lines that are only in the committed `.SRCINFO`
+        outdated_lines: Vec<String>,
+    },
+    /// The `.SRCINFO` couldn't be generated, e.g. because makepkg isn't available
+    Failed { error: String },
+}
+
+/// The most recent check of an origin changeset's `.SRCINFO` in a namespace.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SrcinfoCheck {
+    pub pkgbase: Pkgbase,
+    pub commit_hash: CommitHash,
+    pub outcome: SrcinfoCheckOutcome,
+    pub checked_at: time::OffsetDateTime,
+}
+
+impl SrcinfoCheck {
+    pub fn is_stale(&self) -> bool {
+        matches!(self.outcome, SrcinfoCheckOutcome::Stale { .. })
+    }
+}
+
+/// An origin changeset whose `.SRCINFO` keeps a namespace from building.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockingSrcinfoCheck {
+    pub pkgbase: Pkgbase,
+    pub git_ref: GitRef,
+    /// Outcome of the check of the current commit, `None` if it wasn't checked yet.
+    pub outcome: Option<SrcinfoCheckOutcome>,
+}
+
+/// Compare the committed `.SRCINFO` of `commit_hash` with the one generated from its PKGBUILD.
+/// Errors while generating the `.SRCINFO` are reported as [`SrcinfoCheckOutcome::Failed`].
+pub async fn check_srcinfo(pkgbase: &Pkgbase, commit_hash: &CommitHash) -> SrcinfoCheckOutcome {
+    let dir = BUILD_DIR
+        .join("srcinfo_check")
+        .join(format!("{pkgbase}-{commit_hash}"));
+    let result = async {
+        let committed = export_commit(pkgbase.clone(), commit_hash.clone(), dir.clone()).await?;
+        let generated = generate_srcinfo(&dir).await?;
+        Ok::<_, color_eyre::Report>(compare_srcinfo(&committed, &generated))
+    }
+    .await;
+    if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+        tracing::debug!("Failed to remove {dir}: {e}");
+    }
+
+    result.unwrap_or_else(|e| SrcinfoCheckOutcome::Failed {
+        error: truncate(&format!("{e:#}")),
+    })
+}
+
+/// Check out the tree of `commit_hash` into `dir` and return its committed `.SRCINFO`.
+async fn export_commit(
+    pkgbase: Pkgbase,
+    commit_hash: CommitHash,
+    dir: Utf8PathBuf,
+) -> Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let repo = Repository::open(package_source_path(&pkgbase))?;
+        let commit = repo.find_commit(Oid::from_str(commit_hash.as_ref())?)?;
+        let srcinfo = read_file_from_tree(&repo, &commit.tree()?, ".SRCINFO")
+            .wrap_err("No .SRCINFO committed")?;
+
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+        repo.checkout_tree(
+            commit.as_object(),
+            Some(CheckoutBuilder::new().target_dir(dir.as_std_path()).force()),
+        )
+        .wrap_err("Failed to check out source repository")?;
+
+        Ok(srcinfo)
+    })
+    .await?
+}
+
+/// Run `makepkg --printsrcinfo` in `dir` inside a bubblewrap sandbox.
+/// The sandbox has no network access, no environment variables except for a minimal
+/// `PATH`, and can only write to `dir` and a temporary `/tmp`.
+/// makepkg refuses to run as root, so it runs as an unprivileged user.
+pub async fn generate_srcinfo(dir: &Utf8Path) -> Result<String> {
+    let mut cmd = Command::new("bwrap");
+    cmd.args(["--ro-bind", "/usr", "/usr"])
+        .args(["--symlink", "usr/bin", "/bin"])
+        .args(["--symlink", "usr/bin", "/sbin"])
+        .args(["--symlink", "usr/lib", "/lib"])
+        .args(["--symlink", "usr/lib", "/lib64"])
+        .args(["--ro-bind", "/etc/makepkg.conf", "/etc/makepkg.conf"])
+        .args([
+            "--ro-bind-try",
+            "/etc/makepkg.conf.d",
+            "/etc/makepkg.conf.d",
+        ])
+        .args(["--dev", "/dev"])
+        .args(["--proc", "/proc"])
+        .args(["--tmpfs", "/tmp"])
+        .arg("--bind")
+        .args([dir, dir])
+        .arg("--chdir")
+        .arg(dir)
+        .arg("--clearenv")
+        .args(["--setenv", "PATH", "/usr/bin"])
+        .args(["--setenv", "LANG", "C.UTF-8"])
+        .args(["--setenv", "HOME", "/tmp"])
+        .args(["--unshare-all", "--uid", "65534", "--gid", "65534"])
+        .args(["--die-with-parent", "--new-session"])
+        .args(["--", "makepkg", "--printsrcinfo"])
+        .env_clear()
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+
+    tracing::debug!("{cmd:?}");
+    let output = tokio::time::timeout(PRINTSRCINFO_TIMEOUT, cmd.output())
+        .await
+        .wrap_err("makepkg --printsrcinfo timed out")?
+        .wrap_err("Failed to run bwrap")?;
+    if !output.status.success() {
+        bail!(
+            "makepkg --printsrcinfo failed: {}",
+            truncate(String::from_utf8_lossy(&output.stderr).trim())
+        );
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Compare two `.SRCINFO` files line by line, ignoring comments, empty lines and indentation.
+/// Lines are compared per `pkgbase` or `pkgname` section, so values that moved from one
+/// split package to another are reported as well. Differing lines are prefixed with
+/// the header of their section.
+pub fn compare_srcinfo(committed: &str, generated: &str) -> SrcinfoCheckOutcome {
+    let committed = section_lines(committed);
+    let generated = section_lines(generated);
+    let committed_set: HashSet<_> = committed.iter().collect();
+    let generated_set: HashSet<_> = generated.iter().collect();
+
+    let report = |lines: &[String], other: &HashSet<&String>| -> Vec<String> {
+        lines
+            .iter()
+            .filter(|line| !other.contains(line))
+            .take(MAX_REPORTED_LINES)
+            .map(|line| truncate(line))
+            .collect()
+    };
+    let missing_lines = report(&generated, &committed_set);
+    let outdated_lines = report(&committed, &generated_set);
+
+    if missing_lines.is_empty() && outdated_lines.is_empty() {
+        SrcinfoCheckOutcome::UpToDate
+    } else {
+        SrcinfoCheckOutcome::Stale {
+            missing_lines,
+            outdated_lines,
+        }
+    }
+}
+
+/// Relevant lines of a `.SRCINFO`, prefixed with the header of their section,
+/// e.g. `pkgname = foo: depends = bar`.
+fn section_lines(srcinfo: &str) -> Vec<String> {
+    let mut section = "";
+    let mut lines = Vec::new();
+    for line in srcinfo.lines() {
+        // Section headers are the only lines that aren't indented
+        let is_header = !line.starts_with(char::is_whitespace);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if is_header {
+            section = line;
+            lines.push(line.to_string());
+        } else {
+            lines.push(format!("{section}: {line}"));
+        }
+    }
+    lines
+}
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_REPORTED_LINE_LENGTH) {
+        Some((index, _)) => format!("{}…", &text[..index]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    const SRCINFO: &str =
+        "pkgbase = foo\n\tpkgver = 1.0\n\tpkgrel = 1\n\tarch = any\n\npkgname = foo\n";
+
+    #[rstest]
+    fn test_compare_srcinfo() {
+        let reformatted = format!("# Generated by makepkg\n{}", SRCINFO.replace('\t', "  "));
+        assert_eq!(
+            compare_srcinfo(SRCINFO, &reformatted),
+            SrcinfoCheckOutcome::UpToDate
+        );
+
+        let bumped = SRCINFO.replace("pkgver = 1.0", "pkgver = 1.1");
+        assert_eq!(
+            compare_srcinfo(SRCINFO, &bumped),
+            SrcinfoCheckOutcome::Stale {
+                missing_lines: vec!["pkgbase = foo: pkgver = 1.1".to_string()],
+                outdated_lines: vec!["pkgbase = foo: pkgver = 1.0".to_string()],
+            }
+        );
+    }
+
+    #[rstest]
+    fn test_compare_srcinfo_split_packages() {
+        let committed = "pkgbase = foo\n\tpkgver = 1.0\n\npkgname = foo\n\tdepends = bar\n\npkgname = foo-docs\n";
+        let generated = "pkgbase = foo\n\tpkgver = 1.0\n\npkgname = foo\n\npkgname = foo-docs\n\tdepends = bar\n";
+        assert_eq!(
+            compare_srcinfo(committed, generated),
+            SrcinfoCheckOutcome::Stale {
+                missing_lines: vec!["pkgname = foo-docs: depends = bar".to_string()],
+                outdated_lines: vec!["pkgname = foo: depends = bar".to_string()],
+            }
+        );
+    }
+}
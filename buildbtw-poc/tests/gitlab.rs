@@ -0,0 +1,349 @@
+//! Drive our gitlab integration against the in-process gitlab stand-in.
+//! Run with `cargo test --features gitlab-mock`.
+
+use std::{
+    net::TcpListener,
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use buildbtw_poc::{
+    CommitHash, CreateBuildNamespace, PackageBuildStatus, PipelineTarget, ScheduleBuild,
+    api::ShowNamespaceJson,
+    build_logs::build_logs_dir_path,
+    build_set_graph::BuildSetGraph,
+    git::parse_srcinfo,
+    gitlab::{
+        PipelineStatus, cancel_pipeline, create_pipeline, get_changed_projects_since,
+        get_pipeline_status,
+        job_logs::archive_pipeline_jobs,
+        mock::{MOCK_TOKEN, MockGitlab},
+        pipeline_status::{PipelineId, get_pipeline_statuses},
+        retry_pipeline, set_all_projects_ci_config,
+    },
+    source_info::ConcreteArchitecture,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use reqwest::header::ACCEPT;
+use rstest::*;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+const GROUP: &str = "archlinux/packaging/packages";
+
+const PKGBUILD: &str = "pkgname=foo\npkgver=1.0\npkgrel=1\narch=(x86_64)\n";
+const SRCINFO: &str = "pkgbase = foo
+\tpkgdesc = Test package
+\tpkgver = 1.0
+\tpkgrel = 1
+\turl = https://example.com
+\tarch = x86_64
+\tlicense = MIT
+
+pkgname = foo
+";
+
+struct TestGitlab {
+    dir: tempfile::TempDir,
+    gitlab: MockGitlab,
+    /// Commit on the main branch of the "foo" project.
+    foo_commit: CommitHash,
+}
+
+impl TestGitlab {
+    fn path(&self) -> &Utf8Path {
+        Utf8Path::from_path(self.dir.path()).unwrap()
+    }
+}
+
+#[fixture]
+async fn gitlab() -> TestGitlab {
+    let dir = tempfile::tempdir().unwrap();
+    let repos_dir = Utf8Path::from_path(dir.path()).unwrap().join("remote");
+    let gitlab = MockGitlab::start(&repos_dir, GROUP).await.unwrap();
+    let foo_commit = gitlab
+        .commit_files(
+            "foo",
+            "main",
+            &[("PKGBUILD", PKGBUILD), (".SRCINFO", SRCINFO)],
+        )
+        .unwrap();
+    TestGitlab {
+        dir,
+        gitlab,
+        foo_commit,
+    }
+}
+
+fn schedule_build(iteration: Uuid, commit_hash: CommitHash) -> ScheduleBuild {
+    ScheduleBuild {
+        namespace: Uuid::new_v4(),
+        iteration,
+        source: PipelineTarget {
+            pkgbase: "foo".to_string().into(),
+            branch_name: "main".to_string(),
+            commit_hash,
+        },
+        architecture: ConcreteArchitecture::X86_64,
+        srcinfo: parse_srcinfo(SRCINFO).unwrap(),
+        updated_build_set_graph: BuildSetGraph::default(),
+    }
+}
+
+/// Poll `condition` until it returns `Some`, failing the test after `timeout`.
+async fn wait_for<T, F, Fut>(timeout: Duration, mut condition: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    let start = Instant::now();
+    loop {
+        if let Some(value) = condition().await {
+            return value;
+        }
+        assert!(start.elapsed() < timeout, "Timed out after {timeout:?}");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_changed_projects(#[future] gitlab: TestGitlab) {
+    let TestGitlab { gitlab, .. } = gitlab.await;
+    let client = gitlab.client().await.unwrap();
+
+    let changed = get_changed_projects_since(&client, None, GROUP)
+        .await
+        .unwrap();
+    assert_eq!(
+        changed
+            .iter()
+            .map(|project| project.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["foo"]
+    );
+
+    let after_last_change = OffsetDateTime::now_utc() + time::Duration::minutes(1);
+    let changed = get_changed_projects_since(&client, Some(after_last_change), GROUP)
+        .await
+        .unwrap();
+    assert!(changed.is_empty());
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_pipelines(#[future] gitlab: TestGitlab) {
+    let test_gitlab = gitlab.await;
+    let gitlab = &test_gitlab.gitlab;
+    let client = gitlab.client().await.unwrap();
+
+    let iteration = Uuid::new_v4();
+    let pipeline = create_pipeline(
+        &client,
+        &schedule_build(iteration, test_gitlab.foo_commit.clone()),
+        "test",
+        GROUP,
+        8080,
+    )
+    .await
+    .unwrap();
+    assert_eq!(pipeline.status, PipelineStatus::Created);
+    let mock_pipeline = &gitlab.pipelines()[0];
+    assert_eq!(mock_pipeline.git_ref, "main");
+    assert_eq!(mock_pipeline.variables["PKGBASE"], "foo");
+    assert_eq!(
+        mock_pipeline.variables["ITERATION_ID"],
+        iteration.to_string()
+    );
+
+    gitlab
+        .set_pipeline_status(pipeline.id, PipelineStatus::Running)
+        .unwrap();
+    assert_eq!(
+        get_pipeline_status(&client, pipeline.project_id, pipeline.id)
+            .await
+            .unwrap(),
+        PipelineStatus::Running
+    );
+    let pipeline_id = PipelineId {
+        project_id: pipeline.project_id,
+        pipeline_id: pipeline.id,
+    };
+    let statuses =
+        get_pipeline_statuses(&gitlab.graphql_client().unwrap(), &client, &[pipeline_id])
+            .await
+            .unwrap();
+    assert_eq!(statuses[&pipeline_id], PipelineStatus::Running);
+
+    assert_eq!(
+        cancel_pipeline(&client, pipeline.project_id, pipeline.id)
+            .await
+            .unwrap(),
+        PipelineStatus::Canceled
+    );
+    assert_eq!(
+        retry_pipeline(&client, pipeline.project_id, pipeline.id)
+            .await
+            .unwrap(),
+        PipelineStatus::Pending
+    );
+
+    let logs_dir = test_gitlab.path().join("logs");
+    archive_pipeline_jobs(
+        &client,
+        &gitlab.base_url(),
+        MOCK_TOKEN,
+        pipeline.project_id,
+        pipeline.id,
+        &logs_dir,
+    )
+    .await
+    .unwrap();
+    let trace =
+        std::fs::read_to_string(logs_dir.join(format!("job-{}-build.log", pipeline.id))).unwrap();
+    assert!(trace.contains("foo"));
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_set_all_projects_ci_config(#[future] gitlab: TestGitlab) {
+    let TestGitlab { gitlab, .. } = gitlab.await;
+    gitlab
+        .commit_files("bar", "main", &[("PKGBUILD", "pkgname=bar\n")])
+        .unwrap();
+    let client = gitlab.client().await.unwrap();
+
+    set_all_projects_ci_config(&client, GROUP, "ci/build.yml".to_string())
+        .await
+        .unwrap();
+
+    for project in gitlab.projects() {
+        assert_eq!(project.ci_config_path, "ci/build.yml");
+    }
+}
+
+/// Start a server dispatching builds to the mock gitlab, and follow
+/// a package from being pushed to its pipeline succeeding.
+#[rstest]
+#[tokio::test]
+async fn test_server_builds_on_gitlab(#[future] gitlab: TestGitlab) {
+    let test_gitlab = gitlab.await;
+    let gitlab = &test_gitlab.gitlab;
+
+    // Creating a namespace's pacman repository needs `repo-add`,
+    // which we don't want to depend on here.
+    let bin_dir = test_gitlab.path().join("bin");
+    std::fs::create_dir_all(&bin_dir).unwrap();
+    let repo_add = bin_dir.join("repo-add");
+    std::fs::write(&repo_add, "#!/bin/sh\ntouch \"$1\"\n").unwrap();
+    let mut permissions = std::fs::metadata(&repo_add).unwrap().permissions();
+    std::os::unix::fs::PermissionsExt::set_mode(&mut permissions, 0o755);
+    std::fs::set_permissions(&repo_add, permissions).unwrap();
+
+    let server_dir = test_gitlab.path().join("server");
+    std::fs::create_dir_all(&server_dir).unwrap();
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let server_url = format!("http://127.0.0.1:{port}");
+    let path = format!("{bin_dir}:{}", std::env::var("PATH").unwrap_or_default());
+    let _server = tokio::process::Command::new(env!("CARGO_BIN_EXE_buildbtw-server"))
+        .current_dir(&server_dir)
+        .args(["run", "--interface", "127.0.0.1", "--port"])
+        .arg(port.to_string())
+        .args(["--base-url", &server_url])
+        .env(
+            "DATABASE_URL",
+            format!("sqlite://{server_dir}/buildbtw.sqlite"),
+        )
+        .env("GITLAB_TOKEN", MOCK_TOKEN)
+        .env("GITLAB_DOMAIN", gitlab.domain())
+        .env("GITLAB_PACKAGES_GROUP", GROUP)
+        .env("BUILD_DISPATCH", "gitlab")
+        .env("GITLAB_INSECURE", "true")
+        .env(
+            "SOURCE_REPO_URL_TEMPLATE",
+            gitlab.source_repo_url_template(),
+        )
+        .env("SOURCE_REPO_AUTH", "none")
+        .env("PATH", path)
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    // The source repo polling loop clones all changed projects
+    let source_repo = server_dir.join("source_repos/foo");
+    wait_for(Duration::from_secs(30), || async {
+        source_repo.join(".git").exists().then_some(())
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+    wait_for(Duration::from_secs(30), || async {
+        client
+            .post(format!("{server_url}/namespace"))
+            .json(&CreateBuildNamespace {
+                name: Some("test".to_string()),
+                origin_changesets: vec![("foo".to_string().into(), "main".to_string())],
+                base_repositories: Vec::new(),
+                parent: None,
+                merge_requests: false,
+                bump_pkgrel: false,
+                rebase_origin_changesets: false,
+                build_dispatch: None,
+            })
+            .send()
+            .await
+            .ok()?
+            .error_for_status()
+            .ok()
+    })
+    .await;
+
+    // The namespace update loop dispatches the build to gitlab
+    let pipeline = wait_for(Duration::from_secs(60), || async {
+        gitlab.pipelines().into_iter().next()
+    })
+    .await;
+    assert_eq!(pipeline.variables["PKGBASE"], "foo");
+    assert_eq!(pipeline.variables["NAMESPACE_NAME"], "test");
+
+    gitlab
+        .set_pipeline_status(pipeline.id, PipelineStatus::Success)
+        .unwrap();
+    let iteration = wait_for(Duration::from_secs(60), || async {
+        let namespace: ShowNamespaceJson = client
+            .get(format!("{server_url}/namespace/test"))
+            .header(ACCEPT, "application/json")
+            .send()
+            .await
+            .ok()?
+            .json()
+            .await
+            .ok()?;
+        let iteration = namespace.architecture_iteration?;
+        iteration
+            .build_graph
+            .node_weights()
+            .all(|node| node.status == PackageBuildStatus::Built)
+            .then_some(iteration)
+    })
+    .await;
+
+    // Logs of the finished pipeline are archived in the background
+    let logs_dir = Utf8PathBuf::from(&server_dir).join(build_logs_dir_path(
+        iteration.id,
+        ConcreteArchitecture::X86_64,
+        &"foo".to_string().into(),
+    ));
+    wait_for(Duration::from_secs(30), || async {
+        logs_dir
+            .join(format!("job-{}-build.log", pipeline.id))
+            .exists()
+            .then_some(())
+    })
+    .await;
+}